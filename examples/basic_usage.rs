@@ -189,7 +189,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let nonexistent_u64_key = 999u64;
 
         let byte_bitmap = byte_table.get_bitmap(nonexistent_byte_key)?;
-        let string_bitmap = string_table.get_bitmap(&nonexistent_string_key)?;
+        let string_bitmap = string_table.get_bitmap(nonexistent_string_key)?;
         let u64_bitmap = u64_table.get_bitmap(nonexistent_u64_key)?;
 
         if byte_bitmap.is_empty() && string_bitmap.is_empty() && u64_bitmap.is_empty() {
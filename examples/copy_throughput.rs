@@ -0,0 +1,70 @@
+//! Throughput of `copy_database` on a byte-slice table.
+//!
+//! The table copy inserts the slices borrowed from the source guards, so a
+//! `&[u8]`/`&[u8]` table has no separate zero-copy path. This compares the
+//! plan against a hand-written loop that inserts the guard slices directly,
+//! which is the fastest copy redb allows, and reports the plan's overhead.
+//! Run with `--release`.
+
+use redb::{Database, ReadableDatabase, ReadableTable, TableDefinition};
+use redb_extras::dbcopy::{copy_database, CopyPlan};
+use std::time::{Duration, Instant};
+
+const BLOBS: TableDefinition<&[u8], &[u8]> = TableDefinition::new("blobs");
+const ENTRIES: u64 = 500_000;
+const ROUNDS: usize = 5;
+
+fn direct_copy(
+    source: &Database,
+    destination: &Database,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let read_txn = source.begin_read()?;
+    let write_txn = destination.begin_write()?;
+    {
+        let source_table = read_txn.open_table(BLOBS)?;
+        let mut destination_table = write_txn.open_table(BLOBS)?;
+        for entry in source_table.iter()? {
+            let (key, value) = entry?;
+            destination_table.insert(key.value(), value.value())?;
+        }
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let source = Database::create(dir.path().join("source.redb"))?;
+    let write_txn = source.begin_write()?;
+    {
+        let mut blobs = write_txn.open_table(BLOBS)?;
+        for key in 0..ENTRIES {
+            blobs.insert(key.to_be_bytes().as_slice(), [7u8; 100].as_slice())?;
+        }
+    }
+    write_txn.commit()?;
+
+    let plan = CopyPlan::new().table(BLOBS);
+    let (mut planned, mut direct) = (Duration::ZERO, Duration::ZERO);
+    for round in 0..ROUNDS {
+        let destination = Database::create(dir.path().join(format!("plan{}.redb", round)))?;
+        let started = Instant::now();
+        copy_database(&source, &destination, &plan)?;
+        planned += started.elapsed();
+
+        let destination = Database::create(dir.path().join(format!("direct{}.redb", round)))?;
+        let started = Instant::now();
+        direct_copy(&source, &destination)?;
+        direct += started.elapsed();
+    }
+
+    println!(
+        "{} entries, mean of {} rounds: copy_database {:?}, direct loop {:?} ({:+.1}%)",
+        ENTRIES,
+        ROUNDS,
+        planned / ROUNDS as u32,
+        direct / ROUNDS as u32,
+        (planned.as_secs_f64() / direct.as_secs_f64() - 1.0) * 100.0
+    );
+    Ok(())
+}
@@ -9,15 +9,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let write_txn = db.begin_write()?;
 
     {
-        let mut table =
-            write_txn.open_multimap_table(builder.multimap_table_definition::<u64, u64>(0))?;
+        let mut table = builder.open_multimap_table::<u64, u64>(&write_txn, 0)?;
         table.insert(42u64, 1u64)?;
         table.insert(42u64, 2u64)?;
     }
 
     {
-        let mut table =
-            write_txn.open_multimap_table(builder.multimap_table_definition::<u64, u64>(1))?;
+        let mut table = builder.open_multimap_table::<u64, u64>(&write_txn, 1)?;
         table.insert(42u64, 3u64)?;
     }
 
@@ -0,0 +1,380 @@
+//! Copy steps for the crate's bucketed layouts.
+//!
+//! Key-bucketed tables are copied along with the `KeyBuilder` persisted for
+//! them, and bucket tables of a `TableBucketBuilder` are recorded in the
+//! destination's bucket registry as they are copied, so both can be used
+//! through the same builders after the copy.
+
+use super::{manifest, CopyBatch, CopyKind, CopyStep, DbCopyError, EncodedEntries, TablePlan};
+use crate::error::DatabaseError;
+use crate::key_buckets::{BucketError, BucketedKey, KeyBuilder, BUCKET_CONFIG_TABLE};
+use crate::table_buckets::{TableBucketBuilder, TABLE_BUCKET_REGISTRY};
+use redb::{
+    Key, ReadTransaction, TableDefinition, TableError, TableHandle, Value, WriteTransaction,
+};
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
+use xxhash_rust::xxh3::Xxh3;
+
+/// A key-bucketed table, copied together with its persisted key builder.
+pub(super) struct BucketedTablePlan<K: Key + 'static, V: Value + 'static> {
+    table: TablePlan<BucketedKey<K>, V>,
+    builder: KeyBuilder,
+}
+
+impl<K: Key + 'static, V: Value + 'static> BucketedTablePlan<K, V> {
+    pub(super) fn new(builder: &KeyBuilder, table: TableDefinition<'_, BucketedKey<K>, V>) -> Self {
+        Self {
+            table: TablePlan::new(table),
+            builder: builder.clone(),
+        }
+    }
+
+    fn bucket_error(&self, err: BucketError) -> DbCopyError {
+        DbCopyError::BucketConfigFailed(self.display_name(), err)
+    }
+
+    /// Fails if the source persisted another builder for the table, whose
+    /// keys would then be read into the wrong buckets.
+    fn check_source(&self, source: &ReadTransaction) -> std::result::Result<(), DbCopyError> {
+        match KeyBuilder::load(source, self.name()).map_err(|err| self.bucket_error(err))? {
+            Some(stored) if stored != self.builder => {
+                Err(self.bucket_error(BucketError::ConfigMismatch(format!(
+                    "source persisted {:?} for '{}' but the plan uses {:?}",
+                    stored,
+                    self.name(),
+                    self.builder
+                ))))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Records the builder in the destination, failing if it persisted another one.
+    fn persist(&self, destination: &WriteTransaction) -> std::result::Result<(), DbCopyError> {
+        self.builder
+            .persist(destination, self.name())
+            .map_err(|err| self.bucket_error(err))
+    }
+}
+
+impl<K: Key + 'static, V: Value + 'static> CopyStep for BucketedTablePlan<K, V> {
+    fn name(&self) -> &str {
+        self.table.name()
+    }
+
+    // The key builder is carried over from the source's config table
+    fn reads(&self, table: &str) -> bool {
+        table == self.source_name() || table == BUCKET_CONFIG_TABLE.name()
+    }
+
+    fn kind(&self) -> CopyKind {
+        CopyKind::Table
+    }
+
+    fn type_names(&self) -> (String, String) {
+        self.table.type_names()
+    }
+
+    fn preflight(&self, destination: &ReadTransaction) -> std::result::Result<bool, TableError> {
+        self.table.preflight(destination)
+    }
+
+    fn copy(
+        &self,
+        source: &ReadTransaction,
+        batch: &mut CopyBatch<'_>,
+    ) -> std::result::Result<(), DbCopyError> {
+        self.check_source(source)?;
+        self.persist(batch.txn())?;
+        self.table.copy(source, batch)
+    }
+
+    fn delete(&self, destination: &WriteTransaction) -> std::result::Result<(), DbCopyError> {
+        self.table.delete(destination)?;
+        let delete_error = |err: redb::Error| {
+            DbCopyError::DestinationDeleteFailed(DatabaseError::new(self.display_name(), err))
+        };
+        let mut configs = destination
+            .open_table(BUCKET_CONFIG_TABLE)
+            .map_err(|err| delete_error(err.into()))?;
+        configs
+            .remove(self.name())
+            .map_err(|err| delete_error(err.into()))?;
+        Ok(())
+    }
+
+    fn checksum(
+        &self,
+        destination: &WriteTransaction,
+    ) -> std::result::Result<(u64, u64), redb::Error> {
+        self.table.checksum(destination)
+    }
+
+    fn estimate(&self, source: &ReadTransaction) -> std::result::Result<(u64, u64), DbCopyError> {
+        self.table.estimate(source)
+    }
+
+    fn read_encoded(
+        &self,
+        source: &ReadTransaction,
+        sink: &mut dyn FnMut(EncodedEntries) -> bool,
+    ) -> std::result::Result<(), DbCopyError> {
+        self.check_source(source)?;
+        self.table.read_encoded(source, sink)
+    }
+
+    fn write_encoded(
+        &self,
+        destination: &WriteTransaction,
+        entries: &[(Vec<u8>, Vec<u8>)],
+    ) -> std::result::Result<(), DbCopyError> {
+        self.persist(destination)?;
+        self.table.write_encoded(destination, entries)
+    }
+}
+
+/// Bucket tables of a `TableBucketBuilder` in a range of buckets, copied as
+/// one step and registered in the destination.
+///
+/// Encoded keys and checkpoint values carry the bucket, big-endian, in front
+/// of the key, so export streams and resumed copies know which table an
+/// entry belongs to.
+pub(super) struct TableBucketsPlan<K: Key + 'static, V: Value + 'static> {
+    name: String,
+    builder: TableBucketBuilder,
+    buckets: RangeInclusive<u64>,
+    types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K: Key + 'static, V: Value + 'static> TableBucketsPlan<K, V> {
+    pub(super) fn new(builder: &TableBucketBuilder, buckets: RangeInclusive<u64>) -> Self {
+        Self {
+            name: format!("{}_*", builder.table_prefix()),
+            builder: builder.clone(),
+            buckets,
+            types: PhantomData,
+        }
+    }
+
+    fn bucket_plan(&self, bucket: u64) -> TablePlan<K, V> {
+        TablePlan::new(TableDefinition::new(&self.builder.table_name(bucket)))
+    }
+
+    /// Buckets in the step's range among `tables`, in ascending order.
+    fn buckets_in<'a>(&self, tables: impl Iterator<Item = &'a str>) -> Vec<u64> {
+        let mut buckets: Vec<u64> = tables
+            .filter_map(|table| self.builder.bucket_of_table(table))
+            .filter(|bucket| self.buckets.contains(bucket))
+            .collect();
+        buckets.sort_unstable();
+        buckets
+    }
+
+    fn source_buckets(
+        &self,
+        source: &ReadTransaction,
+    ) -> std::result::Result<Vec<u64>, DbCopyError> {
+        let tables: Vec<String> = source
+            .list_tables()
+            .map_err(|err| {
+                DbCopyError::SourceTableOpenFailed(DatabaseError::new(self.display_name(), err))
+            })?
+            .map(|table| table.name().to_string())
+            .collect();
+        Ok(self.buckets_in(tables.iter().map(String::as_str)))
+    }
+
+    fn destination_buckets(
+        &self,
+        destination: &WriteTransaction,
+    ) -> std::result::Result<Vec<u64>, redb::StorageError> {
+        let tables: Vec<String> = destination
+            .list_tables()?
+            .map(|table| table.name().to_string())
+            .collect();
+        Ok(self.buckets_in(tables.iter().map(String::as_str)))
+    }
+
+    fn register(
+        &self,
+        destination: &WriteTransaction,
+        bucket: u64,
+    ) -> std::result::Result<(), DbCopyError> {
+        self.builder
+            .register(destination, bucket)
+            .map_err(|err| DbCopyError::BucketConfigFailed(self.display_name(), err))
+    }
+}
+
+impl<K: Key + 'static, V: Value + 'static> CopyStep for TableBucketsPlan<K, V> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Copied buckets are registered anew, covering the source registry
+    fn reads(&self, table: &str) -> bool {
+        table == TABLE_BUCKET_REGISTRY.name()
+            || self
+                .builder
+                .bucket_of_table(table)
+                .is_some_and(|bucket| self.buckets.contains(&bucket))
+    }
+
+    fn kind(&self) -> CopyKind {
+        CopyKind::Table
+    }
+
+    fn type_names(&self) -> (String, String) {
+        (
+            K::type_name().name().to_string(),
+            V::type_name().name().to_string(),
+        )
+    }
+
+    fn preflight(&self, destination: &ReadTransaction) -> std::result::Result<bool, TableError> {
+        let mut tables = destination.list_tables().map_err(TableError::Storage)?;
+        Ok(tables.any(|table| self.reads(table.name())))
+    }
+
+    fn copy(
+        &self,
+        source: &ReadTransaction,
+        batch: &mut CopyBatch<'_>,
+    ) -> std::result::Result<(), DbCopyError> {
+        let mut resume = batch.take_resume();
+        let resume_bucket = match &resume {
+            Some((_, tag)) => Some(decode_bucket(tag).ok_or_else(|| {
+                DbCopyError::CheckpointFailed(DatabaseError::new(
+                    self.display_name(),
+                    redb::Error::Corrupted("invalid bucket in checkpoint".to_string()),
+                ))
+            })?),
+            None => None,
+        };
+
+        for bucket in self.source_buckets(source)? {
+            if resume_bucket.is_some_and(|resumed| bucket < resumed) {
+                continue;
+            }
+            if resume_bucket == Some(bucket) {
+                batch.resume_from = resume.take();
+            }
+            self.register(batch.txn(), bucket)?;
+            self.bucket_plan(bucket)
+                .copy_tagged(source, batch, &bucket.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn delete(&self, destination: &WriteTransaction) -> std::result::Result<(), DbCopyError> {
+        for bucket in self.destination_buckets(destination).map_err(|err| {
+            DbCopyError::DestinationDeleteFailed(DatabaseError::new(self.display_name(), err))
+        })? {
+            self.bucket_plan(bucket).delete(destination)?;
+            self.builder
+                .unregister(destination, bucket)
+                .map_err(|err| DbCopyError::BucketConfigFailed(self.display_name(), err))?;
+        }
+        Ok(())
+    }
+
+    fn checksum(
+        &self,
+        destination: &WriteTransaction,
+    ) -> std::result::Result<(u64, u64), redb::Error> {
+        let mut hasher = Xxh3::new();
+        let mut rows = 0;
+        for bucket in self.destination_buckets(destination)? {
+            let plan = self.bucket_plan(bucket);
+            let (bucket_rows, checksum) = manifest::table_checksum(destination, plan.definition())?;
+            hasher.update(&bucket.to_be_bytes());
+            hasher.update(&checksum.to_le_bytes());
+            rows += bucket_rows;
+        }
+        Ok((rows, hasher.digest()))
+    }
+
+    fn estimate(&self, source: &ReadTransaction) -> std::result::Result<(u64, u64), DbCopyError> {
+        let (mut entries, mut bytes) = (0, 0);
+        for bucket in self.source_buckets(source)? {
+            let (bucket_entries, bucket_bytes) = self.bucket_plan(bucket).estimate(source)?;
+            entries += bucket_entries;
+            bytes += bucket_bytes;
+        }
+        Ok((entries, bytes))
+    }
+
+    fn read_encoded(
+        &self,
+        source: &ReadTransaction,
+        sink: &mut dyn FnMut(EncodedEntries) -> bool,
+    ) -> std::result::Result<(), DbCopyError> {
+        let mut open = true;
+        for bucket in self.source_buckets(source)? {
+            let tag = bucket.to_be_bytes();
+            self.bucket_plan(bucket)
+                .read_encoded(source, &mut |chunk| {
+                    let tagged = chunk
+                        .into_iter()
+                        .map(|(key, value)| ([tag.as_slice(), &key].concat(), value))
+                        .collect();
+                    open = sink(tagged);
+                    open
+                })?;
+            if !open {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_encoded(
+        &self,
+        destination: &WriteTransaction,
+        entries: &[(Vec<u8>, Vec<u8>)],
+    ) -> std::result::Result<(), DbCopyError> {
+        let copy_error = |err: redb::Error| {
+            DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+        };
+        let mut rest = entries;
+        while let Some((key, _)) = rest.first() {
+            let bucket = decode_bucket(key).ok_or_else(|| {
+                copy_error(redb::Error::Corrupted(
+                    "invalid bucket in encoded key".to_string(),
+                ))
+            })?;
+            let len = rest
+                .iter()
+                .position(|(key, _)| decode_bucket(key) != Some(bucket))
+                .unwrap_or(rest.len());
+            let (chunk, tail) = rest.split_at(len);
+            rest = tail;
+
+            self.register(destination, bucket)?;
+            let name = self.builder.table_name(bucket);
+            let mut table = destination
+                .open_table(TableDefinition::<K, V>::new(&name))
+                .map_err(|err| {
+                    DbCopyError::DestinationTableOpenFailed(DatabaseError::new(
+                        self.display_name(),
+                        err,
+                    ))
+                })?;
+            for (key, value) in chunk {
+                table
+                    .insert(K::from_bytes(&key[BUCKET_LEN..]), V::from_bytes(value))
+                    .map_err(|err| copy_error(err.into()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Length of the bucket in front of encoded keys and checkpoint values.
+const BUCKET_LEN: usize = 8;
+
+fn decode_bucket(tagged: &[u8]) -> Option<u64> {
+    let bucket = tagged.get(..BUCKET_LEN)?;
+    Some(u64::from_be_bytes(bucket.try_into().ok()?))
+}
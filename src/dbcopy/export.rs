@@ -0,0 +1,479 @@
+//! Portable export streams for copy plans.
+//!
+//! An export holds the encoded keys and values of each plan step, so it can
+//! be shipped between machines or kept as a backup independently of the redb
+//! file format. redb tables can only be created through their types, so an
+//! import needs a plan with the same steps to write the records back.
+//!
+//! The stream is a magic string and format version, followed per table by a
+//! header, length-prefixed records and a trailer with the record count. All
+//! integers are little-endian:
+//!
+//! ```text
+//! "REDBXCPY" version:u32
+//! ( 1 kind:u8 name:str key_type:str value_type:str
+//!   ( 2 key:bytes value:bytes )*
+//!   3 count:u64 )*
+//! 0
+//! ```
+//!
+//! `bytes` and `str` are a `u32` length followed by that many bytes.
+
+use super::{
+    check_destination, CopyKind, CopyMode, CopyPlan, CopyReport, DbCopyError, TableCopyStats,
+    ENCODED_CHUNK,
+};
+use crate::error::DatabaseError;
+use crate::Result;
+use redb::{Database, ReadableDatabase};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Instant;
+
+const MAGIC: &[u8; 8] = b"REDBXCPY";
+const VERSION: u32 = 1;
+
+const TAG_END: u8 = 0;
+const TAG_TABLE: u8 = 1;
+const TAG_ENTRY: u8 = 2;
+const TAG_TABLE_END: u8 = 3;
+
+fn export_error(err: impl std::fmt::Display) -> DbCopyError {
+    DbCopyError::ExportFailed(err.to_string())
+}
+
+fn import_error(err: impl std::fmt::Display) -> DbCopyError {
+    DbCopyError::ImportFailed(err.to_string())
+}
+
+fn kind_byte(kind: CopyKind) -> u8 {
+    match kind {
+        CopyKind::Table => 0,
+        CopyKind::Multimap => 1,
+    }
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(bytes.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record too large"))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = u32::from_le_bytes(read_array(reader)?) as usize;
+    // The length is untrusted, so only buffer what the stream actually holds
+    let mut buf = Vec::new();
+    reader.by_ref().take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "truncated record",
+        ));
+    }
+    Ok(buf)
+}
+
+fn read_str(reader: &mut impl Read) -> std::result::Result<String, DbCopyError> {
+    String::from_utf8(read_bytes(reader).map_err(import_error)?).map_err(import_error)
+}
+
+/// Export the tables described by `plan` from `source` into a file.
+///
+/// See `export_to_writer`.
+pub fn export_to_file(
+    source: &Database,
+    plan: &CopyPlan,
+    path: impl AsRef<Path>,
+) -> Result<CopyReport> {
+    let file = File::create(path).map_err(export_error)?;
+    export_to_writer(source, plan, BufWriter::new(file))
+}
+
+/// Export the tables described by `plan` from `source` as a portable stream.
+///
+/// Each step's range, filter and transform are applied, so the stream holds
+/// exactly what `copy_database` would copy. The plan's `CopyMode` and batch
+/// settings don't affect exports.
+///
+/// The stream is written as the tables are read, with no temporary files,
+/// so it can go through a compressor or over a socket. Writes are small, so
+/// unbuffered writers should be wrapped in a `BufWriter`; `writer` is
+/// flushed once the stream is complete, but finishing a compressor is left
+/// to the caller.
+///
+/// # Returns
+/// Per-table statistics of the export
+pub fn export_to_writer(
+    source: &Database,
+    plan: &CopyPlan,
+    mut writer: impl Write,
+) -> Result<CopyReport> {
+    let started = Instant::now();
+    let source_read = source
+        .begin_read()
+        .map_err(|err| DbCopyError::TransactionFailed(DatabaseError::new("source read", err)))?;
+    plan.check_unplanned(&source_read)?;
+
+    writer.write_all(MAGIC).map_err(export_error)?;
+    writer
+        .write_all(&VERSION.to_le_bytes())
+        .map_err(export_error)?;
+
+    let mut report = CopyReport::default();
+    for step in &plan.steps {
+        let table_started = Instant::now();
+        let (key_type, value_type) = step.type_names();
+        writer
+            .write_all(&[TAG_TABLE, kind_byte(step.kind())])
+            .map_err(export_error)?;
+        for field in [step.name(), key_type.as_str(), value_type.as_str()] {
+            write_bytes(&mut writer, field.as_bytes()).map_err(export_error)?;
+        }
+
+        let (mut entries, mut bytes) = (0u64, 0u64);
+        let mut failed = None;
+        step.read_encoded(&source_read, &mut |chunk| {
+            for (key, value) in &chunk {
+                let written = writer
+                    .write_all(&[TAG_ENTRY])
+                    .and_then(|()| write_bytes(&mut writer, key))
+                    .and_then(|()| write_bytes(&mut writer, value));
+                if let Err(err) = written {
+                    failed = Some(err);
+                    return false;
+                }
+                entries += 1;
+                bytes += (key.len() + value.len()) as u64;
+            }
+            true
+        })?;
+        if let Some(err) = failed {
+            return Err(export_error(format!("{}: {}", step.display_name(), err)).into());
+        }
+
+        writer.write_all(&[TAG_TABLE_END]).map_err(export_error)?;
+        writer
+            .write_all(&entries.to_le_bytes())
+            .map_err(export_error)?;
+        report.tables.push(TableCopyStats {
+            table: step.display_name(),
+            entries,
+            bytes,
+            elapsed: table_started.elapsed(),
+            skipped: false,
+            error: None,
+        });
+    }
+
+    writer.write_all(&[TAG_END]).map_err(export_error)?;
+    writer.flush().map_err(export_error)?;
+    report.elapsed = started.elapsed();
+
+    Ok(report)
+}
+
+/// Import an export file into `destination`.
+///
+/// See `import_from_reader`.
+pub fn import_from_file(
+    destination: &Database,
+    plan: &CopyPlan,
+    path: impl AsRef<Path>,
+) -> Result<CopyReport> {
+    let file = File::open(path).map_err(import_error)?;
+    import_from_reader(destination, plan, BufReader::new(file))
+}
+
+/// Import a stream written by `export_to_writer` into `destination`.
+///
+/// Every table in the stream must have a step in `plan` with the same name,
+/// kind and key and value types, which is used to write its records; steps
+/// without a table in the stream are ignored. Existing destination tables
+/// are handled according to the plan's `CopyMode`, and the whole import is
+/// a single transaction that is only committed once the stream has been
+/// read to its end. Nothing past the end of the stream is read, so
+/// `reader` can carry other data after it, e.g. on a network connection.
+///
+/// # Returns
+/// Per-table statistics of the import, in stream order
+pub fn import_from_reader(
+    destination: &Database,
+    plan: &CopyPlan,
+    mut reader: impl Read,
+) -> Result<CopyReport> {
+    let started = Instant::now();
+    let magic: [u8; 8] = read_array(&mut reader).map_err(import_error)?;
+    if &magic != MAGIC {
+        return Err(import_error("not a copy export stream").into());
+    }
+    let version = u32::from_le_bytes(read_array(&mut reader).map_err(import_error)?);
+    if version != VERSION {
+        return Err(import_error(format!("unsupported export version {}", version)).into());
+    }
+
+    let destination_read = destination.begin_read().map_err(|err| {
+        DbCopyError::TransactionFailed(DatabaseError::new("destination read", err))
+    })?;
+    let existing = check_destination(plan, &destination_read, &HashMap::new())?;
+    drop(destination_read);
+    let steps: HashMap<String, (usize, bool)> = plan
+        .steps
+        .iter()
+        .zip(existing)
+        .enumerate()
+        .map(|(index, (step, exists))| (step.display_name(), (index, exists)))
+        .collect();
+
+    let destination_write = destination.begin_write().map_err(|err| {
+        DbCopyError::TransactionFailed(DatabaseError::new("destination write", err))
+    })?;
+    let mut report = CopyReport::default();
+    loop {
+        match read_array::<1>(&mut reader).map_err(import_error)?[0] {
+            TAG_END => break,
+            TAG_TABLE => {}
+            tag => return Err(import_error(format!("unexpected tag {}", tag)).into()),
+        }
+
+        let table_started = Instant::now();
+        let kind = match read_array::<1>(&mut reader).map_err(import_error)?[0] {
+            0 => CopyKind::Table,
+            1 => CopyKind::Multimap,
+            kind => return Err(import_error(format!("unknown table kind {}", kind)).into()),
+        };
+        let table = format!("{} {}", kind, read_str(&mut reader)?);
+        let types = (read_str(&mut reader)?, read_str(&mut reader)?);
+
+        let &(index, exists) = steps
+            .get(&table)
+            .ok_or_else(|| import_error(format!("{} is not in the plan", table)))?;
+        let step = &plan.steps[index];
+        if step.type_names() != types {
+            return Err(import_error(format!(
+                "{} was exported as <{}, {}>",
+                table, types.0, types.1
+            ))
+            .into());
+        }
+        let skip = exists && plan.mode == CopyMode::Skip;
+        if exists && plan.mode == CopyMode::Overwrite {
+            step.delete(&destination_write)?;
+        }
+
+        let (mut entries, mut bytes) = (0u64, 0u64);
+        let mut chunk = Vec::with_capacity(ENCODED_CHUNK);
+        loop {
+            match read_array::<1>(&mut reader).map_err(import_error)?[0] {
+                TAG_ENTRY => {
+                    let key = read_bytes(&mut reader).map_err(import_error)?;
+                    let value = read_bytes(&mut reader).map_err(import_error)?;
+                    entries += 1;
+                    bytes += (key.len() + value.len()) as u64;
+                    if !skip {
+                        chunk.push((key, value));
+                    }
+                    if chunk.len() == ENCODED_CHUNK {
+                        step.write_encoded(&destination_write, &chunk)?;
+                        chunk.clear();
+                    }
+                }
+                TAG_TABLE_END => break,
+                tag => return Err(import_error(format!("unexpected tag {}", tag)).into()),
+            }
+        }
+        let count = u64::from_le_bytes(read_array(&mut reader).map_err(import_error)?);
+        if count != entries {
+            return Err(import_error(format!(
+                "{} has {} records, expected {}",
+                table, entries, count
+            ))
+            .into());
+        }
+        if !skip {
+            // An empty chunk still creates tables without entries
+            step.write_encoded(&destination_write, &chunk)?;
+        }
+
+        report.tables.push(if skip {
+            TableCopyStats::skipped(table)
+        } else {
+            TableCopyStats {
+                table,
+                entries,
+                bytes,
+                elapsed: table_started.elapsed(),
+                skipped: false,
+                error: None,
+            }
+        });
+    }
+
+    destination_write
+        .commit()
+        .map_err(|err| DbCopyError::CommitFailed(DatabaseError::new("commit", err)))?;
+    report.elapsed = started.elapsed();
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_to_file, export_to_writer, import_from_file, import_from_reader};
+    use crate::dbcopy::{CopyMode, CopyPlan, DbCopyError, TableStep};
+    use crate::Error;
+    use redb::{
+        Database, MultimapTableDefinition, ReadableDatabase, ReadableTableMetadata, TableDefinition,
+    };
+    use tempfile::NamedTempFile;
+
+    const USERS: TableDefinition<&str, u64> = TableDefinition::new("users");
+    const EMPTY: TableDefinition<u64, u64> = TableDefinition::new("empty");
+    const TAGS: MultimapTableDefinition<&str, u64> = MultimapTableDefinition::new("tags");
+
+    fn source() -> (NamedTempFile, Database) {
+        let file = NamedTempFile::new().unwrap();
+        let db = Database::create(file.path()).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut users = write_txn.open_table(USERS).unwrap();
+            users.insert("alice", 1).unwrap();
+            users.insert("bob", 2).unwrap();
+            write_txn.open_table(EMPTY).unwrap();
+            let mut tags = write_txn.open_multimap_table(TAGS).unwrap();
+            tags.insert("alice", 10).unwrap();
+            tags.insert("alice", 20).unwrap();
+        }
+        write_txn.commit().unwrap();
+        (file, db)
+    }
+
+    #[test]
+    fn export_and_import_round_trip() {
+        let (_source_file, source) = source();
+        let plan = CopyPlan::new()
+            .table_step(TableStep::new(USERS).filter(|name, _| *name != "bob"))
+            .table(EMPTY)
+            .multimap(TAGS);
+
+        let export_file = NamedTempFile::new().unwrap();
+        let exported = export_to_file(&source, &plan, export_file.path()).unwrap();
+        assert_eq!(exported.total_entries(), 3);
+
+        let dest_file = NamedTempFile::new().unwrap();
+        let dest = Database::create(dest_file.path()).unwrap();
+        let plan = CopyPlan::new().table(USERS).table(EMPTY).multimap(TAGS);
+        let imported = import_from_file(&dest, &plan, export_file.path()).unwrap();
+        assert_eq!(imported.total_entries(), 3);
+        assert_eq!(imported.total_bytes(), exported.total_bytes());
+
+        let read_txn = dest.begin_read().unwrap();
+        let users = read_txn.open_table(USERS).unwrap();
+        assert_eq!(users.get("alice").unwrap().unwrap().value(), 1);
+        assert!(users.get("bob").unwrap().is_none());
+        assert!(read_txn.open_table(EMPTY).unwrap().is_empty().unwrap());
+        let tags: Vec<u64> = read_txn
+            .open_multimap_table(TAGS)
+            .unwrap()
+            .get("alice")
+            .unwrap()
+            .map(|value| value.unwrap().value())
+            .collect();
+        assert_eq!(tags, vec![10, 20]);
+
+        // Importing again conflicts unless the plan says otherwise
+        assert!(matches!(
+            import_from_file(&dest, &plan, export_file.path()),
+            Err(Error::DbCopy(DbCopyError::DestinationTablesExist(_)))
+        ));
+        let plan = plan.mode(CopyMode::Skip);
+        let report = import_from_file(&dest, &plan, export_file.path()).unwrap();
+        assert!(report.tables.iter().all(|table| table.skipped));
+    }
+
+    #[test]
+    fn import_stops_at_end_of_stream() {
+        let (_source_file, source) = source();
+        let plan = CopyPlan::new().table(USERS).allow_unplanned();
+
+        let mut stream = Vec::new();
+        export_to_writer(&source, &plan, &mut stream).unwrap();
+        stream.extend_from_slice(b"trailer");
+
+        let dest_file = NamedTempFile::new().unwrap();
+        let dest = Database::create(dest_file.path()).unwrap();
+        let mut reader = stream.as_slice();
+        import_from_reader(&dest, &plan, &mut reader).unwrap();
+        assert_eq!(reader, b"trailer");
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn streams_through_a_compressor() {
+        let (_source_file, source) = source();
+        let plan = CopyPlan::new()
+            .table(USERS)
+            .multimap(TAGS)
+            .allow_unplanned();
+
+        let mut encoder = zstd::Encoder::new(Vec::new(), 0).unwrap();
+        export_to_writer(&source, &plan, &mut encoder).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let dest_file = NamedTempFile::new().unwrap();
+        let dest = Database::create(dest_file.path()).unwrap();
+        let decoder = zstd::Decoder::new(compressed.as_slice()).unwrap();
+        let imported = import_from_reader(&dest, &plan, decoder).unwrap();
+        assert_eq!(imported.total_entries(), 4);
+    }
+
+    #[test]
+    fn import_rejects_bad_streams() {
+        let (_source_file, source) = source();
+        let mut stream = Vec::new();
+        let plan = CopyPlan::new().table(USERS).allow_unplanned();
+        export_to_writer(&source, &plan, &mut stream).unwrap();
+
+        let import = |plan: &CopyPlan, stream: &[u8]| {
+            let dest_file = NamedTempFile::new().unwrap();
+            let dest = Database::create(dest_file.path()).unwrap();
+            let result = import_from_reader(&dest, plan, stream);
+            let committed = dest.begin_read().unwrap().open_table(USERS).is_ok();
+            (result, committed)
+        };
+
+        let (result, committed) = import(&CopyPlan::new().table(USERS), &stream);
+        assert!(result.is_ok() && committed);
+
+        // Truncated streams are not committed
+        let (result, committed) =
+            import(&CopyPlan::new().table(USERS), &stream[..stream.len() - 1]);
+        assert!(result.is_err() && !committed);
+
+        // Tables must be in the plan with the same types
+        assert!(import(&CopyPlan::new(), &stream).0.is_err());
+        let renamed: TableDefinition<&str, u32> = TableDefinition::new("users");
+        assert!(import(&CopyPlan::new().table(renamed), &stream).0.is_err());
+
+        assert!(import(&CopyPlan::new().table(USERS), b"not an export")
+            .0
+            .is_err());
+
+        // A corrupt length fails the import instead of allocating it up front
+        let mut corrupt = stream[..14].to_vec();
+        corrupt.extend_from_slice(&u32::MAX.to_le_bytes());
+        corrupt.extend_from_slice(b"users");
+        assert!(matches!(
+            import(&CopyPlan::new().table(USERS), &corrupt).0,
+            Err(Error::DbCopy(DbCopyError::ImportFailed(_)))
+        ));
+    }
+}
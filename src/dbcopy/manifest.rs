@@ -0,0 +1,161 @@
+//! Copy manifests with per-table checksums.
+//!
+//! A manifest table in the destination records, for every table a copy
+//! wrote, where and when it was copied from along with the row count and a
+//! checksum of the table's contents. Audits can later recompute both to
+//! detect partial copies or tables modified after the fact.
+
+use super::DbCopyError;
+use crate::error::DatabaseError;
+use crate::Result;
+use redb::{
+    Database, Key, MultimapTableDefinition, ReadableDatabase, ReadableMultimapTable, ReadableTable,
+    TableDefinition, TableError, Value, WriteTransaction,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Manifest rows: step display name to (source, label, copied at, rows,
+/// checksum).
+pub(super) type ManifestTable<'a> =
+    TableDefinition<'a, &'static str, (&'static str, Option<&'static str>, u64, u64, u64)>;
+
+/// Where a copy records its manifest.
+pub(super) struct ManifestConfig {
+    pub(super) table: String,
+    pub(super) source: String,
+}
+
+/// One table recorded in a copy manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Display name of the destination table, e.g. `"table users"`.
+    pub table: String,
+    /// Source the table was copied from, as given to `CopyPlan::manifest`.
+    pub source: String,
+    /// Label of the source snapshot, as given to `copy_database_labeled`.
+    pub label: Option<String>,
+    /// When the copy started, in seconds since the Unix epoch.
+    pub copied_at: u64,
+    /// Rows in the destination table after the copy; for multimap tables,
+    /// key and value pairs.
+    pub rows: u64,
+    /// Checksum of the destination table after the copy, see `CopyPlan::manifest`.
+    pub checksum: u64,
+}
+
+fn manifest_error(table: &str, err: impl Into<redb::Error>) -> DbCopyError {
+    DbCopyError::ManifestFailed(DatabaseError::new(table, err))
+}
+
+/// Seconds since the Unix epoch, zero for clocks set before it.
+pub(super) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn hash_entry(hasher: &mut Xxh3, key: &[u8], value: &[u8]) {
+    for part in [key, value] {
+        hasher.update(&(part.len() as u32).to_le_bytes());
+        hasher.update(part);
+    }
+}
+
+/// Row count and checksum of a destination table, read in key order.
+pub(super) fn table_checksum<K: Key + 'static, V: Value + 'static>(
+    txn: &WriteTransaction,
+    definition: TableDefinition<'_, K, V>,
+) -> std::result::Result<(u64, u64), redb::Error> {
+    let table = txn.open_table(definition)?;
+    let mut hasher = Xxh3::new();
+    let mut rows = 0;
+    for entry in table.iter()? {
+        let (key, value) = entry?;
+        hash_entry(
+            &mut hasher,
+            K::as_bytes(&key.value()).as_ref(),
+            V::as_bytes(&value.value()).as_ref(),
+        );
+        rows += 1;
+    }
+    Ok((rows, hasher.digest()))
+}
+
+/// Row count and checksum of a destination multimap table, read in key and
+/// then value order.
+pub(super) fn multimap_checksum<K: Key + 'static, V: Key + 'static>(
+    txn: &WriteTransaction,
+    definition: MultimapTableDefinition<'_, K, V>,
+) -> std::result::Result<(u64, u64), redb::Error> {
+    let table = txn.open_multimap_table(definition)?;
+    let mut hasher = Xxh3::new();
+    let mut rows = 0;
+    for entry in table.iter()? {
+        let (key, values) = entry?;
+        let key = key.value();
+        for value in values {
+            let value = value?;
+            hash_entry(
+                &mut hasher,
+                K::as_bytes(&key).as_ref(),
+                V::as_bytes(&value.value()).as_ref(),
+            );
+            rows += 1;
+        }
+    }
+    Ok((rows, hasher.digest()))
+}
+
+/// Writes the manifest row of the table named `step`, copied from the
+/// source snapshot named `label`, if any.
+pub(super) fn record_manifest(
+    txn: &WriteTransaction,
+    manifest: &ManifestConfig,
+    copied_at: u64,
+    label: Option<&str>,
+    step: &str,
+    (rows, checksum): (u64, u64),
+) -> std::result::Result<(), DbCopyError> {
+    let mut table = txn
+        .open_table(ManifestTable::new(&manifest.table))
+        .map_err(|err| manifest_error(&manifest.table, err))?;
+    table
+        .insert(
+            step,
+            (manifest.source.as_str(), label, copied_at, rows, checksum),
+        )
+        .map_err(|err| manifest_error(&manifest.table, err))?;
+    Ok(())
+}
+
+/// Read the copy manifest stored in `table` of `db`.
+///
+/// # Returns
+/// One entry per recorded table, ordered by display name; empty if the
+/// manifest table doesn't exist
+pub fn read_manifest(db: &Database, table: &str) -> Result<Vec<ManifestEntry>> {
+    let read_txn = db
+        .begin_read()
+        .map_err(|err| DbCopyError::TransactionFailed(DatabaseError::new("manifest read", err)))?;
+    let manifest = match read_txn.open_table(ManifestTable::new(table)) {
+        Ok(manifest) => manifest,
+        Err(TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+        Err(err) => return Err(manifest_error(table, err).into()),
+    };
+
+    let mut entries = Vec::new();
+    for entry in manifest.iter().map_err(|err| manifest_error(table, err))? {
+        let (step, row) = entry.map_err(|err| manifest_error(table, err))?;
+        let (source, label, copied_at, rows, checksum) = row.value();
+        entries.push(ManifestEntry {
+            table: step.value().to_string(),
+            source: source.to_string(),
+            label: label.map(str::to_string),
+            copied_at,
+            rows,
+            checksum,
+        });
+    }
+    Ok(entries)
+}
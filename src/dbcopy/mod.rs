@@ -3,44 +3,142 @@
 //! This module provides helpers to copy data between databases using
 //! explicit table definitions supplied by callers.
 
-use crate::Result;
+use crate::error::{DatabaseError, ErrorKind};
+use crate::key_buckets::{BucketError, BucketedKey, KeyBuilder};
+use crate::table_buckets::TableBucketBuilder;
+use crate::{MergeableValue, Result};
+use buckets::{BucketedTablePlan, TableBucketsPlan};
+use manifest::{record_manifest, unix_now, ManifestConfig};
 use redb::{
     Database, MultimapTableDefinition, MultimapTableHandle, ReadTransaction, ReadableDatabase,
     ReadableMultimapTable, ReadableTable, TableDefinition, TableError, TableHandle,
     WriteTransaction,
 };
+use std::borrow::Borrow;
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt;
 use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds, RangeInclusive};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
+mod buckets;
+mod export;
+mod manifest;
+#[cfg(feature = "parallel")]
+mod parallel;
 #[cfg(test)]
 mod tests;
 
+pub use export::{export_to_file, export_to_writer, import_from_file, import_from_reader};
+pub use manifest::{read_manifest, ManifestEntry};
+#[cfg(feature = "parallel")]
+pub use parallel::copy_database_parallel;
+
 /// Errors returned by database copy operations.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum DbCopyError {
     /// One or more destination tables already exist.
     DestinationTablesExist(Vec<String>),
 
+    /// The source has tables the plan neither copies nor excludes.
+    UnplannedTables(Vec<String>),
+
     /// Failed to check destination tables.
-    DestinationCheckFailed(String),
+    DestinationCheckFailed(DatabaseError),
 
     /// Failed to open a source table.
-    SourceTableOpenFailed(String),
+    SourceTableOpenFailed(DatabaseError),
 
     /// Failed to open a destination table.
-    DestinationTableOpenFailed(String),
+    DestinationTableOpenFailed(DatabaseError),
 
     /// Failed while copying table contents.
-    TableCopyFailed(String),
+    TableCopyFailed(DatabaseError),
 
     /// Transaction failures during copy.
-    TransactionFailed(String),
+    TransactionFailed(DatabaseError),
 
     /// Failed to commit the destination transaction.
-    CommitFailed(String),
+    CommitFailed(DatabaseError),
+
+    /// Failed to delete a destination table before overwriting it.
+    DestinationDeleteFailed(DatabaseError),
+
+    /// Failed to read or write the copy checkpoint table.
+    CheckpointFailed(DatabaseError),
+
+    /// Failed to write an export stream.
+    ExportFailed(String),
+
+    /// Failed to read an export stream or apply it to the destination.
+    ImportFailed(String),
+
+    /// Failed to checksum a table or read or write the copy manifest.
+    ManifestFailed(DatabaseError),
+
+    /// Failed to measure or compact the destination after a copy.
+    CompactionFailed(DatabaseError),
+
+    /// Failed to load, check or record the bucket layout of a bucketed table.
+    BucketConfigFailed(String, BucketError),
+}
+
+impl std::error::Error for DbCopyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DbCopyError::DestinationCheckFailed(err)
+            | DbCopyError::SourceTableOpenFailed(err)
+            | DbCopyError::DestinationTableOpenFailed(err)
+            | DbCopyError::TableCopyFailed(err)
+            | DbCopyError::TransactionFailed(err)
+            | DbCopyError::CommitFailed(err)
+            | DbCopyError::DestinationDeleteFailed(err)
+            | DbCopyError::CheckpointFailed(err)
+            | DbCopyError::ManifestFailed(err)
+            | DbCopyError::CompactionFailed(err) => Some(err),
+            DbCopyError::BucketConfigFailed(_, err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
-impl std::error::Error for DbCopyError {}
+impl DbCopyError {
+    /// Classifies the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            DbCopyError::DestinationTablesExist(_) | DbCopyError::UnplannedTables(_) => {
+                ErrorKind::InvalidInput
+            }
+            DbCopyError::DestinationCheckFailed(err)
+            | DbCopyError::SourceTableOpenFailed(err)
+            | DbCopyError::DestinationTableOpenFailed(err)
+            | DbCopyError::TableCopyFailed(err)
+            | DbCopyError::TransactionFailed(err)
+            | DbCopyError::CommitFailed(err)
+            | DbCopyError::DestinationDeleteFailed(err)
+            | DbCopyError::CheckpointFailed(err)
+            | DbCopyError::ManifestFailed(err)
+            | DbCopyError::CompactionFailed(err) => err.kind(),
+            DbCopyError::ExportFailed(_) | DbCopyError::ImportFailed(_) => ErrorKind::Other,
+            DbCopyError::BucketConfigFailed(_, err) => err.kind(),
+        }
+    }
+
+    /// Whether the error leaves the destination transaction unusable, so a
+    /// copy can't go on with its other tables.
+    fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            DbCopyError::TransactionFailed(_)
+                | DbCopyError::CommitFailed(_)
+                | DbCopyError::CheckpointFailed(_)
+        )
+    }
+}
 
 impl fmt::Display for DbCopyError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -48,6 +146,9 @@ impl fmt::Display for DbCopyError {
             DbCopyError::DestinationTablesExist(names) => {
                 write!(f, "Destination already contains: {}", names.join(", "))
             }
+            DbCopyError::UnplannedTables(names) => {
+                write!(f, "Copy plan doesn't cover: {}", names.join(", "))
+            }
             DbCopyError::DestinationCheckFailed(msg) => {
                 write!(f, "Destination check failed: {}", msg)
             }
@@ -60,10 +161,22 @@ impl fmt::Display for DbCopyError {
             DbCopyError::TableCopyFailed(msg) => write!(f, "Table copy failed: {}", msg),
             DbCopyError::TransactionFailed(msg) => write!(f, "Transaction failed: {}", msg),
             DbCopyError::CommitFailed(msg) => write!(f, "Commit failed: {}", msg),
+            DbCopyError::DestinationDeleteFailed(msg) => {
+                write!(f, "Destination delete failed: {}", msg)
+            }
+            DbCopyError::CheckpointFailed(msg) => write!(f, "Checkpoint failed: {}", msg),
+            DbCopyError::ExportFailed(msg) => write!(f, "Export failed: {}", msg),
+            DbCopyError::ImportFailed(msg) => write!(f, "Import failed: {}", msg),
+            DbCopyError::ManifestFailed(msg) => write!(f, "Manifest failed: {}", msg),
+            DbCopyError::CompactionFailed(msg) => write!(f, "Compaction failed: {}", msg),
+            DbCopyError::BucketConfigFailed(table, err) => {
+                write!(f, "Bucket config of {} failed: {}", table, err)
+            }
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 enum CopyKind {
     Table,
     Multimap,
@@ -78,31 +191,382 @@ impl fmt::Display for CopyKind {
     }
 }
 
-trait CopyStep {
+/// Encoded key and value pairs read from a source table.
+type EncodedEntries = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Number of entries per chunk passed from `read_encoded` to the writer.
+const ENCODED_CHUNK: usize = 1024;
+
+trait CopyStep: Sync {
     fn name(&self) -> &str;
+    /// Name of the table read from the source, when it differs from `name`.
+    fn source_name(&self) -> &str {
+        self.name()
+    }
+    /// Whether the step reads the source table named `table`.
+    fn reads(&self, table: &str) -> bool {
+        self.source_name() == table
+    }
     fn kind(&self) -> CopyKind;
+    /// Names of the key and value types, as recorded by redb.
+    fn type_names(&self) -> (String, String);
     fn preflight(&self, destination: &ReadTransaction) -> std::result::Result<bool, TableError>;
     fn copy(
         &self,
         source: &ReadTransaction,
-        destination: &mut WriteTransaction,
+        batch: &mut CopyBatch<'_>,
+    ) -> std::result::Result<(), DbCopyError>;
+    fn delete(&self, destination: &WriteTransaction) -> std::result::Result<(), DbCopyError>;
+    /// Row count and checksum of the destination table, for the manifest.
+    fn checksum(
+        &self,
+        destination: &WriteTransaction,
+    ) -> std::result::Result<(u64, u64), redb::Error>;
+    /// Counts the entries and encoded bytes `copy` would write.
+    fn estimate(&self, source: &ReadTransaction) -> std::result::Result<(u64, u64), DbCopyError>;
+    /// Passes the encoded entries `copy` would write to `sink` in chunks,
+    /// stopping early once it returns false.
+    fn read_encoded(
+        &self,
+        source: &ReadTransaction,
+        sink: &mut dyn FnMut(EncodedEntries) -> bool,
     ) -> std::result::Result<(), DbCopyError>;
+    /// Writes a chunk produced by `read_encoded`.
+    fn write_encoded(
+        &self,
+        destination: &WriteTransaction,
+        entries: &[(Vec<u8>, Vec<u8>)],
+    ) -> std::result::Result<(), DbCopyError>;
+
+    /// Whether an existing destination table is merged into instead of
+    /// being handled by the plan's `CopyMode`.
+    fn merges_existing(&self) -> bool {
+        false
+    }
 
     fn display_name(&self) -> String {
         format!("{} {}", self.kind(), self.name())
     }
 }
 
+/// How `copy_database` treats tables that already exist in the destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CopyMode {
+    /// Refuse to copy anything if any destination table exists.
+    #[default]
+    Fail,
+
+    /// Leave existing destination tables untouched and copy the others.
+    Skip,
+
+    /// Delete existing destination tables and copy them anew.
+    Overwrite,
+
+    /// Copy into existing destination tables; source entries replace
+    /// destination entries with the same key, and multimap values are added.
+    Append,
+}
+
+/// What a copy does when a table fails to copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    /// Stop and return the error; nothing past the last batch commit is kept.
+    #[default]
+    Abort,
+
+    /// Record the error in the table's `TableCopyStats` and go on with the
+    /// next step, e.g. to salvage what can be read from a damaged source.
+    Continue,
+}
+
+/// Number of entries copied between two progress callbacks within a table.
+pub const PROGRESS_INTERVAL: u64 = 10_000;
+
+/// Progress of the table currently being copied, passed to `CopyPlan::on_progress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyProgress<'a> {
+    /// Display name of the table, e.g. `"table users"`.
+    pub table: &'a str,
+    /// Entries copied into the table so far.
+    pub entries: u64,
+    /// Encoded key and value bytes copied into the table so far.
+    pub bytes: u64,
+    /// Time spent on the table so far.
+    pub elapsed: Duration,
+    /// Whether the table is done.
+    pub finished: bool,
+}
+
+/// Statistics for one step of a copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableCopyStats {
+    /// Display name of the table, e.g. `"table users"`.
+    pub table: String,
+    /// Entries copied into the table.
+    pub entries: u64,
+    /// Encoded key and value bytes copied into the table.
+    pub bytes: u64,
+    /// Time spent copying the table.
+    pub elapsed: Duration,
+    /// Whether the table was left untouched by `CopyMode::Skip`.
+    pub skipped: bool,
+    /// Why the table failed to copy under `ErrorPolicy::Continue`.
+    pub error: Option<String>,
+}
+
+/// What `copy_database_dry_run` found for one step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableCopyEstimate {
+    /// Display name of the table, e.g. `"table users"`.
+    pub table: String,
+    /// Whether the table already exists in the destination.
+    pub destination_exists: bool,
+    /// Entries the step would copy, after its range and filter.
+    pub entries: u64,
+    /// Encoded key and value bytes the step would copy.
+    pub bytes: u64,
+}
+
+impl TableCopyStats {
+    fn skipped(table: String) -> Self {
+        Self {
+            table,
+            entries: 0,
+            bytes: 0,
+            elapsed: Duration::ZERO,
+            skipped: true,
+            error: None,
+        }
+    }
+}
+
+/// Destination space before and after `copy_and_compact` compacted it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Bytes of allocated pages after the copy, before compacting.
+    pub bytes_before: u64,
+    /// Bytes of allocated pages after compacting.
+    pub bytes_after: u64,
+    /// Time spent compacting.
+    pub elapsed: Duration,
+}
+
+/// Outcome of `copy_database`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CopyReport {
+    /// Statistics for each step, in plan order.
+    pub tables: Vec<TableCopyStats>,
+    /// Time spent on the whole copy.
+    pub elapsed: Duration,
+    /// Destination compaction, for copies made with `copy_and_compact`.
+    pub compaction: Option<CompactionStats>,
+}
+
+impl CopyReport {
+    /// Total number of entries copied.
+    pub fn total_entries(&self) -> u64 {
+        self.tables.iter().map(|table| table.entries).sum()
+    }
+
+    /// Total number of encoded key and value bytes copied.
+    pub fn total_bytes(&self) -> u64 {
+        self.tables.iter().map(|table| table.bytes).sum()
+    }
+}
+
+type ProgressFn = dyn Fn(&CopyProgress<'_>);
+
 /// Builder for a database copy plan.
 #[derive(Default)]
 pub struct CopyPlan {
     steps: Vec<Box<dyn CopyStep>>,
+    mode: CopyMode,
+    batch_entries: Option<u64>,
+    batch_bytes: Option<u64>,
+    throttle_entries: Option<u64>,
+    throttle_bytes: Option<u64>,
+    progress: Option<Box<ProgressFn>>,
+    checkpoint: Option<String>,
+    manifest: Option<ManifestConfig>,
+    exclusions: Vec<Exclusion>,
+    on_error: ErrorPolicy,
+    allow_unplanned: bool,
+}
+
+/// Source tables a plan leaves out, see `CopyPlan::exclude`.
+enum Exclusion {
+    Name(String),
+    Prefix(String),
+}
+
+impl Exclusion {
+    fn matches(&self, table: &str) -> bool {
+        match self {
+            Exclusion::Name(name) => table == name,
+            Exclusion::Prefix(prefix) => table.starts_with(prefix.as_str()),
+        }
+    }
 }
 
 impl CopyPlan {
     /// Create a new empty copy plan.
     pub fn new() -> Self {
-        Self { steps: Vec::new() }
+        Self {
+            steps: Vec::new(),
+            mode: CopyMode::Fail,
+            batch_entries: None,
+            batch_bytes: None,
+            throttle_entries: None,
+            throttle_bytes: None,
+            progress: None,
+            checkpoint: None,
+            manifest: None,
+            exclusions: Vec::new(),
+            on_error: ErrorPolicy::Abort,
+            allow_unplanned: false,
+        }
+    }
+
+    /// Set what happens when a table fails to copy, `ErrorPolicy::Abort` by default.
+    ///
+    /// With `ErrorPolicy::Continue`, a failed table the copy created, or
+    /// emptied for `CopyMode::Overwrite`, is deleted from the destination,
+    /// while existing tables copied into with `CopyMode::Append` or
+    /// `table_merged` keep the entries written before the failure. Failures
+    /// of the destination transaction itself, such as a batch commit, still
+    /// abort the copy.
+    pub fn on_error(mut self, policy: ErrorPolicy) -> Self {
+        self.on_error = policy;
+        self
+    }
+
+    /// Copy only the planned tables, even if the source has others.
+    ///
+    /// By default a copy fails with `DbCopyError::UnplannedTables` before
+    /// writing anything when the source has tables the plan neither copies
+    /// nor excludes, since redb tables can't be copied without their
+    /// definitions and would otherwise be silently left behind. Call this
+    /// for plans that deliberately copy a subset of the source.
+    pub fn allow_unplanned(mut self) -> Self {
+        self.allow_unplanned = true;
+        self
+    }
+
+    /// Leave the source table named `table` out of the plan.
+    ///
+    /// Steps reading it are dropped, whether they were added before or after
+    /// this call, and `unplanned_tables` no longer reports it. Useful with
+    /// plans built from the tables found in a database, such as
+    /// `TableBucketBuilder::copy_plan`, to skip internal or scratch tables.
+    pub fn exclude(self, table: &str) -> Self {
+        self.excluding(Exclusion::Name(table.to_string()))
+    }
+
+    /// Leave every source table whose name starts with `prefix` out of the plan.
+    ///
+    /// See `exclude`.
+    pub fn exclude_prefix(self, prefix: &str) -> Self {
+        self.excluding(Exclusion::Prefix(prefix.to_string()))
+    }
+
+    fn excluding(mut self, exclusion: Exclusion) -> Self {
+        self.steps
+            .retain(|step| !exclusion.matches(step.source_name()));
+        self.exclusions.push(exclusion);
+        self
+    }
+
+    fn excludes(&self, table: &str) -> bool {
+        self.exclusions
+            .iter()
+            .any(|exclusion| exclusion.matches(table))
+    }
+
+    /// Adds `step` unless its source table is excluded.
+    fn push(&mut self, step: Box<dyn CopyStep>) {
+        if !self.excludes(step.source_name()) {
+            self.steps.push(step);
+        }
+    }
+
+    /// Record a manifest of the copy in the destination table named `table`.
+    ///
+    /// Each table the copy writes gets a row with `source`, e.g. the source
+    /// database path, the copy's start time and the table's row count and
+    /// checksum, read back with `read_manifest`. The checksum is the xxh3-64
+    /// digest of the destination table's entries in key order (and value
+    /// order for multimaps), each key and value prefixed with its length as
+    /// a little-endian `u32`. It covers the whole table after the copy, so
+    /// it also reflects rows kept by `CopyMode::Append` or `table_merged`.
+    /// Skipped tables keep the row of the copy that wrote them, if any.
+    pub fn manifest(mut self, table: &str, source: impl AsRef<Path>) -> Self {
+        self.manifest = Some(ManifestConfig {
+            table: table.to_string(),
+            source: source.as_ref().display().to_string(),
+        });
+        self
+    }
+
+    /// Record copy progress in the destination table named `table`.
+    ///
+    /// Every batch commit also stores, per step, the last copied key, and
+    /// each finished step is marked as done, so `resume_copy` can pick up
+    /// after a crash or restart. Combine with `batch_entries` or
+    /// `batch_bytes`; an unbatched copy commits only once. The table is left
+    /// in the destination and can be deleted once the copy is complete.
+    pub fn checkpoint(mut self, table: &str) -> Self {
+        self.checkpoint = Some(table.to_string());
+        self
+    }
+
+    /// Call `progress` while tables are copied.
+    ///
+    /// It runs every `PROGRESS_INTERVAL` entries of a table and once more
+    /// when the table is finished.
+    pub fn on_progress(mut self, progress: impl Fn(&CopyProgress<'_>) + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Commit the destination every `entries` copied entries.
+    ///
+    /// See `copy_database` for what a batched copy leaves behind on failure.
+    pub fn batch_entries(mut self, entries: u64) -> Self {
+        self.batch_entries = Some(entries.max(1));
+        self
+    }
+
+    /// Commit the destination once the copied keys and values reach `bytes`.
+    ///
+    /// See `copy_database` for what a batched copy leaves behind on failure.
+    pub fn batch_bytes(mut self, bytes: u64) -> Self {
+        self.batch_bytes = Some(bytes.max(1));
+        self
+    }
+
+    /// Copy at most `per_second` entries per second, on average.
+    ///
+    /// The copy sleeps whenever it gets ahead of the rate, so a background
+    /// copy leaves disk and CPU time to other work. Sleeps happen with the
+    /// destination write transaction open; combine with `batch_entries` or
+    /// `batch_bytes` so other writers get the lock between commits.
+    pub fn throttle_entries(mut self, per_second: u64) -> Self {
+        self.throttle_entries = Some(per_second.max(1));
+        self
+    }
+
+    /// Copy at most `per_second` bytes of keys and values per second, on average.
+    ///
+    /// See `throttle_entries`.
+    pub fn throttle_bytes(mut self, per_second: u64) -> Self {
+        self.throttle_bytes = Some(per_second.max(1));
+        self
+    }
+
+    /// Set how existing destination tables are handled, `CopyMode::Fail` by default.
+    pub fn mode(mut self, mode: CopyMode) -> Self {
+        self.mode = mode;
+        self
     }
 
     /// Add a normal table to the copy plan.
@@ -110,7 +574,25 @@ impl CopyPlan {
         mut self,
         table: TableDefinition<'_, K, V>,
     ) -> Self {
-        self.steps.push(Box::new(TablePlan::new(table)));
+        self.push(Box::new(TablePlan::new(table)));
+        self
+    }
+
+    /// Add a normal table to the copy plan, copying only the keys in `range`.
+    ///
+    /// Useful for splitting a table into shards by key. The rest of the plan,
+    /// including `CopyMode`, applies to the destination table as a whole.
+    pub fn table_range<'a, K, V, KR>(
+        mut self,
+        table: TableDefinition<'_, K, V>,
+        range: impl RangeBounds<KR>,
+    ) -> Self
+    where
+        K: redb::Key + 'static,
+        V: redb::Value + 'static,
+        KR: Borrow<K::SelfType<'a>>,
+    {
+        self.push(Box::new(TablePlan::new(table).with_range(range)));
         self
     }
 
@@ -119,201 +601,1947 @@ impl CopyPlan {
         mut self,
         table: MultimapTableDefinition<'_, K, V>,
     ) -> Self {
-        self.steps.push(Box::new(MultimapPlan::new(table)));
+        self.push(Box::new(MultimapPlan::new(table)));
         self
     }
-}
 
-/// Copy all tables described by `plan` from `source` to `destination`.
-pub fn copy_database(source: &Database, destination: &Database, plan: &CopyPlan) -> Result<()> {
-    let source_read = source
-        .begin_read()
-        .map_err(|err| DbCopyError::TransactionFailed(format!("source read: {}", err)))?;
-    let destination_read = destination
-        .begin_read()
-        .map_err(|err| DbCopyError::TransactionFailed(format!("destination read: {}", err)))?;
+    /// Add a configured table step to the copy plan.
+    pub fn table_step<K: redb::Key + 'static, V: redb::Value + 'static>(
+        mut self,
+        step: TableStep<K, V>,
+    ) -> Self {
+        self.push(Box::new(step.0));
+        self
+    }
 
-    let mut conflicts = Vec::new();
-    for step in &plan.steps {
-        match step.preflight(&destination_read) {
-            Ok(true) => conflicts.push(step.display_name()),
-            Ok(false) => {}
-            Err(err) => {
-                return Err(DbCopyError::DestinationCheckFailed(format!(
-                    "{}: {}",
-                    step.display_name(),
-                    err
-                ))
-                .into())
-            }
-        }
+    /// Add a configured multimap table step to the copy plan.
+    pub fn multimap_step<K: redb::Key + 'static, V: redb::Key + 'static>(
+        mut self,
+        step: MultimapStep<K, V>,
+    ) -> Self {
+        self.push(Box::new(step.0));
+        self
     }
 
-    if !conflicts.is_empty() {
-        return Err(DbCopyError::DestinationTablesExist(conflicts).into());
+    /// Add a normal table whose values are merged into an existing destination table.
+    ///
+    /// For keys already present in the destination, the stored value and the
+    /// incoming one are combined with `MergeableValue::merge`; other keys are
+    /// inserted as-is. An existing destination table is never a conflict for
+    /// this step, whatever the plan's `CopyMode`.
+    pub fn table_merged<K, V>(mut self, table: TableDefinition<'_, K, V>) -> Self
+    where
+        K: redb::Key + 'static,
+        V: redb::Value + MergeableValue + 'static,
+        for<'b> V: From<V::SelfType<'b>>,
+        for<'b> V: Borrow<V::SelfType<'b>>,
+    {
+        self.push(Box::new(MergedTablePlan(TablePlan::new(table))));
+        self
     }
 
-    drop(destination_read);
+    /// Add a key-bucketed table whose keys were built with `key_builder`.
+    ///
+    /// The table is copied as is and `key_builder` is persisted for it in
+    /// the destination, as with `KeyBuilder::persist`. The step fails if the
+    /// source or the destination persisted a different builder for the
+    /// table, since its keys would then be read into the wrong buckets.
+    pub fn bucketed<K, V>(
+        mut self,
+        key_builder: &KeyBuilder,
+        table: TableDefinition<'_, BucketedKey<K>, V>,
+    ) -> Self
+    where
+        K: redb::Key + 'static,
+        V: redb::Value + 'static,
+    {
+        self.push(Box::new(BucketedTablePlan::new(key_builder, table)));
+        self
+    }
 
-    let mut destination_write = destination
-        .begin_write()
-        .map_err(|err| DbCopyError::TransactionFailed(format!("destination write: {}", err)))?;
+    /// Add the bucket tables of `builder` whose buckets fall in `range`.
+    ///
+    /// Which buckets exist is looked up in the source when the plan runs,
+    /// and each bucket copied is recorded in the destination's bucket
+    /// registry, so `TableBucketBuilder::registered_buckets` and the bucket
+    /// iterators find it. The step is named after the builder's prefix, e.g.
+    /// `"table events_*"`, and treats the bucket tables in `range` as one
+    /// table for the plan's `CopyMode`, reports and manifests.
+    pub fn table_buckets<K, V>(
+        mut self,
+        builder: &TableBucketBuilder,
+        range: RangeInclusive<u64>,
+    ) -> Self
+    where
+        K: redb::Key + 'static,
+        V: redb::Value + 'static,
+    {
+        self.push(Box::new(TableBucketsPlan::<K, V>::new(builder, range)));
+        self
+    }
 
-    for step in &plan.steps {
-        step.copy(&source_read, &mut destination_write)?;
+    /// Add a table whose values are converted to a new type while copying.
+    ///
+    /// Every entry of `source` is copied into `destination` under the same
+    /// key, with its value passed through `convert`, so a copy can double as
+    /// a schema migration, e.g. widening `u32` values to `u64`. The tables
+    /// may have different names; the destination one is handled by the
+    /// plan's `CopyMode` and names the step in reports.
+    pub fn table_convert<K, V1, V2, F>(
+        mut self,
+        source: TableDefinition<'_, K, V1>,
+        destination: TableDefinition<'_, K, V2>,
+        convert: F,
+    ) -> Self
+    where
+        K: redb::Key + 'static,
+        V1: redb::Value + 'static,
+        V2: redb::Value + 'static,
+        for<'b> V1: From<V1::SelfType<'b>>,
+        for<'b> V2: Borrow<V2::SelfType<'b>>,
+        F: Fn(V1) -> V2 + Send + Sync + 'static,
+    {
+        self.push(Box::new(ConvertedTablePlan::<K, V1, V2> {
+            source: source.name().to_string(),
+            name: destination.name().to_string(),
+            convert: Box::new(move |value| {
+                let converted = convert(V1::from(V1::from_bytes(value)));
+                let bytes = V2::as_bytes(converted.borrow()).as_ref().to_vec();
+                bytes
+            }),
+            types: PhantomData,
+        }));
+        self
     }
 
-    destination_write
-        .commit()
-        .map_err(|err| DbCopyError::CommitFailed(err.to_string()))?;
+    /// List the tables of `source` that this plan neither copies nor excludes.
+    ///
+    /// redb tables can only be read through their key and value types, so a
+    /// plan can't discover and copy tables on its own. Copies fail on these
+    /// tables unless `allow_unplanned` is set; this lists them up front.
+    ///
+    /// # Returns
+    /// Display names such as `"table users"`, tables before multimap tables
+    pub fn unplanned_tables(&self, source: &Database) -> Result<Vec<String>> {
+        let read_txn = source.begin_read().map_err(|err| {
+            DbCopyError::TransactionFailed(DatabaseError::new("source read", err))
+        })?;
+        Ok(self.unplanned_in(&read_txn)?)
+    }
 
-    Ok(())
-}
+    /// Fails with `UnplannedTables` if `source_read` has tables the plan
+    /// doesn't cover, unless `allow_unplanned` was called.
+    fn check_unplanned(
+        &self,
+        source_read: &ReadTransaction,
+    ) -> std::result::Result<(), DbCopyError> {
+        if self.allow_unplanned {
+            return Ok(());
+        }
+        let unplanned = self.unplanned_in(source_read)?;
+        if unplanned.is_empty() {
+            Ok(())
+        } else {
+            Err(DbCopyError::UnplannedTables(unplanned))
+        }
+    }
 
-struct TablePlan<K: redb::Key + 'static, V: redb::Value + 'static> {
-    name: String,
-    _key: PhantomData<K>,
-    _value: PhantomData<V>,
-}
+    /// `unplanned_tables` within an open read transaction.
+    fn unplanned_in(
+        &self,
+        read_txn: &ReadTransaction,
+    ) -> std::result::Result<Vec<String>, DbCopyError> {
+        let planned = |kind: CopyKind, name: &str| {
+            self.excludes(name)
+                || self
+                    .steps
+                    .iter()
+                    .any(|step| step.kind() == kind && step.reads(name))
+        };
 
-impl<K: redb::Key + 'static, V: redb::Value + 'static> TablePlan<K, V> {
-    fn new(table: TableDefinition<'_, K, V>) -> Self {
-        Self {
-            name: table.name().to_string(),
-            _key: PhantomData,
-            _value: PhantomData,
+        let mut unplanned = Vec::new();
+        let tables = read_txn.list_tables().map_err(|err| {
+            DbCopyError::SourceTableOpenFailed(DatabaseError::new("list tables", err))
+        })?;
+        for table in tables {
+            if !planned(CopyKind::Table, table.name()) {
+                unplanned.push(format!("{} {}", CopyKind::Table, table.name()));
+            }
+        }
+        let multimaps = read_txn.list_multimap_tables().map_err(|err| {
+            DbCopyError::SourceTableOpenFailed(DatabaseError::new("list multimap tables", err))
+        })?;
+        for table in multimaps {
+            if !planned(CopyKind::Multimap, table.name()) {
+                unplanned.push(format!("{} {}", CopyKind::Multimap, table.name()));
+            }
         }
-    }
 
-    fn definition(&self) -> TableDefinition<'_, K, V> {
-        TableDefinition::new(self.name.as_str())
+        Ok(unplanned)
     }
 }
 
-impl<K: redb::Key + 'static, V: redb::Value + 'static> CopyStep for TablePlan<K, V> {
-    fn name(&self) -> &str {
-        &self.name
+/// A table copy step with optional key range, filter and transform.
+///
+/// The filter and transform run on each entry as it is streamed from the
+/// source, e.g. to drop stale rows or strip fields from values. A plain
+/// `CopyPlan::table` step is a `TableStep` with none of them set.
+pub struct TableStep<K: redb::Key + 'static, V: redb::Value + 'static>(TablePlan<K, V>);
+
+impl<K: redb::Key + 'static, V: redb::Value + 'static> TableStep<K, V> {
+    /// Create a step copying the whole table.
+    pub fn new(table: TableDefinition<'_, K, V>) -> Self {
+        Self(TablePlan::new(table))
     }
 
-    fn kind(&self) -> CopyKind {
-        CopyKind::Table
+    /// Only copy the keys in `range`.
+    pub fn range<'a, KR>(self, range: impl RangeBounds<KR>) -> Self
+    where
+        KR: Borrow<K::SelfType<'a>>,
+    {
+        Self(self.0.with_range(range))
     }
 
-    fn preflight(&self, destination: &ReadTransaction) -> std::result::Result<bool, TableError> {
-        match destination.open_table(self.definition()) {
-            Ok(_) => Ok(true),
-            Err(TableError::TableDoesNotExist(_)) => Ok(false),
-            Err(err) => Err(err),
-        }
+    /// Only copy entries for which `filter` returns true.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: for<'k, 'v> Fn(&K::SelfType<'k>, &V::SelfType<'v>) -> bool + Send + Sync + 'static,
+    {
+        self.0.hooks.set_filter(filter);
+        self
     }
 
-    fn copy(
-        &self,
-        source: &ReadTransaction,
-        destination: &mut WriteTransaction,
-    ) -> std::result::Result<(), DbCopyError> {
-        let source_table = source.open_table(self.definition()).map_err(|err| {
-            DbCopyError::SourceTableOpenFailed(format!("{}: {}", self.display_name(), err))
-        })?;
-        let mut destination_table = destination.open_table(self.definition()).map_err(|err| {
-            DbCopyError::DestinationTableOpenFailed(format!("{}: {}", self.display_name(), err))
-        })?;
-        let iter = source_table.iter().map_err(|err| {
-            DbCopyError::TableCopyFailed(format!("{}: {}", self.display_name(), err))
-        })?;
+    /// Replace each copied value with the one returned by `map`.
+    ///
+    /// Runs after the filter, so only kept entries are transformed.
+    pub fn map<F>(mut self, map: F) -> Self
+    where
+        F: for<'k, 'v> Fn(&K::SelfType<'k>, &V::SelfType<'v>) -> V + Send + Sync + 'static,
+        for<'b> V: Borrow<V::SelfType<'b>>,
+    {
+        self.0.hooks.set_map(map);
+        self
+    }
+}
 
-        for entry in iter {
-            let (key, value) = entry.map_err(|err| {
-                DbCopyError::TableCopyFailed(format!("{}: {}", self.display_name(), err))
-            })?;
-            destination_table
-                .insert(key.value(), value.value())
-                .map_err(|err| {
-                    DbCopyError::TableCopyFailed(format!("{}: {}", self.display_name(), err))
-                })?;
-        }
+/// A multimap table copy step with optional filter and transform.
+///
+/// Both hooks see each key and value pair on its own, like `TableStep`.
+pub struct MultimapStep<K: redb::Key + 'static, V: redb::Key + 'static>(MultimapPlan<K, V>);
 
-        Ok(())
+impl<K: redb::Key + 'static, V: redb::Key + 'static> MultimapStep<K, V> {
+    /// Create a step copying the whole multimap table.
+    pub fn new(table: MultimapTableDefinition<'_, K, V>) -> Self {
+        Self(MultimapPlan::new(table))
+    }
+
+    /// Only copy values for which `filter` returns true.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: for<'k, 'v> Fn(&K::SelfType<'k>, &V::SelfType<'v>) -> bool + Send + Sync + 'static,
+    {
+        self.0.hooks.set_filter(filter);
+        self
+    }
+
+    /// Replace each copied value with the one returned by `map`.
+    ///
+    /// Runs after the filter, so only kept values are transformed.
+    pub fn map<F>(mut self, map: F) -> Self
+    where
+        F: for<'k, 'v> Fn(&K::SelfType<'k>, &V::SelfType<'v>) -> V + Send + Sync + 'static,
+        for<'b> V: Borrow<V::SelfType<'b>>,
+    {
+        self.0.hooks.set_map(map);
+        self
     }
 }
 
-struct MultimapPlan<K: redb::Key + 'static, V: redb::Key + 'static> {
-    name: String,
-    _key: PhantomData<K>,
-    _value: PhantomData<V>,
+/// Copy all tables described by `plan` from `source` to `destination`.
+///
+/// Tables already present in the destination are handled according to the
+/// plan's `CopyMode`.
+///
+/// By default all changes to the destination are made in a single
+/// transaction. With `CopyPlan::batch_entries` or `batch_bytes` set, the
+/// destination is committed whenever a limit is reached, possibly in the
+/// middle of a table. A failed batched copy then leaves every earlier step
+/// complete and the failing table holding a prefix, in key order, of what it
+/// would have received. Re-running the plan with `CopyMode::Overwrite` or
+/// `CopyMode::Append` finishes the copy, except for `table_merged` steps,
+/// which would merge the already copied prefix a second time. Plans with a
+/// `CopyPlan::checkpoint` table can instead be continued with `resume_copy`,
+/// which is exact for every kind of step.
+///
+/// # Returns
+/// Per-table statistics of the copy
+pub fn copy_database(
+    source: &Database,
+    destination: &Database,
+    plan: &CopyPlan,
+) -> Result<CopyReport> {
+    run_copy(source, destination, plan, false, None)
 }
 
-impl<K: redb::Key + 'static, V: redb::Key + 'static> MultimapPlan<K, V> {
-    fn new(table: MultimapTableDefinition<'_, K, V>) -> Self {
-        Self {
-            name: table.name().to_string(),
-            _key: PhantomData,
-            _value: PhantomData,
-        }
-    }
+/// Copy all tables described by `plan`, then compact the destination.
+///
+/// Works like `copy_database` and, once the copy has committed, runs
+/// `Database::compact` on the destination so a migration hands back a tight
+/// file. Compaction needs exclusive access to the destination, hence the
+/// mutable borrow, and fails while other transactions on it are open.
+///
+/// # Returns
+/// Per-table statistics of the copy, with `compaction` set to the
+/// destination's allocated bytes before and after compacting
+pub fn copy_and_compact(
+    source: &Database,
+    destination: &mut Database,
+    plan: &CopyPlan,
+) -> Result<CopyReport> {
+    let mut report = run_copy(source, destination, plan, false, None)?;
 
-    fn definition(&self) -> MultimapTableDefinition<'_, K, V> {
-        MultimapTableDefinition::new(self.name.as_str())
-    }
+    let started = Instant::now();
+    let bytes_before = allocated_bytes(destination)?;
+    destination
+        .compact()
+        .map_err(|err| DbCopyError::CompactionFailed(DatabaseError::new("compact", err)))?;
+    report.compaction = Some(CompactionStats {
+        bytes_before,
+        bytes_after: allocated_bytes(destination)?,
+        elapsed: started.elapsed(),
+    });
+    report.elapsed += started.elapsed();
+
+    Ok(report)
 }
 
-impl<K: redb::Key + 'static, V: redb::Key + 'static> CopyStep for MultimapPlan<K, V> {
-    fn name(&self) -> &str {
-        &self.name
+/// Bytes of pages allocated in `db`.
+fn allocated_bytes(db: &Database) -> std::result::Result<u64, DbCopyError> {
+    let stats = db
+        .begin_write()
+        .and_then(|txn| Ok(txn.stats()?))
+        .map_err(|err| DbCopyError::CompactionFailed(DatabaseError::new("compact", err)))?;
+    Ok(stats.allocated_pages() * stats.page_size() as u64)
+}
+
+/// Copy all tables described by `plan` and label the source snapshot in its manifest.
+///
+/// Works like `copy_database`, and every manifest row the copy writes also
+/// records `label`, e.g. a block height or the id of the last event applied
+/// to the source, so downstream systems know which state of the source the
+/// copy holds. All tables are read from one snapshot of `source`, taken when
+/// the copy starts, which is also the time the manifest records.
+///
+/// # Arguments
+/// * `label` - Name of the source snapshot, recorded in `ManifestEntry::label`
+/// * `source` - The database to copy from
+/// * `destination` - The database to copy into
+/// * `plan` - The tables to copy, which must have a `CopyPlan::manifest`
+///
+/// # Returns
+/// Per-table statistics of the copy
+pub fn copy_database_labeled(
+    label: &str,
+    source: &Database,
+    destination: &Database,
+    plan: &CopyPlan,
+) -> Result<CopyReport> {
+    if plan.manifest.is_none() {
+        return Err(crate::Error::InvalidInput(
+            "plan has no manifest table to record the label in".to_string(),
+        ));
     }
+    run_copy(source, destination, plan, false, Some(label))
+}
 
-    fn kind(&self) -> CopyKind {
-        CopyKind::Multimap
+/// Copy all tables described by `plan` from each of `sources` into `destination`.
+///
+/// Consolidates databases sharing a layout, such as per-shard files, into
+/// one. The first source is copied as in `copy_database`, with the plan's
+/// `CopyMode` applied to tables already in the destination. Each later
+/// source is then copied into the tables the earlier ones wrote: its
+/// entries are merged into existing values for `table_merged` steps,
+/// replace values of equal keys for other tables, and add to the values of
+/// multimaps. Tables the plan's mode skips stay skipped for every source.
+///
+/// All sources are copied in a single destination transaction, subject to
+/// the plan's batch limits. Checkpoints are not recorded, since a position
+/// in one source means nothing in the next; `resume_copy` can't continue a
+/// consolidation.
+///
+/// # Arguments
+/// * `sources` - The databases to copy from, in the order they're applied
+/// * `destination` - The database to copy into
+/// * `plan` - The tables to copy from every source
+///
+/// # Returns
+/// Per-table statistics of the copy, with one entry per step for each
+/// source, in source order
+pub fn copy_databases(
+    sources: &[&Database],
+    destination: &Database,
+    plan: &CopyPlan,
+) -> Result<CopyReport> {
+    let started = Instant::now();
+    let source_reads = sources
+        .iter()
+        .enumerate()
+        .map(|(index, source)| {
+            source.begin_read().map_err(|err| {
+                DbCopyError::TransactionFailed(DatabaseError::new(
+                    format!("source {} read", index),
+                    err,
+                ))
+            })
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    for source_read in &source_reads {
+        plan.check_unplanned(source_read)?;
     }
+    let destination_read = destination.begin_read().map_err(|err| {
+        DbCopyError::TransactionFailed(DatabaseError::new("destination read", err))
+    })?;
+    let existing = check_destination(plan, &destination_read, &HashMap::new())?;
+    drop(destination_read);
 
-    fn preflight(&self, destination: &ReadTransaction) -> std::result::Result<bool, TableError> {
-        match destination.open_multimap_table(self.definition()) {
-            Ok(_) => Ok(true),
-            Err(TableError::TableDoesNotExist(_)) => Ok(false),
-            Err(err) => Err(err),
+    let mut conflicts = conflict_modes(plan, existing);
+    let mut batch = CopyBatch::begin(destination, plan)?;
+    batch.checkpoint = None;
+    let mut report = CopyReport::default();
+    for source_read in &source_reads {
+        let copied = copy_steps(
+            source_read,
+            &mut batch,
+            plan,
+            conflicts.clone(),
+            HashMap::new(),
+        )?;
+        report.tables.extend(copied.tables);
+        // Later sources add to whatever the earlier ones left in each table
+        for conflict in &mut conflicts {
+            if *conflict != Some(CopyMode::Skip) {
+                *conflict = Some(CopyMode::Append);
+            }
         }
     }
+    batch.commit()?;
+    report.elapsed = started.elapsed();
 
-    fn copy(
-        &self,
-        source: &ReadTransaction,
-        destination: &mut WriteTransaction,
-    ) -> std::result::Result<(), DbCopyError> {
-        let source_table = source
-            .open_multimap_table(self.definition())
-            .map_err(|err| {
-                DbCopyError::SourceTableOpenFailed(format!("{}: {}", self.display_name(), err))
-            })?;
-        let mut destination_table =
-            destination
-                .open_multimap_table(self.definition())
+    Ok(report)
+}
+
+/// Copy all tables described by `plan` into a write transaction owned by the caller.
+///
+/// Works like `copy_database`, but reads from `source_read` and writes into
+/// `destination` without committing it, so the copy can be combined with
+/// other writes and committed, or aborted, atomically. The plan's `CopyMode`,
+/// progress callback and throttles apply as usual; batch limits and
+/// checkpoints are ignored, since only the caller commits.
+///
+/// # Arguments
+/// * `source_read` - Read transaction on the database to copy from
+/// * `destination` - Write transaction on the database to copy into
+/// * `plan` - The tables to copy
+///
+/// # Returns
+/// Per-table statistics of the copy
+pub fn copy_into_txn(
+    source_read: &ReadTransaction,
+    destination: &WriteTransaction,
+    plan: &CopyPlan,
+) -> Result<CopyReport> {
+    let started = Instant::now();
+    plan.check_unplanned(source_read)?;
+    let list_error = |err: redb::StorageError| {
+        DbCopyError::DestinationCheckFailed(DatabaseError::new("list tables", err))
+    };
+    // Opening a table in a write transaction would create it, so look for names
+    let tables: Vec<String> = destination
+        .list_tables()
+        .map_err(list_error)?
+        .map(|table| table.name().to_string())
+        .collect();
+    let multimaps: Vec<String> = destination
+        .list_multimap_tables()
+        .map_err(list_error)?
+        .map(|table| table.name().to_string())
+        .collect();
+    let existing = check_steps(
+        plan,
+        |step| {
+            let names = match step.kind() {
+                CopyKind::Table => &tables,
+                CopyKind::Multimap => &multimaps,
+            };
+            Ok(names.iter().any(|name| name == step.name()))
+        },
+        &HashMap::new(),
+    )?;
+
+    let mut batch = CopyBatch::within(destination, plan);
+    let conflicts = conflict_modes(plan, existing);
+    let mut report = copy_steps(source_read, &mut batch, plan, conflicts, HashMap::new())?;
+    report.elapsed = started.elapsed();
+
+    Ok(report)
+}
+
+/// Continue a checkpointed copy interrupted before it finished.
+///
+/// Steps the plan's checkpoint table marks as done are skipped and reported
+/// as such, steps with a recorded position continue after it, and steps
+/// that never committed anything start from scratch as in `copy_database`.
+/// The plan must be the one used for the interrupted copy. Without a
+/// checkpoint table in the destination, this is the same as `copy_database`.
+///
+/// # Returns
+/// Per-table statistics of this run; entries copied before the
+/// interruption are not included
+pub fn resume_copy(
+    source: &Database,
+    destination: &Database,
+    plan: &CopyPlan,
+) -> Result<CopyReport> {
+    if plan.checkpoint.is_none() {
+        return Err(crate::Error::InvalidInput(
+            "plan has no checkpoint table to resume from".to_string(),
+        ));
+    }
+    run_copy(source, destination, plan, true, None)
+}
+
+/// Inspect what `copy_database` would do without writing anything.
+///
+/// Each step's source table is read in full, applying its range, filter and
+/// transform, so this costs about as much reading as the copy itself. For
+/// `table_merged` steps the bytes are those of the incoming values, before
+/// merging. The plan's `CopyMode` is not applied; check
+/// `destination_exists` to see which steps it would affect.
+///
+/// # Returns
+/// One estimate per step, in plan order
+pub fn copy_database_dry_run(
+    source: &Database,
+    destination: &Database,
+    plan: &CopyPlan,
+) -> Result<Vec<TableCopyEstimate>> {
+    let source_read = source
+        .begin_read()
+        .map_err(|err| DbCopyError::TransactionFailed(DatabaseError::new("source read", err)))?;
+    let destination_read = destination.begin_read().map_err(|err| {
+        DbCopyError::TransactionFailed(DatabaseError::new("destination read", err))
+    })?;
+
+    let mut estimates = Vec::with_capacity(plan.steps.len());
+    for step in &plan.steps {
+        let destination_exists = step.preflight(&destination_read).map_err(|err| {
+            DbCopyError::DestinationCheckFailed(DatabaseError::new(step.display_name(), err))
+        })?;
+        let (entries, bytes) = step.estimate(&source_read)?;
+        estimates.push(TableCopyEstimate {
+            table: step.display_name(),
+            destination_exists,
+            entries,
+            bytes,
+        });
+    }
+
+    Ok(estimates)
+}
+
+/// Checks which destination tables of the plan exist, failing on conflicts
+/// in `CopyMode::Fail`. Steps with a checkpoint are reported as new, since
+/// their tables were created by the copy being resumed.
+fn check_destination(
+    plan: &CopyPlan,
+    destination: &ReadTransaction,
+    checkpoints: &HashMap<String, Checkpoint>,
+) -> std::result::Result<Vec<bool>, DbCopyError> {
+    check_steps(plan, |step| step.preflight(destination), checkpoints)
+}
+
+/// `check_destination` with the existence check of each step supplied by
+/// the caller.
+fn check_steps(
+    plan: &CopyPlan,
+    preflight: impl Fn(&dyn CopyStep) -> std::result::Result<bool, TableError>,
+    checkpoints: &HashMap<String, Checkpoint>,
+) -> std::result::Result<Vec<bool>, DbCopyError> {
+    let mut conflicts = Vec::new();
+    let mut existing = Vec::with_capacity(plan.steps.len());
+    for step in &plan.steps {
+        if checkpoints.contains_key(&step.display_name()) {
+            existing.push(false);
+            continue;
+        }
+        match preflight(step.as_ref()) {
+            Ok(true) if step.merges_existing() => existing.push(false),
+            Ok(true) => {
+                conflicts.push(step.display_name());
+                existing.push(true);
+            }
+            Ok(false) => existing.push(false),
+            Err(err) => {
+                return Err(DbCopyError::DestinationCheckFailed(DatabaseError::new(
+                    step.display_name(),
+                    err,
+                )))
+            }
+        }
+    }
+
+    if plan.mode == CopyMode::Fail && !conflicts.is_empty() {
+        return Err(DbCopyError::DestinationTablesExist(conflicts));
+    }
+    Ok(existing)
+}
+
+fn run_copy(
+    source: &Database,
+    destination: &Database,
+    plan: &CopyPlan,
+    resume: bool,
+    label: Option<&str>,
+) -> Result<CopyReport> {
+    let started = Instant::now();
+    let source_read = source
+        .begin_read()
+        .map_err(|err| DbCopyError::TransactionFailed(DatabaseError::new("source read", err)))?;
+    plan.check_unplanned(&source_read)?;
+    let destination_read = destination.begin_read().map_err(|err| {
+        DbCopyError::TransactionFailed(DatabaseError::new("destination read", err))
+    })?;
+
+    let checkpoints = match &plan.checkpoint {
+        Some(table) if resume => read_checkpoints(&destination_read, table)?,
+        _ => HashMap::new(),
+    };
+
+    let existing = check_destination(plan, &destination_read, &checkpoints)?;
+    drop(destination_read);
+
+    let mut batch = CopyBatch::begin(destination, plan)?;
+    batch.label = label;
+    if !resume {
+        batch.clear_checkpoints()?;
+    }
+    let conflicts = conflict_modes(plan, existing);
+    let mut report = copy_steps(&source_read, &mut batch, plan, conflicts, checkpoints)?;
+    batch.commit()?;
+    report.elapsed = started.elapsed();
+
+    Ok(report)
+}
+
+/// How each step handles its existing destination table, `None` for steps
+/// whose table doesn't exist.
+fn conflict_modes(plan: &CopyPlan, existing: Vec<bool>) -> Vec<Option<CopyMode>> {
+    existing
+        .into_iter()
+        .map(|exists| exists.then_some(plan.mode))
+        .collect()
+}
+
+/// Copies every step of the plan through `batch`, which is left uncommitted.
+/// `conflicts` holds, per step, how an existing destination table is handled.
+fn copy_steps(
+    source_read: &ReadTransaction,
+    batch: &mut CopyBatch<'_>,
+    plan: &CopyPlan,
+    conflicts: Vec<Option<CopyMode>>,
+    mut checkpoints: HashMap<String, Checkpoint>,
+) -> std::result::Result<CopyReport, DbCopyError> {
+    let mut report = CopyReport::default();
+    for (step, conflict) in plan.steps.iter().zip(conflicts) {
+        let position = match checkpoints.remove(&step.display_name()) {
+            Some(Checkpoint::Done) => {
+                report
+                    .tables
+                    .push(TableCopyStats::skipped(step.display_name()));
+                continue;
+            }
+            Some(Checkpoint::At(key, value)) => Some((key, value)),
+            None => None,
+        };
+        match conflict {
+            Some(CopyMode::Skip) => {
+                report
+                    .tables
+                    .push(TableCopyStats::skipped(step.display_name()));
+                continue;
+            }
+            Some(CopyMode::Overwrite) => step.delete(batch.txn())?,
+            Some(CopyMode::Fail | CopyMode::Append) | None => {}
+        }
+        // Tables holding only what this copy wrote can be dropped on failure
+        let discard =
+            !step.merges_existing() && matches!(conflict, None | Some(CopyMode::Overwrite));
+        batch.begin_table(step.display_name(), position);
+        match copy_step(source_read, batch, plan, step.as_ref()) {
+            Ok(()) => report.tables.push(batch.finish_table()?),
+            Err(err) if plan.on_error == ErrorPolicy::Continue && !err.is_fatal() => {
+                if discard {
+                    step.delete(batch.txn())?;
+                }
+                report.tables.push(batch.fail_table(err.to_string())?);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Copies one step and records it in the plan's manifest.
+fn copy_step(
+    source_read: &ReadTransaction,
+    batch: &mut CopyBatch<'_>,
+    plan: &CopyPlan,
+    step: &dyn CopyStep,
+) -> std::result::Result<(), DbCopyError> {
+    step.copy(source_read, batch)?;
+    if let Some(manifest) = &plan.manifest {
+        let checksum = step.checksum(batch.txn()).map_err(|err| {
+            DbCopyError::ManifestFailed(DatabaseError::new(step.display_name(), err))
+        })?;
+        record_manifest(
+            batch.txn(),
+            manifest,
+            batch.started_at,
+            batch.label,
+            &step.display_name(),
+            checksum,
+        )?;
+    }
+    Ok(())
+}
+
+/// Checkpoint rows: step display name to (done, last key, last value).
+type CheckpointTable<'a> = TableDefinition<'a, &'static str, (bool, &'static [u8], &'static [u8])>;
+
+/// Recorded state of one step of a checkpointed copy.
+enum Checkpoint {
+    Done,
+    /// Encoded key, and value for multimaps, of the last entry committed
+    At(Vec<u8>, Vec<u8>),
+}
+
+fn checkpoint_error(table: &str, err: impl Into<redb::Error>) -> DbCopyError {
+    DbCopyError::CheckpointFailed(DatabaseError::new(table, err))
+}
+
+fn read_checkpoints(
+    destination: &ReadTransaction,
+    table: &str,
+) -> std::result::Result<HashMap<String, Checkpoint>, DbCopyError> {
+    let checkpoints = match destination.open_table(CheckpointTable::new(table)) {
+        Ok(checkpoints) => checkpoints,
+        Err(TableError::TableDoesNotExist(_)) => return Ok(HashMap::new()),
+        Err(err) => return Err(checkpoint_error(table, err)),
+    };
+
+    let mut found = HashMap::new();
+    for entry in checkpoints
+        .iter()
+        .map_err(|err| checkpoint_error(table, err))?
+    {
+        let (step, state) = entry.map_err(|err| checkpoint_error(table, err))?;
+        let (done, key, value) = state.value();
+        let checkpoint = if done {
+            Checkpoint::Done
+        } else {
+            Checkpoint::At(key.to_vec(), value.to_vec())
+        };
+        found.insert(step.value().to_string(), checkpoint);
+    }
+    Ok(found)
+}
+
+/// The destination write transaction of a copy, replaced by a new one each
+/// time the plan's batch limits are reached.
+struct CopyBatch<'db> {
+    txn: BatchTxn<'db>,
+    max_entries: Option<u64>,
+    max_bytes: Option<u64>,
+    entries: Cell<u64>,
+    bytes: Cell<u64>,
+    throttle: Throttle,
+    progress: Option<&'db ProgressFn>,
+    table: String,
+    table_started: Instant,
+    table_entries: Cell<u64>,
+    table_bytes: Cell<u64>,
+    checkpoint: Option<&'db str>,
+    /// Start of the copy in seconds since the Unix epoch, for the manifest.
+    started_at: u64,
+    /// Label of the source snapshot, for the manifest.
+    label: Option<&'db str>,
+    resume_from: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+/// Where a copy batch writes: its own transactions on the destination, or a
+/// single transaction owned by the caller.
+enum BatchTxn<'db> {
+    Owned(&'db Database, Option<Box<WriteTransaction>>),
+    Borrowed(&'db WriteTransaction),
+}
+
+impl<'db> CopyBatch<'db> {
+    fn begin(
+        destination: &'db Database,
+        plan: &'db CopyPlan,
+    ) -> std::result::Result<Self, DbCopyError> {
+        let mut batch = Self::with_txn(BatchTxn::Owned(destination, None), plan);
+        batch.open()?;
+        Ok(batch)
+    }
+
+    /// A batch writing into the caller's transaction, which is never
+    /// committed here, so batch limits and checkpoints don't apply.
+    fn within(destination: &'db WriteTransaction, plan: &'db CopyPlan) -> Self {
+        let mut batch = Self::with_txn(BatchTxn::Borrowed(destination), plan);
+        batch.max_entries = None;
+        batch.max_bytes = None;
+        batch.checkpoint = None;
+        batch
+    }
+
+    fn with_txn(txn: BatchTxn<'db>, plan: &'db CopyPlan) -> Self {
+        Self {
+            txn,
+            max_entries: plan.batch_entries,
+            max_bytes: plan.batch_bytes,
+            entries: Cell::new(0),
+            bytes: Cell::new(0),
+            throttle: Throttle {
+                entries_per_second: plan.throttle_entries,
+                bytes_per_second: plan.throttle_bytes,
+                started: Instant::now(),
+                entries: Cell::new(0),
+                bytes: Cell::new(0),
+            },
+            progress: plan.progress.as_deref(),
+            table: String::new(),
+            table_started: Instant::now(),
+            table_entries: Cell::new(0),
+            table_bytes: Cell::new(0),
+            checkpoint: plan.checkpoint.as_deref(),
+            started_at: unix_now(),
+            label: None,
+            resume_from: None,
+        }
+    }
+
+    fn open(&mut self) -> std::result::Result<(), DbCopyError> {
+        if let BatchTxn::Owned(destination, txn) = &mut self.txn {
+            *txn = Some(Box::new(destination.begin_write().map_err(|err| {
+                DbCopyError::TransactionFailed(DatabaseError::new("destination write", err))
+            })?));
+        }
+        self.entries.set(0);
+        self.bytes.set(0);
+        Ok(())
+    }
+
+    fn txn(&self) -> &WriteTransaction {
+        match &self.txn {
+            BatchTxn::Owned(_, txn) => txn.as_ref().expect("copy batch transaction is open"),
+            BatchTxn::Borrowed(txn) => txn,
+        }
+    }
+
+    /// Starts counting entries for the table named `table`, which resumes
+    /// after `position` if a checkpoint recorded one.
+    fn begin_table(&mut self, table: String, position: Option<(Vec<u8>, Vec<u8>)>) {
+        self.table = table;
+        self.resume_from = position;
+        self.table_started = Instant::now();
+        self.table_entries.set(0);
+        self.table_bytes.set(0);
+    }
+
+    /// Encoded key and value of the entry the current table resumes after.
+    fn take_resume(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        self.resume_from.take()
+    }
+
+    /// Marks the current table as finished and returns its statistics.
+    fn finish_table(&mut self) -> std::result::Result<TableCopyStats, DbCopyError> {
+        self.save_checkpoint(true, &[], &[])?;
+        self.report_progress(true);
+        Ok(TableCopyStats {
+            table: std::mem::take(&mut self.table),
+            entries: self.table_entries.get(),
+            bytes: self.table_bytes.get(),
+            elapsed: self.table_started.elapsed(),
+            skipped: false,
+            error: None,
+        })
+    }
+
+    /// Marks the current table as failed with `error` and returns its
+    /// statistics. Its checkpoint is dropped, so a resumed copy retries it
+    /// from the start.
+    fn fail_table(&mut self, error: String) -> std::result::Result<TableCopyStats, DbCopyError> {
+        if let Some(checkpoint) = self.checkpoint {
+            let mut checkpoints = self
+                .txn()
+                .open_table(CheckpointTable::new(checkpoint))
+                .map_err(|err| checkpoint_error(checkpoint, err))?;
+            checkpoints
+                .remove(self.table.as_str())
+                .map_err(|err| checkpoint_error(checkpoint, err))?;
+        }
+        Ok(TableCopyStats {
+            table: std::mem::take(&mut self.table),
+            entries: self.table_entries.get(),
+            bytes: self.table_bytes.get(),
+            elapsed: self.table_started.elapsed(),
+            skipped: false,
+            error: Some(error),
+        })
+    }
+
+    fn save_checkpoint(
+        &self,
+        done: bool,
+        key: &[u8],
+        value: &[u8],
+    ) -> std::result::Result<(), DbCopyError> {
+        let Some(checkpoint) = self.checkpoint else {
+            return Ok(());
+        };
+        let mut checkpoints = self
+            .txn()
+            .open_table(CheckpointTable::new(checkpoint))
+            .map_err(|err| checkpoint_error(checkpoint, err))?;
+        checkpoints
+            .insert(self.table.as_str(), (done, key, value))
+            .map_err(|err| checkpoint_error(checkpoint, err))?;
+        Ok(())
+    }
+
+    /// Drops checkpoints left by an earlier copy into the same table.
+    fn clear_checkpoints(&self) -> std::result::Result<(), DbCopyError> {
+        if let Some(checkpoint) = self.checkpoint {
+            self.txn()
+                .delete_table(CheckpointTable::new(checkpoint))
+                .map_err(|err| checkpoint_error(checkpoint, err))?;
+        }
+        Ok(())
+    }
+
+    fn report_progress(&self, finished: bool) {
+        if let Some(progress) = self.progress {
+            progress(&CopyProgress {
+                table: &self.table,
+                entries: self.table_entries.get(),
+                bytes: self.table_bytes.get(),
+                elapsed: self.table_started.elapsed(),
+                finished,
+            });
+        }
+    }
+
+    /// Counts a copied entry of `size` bytes and returns whether the batch is full.
+    fn record(&self, size: usize) -> bool {
+        self.entries.set(self.entries.get() + 1);
+        self.bytes.set(self.bytes.get() + size as u64);
+        self.table_entries.set(self.table_entries.get() + 1);
+        self.table_bytes.set(self.table_bytes.get() + size as u64);
+        if self.table_entries.get() % PROGRESS_INTERVAL == 0 {
+            self.report_progress(false);
+        }
+        self.throttle.record(size);
+        self.max_entries
+            .is_some_and(|max| self.entries.get() >= max)
+            || self.max_bytes.is_some_and(|max| self.bytes.get() >= max)
+    }
+
+    fn commit(&mut self) -> std::result::Result<(), DbCopyError> {
+        if let BatchTxn::Owned(_, txn) = &mut self.txn {
+            let Some(txn) = txn.take() else {
+                return Ok(());
+            };
+            txn.commit()
+                .map_err(|err| DbCopyError::CommitFailed(DatabaseError::new("commit", err)))?;
+        }
+        Ok(())
+    }
+
+    /// Commits the full batch, whose last entry was `key` and `value`, and
+    /// starts the next one.
+    fn next(&mut self, key: &[u8], value: &[u8]) -> std::result::Result<(), DbCopyError> {
+        self.save_checkpoint(false, key, value)?;
+        self.commit()?;
+        self.open()
+    }
+}
+
+/// Shortest sleep a throttled copy takes; smaller leads are carried over.
+const THROTTLE_MIN_SLEEP: Duration = Duration::from_millis(1);
+
+/// Rate limits of a copy, applied over everything it has copied so far.
+struct Throttle {
+    entries_per_second: Option<u64>,
+    bytes_per_second: Option<u64>,
+    started: Instant,
+    entries: Cell<u64>,
+    bytes: Cell<u64>,
+}
+
+impl Throttle {
+    /// Counts a copied entry of `size` bytes and sleeps if the copy is
+    /// ahead of the configured rates.
+    fn record(&self, size: usize) {
+        if self.entries_per_second.is_none() && self.bytes_per_second.is_none() {
+            return;
+        }
+        self.entries.set(self.entries.get() + 1);
+        self.bytes.set(self.bytes.get() + size as u64);
+
+        let due = |copied: u64, rate: Option<u64>| {
+            rate.map_or(Duration::ZERO, |rate| {
+                Duration::from_secs_f64(copied as f64 / rate as f64)
+            })
+        };
+        let due = due(self.entries.get(), self.entries_per_second)
+            .max(due(self.bytes.get(), self.bytes_per_second));
+        let ahead = due.saturating_sub(self.started.elapsed());
+        if ahead >= THROTTLE_MIN_SLEEP {
+            std::thread::sleep(ahead);
+        }
+    }
+}
+
+struct TablePlan<K: redb::Key + 'static, V: redb::Value + 'static> {
+    name: String,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    hooks: EntryHooks<K, V>,
+}
+
+impl<K: redb::Key + 'static, V: redb::Value + 'static> TablePlan<K, V> {
+    fn new(table: TableDefinition<'_, K, V>) -> Self {
+        Self {
+            name: table.name().to_string(),
+            start: Bound::Unbounded,
+            end: Bound::Unbounded,
+            hooks: EntryHooks::default(),
+        }
+    }
+
+    fn with_range<'a, KR>(mut self, range: impl RangeBounds<KR>) -> Self
+    where
+        KR: Borrow<K::SelfType<'a>>,
+    {
+        self.start = encode_bound::<K, KR>(range.start_bound());
+        self.end = encode_bound::<K, KR>(range.end_bound());
+        self
+    }
+
+    fn definition(&self) -> TableDefinition<'_, K, V> {
+        TableDefinition::new(self.name.as_str())
+    }
+
+    /// Copies the step, storing `tag` as the value of its checkpoints so
+    /// steps spanning several tables can tell which one a position is in.
+    fn copy_tagged(
+        &self,
+        source: &ReadTransaction,
+        batch: &mut CopyBatch<'_>,
+        tag: &[u8],
+    ) -> std::result::Result<(), DbCopyError> {
+        let source_table = source.open_table(self.definition()).map_err(|err| {
+            DbCopyError::SourceTableOpenFailed(DatabaseError::new(self.display_name(), err))
+        })?;
+
+        let mut resume = match batch.take_resume() {
+            Some((key, _)) => Bound::Excluded(key),
+            None => self.start.clone(),
+        };
+        loop {
+            let mut full_at = None;
+            {
+                let mut destination_table =
+                    batch.txn().open_table(self.definition()).map_err(|err| {
+                        DbCopyError::DestinationTableOpenFailed(DatabaseError::new(
+                            self.display_name(),
+                            err,
+                        ))
+                    })?;
+                let range = (decode_bound::<K>(&resume), decode_bound::<K>(&self.end));
+                let iter = source_table.range(range).map_err(|err| {
+                    DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+                })?;
+
+                for entry in iter {
+                    let (key, value) = entry.map_err(|err| {
+                        DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+                    })?;
+                    // Borrowed types such as `&[u8]` decode to slices of the
+                    // source page, so unmapped entries are inserted without
+                    // being copied into owned buffers first
+                    let (key, value) = (key.value(), value.value());
+                    if !self.hooks.keep(&key, &value) {
+                        continue;
+                    }
+                    let key_bytes = K::as_bytes(&key);
+                    let (inserted, size) = match self.hooks.map(&key, &value) {
+                        Some(mapped) => (
+                            destination_table.insert(&key, V::from_bytes(&mapped)),
+                            mapped.len(),
+                        ),
+                        None => (
+                            destination_table.insert(&key, &value),
+                            V::as_bytes(&value).as_ref().len(),
+                        ),
+                    };
+                    inserted.map_err(|err| {
+                        DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+                    })?;
+                    if batch.record(key_bytes.as_ref().len() + size) {
+                        full_at = Some(key_bytes.as_ref().to_vec());
+                        break;
+                    }
+                }
+            }
+
+            match full_at {
+                Some(key) => {
+                    batch.next(&key, tag)?;
+                    resume = Bound::Excluded(key);
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Visits the encoded key and final value of every entry the step copies,
+    /// until `visit` returns false.
+    fn scan(
+        &self,
+        source: &ReadTransaction,
+        mut visit: impl FnMut(&[u8], &[u8]) -> bool,
+    ) -> std::result::Result<(), DbCopyError> {
+        let source_table = source.open_table(self.definition()).map_err(|err| {
+            DbCopyError::SourceTableOpenFailed(DatabaseError::new(self.display_name(), err))
+        })?;
+        let range = (decode_bound::<K>(&self.start), decode_bound::<K>(&self.end));
+        let iter = source_table.range(range).map_err(|err| {
+            DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+        })?;
+
+        for entry in iter {
+            let (key, value) = entry.map_err(|err| {
+                DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+            })?;
+            let (key, value) = (key.value(), value.value());
+            if !self.hooks.keep(&key, &value) {
+                continue;
+            }
+            let keep_going = match self.hooks.map(&key, &value) {
+                Some(mapped) => visit(K::as_bytes(&key).as_ref(), &mapped),
+                None => visit(K::as_bytes(&key).as_ref(), V::as_bytes(&value).as_ref()),
+            };
+            if !keep_going {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn encode_bound<'a, K, KR>(bound: Bound<&KR>) -> Bound<Vec<u8>>
+where
+    K: redb::Key + 'static,
+    KR: Borrow<K::SelfType<'a>>,
+{
+    match bound {
+        Bound::Included(key) => Bound::Included(K::as_bytes(key.borrow()).as_ref().to_vec()),
+        Bound::Excluded(key) => Bound::Excluded(K::as_bytes(key.borrow()).as_ref().to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+fn decode_bound<K: redb::Key + 'static>(bound: &Bound<Vec<u8>>) -> Bound<K::SelfType<'_>> {
+    match bound {
+        Bound::Included(bytes) => Bound::Included(K::from_bytes(bytes)),
+        Bound::Excluded(bytes) => Bound::Excluded(K::from_bytes(bytes)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+type EntryFilter<K, V> = dyn for<'k, 'v> Fn(&<K as redb::Value>::SelfType<'k>, &<V as redb::Value>::SelfType<'v>) -> bool
+    + Send
+    + Sync;
+type EntryMap<K, V> = dyn for<'k, 'v> Fn(&<K as redb::Value>::SelfType<'k>, &<V as redb::Value>::SelfType<'v>) -> Vec<u8>
+    + Send
+    + Sync;
+
+/// Per-entry filter and transform applied while a step streams its entries.
+struct EntryHooks<K: redb::Value + 'static, V: redb::Value + 'static> {
+    filter: Option<Box<EntryFilter<K, V>>>,
+    // Produces the encoded replacement value
+    map: Option<Box<EntryMap<K, V>>>,
+}
+
+impl<K: redb::Value + 'static, V: redb::Value + 'static> Default for EntryHooks<K, V> {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            map: None,
+        }
+    }
+}
+
+impl<K: redb::Value + 'static, V: redb::Value + 'static> EntryHooks<K, V> {
+    fn set_filter<F>(&mut self, filter: F)
+    where
+        F: for<'k, 'v> Fn(&K::SelfType<'k>, &V::SelfType<'v>) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(Box::new(filter));
+    }
+
+    fn set_map<F>(&mut self, map: F)
+    where
+        F: for<'k, 'v> Fn(&K::SelfType<'k>, &V::SelfType<'v>) -> V + Send + Sync + 'static,
+        for<'b> V: Borrow<V::SelfType<'b>>,
+    {
+        self.map = Some(Box::new(move |key, value| {
+            V::as_bytes(map(key, value).borrow()).as_ref().to_vec()
+        }));
+    }
+
+    fn keep(&self, key: &K::SelfType<'_>, value: &V::SelfType<'_>) -> bool {
+        self.filter
+            .as_ref()
+            .map_or(true, |filter| filter(key, value))
+    }
+
+    fn map(&self, key: &K::SelfType<'_>, value: &V::SelfType<'_>) -> Option<Vec<u8>> {
+        self.map.as_ref().map(|map| map(key, value))
+    }
+}
+
+impl<K: redb::Key + 'static, V: redb::Value + 'static> CopyStep for TablePlan<K, V> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> CopyKind {
+        CopyKind::Table
+    }
+
+    fn type_names(&self) -> (String, String) {
+        (
+            K::type_name().name().to_string(),
+            V::type_name().name().to_string(),
+        )
+    }
+
+    fn preflight(&self, destination: &ReadTransaction) -> std::result::Result<bool, TableError> {
+        match destination.open_table(self.definition()) {
+            Ok(_) => Ok(true),
+            Err(TableError::TableDoesNotExist(_)) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn copy(
+        &self,
+        source: &ReadTransaction,
+        batch: &mut CopyBatch<'_>,
+    ) -> std::result::Result<(), DbCopyError> {
+        self.copy_tagged(source, batch, &[])
+    }
+
+    fn checksum(
+        &self,
+        destination: &WriteTransaction,
+    ) -> std::result::Result<(u64, u64), redb::Error> {
+        manifest::table_checksum(destination, self.definition())
+    }
+
+    fn delete(&self, destination: &WriteTransaction) -> std::result::Result<(), DbCopyError> {
+        destination
+            .delete_table(self.definition())
+            .map(|_| ())
+            .map_err(|err| {
+                DbCopyError::DestinationDeleteFailed(DatabaseError::new(self.display_name(), err))
+            })
+    }
+
+    fn estimate(&self, source: &ReadTransaction) -> std::result::Result<(u64, u64), DbCopyError> {
+        let (mut entries, mut bytes) = (0, 0);
+        self.scan(source, |key, value| {
+            entries += 1;
+            bytes += (key.len() + value.len()) as u64;
+            true
+        })?;
+        Ok((entries, bytes))
+    }
+
+    fn read_encoded(
+        &self,
+        source: &ReadTransaction,
+        sink: &mut dyn FnMut(EncodedEntries) -> bool,
+    ) -> std::result::Result<(), DbCopyError> {
+        let mut chunk = Vec::with_capacity(ENCODED_CHUNK);
+        let mut open = true;
+        self.scan(source, |key, value| {
+            chunk.push((key.to_vec(), value.to_vec()));
+            if chunk.len() == ENCODED_CHUNK {
+                open = sink(std::mem::replace(
+                    &mut chunk,
+                    Vec::with_capacity(ENCODED_CHUNK),
+                ));
+            }
+            open
+        })?;
+        if open && !chunk.is_empty() {
+            sink(chunk);
+        }
+        Ok(())
+    }
+
+    fn write_encoded(
+        &self,
+        destination: &WriteTransaction,
+        entries: &[(Vec<u8>, Vec<u8>)],
+    ) -> std::result::Result<(), DbCopyError> {
+        let mut destination_table = destination.open_table(self.definition()).map_err(|err| {
+            DbCopyError::DestinationTableOpenFailed(DatabaseError::new(self.display_name(), err))
+        })?;
+        for (key, value) in entries {
+            destination_table
+                .insert(K::from_bytes(key), V::from_bytes(value))
                 .map_err(|err| {
-                    DbCopyError::DestinationTableOpenFailed(format!(
-                        "{}: {}",
-                        self.display_name(),
-                        err
-                    ))
+                    DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+                })?;
+        }
+        Ok(())
+    }
+}
+
+struct MergedTablePlan<K: redb::Key + 'static, V: redb::Value + 'static>(TablePlan<K, V>);
+
+impl<K, V> CopyStep for MergedTablePlan<K, V>
+where
+    K: redb::Key + 'static,
+    V: redb::Value + MergeableValue + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+    for<'b> V: Borrow<V::SelfType<'b>>,
+{
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn kind(&self) -> CopyKind {
+        CopyKind::Table
+    }
+
+    fn type_names(&self) -> (String, String) {
+        self.0.type_names()
+    }
+
+    fn preflight(&self, destination: &ReadTransaction) -> std::result::Result<bool, TableError> {
+        self.0.preflight(destination)
+    }
+
+    fn copy(
+        &self,
+        source: &ReadTransaction,
+        batch: &mut CopyBatch<'_>,
+    ) -> std::result::Result<(), DbCopyError> {
+        let source_table = source.open_table(self.0.definition()).map_err(|err| {
+            DbCopyError::SourceTableOpenFailed(DatabaseError::new(self.display_name(), err))
+        })?;
+
+        let mut resume = match batch.take_resume() {
+            Some((key, _)) => Bound::Excluded(key),
+            None => Bound::Unbounded,
+        };
+        loop {
+            let mut full_at = None;
+            {
+                let mut destination_table =
+                    batch.txn().open_table(self.0.definition()).map_err(|err| {
+                        DbCopyError::DestinationTableOpenFailed(DatabaseError::new(
+                            self.display_name(),
+                            err,
+                        ))
+                    })?;
+                let range = (decode_bound::<K>(&resume), Bound::Unbounded);
+                let iter = source_table.range(range).map_err(|err| {
+                    DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+                })?;
+
+                for entry in iter {
+                    let (key, value) = entry.map_err(|err| {
+                        DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+                    })?;
+                    let existing = destination_table
+                        .get(key.value())
+                        .map_err(|err| {
+                            DbCopyError::TableCopyFailed(DatabaseError::new(
+                                self.display_name(),
+                                err,
+                            ))
+                        })?
+                        .map(|guard| V::from(guard.value()));
+                    let merged = V::merge(existing, V::from(value.value()));
+                    let key_bytes = K::as_bytes(&key.value()).as_ref().to_vec();
+                    let size = key_bytes.len() + V::as_bytes(merged.borrow()).as_ref().len();
+                    destination_table
+                        .insert(key.value(), merged)
+                        .map_err(|err| {
+                            DbCopyError::TableCopyFailed(DatabaseError::new(
+                                self.display_name(),
+                                err,
+                            ))
+                        })?;
+                    if batch.record(size) {
+                        full_at = Some(key_bytes);
+                        break;
+                    }
+                }
+            }
+
+            match full_at {
+                Some(key) => {
+                    batch.next(&key, &[])?;
+                    resume = Bound::Excluded(key);
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn checksum(
+        &self,
+        destination: &WriteTransaction,
+    ) -> std::result::Result<(u64, u64), redb::Error> {
+        self.0.checksum(destination)
+    }
+
+    fn delete(&self, destination: &WriteTransaction) -> std::result::Result<(), DbCopyError> {
+        self.0.delete(destination)
+    }
+
+    fn read_encoded(
+        &self,
+        source: &ReadTransaction,
+        sink: &mut dyn FnMut(EncodedEntries) -> bool,
+    ) -> std::result::Result<(), DbCopyError> {
+        self.0.read_encoded(source, sink)
+    }
+
+    fn write_encoded(
+        &self,
+        destination: &WriteTransaction,
+        entries: &[(Vec<u8>, Vec<u8>)],
+    ) -> std::result::Result<(), DbCopyError> {
+        let mut destination_table = destination.open_table(self.0.definition()).map_err(|err| {
+            DbCopyError::DestinationTableOpenFailed(DatabaseError::new(self.display_name(), err))
+        })?;
+        for (key, value) in entries {
+            let key = K::from_bytes(key);
+            let existing = destination_table
+                .get(&key)
+                .map_err(|err| {
+                    DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+                })?
+                .map(|guard| V::from(guard.value()));
+            let merged = V::merge(existing, V::from(V::from_bytes(value)));
+            destination_table.insert(&key, merged).map_err(|err| {
+                DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+            })?;
+        }
+        Ok(())
+    }
+
+    fn estimate(&self, source: &ReadTransaction) -> std::result::Result<(u64, u64), DbCopyError> {
+        self.0.estimate(source)
+    }
+
+    fn merges_existing(&self) -> bool {
+        true
+    }
+}
+
+/// Converts an encoded source value into an encoded destination value.
+type ValueConverter = dyn Fn(&[u8]) -> Vec<u8> + Send + Sync;
+// A function type keeps the step Sync whatever the key and value types
+type ConvertedTypes<K, V1, V2> = fn() -> (K, V1, V2);
+
+struct ConvertedTablePlan<K, V1, V2>
+where
+    K: redb::Key + 'static,
+    V1: redb::Value + 'static,
+    V2: redb::Value + 'static,
+{
+    source: String,
+    name: String,
+    convert: Box<ValueConverter>,
+    types: PhantomData<ConvertedTypes<K, V1, V2>>,
+}
+
+impl<K, V1, V2> ConvertedTablePlan<K, V1, V2>
+where
+    K: redb::Key + 'static,
+    V1: redb::Value + 'static,
+    V2: redb::Value + 'static,
+{
+    fn source_definition(&self) -> TableDefinition<'_, K, V1> {
+        TableDefinition::new(self.source.as_str())
+    }
+
+    fn definition(&self) -> TableDefinition<'_, K, V2> {
+        TableDefinition::new(self.name.as_str())
+    }
+
+    /// Visits the encoded key and converted value of every source entry,
+    /// until `visit` returns false.
+    fn scan(
+        &self,
+        source: &ReadTransaction,
+        mut visit: impl FnMut(&[u8], &[u8]) -> bool,
+    ) -> std::result::Result<(), DbCopyError> {
+        let source_table = source.open_table(self.source_definition()).map_err(|err| {
+            DbCopyError::SourceTableOpenFailed(DatabaseError::new(self.display_name(), err))
+        })?;
+        let iter = source_table.iter().map_err(|err| {
+            DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+        })?;
+
+        for entry in iter {
+            let (key, value) = entry.map_err(|err| {
+                DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+            })?;
+            let converted = (self.convert)(V1::as_bytes(&value.value()).as_ref());
+            if !visit(K::as_bytes(&key.value()).as_ref(), &converted) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K, V1, V2> CopyStep for ConvertedTablePlan<K, V1, V2>
+where
+    K: redb::Key + 'static,
+    V1: redb::Value + 'static,
+    V2: redb::Value + 'static,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn source_name(&self) -> &str {
+        &self.source
+    }
+
+    fn kind(&self) -> CopyKind {
+        CopyKind::Table
+    }
+
+    fn type_names(&self) -> (String, String) {
+        (
+            K::type_name().name().to_string(),
+            V2::type_name().name().to_string(),
+        )
+    }
+
+    fn preflight(&self, destination: &ReadTransaction) -> std::result::Result<bool, TableError> {
+        match destination.open_table(self.definition()) {
+            Ok(_) => Ok(true),
+            Err(TableError::TableDoesNotExist(_)) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn copy(
+        &self,
+        source: &ReadTransaction,
+        batch: &mut CopyBatch<'_>,
+    ) -> std::result::Result<(), DbCopyError> {
+        let source_table = source.open_table(self.source_definition()).map_err(|err| {
+            DbCopyError::SourceTableOpenFailed(DatabaseError::new(self.display_name(), err))
+        })?;
+
+        let mut resume = match batch.take_resume() {
+            Some((key, _)) => Bound::Excluded(key),
+            None => Bound::Unbounded,
+        };
+        loop {
+            let mut full_at = None;
+            {
+                let mut destination_table =
+                    batch.txn().open_table(self.definition()).map_err(|err| {
+                        DbCopyError::DestinationTableOpenFailed(DatabaseError::new(
+                            self.display_name(),
+                            err,
+                        ))
+                    })?;
+                let range = (decode_bound::<K>(&resume), Bound::Unbounded);
+                let iter = source_table.range(range).map_err(|err| {
+                    DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+                })?;
+
+                for entry in iter {
+                    let (key, value) = entry.map_err(|err| {
+                        DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+                    })?;
+                    let key = key.value();
+                    let converted = (self.convert)(V1::as_bytes(&value.value()).as_ref());
+                    destination_table
+                        .insert(&key, V2::from_bytes(&converted))
+                        .map_err(|err| {
+                            DbCopyError::TableCopyFailed(DatabaseError::new(
+                                self.display_name(),
+                                err,
+                            ))
+                        })?;
+                    let key_bytes = K::as_bytes(&key);
+                    if batch.record(key_bytes.as_ref().len() + converted.len()) {
+                        full_at = Some(key_bytes.as_ref().to_vec());
+                        break;
+                    }
+                }
+            }
+
+            match full_at {
+                Some(key) => {
+                    batch.next(&key, &[])?;
+                    resume = Bound::Excluded(key);
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn checksum(
+        &self,
+        destination: &WriteTransaction,
+    ) -> std::result::Result<(u64, u64), redb::Error> {
+        manifest::table_checksum(destination, self.definition())
+    }
+
+    fn delete(&self, destination: &WriteTransaction) -> std::result::Result<(), DbCopyError> {
+        destination
+            .delete_table(self.definition())
+            .map(|_| ())
+            .map_err(|err| {
+                DbCopyError::DestinationDeleteFailed(DatabaseError::new(self.display_name(), err))
+            })
+    }
+
+    fn estimate(&self, source: &ReadTransaction) -> std::result::Result<(u64, u64), DbCopyError> {
+        let (mut entries, mut bytes) = (0, 0);
+        self.scan(source, |key, value| {
+            entries += 1;
+            bytes += (key.len() + value.len()) as u64;
+            true
+        })?;
+        Ok((entries, bytes))
+    }
+
+    fn read_encoded(
+        &self,
+        source: &ReadTransaction,
+        sink: &mut dyn FnMut(EncodedEntries) -> bool,
+    ) -> std::result::Result<(), DbCopyError> {
+        let mut chunk = Vec::with_capacity(ENCODED_CHUNK);
+        let mut open = true;
+        self.scan(source, |key, value| {
+            chunk.push((key.to_vec(), value.to_vec()));
+            if chunk.len() == ENCODED_CHUNK {
+                open = sink(std::mem::replace(
+                    &mut chunk,
+                    Vec::with_capacity(ENCODED_CHUNK),
+                ));
+            }
+            open
+        })?;
+        if open && !chunk.is_empty() {
+            sink(chunk);
+        }
+        Ok(())
+    }
+
+    fn write_encoded(
+        &self,
+        destination: &WriteTransaction,
+        entries: &[(Vec<u8>, Vec<u8>)],
+    ) -> std::result::Result<(), DbCopyError> {
+        let mut destination_table = destination.open_table(self.definition()).map_err(|err| {
+            DbCopyError::DestinationTableOpenFailed(DatabaseError::new(self.display_name(), err))
+        })?;
+        for (key, value) in entries {
+            destination_table
+                .insert(K::from_bytes(key), V2::from_bytes(value))
+                .map_err(|err| {
+                    DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
                 })?;
+        }
+        Ok(())
+    }
+}
+
+struct MultimapPlan<K: redb::Key + 'static, V: redb::Key + 'static> {
+    name: String,
+    hooks: EntryHooks<K, V>,
+}
+
+impl<K: redb::Key + 'static, V: redb::Key + 'static> MultimapPlan<K, V> {
+    fn new(table: MultimapTableDefinition<'_, K, V>) -> Self {
+        Self {
+            name: table.name().to_string(),
+            hooks: EntryHooks::default(),
+        }
+    }
+
+    fn definition(&self) -> MultimapTableDefinition<'_, K, V> {
+        MultimapTableDefinition::new(self.name.as_str())
+    }
+
+    /// Visits the encoded key and final value of every pair the step copies,
+    /// until `visit` returns false.
+    fn scan(
+        &self,
+        source: &ReadTransaction,
+        mut visit: impl FnMut(&[u8], &[u8]) -> bool,
+    ) -> std::result::Result<(), DbCopyError> {
+        let source_table = source
+            .open_multimap_table(self.definition())
+            .map_err(|err| {
+                DbCopyError::SourceTableOpenFailed(DatabaseError::new(self.display_name(), err))
+            })?;
         let iter = source_table.iter().map_err(|err| {
-            DbCopyError::TableCopyFailed(format!("{}: {}", self.display_name(), err))
+            DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
         })?;
 
         for entry in iter {
             let (key, values) = entry.map_err(|err| {
-                DbCopyError::TableCopyFailed(format!("{}: {}", self.display_name(), err))
+                DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
             })?;
+            let key = key.value();
             for value in values {
                 let value = value.map_err(|err| {
-                    DbCopyError::TableCopyFailed(format!("{}: {}", self.display_name(), err))
+                    DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
                 })?;
-                destination_table
-                    .insert(key.value(), value.value())
+                let value = value.value();
+                if !self.hooks.keep(&key, &value) {
+                    continue;
+                }
+                let keep_going = match self.hooks.map(&key, &value) {
+                    Some(mapped) => visit(K::as_bytes(&key).as_ref(), &mapped),
+                    None => visit(K::as_bytes(&key).as_ref(), V::as_bytes(&value).as_ref()),
+                };
+                if !keep_going {
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<K: redb::Key + 'static, V: redb::Key + 'static> CopyStep for MultimapPlan<K, V> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> CopyKind {
+        CopyKind::Multimap
+    }
+
+    fn type_names(&self) -> (String, String) {
+        (
+            K::type_name().name().to_string(),
+            V::type_name().name().to_string(),
+        )
+    }
+
+    fn preflight(&self, destination: &ReadTransaction) -> std::result::Result<bool, TableError> {
+        match destination.open_multimap_table(self.definition()) {
+            Ok(_) => Ok(true),
+            Err(TableError::TableDoesNotExist(_)) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn copy(
+        &self,
+        source: &ReadTransaction,
+        batch: &mut CopyBatch<'_>,
+    ) -> std::result::Result<(), DbCopyError> {
+        let source_table = source
+            .open_multimap_table(self.definition())
+            .map_err(|err| {
+                DbCopyError::SourceTableOpenFailed(DatabaseError::new(self.display_name(), err))
+            })?;
+
+        // Encoded key and value of the last entry copied before a commit
+        let mut resume = batch.take_resume();
+        loop {
+            let mut full_at = None;
+            {
+                let mut destination_table = batch
+                    .txn()
+                    .open_multimap_table(self.definition())
                     .map_err(|err| {
-                        DbCopyError::TableCopyFailed(format!("{}: {}", self.display_name(), err))
+                        DbCopyError::DestinationTableOpenFailed(DatabaseError::new(
+                            self.display_name(),
+                            err,
+                        ))
                     })?;
+                let start = match &resume {
+                    Some((key, _)) => Bound::Included(K::from_bytes(key)),
+                    None => Bound::Unbounded,
+                };
+                let iter = source_table
+                    .range((start, Bound::Unbounded))
+                    .map_err(|err| {
+                        DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+                    })?;
+
+                'entries: for entry in iter {
+                    let (key, values) = entry.map_err(|err| {
+                        DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+                    })?;
+                    let key = key.value();
+                    let key_bytes = K::as_bytes(&key);
+                    let resume_value = resume.as_ref().and_then(|(resume_key, value)| {
+                        (K::compare(key_bytes.as_ref(), resume_key) == Ordering::Equal)
+                            .then_some(value)
+                    });
+
+                    for value in values {
+                        let value = value.map_err(|err| {
+                            DbCopyError::TableCopyFailed(DatabaseError::new(
+                                self.display_name(),
+                                err,
+                            ))
+                        })?;
+                        let value = value.value();
+                        let value_bytes = V::as_bytes(&value);
+                        if resume_value.is_some_and(|resume_value| {
+                            V::compare(value_bytes.as_ref(), resume_value) != Ordering::Greater
+                        }) {
+                            continue;
+                        }
+                        if !self.hooks.keep(&key, &value) {
+                            continue;
+                        }
+                        let (inserted, size) = match self.hooks.map(&key, &value) {
+                            Some(mapped) => (
+                                destination_table.insert(&key, V::from_bytes(&mapped)),
+                                mapped.len(),
+                            ),
+                            None => (
+                                destination_table.insert(&key, &value),
+                                value_bytes.as_ref().len(),
+                            ),
+                        };
+                        inserted.map_err(|err| {
+                            DbCopyError::TableCopyFailed(DatabaseError::new(
+                                self.display_name(),
+                                err,
+                            ))
+                        })?;
+                        if batch.record(key_bytes.as_ref().len() + size) {
+                            full_at =
+                                Some((key_bytes.as_ref().to_vec(), value_bytes.as_ref().to_vec()));
+                            break 'entries;
+                        }
+                    }
+                }
+            }
+
+            match full_at {
+                Some((key, value)) => {
+                    batch.next(&key, &value)?;
+                    resume = Some((key, value));
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn checksum(
+        &self,
+        destination: &WriteTransaction,
+    ) -> std::result::Result<(u64, u64), redb::Error> {
+        manifest::multimap_checksum(destination, self.definition())
+    }
+
+    fn delete(&self, destination: &WriteTransaction) -> std::result::Result<(), DbCopyError> {
+        destination
+            .delete_multimap_table(self.definition())
+            .map(|_| ())
+            .map_err(|err| {
+                DbCopyError::DestinationDeleteFailed(DatabaseError::new(self.display_name(), err))
+            })
+    }
+
+    fn estimate(&self, source: &ReadTransaction) -> std::result::Result<(u64, u64), DbCopyError> {
+        let (mut entries, mut bytes) = (0, 0);
+        self.scan(source, |key, value| {
+            entries += 1;
+            bytes += (key.len() + value.len()) as u64;
+            true
+        })?;
+        Ok((entries, bytes))
+    }
+
+    fn read_encoded(
+        &self,
+        source: &ReadTransaction,
+        sink: &mut dyn FnMut(EncodedEntries) -> bool,
+    ) -> std::result::Result<(), DbCopyError> {
+        let mut chunk = Vec::with_capacity(ENCODED_CHUNK);
+        let mut open = true;
+        self.scan(source, |key, value| {
+            chunk.push((key.to_vec(), value.to_vec()));
+            if chunk.len() == ENCODED_CHUNK {
+                open = sink(std::mem::replace(
+                    &mut chunk,
+                    Vec::with_capacity(ENCODED_CHUNK),
+                ));
             }
+            open
+        })?;
+        if open && !chunk.is_empty() {
+            sink(chunk);
         }
+        Ok(())
+    }
 
+    fn write_encoded(
+        &self,
+        destination: &WriteTransaction,
+        entries: &[(Vec<u8>, Vec<u8>)],
+    ) -> std::result::Result<(), DbCopyError> {
+        let mut destination_table =
+            destination
+                .open_multimap_table(self.definition())
+                .map_err(|err| {
+                    DbCopyError::DestinationTableOpenFailed(DatabaseError::new(
+                        self.display_name(),
+                        err,
+                    ))
+                })?;
+        for (key, value) in entries {
+            destination_table
+                .insert(K::from_bytes(key), V::from_bytes(value))
+                .map_err(|err| {
+                    DbCopyError::TableCopyFailed(DatabaseError::new(self.display_name(), err))
+                })?;
+        }
         Ok(())
     }
 }
@@ -0,0 +1,243 @@
+//! Parallel database copy.
+//!
+//! Plan steps write distinct tables, so their source tables can be read and
+//! encoded on worker threads. The calling thread is the single writer and
+//! applies the encoded chunks to one destination write transaction.
+
+use super::{
+    check_destination, CopyMode, CopyPlan, CopyProgress, CopyReport, DbCopyError, EncodedEntries,
+    TableCopyStats, PROGRESS_INTERVAL,
+};
+use crate::error::DatabaseError;
+use crate::Result;
+use redb::{Database, ReadableDatabase};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Message from a reader thread to the writer, tagged with the step index.
+enum Message {
+    Chunk(usize, EncodedEntries),
+    Done(usize),
+    Failed(DbCopyError),
+}
+
+/// Copy all tables described by `plan`, reading source tables on worker threads.
+///
+/// Each step is read by one of `threads` workers, which apply the step's
+/// range, filter and transform and send the encoded entries to the calling
+/// thread. The calling thread writes everything in a single destination
+/// transaction, so the destination ends up as with `copy_database`. The
+/// plan's `CopyMode` and progress callback apply as usual, but batch limits,
+/// throttles, checkpoints and manifests are ignored, and any failure aborts
+/// the copy whatever the plan's `ErrorPolicy`. Progress is reported per
+/// received chunk, so callbacks for different tables interleave.
+///
+/// # Arguments
+/// * `source` - The database to copy from
+/// * `destination` - The database to copy into
+/// * `plan` - The tables to copy
+/// * `threads` - Number of reader threads, at least one is used
+///
+/// # Returns
+/// Per-table statistics of the copy, in plan order
+pub fn copy_database_parallel(
+    source: &Database,
+    destination: &Database,
+    plan: &CopyPlan,
+    threads: usize,
+) -> Result<CopyReport> {
+    let started = Instant::now();
+    let source_read = source
+        .begin_read()
+        .map_err(|err| DbCopyError::TransactionFailed(DatabaseError::new("source read", err)))?;
+    plan.check_unplanned(&source_read)?;
+    let destination_read = destination.begin_read().map_err(|err| {
+        DbCopyError::TransactionFailed(DatabaseError::new("destination read", err))
+    })?;
+    let existing = check_destination(plan, &destination_read, &HashMap::new())?;
+    drop(destination_read);
+
+    let destination_write = destination.begin_write().map_err(|err| {
+        DbCopyError::TransactionFailed(DatabaseError::new("destination write", err))
+    })?;
+
+    let mut tables = Vec::with_capacity(plan.steps.len());
+    let mut pending = Vec::new();
+    for (index, (step, exists)) in plan.steps.iter().zip(existing).enumerate() {
+        if exists {
+            match plan.mode {
+                CopyMode::Skip => {
+                    tables.push(TableCopyStats::skipped(step.display_name()));
+                    continue;
+                }
+                CopyMode::Overwrite => step.delete(&destination_write)?,
+                CopyMode::Fail | CopyMode::Append => {}
+            }
+        }
+        tables.push(TableCopyStats {
+            table: step.display_name(),
+            entries: 0,
+            bytes: 0,
+            elapsed: Duration::ZERO,
+            skipped: false,
+            error: None,
+        });
+        pending.push(index);
+    }
+
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::sync_channel(threads.max(1) * 2);
+    std::thread::scope(|scope| {
+        for _ in 0..threads.max(1).min(pending.len()) {
+            let sender = sender.clone();
+            // The progress callback isn't Sync, so workers only see the steps
+            let (next, pending, source_read, steps) = (&next, &pending, &source_read, &plan.steps);
+            scope.spawn(move || {
+                while let Some(&index) = pending.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let result = steps[index].read_encoded(source_read, &mut |chunk| {
+                        sender.send(Message::Chunk(index, chunk)).is_ok()
+                    });
+                    let message = match result {
+                        Ok(()) => Message::Done(index),
+                        Err(err) => Message::Failed(err),
+                    };
+                    if sender.send(message).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // Returning early drops the receiver, which stops the workers
+        let mut first_seen: Vec<Option<Instant>> = vec![None; plan.steps.len()];
+        for message in receiver {
+            let (index, chunk, finished) = match message {
+                Message::Chunk(index, chunk) => (index, chunk, false),
+                Message::Done(index) => (index, Vec::new(), true),
+                Message::Failed(err) => return Err(err),
+            };
+            let step = &plan.steps[index];
+            let first_seen = *first_seen[index].get_or_insert_with(Instant::now);
+            // Writing the empty final chunk creates tables with no entries
+            step.write_encoded(&destination_write, &chunk)?;
+
+            let stats = &mut tables[index];
+            let before = stats.entries;
+            stats.entries += chunk.len() as u64;
+            stats.bytes += chunk
+                .iter()
+                .map(|(key, value)| (key.len() + value.len()) as u64)
+                .sum::<u64>();
+            stats.elapsed = first_seen.elapsed();
+            if let Some(progress) = &plan.progress {
+                if finished || stats.entries / PROGRESS_INTERVAL > before / PROGRESS_INTERVAL {
+                    progress(&CopyProgress {
+                        table: &stats.table,
+                        entries: stats.entries,
+                        bytes: stats.bytes,
+                        elapsed: stats.elapsed,
+                        finished,
+                    });
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    destination_write
+        .commit()
+        .map_err(|err| DbCopyError::CommitFailed(DatabaseError::new("commit", err)))?;
+
+    Ok(CopyReport {
+        tables,
+        elapsed: started.elapsed(),
+        compaction: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::copy_database_parallel;
+    use crate::dbcopy::{copy_database, CopyPlan, TableStep};
+    use redb::{
+        Database, MultimapTableDefinition, ReadableDatabase, ReadableMultimapTable, ReadableTable,
+        TableDefinition,
+    };
+    use tempfile::NamedTempFile;
+
+    const COUNTS: TableDefinition<u64, u64> = TableDefinition::new("counts");
+    const EVENS: TableDefinition<u64, u64> = TableDefinition::new("evens");
+    const EMPTY: TableDefinition<u64, u64> = TableDefinition::new("empty");
+    const LINKS: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("links");
+
+    fn plan() -> CopyPlan {
+        CopyPlan::new()
+            .table(COUNTS)
+            .table_step(TableStep::new(EVENS).filter(|key, _| key % 2 == 0))
+            .table(EMPTY)
+            .multimap(LINKS)
+    }
+
+    fn contents(db: &Database) -> Vec<(&'static str, u64, u64)> {
+        let read_txn = db.begin_read().unwrap();
+        let mut found = Vec::new();
+        for (name, table) in [("counts", COUNTS), ("evens", EVENS), ("empty", EMPTY)] {
+            for entry in read_txn.open_table(table).unwrap().iter().unwrap() {
+                let (key, value) = entry.unwrap();
+                found.push((name, key.value(), value.value()));
+            }
+        }
+        for entry in read_txn.open_multimap_table(LINKS).unwrap().iter().unwrap() {
+            let (key, values) = entry.unwrap();
+            for value in values {
+                found.push(("links", key.value(), value.unwrap().value()));
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn matches_sequential_copy() {
+        let source_file = NamedTempFile::new().unwrap();
+        let source = Database::create(source_file.path()).unwrap();
+        let write_txn = source.begin_write().unwrap();
+        {
+            let mut counts = write_txn.open_table(COUNTS).unwrap();
+            let mut evens = write_txn.open_table(EVENS).unwrap();
+            let mut links = write_txn.open_multimap_table(LINKS).unwrap();
+            write_txn.open_table(EMPTY).unwrap();
+            for key in 0..5000u64 {
+                counts.insert(key, key * 3).unwrap();
+                evens.insert(key, key).unwrap();
+                links.insert(key % 7, key).unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+
+        let sequential_file = NamedTempFile::new().unwrap();
+        let sequential = Database::create(sequential_file.path()).unwrap();
+        let expected = copy_database(&source, &sequential, &plan()).unwrap();
+
+        for threads in [0, 2, 8] {
+            let parallel_file = NamedTempFile::new().unwrap();
+            let parallel = Database::create(parallel_file.path()).unwrap();
+            let report = copy_database_parallel(&source, &parallel, &plan(), threads).unwrap();
+
+            assert_eq!(contents(&parallel), contents(&sequential));
+            assert_eq!(report.total_entries(), expected.total_entries());
+            assert_eq!(report.total_bytes(), expected.total_bytes());
+            assert_eq!(report.tables.len(), 4);
+        }
+
+        // Missing source tables fail the whole copy without committing
+        let failed_file = NamedTempFile::new().unwrap();
+        let failed = Database::create(failed_file.path()).unwrap();
+        let missing: TableDefinition<u64, u64> = TableDefinition::new("missing");
+        let plan = plan().table(missing);
+        assert!(copy_database_parallel(&source, &failed, &plan, 2).is_err());
+        assert!(failed.begin_read().unwrap().open_table(COUNTS).is_err());
+    }
+}
@@ -1,6 +1,20 @@
-use super::{copy_database, CopyPlan, DbCopyError};
+use super::{
+    copy_and_compact, copy_database, copy_database_dry_run, copy_database_labeled, copy_databases,
+    copy_into_txn, export_to_writer, import_from_reader, read_manifest, resume_copy, CopyMode,
+    CopyPlan, DbCopyError, ErrorPolicy, MultimapStep, TableCopyEstimate, TableStep,
+    PROGRESS_INTERVAL,
+};
+use crate::key_buckets::{BucketedKey, KeyBuilder};
+use crate::roaring::RoaringValue;
+use crate::table_buckets::TableBucketBuilder;
 use crate::Error;
-use redb::{Database, MultimapTableDefinition, ReadableDatabase, TableDefinition};
+use redb::{
+    Database, MultimapTableDefinition, ReadableDatabase, ReadableMultimapTable, ReadableTable,
+    ReadableTableMetadata, TableDefinition,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 use tempfile::NamedTempFile;
 
 const USERS: TableDefinition<&str, u64> = TableDefinition::new("users");
@@ -98,3 +112,1029 @@ fn destination_conflicts_detected_before_copy() {
         other => panic!("unexpected result: {other:?}"),
     }
 }
+
+fn seeded_pair() -> (NamedTempFile, NamedTempFile, Database, Database) {
+    let source_file = NamedTempFile::new().unwrap();
+    let dest_file = NamedTempFile::new().unwrap();
+    let source = Database::create(source_file.path()).unwrap();
+    let dest = Database::create(dest_file.path()).unwrap();
+
+    let source_txn = source.begin_write().unwrap();
+    {
+        let mut users = source_txn.open_table(USERS).unwrap();
+        users.insert("alice", 1).unwrap();
+        users.insert("bob", 2).unwrap();
+
+        let mut tags = source_txn.open_multimap_table(TAGS).unwrap();
+        tags.insert("alice", 10).unwrap();
+    }
+    source_txn.commit().unwrap();
+
+    let dest_txn = dest.begin_write().unwrap();
+    {
+        let mut users = dest_txn.open_table(USERS).unwrap();
+        users.insert("alice", 99).unwrap();
+        users.insert("existing", 7).unwrap();
+
+        let mut tags = dest_txn.open_multimap_table(TAGS).unwrap();
+        tags.insert("alice", 5).unwrap();
+    }
+    dest_txn.commit().unwrap();
+
+    (source_file, dest_file, source, dest)
+}
+
+fn dest_state(dest: &Database) -> (Vec<(String, u64)>, Vec<u64>) {
+    let read_txn = dest.begin_read().unwrap();
+    let users = read_txn
+        .open_table(USERS)
+        .unwrap()
+        .iter()
+        .unwrap()
+        .map(|entry| {
+            let (key, value) = entry.unwrap();
+            (key.value().to_string(), value.value())
+        })
+        .collect();
+    let tags = read_txn
+        .open_multimap_table(TAGS)
+        .unwrap()
+        .get("alice")
+        .unwrap()
+        .map(|value| value.unwrap().value())
+        .collect();
+    (users, tags)
+}
+
+#[test]
+fn skip_mode_leaves_existing_tables() {
+    let (_source_file, _dest_file, source, dest) = seeded_pair();
+    let source_txn = source.begin_write().unwrap();
+    {
+        let mut blobs = source_txn.open_table(BLOBS).unwrap();
+        blobs.insert("one", b"first".as_slice()).unwrap();
+    }
+    source_txn.commit().unwrap();
+
+    let plan = CopyPlan::new()
+        .table(USERS)
+        .multimap(TAGS)
+        .table(BLOBS)
+        .mode(CopyMode::Skip);
+    copy_database(&source, &dest, &plan).unwrap();
+
+    let (users, tags) = dest_state(&dest);
+    assert_eq!(
+        users,
+        vec![("alice".to_string(), 99), ("existing".to_string(), 7)]
+    );
+    assert_eq!(tags, vec![5]);
+
+    // Missing tables are still copied
+    let read_txn = dest.begin_read().unwrap();
+    assert_eq!(read_txn.open_table(BLOBS).unwrap().len().unwrap(), 1);
+}
+
+#[test]
+fn overwrite_mode_replaces_existing_tables() {
+    let (_source_file, _dest_file, source, dest) = seeded_pair();
+
+    let plan = CopyPlan::new()
+        .table(USERS)
+        .multimap(TAGS)
+        .mode(CopyMode::Overwrite);
+    copy_database(&source, &dest, &plan).unwrap();
+
+    let (users, tags) = dest_state(&dest);
+    assert_eq!(
+        users,
+        vec![("alice".to_string(), 1), ("bob".to_string(), 2)]
+    );
+    assert_eq!(tags, vec![10]);
+}
+
+#[test]
+fn append_mode_copies_into_existing_tables() {
+    let (_source_file, _dest_file, source, dest) = seeded_pair();
+
+    let plan = CopyPlan::new()
+        .table(USERS)
+        .multimap(TAGS)
+        .mode(CopyMode::Append);
+    copy_database(&source, &dest, &plan).unwrap();
+
+    let (users, tags) = dest_state(&dest);
+    assert_eq!(
+        users,
+        vec![
+            ("alice".to_string(), 1),
+            ("bob".to_string(), 2),
+            ("existing".to_string(), 7)
+        ]
+    );
+    assert_eq!(tags, vec![5, 10]);
+}
+
+#[test]
+fn copy_into_txn_commits_with_caller_writes() {
+    const META: TableDefinition<&str, u64> = TableDefinition::new("meta");
+    let (_source_file, _dest_file, source, dest) = seeded_pair();
+    let source_read = source.begin_read().unwrap();
+
+    // Conflicts are found through the caller's transaction
+    let write_txn = dest.begin_write().unwrap();
+    assert!(matches!(
+        copy_into_txn(
+            &source_read,
+            &write_txn,
+            &CopyPlan::new().table(USERS).allow_unplanned()
+        ),
+        Err(Error::DbCopy(DbCopyError::DestinationTablesExist(_)))
+    ));
+
+    // Nothing lands until the caller commits
+    let plan = CopyPlan::new()
+        .table(USERS)
+        .multimap(TAGS)
+        .mode(CopyMode::Overwrite)
+        .batch_entries(1);
+    let report = copy_into_txn(&source_read, &write_txn, &plan).unwrap();
+    assert_eq!(report.total_entries(), 3);
+    write_txn.abort().unwrap();
+    assert_eq!(dest_state(&dest).1, vec![5]);
+
+    let write_txn = dest.begin_write().unwrap();
+    copy_into_txn(&source_read, &write_txn, &plan).unwrap();
+    write_txn
+        .open_table(META)
+        .unwrap()
+        .insert("copied", 1)
+        .unwrap();
+    write_txn.commit().unwrap();
+
+    let (users, tags) = dest_state(&dest);
+    assert_eq!(
+        users,
+        vec![("alice".to_string(), 1), ("bob".to_string(), 2)]
+    );
+    assert_eq!(tags, vec![10]);
+    let read_txn = dest.begin_read().unwrap();
+    let meta = read_txn.open_table(META).unwrap();
+    assert_eq!(meta.get("copied").unwrap().unwrap().value(), 1);
+}
+
+#[test]
+fn merged_table_combines_existing_values() {
+    const VISITS: TableDefinition<&str, RoaringValue> = TableDefinition::new("visits");
+
+    let source_file = NamedTempFile::new().unwrap();
+    let dest_file = NamedTempFile::new().unwrap();
+    let source = Database::create(source_file.path()).unwrap();
+    let dest = Database::create(dest_file.path()).unwrap();
+
+    let source_txn = source.begin_write().unwrap();
+    {
+        let mut visits = source_txn.open_table(VISITS).unwrap();
+        visits
+            .insert("alice", RoaringValue::from_iter([1, 2]))
+            .unwrap();
+        visits.insert("bob", RoaringValue::from_iter([7])).unwrap();
+    }
+    source_txn.commit().unwrap();
+
+    let dest_txn = dest.begin_write().unwrap();
+    {
+        let mut visits = dest_txn.open_table(VISITS).unwrap();
+        visits
+            .insert("alice", RoaringValue::from_iter([2, 3]))
+            .unwrap();
+        visits
+            .insert("carol", RoaringValue::from_iter([9]))
+            .unwrap();
+    }
+    dest_txn.commit().unwrap();
+
+    // Existing merged tables are not conflicts, even in the default mode
+    let plan = CopyPlan::new().table_merged(VISITS);
+    copy_database(&source, &dest, &plan).unwrap();
+
+    let read_txn = dest.begin_read().unwrap();
+    let visits = read_txn.open_table(VISITS).unwrap();
+    let ids = |key: &str| -> Vec<u64> {
+        visits
+            .get(key)
+            .unwrap()
+            .unwrap()
+            .value()
+            .bitmap()
+            .iter()
+            .collect()
+    };
+    assert_eq!(ids("alice"), vec![1, 2, 3]);
+    assert_eq!(ids("bob"), vec![7]);
+    assert_eq!(ids("carol"), vec![9]);
+}
+
+#[test]
+fn table_range_copies_key_subrange() {
+    let source_file = NamedTempFile::new().unwrap();
+    let low_file = NamedTempFile::new().unwrap();
+    let high_file = NamedTempFile::new().unwrap();
+    let source = Database::create(source_file.path()).unwrap();
+    let low = Database::create(low_file.path()).unwrap();
+    let high = Database::create(high_file.path()).unwrap();
+
+    let source_txn = source.begin_write().unwrap();
+    {
+        let mut users = source_txn.open_table(USERS).unwrap();
+        for (name, id) in [("alice", 1), ("bob", 2), ("mallory", 3), ("zoe", 4)] {
+            users.insert(name, id).unwrap();
+        }
+    }
+    source_txn.commit().unwrap();
+
+    copy_database(&source, &low, &CopyPlan::new().table_range(USERS, .."m")).unwrap();
+    copy_database(&source, &high, &CopyPlan::new().table_range(USERS, "m"..)).unwrap();
+
+    let keys = |db: &Database| -> Vec<String> {
+        let read_txn = db.begin_read().unwrap();
+        let users = read_txn.open_table(USERS).unwrap();
+        users
+            .iter()
+            .unwrap()
+            .map(|entry| entry.unwrap().0.value().to_string())
+            .collect()
+    };
+    assert_eq!(keys(&low), vec!["alice", "bob"]);
+    assert_eq!(keys(&high), vec!["mallory", "zoe"]);
+}
+
+#[test]
+fn step_filter_and_map_apply_per_entry() {
+    let source_file = NamedTempFile::new().unwrap();
+    let dest_file = NamedTempFile::new().unwrap();
+    let source = Database::create(source_file.path()).unwrap();
+    let dest = Database::create(dest_file.path()).unwrap();
+
+    let source_txn = source.begin_write().unwrap();
+    {
+        let mut users = source_txn.open_table(USERS).unwrap();
+        for (name, id) in [("alice", 1), ("bob", 2), ("carol", 3)] {
+            users.insert(name, id).unwrap();
+        }
+
+        let mut tags = source_txn.open_multimap_table(TAGS).unwrap();
+        for tag in [10, 15, 20] {
+            tags.insert("alice", tag).unwrap();
+        }
+    }
+    source_txn.commit().unwrap();
+
+    let plan = CopyPlan::new()
+        .table_step(
+            TableStep::new(USERS)
+                .filter(|name, _| *name != "bob")
+                .map(|_, id| id * 100),
+        )
+        .multimap_step(MultimapStep::new(TAGS).filter(|_, tag| tag % 10 == 0));
+    copy_database(&source, &dest, &plan).unwrap();
+
+    let (users, tags) = dest_state(&dest);
+    assert_eq!(
+        users,
+        vec![("alice".to_string(), 100), ("carol".to_string(), 300)]
+    );
+    assert_eq!(tags, vec![10, 20]);
+}
+
+#[test]
+fn table_convert_migrates_value_types() {
+    const SCORES: TableDefinition<&str, u32> = TableDefinition::new("scores");
+    const WIDE: TableDefinition<&str, u64> = TableDefinition::new("scores_v2");
+    let source_file = NamedTempFile::new().unwrap();
+    let dest_file = NamedTempFile::new().unwrap();
+    let source = Database::create(source_file.path()).unwrap();
+    let dest = Database::create(dest_file.path()).unwrap();
+
+    let write_txn = source.begin_write().unwrap();
+    {
+        let mut scores = write_txn.open_table(SCORES).unwrap();
+        for (index, name) in ["a", "b", "c", "d", "e"].into_iter().enumerate() {
+            scores.insert(name, u32::MAX - index as u32).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let plan = CopyPlan::new()
+        .table_convert(SCORES, WIDE, |score: u32| u64::from(score) * 2)
+        .batch_entries(2);
+    assert!(plan.unplanned_tables(&source).unwrap().is_empty());
+    let report = copy_database(&source, &dest, &plan).unwrap();
+    assert_eq!(report.tables[0].table, "table scores_v2");
+    assert_eq!(report.total_entries(), 5);
+    assert_eq!(report.total_bytes(), 5 * (1 + 8));
+
+    let read_txn = dest.begin_read().unwrap();
+    let wide = read_txn.open_table(WIDE).unwrap();
+    assert_eq!(
+        wide.get("e").unwrap().unwrap().value(),
+        u64::from(u32::MAX - 4) * 2
+    );
+    assert_eq!(wide.len().unwrap(), 5);
+    assert!(read_txn.open_table(SCORES).is_err());
+}
+
+#[test]
+fn unplanned_tables_lists_missing_definitions() {
+    let source_file = NamedTempFile::new().unwrap();
+    let source = Database::create(source_file.path()).unwrap();
+
+    let write_txn = source.begin_write().unwrap();
+    {
+        write_txn.open_table(USERS).unwrap();
+        write_txn.open_table(BLOBS).unwrap();
+        write_txn.open_multimap_table(TAGS).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let plan = CopyPlan::new().table(USERS);
+    assert_eq!(
+        plan.unplanned_tables(&source).unwrap(),
+        vec!["table blobs", "multimap table tags"]
+    );
+
+    let plan = plan.table_step(TableStep::new(BLOBS)).multimap(TAGS);
+    assert!(plan.unplanned_tables(&source).unwrap().is_empty());
+}
+
+#[test]
+fn unplanned_tables_fail_the_copy_unless_allowed() {
+    let (_source_file, _dest_file, source, dest) = seeded_pair();
+
+    // Nothing is written when the plan leaves out a source table
+    let plan = CopyPlan::new().table(USERS).mode(CopyMode::Overwrite);
+    match copy_database(&source, &dest, &plan) {
+        Err(Error::DbCopy(DbCopyError::UnplannedTables(tables))) => {
+            assert_eq!(tables, vec!["multimap table tags"])
+        }
+        other => panic!("expected UnplannedTables, got {:?}", other.map(|_| ())),
+    }
+    assert!(dest_state(&dest)
+        .0
+        .iter()
+        .any(|(name, _)| name == "existing"));
+
+    copy_database(&source, &dest, &plan.exclude("tags")).unwrap();
+
+    let plan = CopyPlan::new()
+        .table(USERS)
+        .mode(CopyMode::Overwrite)
+        .allow_unplanned();
+    copy_database(&source, &dest, &plan).unwrap();
+    assert_eq!(
+        dest_state(&dest).0,
+        vec![("alice".to_string(), 1), ("bob".to_string(), 2)]
+    );
+}
+
+#[test]
+fn exclusions_drop_steps_and_unplanned_tables() {
+    const TMP_USERS: TableDefinition<&str, u64> = TableDefinition::new("tmp_users");
+    let (_source_file, _dest_file, source, dest) = seeded_pair();
+    let write_txn = source.begin_write().unwrap();
+    {
+        write_txn.open_table(BLOBS).unwrap();
+        write_txn
+            .open_table(TMP_USERS)
+            .unwrap()
+            .insert("x", 1)
+            .unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let plan = CopyPlan::new()
+        .table(TMP_USERS)
+        .exclude_prefix("tmp_")
+        .table(USERS)
+        .table(BLOBS)
+        .multimap(TAGS)
+        .exclude("blobs")
+        .mode(CopyMode::Overwrite);
+    assert!(plan.unplanned_tables(&source).unwrap().is_empty());
+    let report = copy_database(&source, &dest, &plan).unwrap();
+    let copied: Vec<&str> = report
+        .tables
+        .iter()
+        .map(|table| table.table.as_str())
+        .collect();
+    assert_eq!(copied, vec!["table users", "multimap table tags"]);
+
+    let read_txn = dest.begin_read().unwrap();
+    assert!(read_txn.open_table(BLOBS).is_err());
+    assert!(read_txn.open_table(TMP_USERS).is_err());
+}
+
+#[test]
+fn continue_policy_records_failed_tables() {
+    let (_source_file, _dest_file, source, _) = seeded_pair();
+    let dest_file = NamedTempFile::new().unwrap();
+    let dest = Database::create(dest_file.path()).unwrap();
+
+    // The source has no blobs table, so that step fails
+    let plan = CopyPlan::new().table(USERS).table(BLOBS).multimap(TAGS);
+    let err = copy_database(&source, &dest, &plan).unwrap_err();
+    let Error::DbCopy(DbCopyError::SourceTableOpenFailed(open_err)) = &err else {
+        panic!("unexpected error: {}", err);
+    };
+    assert!(matches!(
+        open_err.redb_error(),
+        redb::Error::TableDoesNotExist(name) if name == "blobs"
+    ));
+
+    // The redb error stays reachable through the source chain
+    let mut chain = std::error::Error::source(&err);
+    let mut reached_redb = false;
+    while let Some(cause) = chain {
+        reached_redb |= cause.downcast_ref::<redb::Error>().is_some();
+        chain = cause.source();
+    }
+    assert!(reached_redb);
+    assert!(dest.begin_read().unwrap().open_table(USERS).is_err());
+
+    let plan = plan.on_error(ErrorPolicy::Continue);
+    let report = copy_database(&source, &dest, &plan).unwrap();
+    let errors: Vec<bool> = report
+        .tables
+        .iter()
+        .map(|table| table.error.is_some())
+        .collect();
+    assert_eq!(errors, vec![false, true, false]);
+    assert_eq!(report.total_entries(), 3);
+
+    let (users, tags) = dest_state(&dest);
+    assert_eq!(
+        users,
+        vec![("alice".to_string(), 1), ("bob".to_string(), 2)]
+    );
+    assert_eq!(tags, vec![10]);
+    assert!(dest.begin_read().unwrap().open_table(BLOBS).is_err());
+}
+
+#[test]
+fn copy_and_compact_reports_destination_space() {
+    const LOG: TableDefinition<u64, &[u8]> = TableDefinition::new("log");
+    let (_source_file, _dest_file, source, mut dest) = seeded_pair();
+
+    // Leave plenty of freed pages behind in the destination
+    let write_txn = dest.begin_write().unwrap();
+    {
+        let mut log = write_txn.open_table(LOG).unwrap();
+        for key in 0..2000u64 {
+            log.insert(key, [1u8; 512].as_slice()).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+    let write_txn = dest.begin_write().unwrap();
+    write_txn.delete_table(LOG).unwrap();
+    write_txn.commit().unwrap();
+
+    let plan = CopyPlan::new()
+        .table(USERS)
+        .multimap(TAGS)
+        .mode(CopyMode::Overwrite);
+    let report = copy_and_compact(&source, &mut dest, &plan).unwrap();
+    let compaction = report.compaction.unwrap();
+    assert!(compaction.bytes_after < compaction.bytes_before);
+    assert_eq!(dest_state(&dest).0.len(), 2);
+
+    assert!(copy_database(&source, &dest, &plan)
+        .unwrap()
+        .compaction
+        .is_none());
+}
+
+#[test]
+fn batched_copy_resumes_across_commits() {
+    const COUNTS: TableDefinition<u64, u64> = TableDefinition::new("counts");
+    const LINKS: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("links");
+
+    let source_file = NamedTempFile::new().unwrap();
+    let source = Database::create(source_file.path()).unwrap();
+    let source_txn = source.begin_write().unwrap();
+    {
+        let mut counts = source_txn.open_table(COUNTS).unwrap();
+        let mut links = source_txn.open_multimap_table(LINKS).unwrap();
+        for key in 0..50u64 {
+            counts.insert(key, key * 2).unwrap();
+            for link in 0..key % 4 {
+                links.insert(key, link).unwrap();
+            }
+        }
+    }
+    source_txn.commit().unwrap();
+
+    type Entries = Vec<(u64, u64)>;
+    let contents = |db: &Database| -> (Entries, Entries) {
+        let read_txn = db.begin_read().unwrap();
+        let counts = read_txn
+            .open_table(COUNTS)
+            .unwrap()
+            .iter()
+            .unwrap()
+            .map(|entry| {
+                let (key, value) = entry.unwrap();
+                (key.value(), value.value())
+            })
+            .collect();
+        let mut links = Vec::new();
+        for entry in read_txn.open_multimap_table(LINKS).unwrap().iter().unwrap() {
+            let (key, values) = entry.unwrap();
+            for value in values {
+                links.push((key.value(), value.unwrap().value()));
+            }
+        }
+        (counts, links)
+    };
+
+    for plan in [
+        CopyPlan::new().batch_entries(7),
+        CopyPlan::new().batch_entries(1),
+        CopyPlan::new().batch_bytes(100),
+    ] {
+        let dest_file = NamedTempFile::new().unwrap();
+        let dest = Database::create(dest_file.path()).unwrap();
+        let plan = plan.table(COUNTS).multimap(LINKS);
+        copy_database(&source, &dest, &plan).unwrap();
+        assert_eq!(contents(&dest), contents(&source));
+    }
+}
+
+#[test]
+fn throttled_copy_keeps_to_rate() {
+    let source_file = NamedTempFile::new().unwrap();
+    let dest_file = NamedTempFile::new().unwrap();
+    let source = Database::create(source_file.path()).unwrap();
+    let dest = Database::create(dest_file.path()).unwrap();
+
+    let write_txn = source.begin_write().unwrap();
+    {
+        let mut users = write_txn.open_table(USERS).unwrap();
+        for index in 0..100u64 {
+            users
+                .insert(format!("user{:03}", index).as_str(), index)
+                .unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    // 100 entries at 1000 per second take about 100ms
+    let plan = CopyPlan::new().table(USERS).throttle_entries(1000);
+    let started = Instant::now();
+    let report = copy_database(&source, &dest, &plan).unwrap();
+    assert_eq!(report.total_entries(), 100);
+    assert!(started.elapsed() >= Duration::from_millis(95));
+}
+
+#[test]
+fn manifest_records_rows_and_checksums() {
+    let (_source_file, _dest_file, source, dest) = seeded_pair();
+    let plan = CopyPlan::new()
+        .table(USERS)
+        .multimap(TAGS)
+        .mode(CopyMode::Overwrite)
+        .manifest("copy_manifest", "/data/source.redb");
+    copy_database(&source, &dest, &plan).unwrap();
+
+    let manifest = read_manifest(&dest, "copy_manifest").unwrap();
+    assert_eq!(manifest.len(), 2);
+    assert_eq!(manifest[0].table, "multimap table tags");
+    assert_eq!(manifest[0].rows, 1);
+    assert_eq!(manifest[1].table, "table users");
+    assert_eq!(manifest[1].rows, 2);
+    assert!(manifest
+        .iter()
+        .all(|entry| entry.source == "/data/source.redb" && entry.copied_at > 0));
+
+    // Identical contents give identical checksums, changed ones don't
+    let sums = |db: &Database| -> Vec<(String, u64, u64)> {
+        read_manifest(db, "copy_manifest")
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.table, entry.rows, entry.checksum))
+            .collect()
+    };
+    let copy_file = NamedTempFile::new().unwrap();
+    let copy = Database::create(copy_file.path()).unwrap();
+    copy_database(&source, &copy, &plan).unwrap();
+    assert_eq!(sums(&copy), sums(&dest));
+
+    let write_txn = source.begin_write().unwrap();
+    write_txn
+        .open_table(USERS)
+        .unwrap()
+        .insert("bob", 3)
+        .unwrap();
+    write_txn.commit().unwrap();
+    copy_database(&source, &copy, &plan).unwrap();
+    let (changed, original) = (sums(&copy), sums(&dest));
+    assert_eq!(changed[0], original[0]);
+    assert_ne!(changed[1].2, original[1].2);
+
+    assert!(read_manifest(&dest, "missing").unwrap().is_empty());
+}
+
+#[test]
+fn labeled_copy_records_snapshot_label() {
+    let (_source_file, _dest_file, source, dest) = seeded_pair();
+    let plan = CopyPlan::new()
+        .table(USERS)
+        .mode(CopyMode::Overwrite)
+        .allow_unplanned();
+    let result = copy_database_labeled("height 42", &source, &dest, &plan);
+    assert!(matches!(result, Err(Error::InvalidInput(_))));
+
+    let plan = plan.manifest("copy_manifest", "/data/source.redb");
+    copy_database(&source, &dest, &plan).unwrap();
+    assert_eq!(
+        read_manifest(&dest, "copy_manifest").unwrap()[0].label,
+        None
+    );
+
+    copy_database_labeled("height 42", &source, &dest, &plan).unwrap();
+    let manifest = read_manifest(&dest, "copy_manifest").unwrap();
+    assert_eq!(manifest.len(), 1);
+    assert_eq!(manifest[0].label.as_deref(), Some("height 42"));
+}
+
+#[test]
+fn progress_and_report_cover_each_table() {
+    const COUNTS: TableDefinition<u64, u64> = TableDefinition::new("counts");
+
+    let (_source_file, _dest_file, source, dest) = seeded_pair();
+    let source_txn = source.begin_write().unwrap();
+    {
+        let mut counts = source_txn.open_table(COUNTS).unwrap();
+        for key in 0..PROGRESS_INTERVAL * 2 + 5 {
+            counts.insert(key, key).unwrap();
+        }
+    }
+    source_txn.commit().unwrap();
+
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let plan = CopyPlan::new()
+        .table(USERS)
+        .table(COUNTS)
+        .mode(CopyMode::Skip)
+        .allow_unplanned()
+        .on_progress({
+            let seen = Rc::clone(&seen);
+            move |progress| {
+                seen.borrow_mut().push((
+                    progress.table.to_string(),
+                    progress.entries,
+                    progress.finished,
+                ))
+            }
+        });
+    let report = copy_database(&source, &dest, &plan).unwrap();
+
+    assert_eq!(
+        *seen.borrow(),
+        vec![
+            ("table counts".to_string(), PROGRESS_INTERVAL, false),
+            ("table counts".to_string(), PROGRESS_INTERVAL * 2, false),
+            ("table counts".to_string(), PROGRESS_INTERVAL * 2 + 5, true),
+        ]
+    );
+
+    let tables: Vec<(&str, u64, u64, bool)> = report
+        .tables
+        .iter()
+        .map(|table| {
+            (
+                table.table.as_str(),
+                table.entries,
+                table.bytes,
+                table.skipped,
+            )
+        })
+        .collect();
+    let count_entries = PROGRESS_INTERVAL * 2 + 5;
+    assert_eq!(
+        tables,
+        vec![
+            ("table users", 0, 0, true),
+            ("table counts", count_entries, count_entries * 16, false),
+        ]
+    );
+    assert_eq!(report.total_entries(), count_entries);
+    assert_eq!(report.total_bytes(), count_entries * 16);
+}
+
+#[test]
+fn resume_copy_continues_from_checkpoint() {
+    const COUNTS: TableDefinition<u64, u64> = TableDefinition::new("counts");
+    const LINKS: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("links");
+    const CHECKPOINT: &str = "copy_checkpoint";
+
+    let source_file = NamedTempFile::new().unwrap();
+    let dest_file = NamedTempFile::new().unwrap();
+    let source = Database::create(source_file.path()).unwrap();
+    let dest = Database::create(dest_file.path()).unwrap();
+
+    let source_txn = source.begin_write().unwrap();
+    {
+        let mut counts = source_txn.open_table(COUNTS).unwrap();
+        let mut links = source_txn.open_multimap_table(LINKS).unwrap();
+        for key in 0..50u64 {
+            counts.insert(key, key * 2).unwrap();
+            for link in 0..3 {
+                links.insert(key % 5, key * 10 + link).unwrap();
+            }
+        }
+    }
+    source_txn.commit().unwrap();
+
+    let plan = CopyPlan::new()
+        .table(COUNTS)
+        .multimap(LINKS)
+        .table(USERS)
+        .batch_entries(7)
+        .checkpoint(CHECKPOINT);
+
+    // USERS is missing from the source, so the copy stops after some batches
+    assert!(copy_database(&source, &dest, &plan).is_err());
+
+    let source_txn = source.begin_write().unwrap();
+    source_txn
+        .open_table(USERS)
+        .unwrap()
+        .insert("alice", 1)
+        .unwrap();
+    source_txn.commit().unwrap();
+
+    let report = resume_copy(&source, &dest, &plan).unwrap();
+    let resumed: Vec<(&str, u64)> = report
+        .tables
+        .iter()
+        .map(|table| (table.table.as_str(), table.entries))
+        .collect();
+    // 196 of the 200 entries were committed; the last batch ended in the multimap
+    assert_eq!(
+        resumed,
+        vec![
+            ("table counts", 0),
+            ("multimap table links", 4),
+            ("table users", 1)
+        ]
+    );
+
+    let read_txn = dest.begin_read().unwrap();
+    assert_eq!(read_txn.open_table(COUNTS).unwrap().len().unwrap(), 50);
+    assert_eq!(
+        read_txn.open_multimap_table(LINKS).unwrap().len().unwrap(),
+        150
+    );
+    assert_eq!(
+        read_txn
+            .open_table(USERS)
+            .unwrap()
+            .get("alice")
+            .unwrap()
+            .unwrap()
+            .value(),
+        1
+    );
+
+    // A finished copy resumes to a no-op
+    let report = resume_copy(&source, &dest, &plan).unwrap();
+    assert!(report.tables.iter().all(|table| table.skipped));
+    assert!(resume_copy(&source, &dest, &CopyPlan::new().table(USERS)).is_err());
+}
+
+#[test]
+fn dry_run_estimates_without_writing() {
+    let (_source_file, _dest_file, source, dest) = seeded_pair();
+    let source_txn = source.begin_write().unwrap();
+    {
+        let mut blobs = source_txn.open_table(BLOBS).unwrap();
+        blobs.insert("one", b"first".as_slice()).unwrap();
+    }
+    source_txn.commit().unwrap();
+
+    let plan = CopyPlan::new()
+        .table_step(TableStep::new(USERS).filter(|name, _| *name != "bob"))
+        .multimap(TAGS)
+        .table(BLOBS);
+    let estimates = copy_database_dry_run(&source, &dest, &plan).unwrap();
+
+    assert_eq!(
+        estimates,
+        vec![
+            TableCopyEstimate {
+                table: "table users".to_string(),
+                destination_exists: true,
+                entries: 1,
+                bytes: 5 + 8,
+            },
+            TableCopyEstimate {
+                table: "multimap table tags".to_string(),
+                destination_exists: true,
+                entries: 1,
+                bytes: 5 + 8,
+            },
+            TableCopyEstimate {
+                table: "table blobs".to_string(),
+                destination_exists: false,
+                entries: 1,
+                bytes: 3 + 5,
+            },
+        ]
+    );
+
+    let read_txn = dest.begin_read().unwrap();
+    assert!(read_txn.open_table(BLOBS).is_err());
+    assert_eq!(dest_state(&dest).0.len(), 2);
+}
+
+#[test]
+fn copy_databases_consolidates_shards() {
+    const VISITS: TableDefinition<&str, RoaringValue> = TableDefinition::new("visits");
+    let (_source_file, _dest_file, source, dest) = seeded_pair();
+    let shard_file = NamedTempFile::new().unwrap();
+    let shard = Database::create(shard_file.path()).unwrap();
+
+    let source_txn = source.begin_write().unwrap();
+    {
+        let mut visits = source_txn.open_table(VISITS).unwrap();
+        visits
+            .insert("alice", RoaringValue::from_iter([1, 2]))
+            .unwrap();
+    }
+    source_txn.commit().unwrap();
+
+    let shard_txn = shard.begin_write().unwrap();
+    {
+        let mut users = shard_txn.open_table(USERS).unwrap();
+        users.insert("bob", 20).unwrap();
+        users.insert("carol", 3).unwrap();
+
+        let mut tags = shard_txn.open_multimap_table(TAGS).unwrap();
+        tags.insert("alice", 30).unwrap();
+
+        let mut visits = shard_txn.open_table(VISITS).unwrap();
+        visits
+            .insert("alice", RoaringValue::from_iter([2, 3]))
+            .unwrap();
+    }
+    shard_txn.commit().unwrap();
+
+    let plan = CopyPlan::new()
+        .mode(CopyMode::Overwrite)
+        .table(USERS)
+        .multimap(TAGS)
+        .table_merged(VISITS);
+    let report = copy_databases(&[&source, &shard], &dest, &plan).unwrap();
+
+    let entries: Vec<_> = report
+        .tables
+        .iter()
+        .map(|stats| (stats.table.as_str(), stats.entries))
+        .collect();
+    assert_eq!(
+        entries,
+        vec![
+            ("table users", 2),
+            ("multimap table tags", 1),
+            ("table visits", 1),
+            ("table users", 2),
+            ("multimap table tags", 1),
+            ("table visits", 1),
+        ]
+    );
+
+    // Only the first source overwrites, later ones add to its tables
+    let (users, tags) = dest_state(&dest);
+    assert_eq!(
+        users,
+        vec![
+            ("alice".to_string(), 1),
+            ("bob".to_string(), 20),
+            ("carol".to_string(), 3),
+        ]
+    );
+    assert_eq!(tags, vec![10, 30]);
+
+    let read_txn = dest.begin_read().unwrap();
+    let visits = read_txn.open_table(VISITS).unwrap();
+    let alice = visits.get("alice").unwrap().unwrap().value();
+    assert_eq!(alice.bitmap().iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+}
+
+#[test]
+fn bucketed_step_persists_key_builder() {
+    const EVENTS: TableDefinition<BucketedKey<u64>, u64> = TableDefinition::new("events");
+    let (_source_file, _dest_file, source, dest) = seeded_pair();
+    let builder = KeyBuilder::new(10).unwrap();
+
+    let source_txn = source.begin_write().unwrap();
+    {
+        builder.persist(&source_txn, "events").unwrap();
+        let mut events = source_txn.open_table(EVENTS).unwrap();
+        events.insert(builder.bucketed_key(1, 5), 50).unwrap();
+        events.insert(builder.bucketed_key(1, 25), 250).unwrap();
+    }
+    source_txn.commit().unwrap();
+
+    // A builder other than the one the source persisted is refused
+    let other = KeyBuilder::new(20).unwrap();
+    let plan = CopyPlan::new().bucketed(&other, EVENTS).allow_unplanned();
+    let result = copy_database(&source, &dest, &plan);
+    assert!(matches!(
+        result,
+        Err(Error::DbCopy(DbCopyError::BucketConfigFailed(_, _)))
+    ));
+
+    let plan = CopyPlan::new().bucketed(&builder, EVENTS).allow_unplanned();
+    let report = copy_database(&source, &dest, &plan).unwrap();
+    assert_eq!(report.total_entries(), 2);
+
+    let read_txn = dest.begin_read().unwrap();
+    assert_eq!(
+        KeyBuilder::load(&read_txn, "events").unwrap(),
+        Some(builder)
+    );
+    let events = read_txn.open_table(EVENTS).unwrap();
+    assert_eq!(events.len().unwrap(), 2);
+}
+
+#[test]
+fn table_buckets_step_registers_copied_buckets() {
+    let (_source_file, _dest_file, source, dest) = seeded_pair();
+    let builder = TableBucketBuilder::new(100, "events").unwrap();
+
+    let source_txn = source.begin_write().unwrap();
+    for sequence in [150, 220, 250, 1_010] {
+        builder
+            .insert::<u64, u64>(&source_txn, sequence, sequence, sequence * 2)
+            .unwrap();
+    }
+    source_txn.commit().unwrap();
+
+    let plan = CopyPlan::new()
+        .table_buckets::<u64, u64>(&builder, 2..=10)
+        .table(BLOBS)
+        .batch_entries(2)
+        .checkpoint("copy_checkpoints")
+        .allow_unplanned();
+
+    // BLOBS is missing from the source, so the copy stops after the first
+    // batch, which ended in bucket 2
+    assert!(copy_database(&source, &dest, &plan).is_err());
+
+    let source_txn = source.begin_write().unwrap();
+    source_txn
+        .open_table(BLOBS)
+        .unwrap()
+        .insert("one", b"first".as_slice())
+        .unwrap();
+    source_txn.commit().unwrap();
+
+    let report = resume_copy(&source, &dest, &plan).unwrap();
+    assert_eq!(report.tables[0].table, "table events_*");
+    assert_eq!(report.tables[0].entries, 1);
+
+    let read_txn = dest.begin_read().unwrap();
+    assert_eq!(builder.registered_buckets(&read_txn).unwrap(), vec![2, 10]);
+    let bucket = read_txn
+        .open_table(TableDefinition::<u64, u64>::new("events_10"))
+        .unwrap();
+    assert_eq!(bucket.get(1_010).unwrap().unwrap().value(), 2_020);
+    assert!(read_txn
+        .open_table(TableDefinition::<u64, u64>::new("events_1"))
+        .is_err());
+
+    let unplanned = plan.unplanned_tables(&source).unwrap();
+    assert!(unplanned.contains(&"table events_1".to_string()));
+    assert!(!unplanned.contains(&"table events_2".to_string()));
+
+    // Export streams carry the bucket of every entry
+    let imported_file = NamedTempFile::new().unwrap();
+    let imported = Database::create(imported_file.path()).unwrap();
+    let plan = CopyPlan::new()
+        .table_buckets::<u64, u64>(&builder, 0..=u64::MAX)
+        .allow_unplanned();
+    let mut stream = Vec::new();
+    export_to_writer(&source, &plan, &mut stream).unwrap();
+    import_from_reader(&imported, &plan, stream.as_slice()).unwrap();
+
+    let read_txn = imported.begin_read().unwrap();
+    assert_eq!(
+        builder.registered_buckets(&read_txn).unwrap(),
+        vec![1, 2, 10]
+    );
+    let bucket = read_txn
+        .open_table(TableDefinition::<u64, u64>::new("events_2"))
+        .unwrap();
+    assert_eq!(bucket.len().unwrap(), 2);
+}
@@ -0,0 +1,161 @@
+//! Values stored as plain CBOR.
+//!
+//! `CborValue<T>` stores a `T` as a bare CBOR item, with no prefix, so
+//! tables written by this crate can be read by any CBOR decoder. Structs are
+//! encoded as maps keyed by field name: readers skip fields they don't know,
+//! and fields added later can be read from older rows when they're marked
+//! `#[serde(default)]`.
+
+use crate::encoding::{EncodingError, StableName};
+use redb::{TypeName, Value};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+/// A redb value storing a `T` as CBOR.
+///
+/// Values are read and written as the plain `T`, e.g.
+/// `table.insert(key, &event)`. `T` names itself through `StableName`, which
+/// becomes part of the table's persisted type name; keeping the name when
+/// fields are added lets the new struct open tables written with the old one.
+#[derive(Debug)]
+pub struct CborValue<T>(PhantomData<T>);
+
+impl<T: Serialize + DeserializeOwned> CborValue<T> {
+    /// Encodes a value as stored in the table.
+    ///
+    /// # Returns
+    /// The CBOR encoding, or `Malformed` if `value` can't be serialized
+    pub fn encode(value: &T) -> Result<Vec<u8>, EncodingError> {
+        serde_cbor::to_vec(value)
+            .map_err(|err| EncodingError::Malformed(format!("CBOR encoding failed: {}", err)))
+    }
+
+    /// Decodes a value from CBOR.
+    ///
+    /// # Returns
+    /// The value, or `Malformed` if the data isn't a CBOR encoding of a `T`
+    pub fn decode(data: &[u8]) -> Result<T, EncodingError> {
+        serde_cbor::from_slice(data)
+            .map_err(|err| EncodingError::Malformed(format!("CBOR decoding failed: {}", err)))
+    }
+}
+
+impl<T> Value for CborValue<T>
+where
+    T: Serialize + DeserializeOwned + StableName + Debug + 'static,
+{
+    type SelfType<'a>
+        = T
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        Self::decode(data).unwrap_or_else(|err| panic!("CborValue data is invalid: {}", err))
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        Self::encode(value).unwrap_or_else(|err| panic!("CborValue can't encode value: {}", err))
+    }
+
+    fn type_name() -> TypeName {
+        TypeName::new(&format!("redb_extras::encoding::CborValue<{}>", T::NAME))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redb::{Database, ReadableDatabase, TableDefinition};
+    use serde::Deserialize;
+    use tempfile::NamedTempFile;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct EventV1 {
+        kind: String,
+        at: u64,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct EventV2 {
+        kind: String,
+        at: u64,
+        #[serde(default)]
+        source: Option<String>,
+    }
+
+    // Both versions share a name, so either can open the table
+    impl StableName for EventV1 {
+        const NAME: &'static str = "Event";
+    }
+
+    impl StableName for EventV2 {
+        const NAME: &'static str = "Event";
+    }
+
+    #[test]
+    fn fields_can_be_added() {
+        const EVENTS: TableDefinition<u64, CborValue<EventV1>> = TableDefinition::new("events");
+        let file = NamedTempFile::new().unwrap();
+        let db = Database::create(file.path()).unwrap();
+
+        let event = EventV1 {
+            kind: "login".to_string(),
+            at: 7,
+        };
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut events = write_txn.open_table(EVENTS).unwrap();
+            events.insert(1, &event).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let events = read_txn.open_table(EVENTS).unwrap();
+        assert_eq!(events.get(1).unwrap().unwrap().value(), event);
+
+        // A map of two entries, readable without this crate
+        let raw = CborValue::encode(&event).unwrap();
+        assert_eq!(raw[0], 0xA2);
+        let value: serde_cbor::Value = serde_cbor::from_slice(&raw).unwrap();
+        assert!(matches!(value, serde_cbor::Value::Map(ref map) if map.len() == 2));
+
+        // Newer readers default the added field, older ones skip it
+        let upgraded = CborValue::<EventV2>::decode(&raw).unwrap();
+        assert_eq!(upgraded.source, None);
+        let newer = CborValue::encode(&EventV2 {
+            source: Some("web".to_string()),
+            ..upgraded
+        })
+        .unwrap();
+        assert_eq!(
+            CborValue::<EventV1>::decode(&newer).unwrap(),
+            EventV1 {
+                kind: "login".to_string(),
+                at: 7
+            }
+        );
+        assert!(CborValue::<EventV1>::decode(&newer[..3]).is_err());
+
+        const EVENTS_V2: TableDefinition<u64, CborValue<EventV2>> = TableDefinition::new("events");
+        let events = read_txn.open_table(EVENTS_V2).unwrap();
+        assert_eq!(events.get(1).unwrap().unwrap().value().at, 7);
+    }
+}
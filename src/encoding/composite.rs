@@ -0,0 +1,517 @@
+//! Composite keys built from several typed components.
+//!
+//! Components are concatenated with a framing that keeps the encoded bytes
+//! in component order: `u64` as 8 big-endian bytes, `i64` as encoded by
+//! `order::encode_i64`, and byte strings and strings escaped and terminated
+//! as described in `escape`. The terminator sorts below every other byte, so
+//! a string sorts before the longer strings it's a prefix of, and no
+//! component can run into the next. Comparing encoded keys bytewise
+//! therefore compares their components in order.
+//!
+//! `KeyCodec` describes a layout at runtime and encodes `KeyPart`s, while
+//! `CompositeKey<(A, B, ...)>` is a redb key over a tuple of components with
+//! the same encoding, so keys written through either can be read by the
+//! other.
+
+use crate::encoding::escape::{decode_escaped, encode_escaped};
+use crate::encoding::order::{decode_i64, encode_i64};
+use crate::encoding::EncodingError;
+use redb::{Key, TypeName, Value};
+use std::cmp::Ordering;
+use std::fmt::{self, Debug};
+use std::marker::PhantomData;
+
+/// Type of a single key component.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentKind {
+    U64,
+    I64,
+    Bytes,
+    Str,
+}
+
+impl fmt::Display for ComponentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ComponentKind::U64 => write!(f, "u64"),
+            ComponentKind::I64 => write!(f, "i64"),
+            ComponentKind::Bytes => write!(f, "bytes"),
+            ComponentKind::Str => write!(f, "str"),
+        }
+    }
+}
+
+/// Value of a single key component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyPart {
+    U64(u64),
+    I64(i64),
+    Bytes(Vec<u8>),
+    Str(String),
+}
+
+impl KeyPart {
+    /// Get the type of the component.
+    pub fn kind(&self) -> ComponentKind {
+        match self {
+            KeyPart::U64(_) => ComponentKind::U64,
+            KeyPart::I64(_) => ComponentKind::I64,
+            KeyPart::Bytes(_) => ComponentKind::Bytes,
+            KeyPart::Str(_) => ComponentKind::Str,
+        }
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            KeyPart::U64(value) => value.encode_into(out),
+            KeyPart::I64(value) => value.encode_into(out),
+            KeyPart::Bytes(value) => value.encode_into(out),
+            KeyPart::Str(value) => value.encode_into(out),
+        }
+    }
+
+    fn decode_from(kind: ComponentKind, data: &mut &[u8]) -> Result<Self, EncodingError> {
+        Ok(match kind {
+            ComponentKind::U64 => KeyPart::U64(u64::decode_from(data)?),
+            ComponentKind::I64 => KeyPart::I64(i64::decode_from(data)?),
+            ComponentKind::Bytes => KeyPart::Bytes(Vec::decode_from(data)?),
+            ComponentKind::Str => KeyPart::Str(String::decode_from(data)?),
+        })
+    }
+}
+
+impl From<u64> for KeyPart {
+    fn from(value: u64) -> Self {
+        KeyPart::U64(value)
+    }
+}
+
+impl From<i64> for KeyPart {
+    fn from(value: i64) -> Self {
+        KeyPart::I64(value)
+    }
+}
+
+impl From<&[u8]> for KeyPart {
+    fn from(value: &[u8]) -> Self {
+        KeyPart::Bytes(value.to_vec())
+    }
+}
+
+impl From<Vec<u8>> for KeyPart {
+    fn from(value: Vec<u8>) -> Self {
+        KeyPart::Bytes(value)
+    }
+}
+
+impl From<&str> for KeyPart {
+    fn from(value: &str) -> Self {
+        KeyPart::Str(value.to_string())
+    }
+}
+
+impl From<String> for KeyPart {
+    fn from(value: String) -> Self {
+        KeyPart::Str(value)
+    }
+}
+
+/// Builder for a composite key layout.
+///
+/// List the components in order, e.g. `KeyCodec::new().str().u64()` for
+/// keys made of a name and a sequence number, then encode and decode keys
+/// with that layout.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyCodec {
+    components: Vec<ComponentKind>,
+}
+
+impl KeyCodec {
+    /// Create a codec with no components.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a `u64` component.
+    pub fn u64(self) -> Self {
+        self.component(ComponentKind::U64)
+    }
+
+    /// Append an `i64` component.
+    pub fn i64(self) -> Self {
+        self.component(ComponentKind::I64)
+    }
+
+    /// Append a byte string component.
+    pub fn bytes(self) -> Self {
+        self.component(ComponentKind::Bytes)
+    }
+
+    /// Append a string component.
+    pub fn str(self) -> Self {
+        self.component(ComponentKind::Str)
+    }
+
+    /// Append a component of the given type.
+    pub fn component(mut self, kind: ComponentKind) -> Self {
+        self.components.push(kind);
+        self
+    }
+
+    /// Get the component types, in order.
+    pub fn components(&self) -> &[ComponentKind] {
+        &self.components
+    }
+
+    /// Encode a key with every component of the layout.
+    ///
+    /// # Returns
+    /// The encoded key, or `SchemaMismatch` if `parts` don't match the layout
+    pub fn encode(&self, parts: &[KeyPart]) -> Result<Vec<u8>, EncodingError> {
+        if parts.len() != self.components.len() {
+            return Err(EncodingError::SchemaMismatch(format!(
+                "expected {} components, got {}",
+                self.components.len(),
+                parts.len()
+            )));
+        }
+        self.encode_prefix(parts)
+    }
+
+    /// Encode the leading components of a key.
+    ///
+    /// Keys whose first components equal `parts` start with the returned
+    /// bytes, so `prefix_range` of the prefix ranges over them.
+    ///
+    /// # Returns
+    /// The encoded prefix, or `SchemaMismatch` if `parts` don't match the
+    /// start of the layout
+    pub fn encode_prefix(&self, parts: &[KeyPart]) -> Result<Vec<u8>, EncodingError> {
+        if parts.len() > self.components.len() {
+            return Err(EncodingError::SchemaMismatch(format!(
+                "expected at most {} components, got {}",
+                self.components.len(),
+                parts.len()
+            )));
+        }
+
+        let mut encoded = Vec::new();
+        for (index, (part, kind)) in parts.iter().zip(&self.components).enumerate() {
+            if part.kind() != *kind {
+                return Err(EncodingError::SchemaMismatch(format!(
+                    "component {} is {}, got {}",
+                    index,
+                    kind,
+                    part.kind()
+                )));
+            }
+            part.encode_into(&mut encoded);
+        }
+        Ok(encoded)
+    }
+
+    /// Decode a key encoded with this layout.
+    ///
+    /// # Returns
+    /// One part per component, or `Malformed` if the key doesn't hold
+    /// exactly the layout's components
+    pub fn decode(&self, key: &[u8]) -> Result<Vec<KeyPart>, EncodingError> {
+        let mut data = key;
+        let parts = self
+            .components
+            .iter()
+            .map(|kind| KeyPart::decode_from(*kind, &mut data))
+            .collect::<Result<Vec<_>, _>>()?;
+        finish(data)?;
+        Ok(parts)
+    }
+}
+
+/// Types usable as components of a `CompositeKey`.
+pub trait KeyComponent: Sized + Debug + 'static {
+    /// Component type in a `KeyCodec` layout.
+    const KIND: ComponentKind;
+
+    /// Append the framed encoding of the component to `out`.
+    fn encode_into(&self, out: &mut Vec<u8>);
+
+    /// Decode a component from the front of `data` and advance past it.
+    fn decode_from(data: &mut &[u8]) -> Result<Self, EncodingError>;
+}
+
+impl KeyComponent for u64 {
+    const KIND: ComponentKind = ComponentKind::U64;
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+
+    fn decode_from(data: &mut &[u8]) -> Result<Self, EncodingError> {
+        Ok(u64::from_be_bytes(take_array(data)?))
+    }
+}
+
+impl KeyComponent for i64 {
+    const KIND: ComponentKind = ComponentKind::I64;
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&encode_i64(*self));
+    }
+
+    fn decode_from(data: &mut &[u8]) -> Result<Self, EncodingError> {
+        Ok(decode_i64(take_array(data)?))
+    }
+}
+
+impl KeyComponent for Vec<u8> {
+    const KIND: ComponentKind = ComponentKind::Bytes;
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_escaped(self, out);
+    }
+
+    fn decode_from(data: &mut &[u8]) -> Result<Self, EncodingError> {
+        decode_escaped(data)
+    }
+}
+
+impl KeyComponent for String {
+    const KIND: ComponentKind = ComponentKind::Str;
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_escaped(self.as_bytes(), out);
+    }
+
+    fn decode_from(data: &mut &[u8]) -> Result<Self, EncodingError> {
+        String::from_utf8(decode_escaped(data)?)
+            .map_err(|err| EncodingError::Malformed(format!("invalid str component: {}", err)))
+    }
+}
+
+fn take_array<const N: usize>(data: &mut &[u8]) -> Result<[u8; N], EncodingError> {
+    if data.len() < N {
+        return Err(EncodingError::Malformed(format!(
+            "expected {} more bytes, got {}",
+            N,
+            data.len()
+        )));
+    }
+    let (head, tail) = data.split_at(N);
+    *data = tail;
+    Ok(head.try_into().expect("split at N"))
+}
+
+fn finish(data: &[u8]) -> Result<(), EncodingError> {
+    if data.is_empty() {
+        Ok(())
+    } else {
+        Err(EncodingError::Malformed(format!(
+            "{} trailing bytes after the last component",
+            data.len()
+        )))
+    }
+}
+
+/// Tuples of `KeyComponent`s stored by a `CompositeKey`.
+pub trait KeyComponents: Sized + Debug + 'static {
+    /// The matching `KeyCodec` layout.
+    fn codec() -> KeyCodec;
+
+    /// Append the encoding of every component to `out`.
+    fn encode_into(&self, out: &mut Vec<u8>);
+
+    /// Decode every component of `data`.
+    fn decode(data: &[u8]) -> Result<Self, EncodingError>;
+}
+
+macro_rules! key_components {
+    ($($name:ident: $index:tt),+) => {
+        impl<$($name: KeyComponent),+> KeyComponents for ($($name,)+) {
+            fn codec() -> KeyCodec {
+                KeyCodec::new()$(.component($name::KIND))+
+            }
+
+            fn encode_into(&self, out: &mut Vec<u8>) {
+                $(self.$index.encode_into(out);)+
+            }
+
+            fn decode(mut data: &[u8]) -> Result<Self, EncodingError> {
+                let decoded = ($($name::decode_from(&mut data)?,)+);
+                finish(data)?;
+                Ok(decoded)
+            }
+        }
+    };
+}
+
+key_components!(A: 0);
+key_components!(A: 0, B: 1);
+key_components!(A: 0, B: 1, C: 2);
+key_components!(A: 0, B: 1, C: 2, D: 3);
+
+/// A redb key over a tuple of components, e.g. `CompositeKey<(String, u64)>`.
+///
+/// Keys are encoded as described in the module docs and compare bytewise,
+/// so they sort by their first component, then their second and so on,
+/// with the same bytes `T::codec()` produces for the same components.
+/// Values are read and written as the plain tuple.
+#[derive(Debug)]
+pub struct CompositeKey<T>(PhantomData<T>);
+
+impl<T: KeyComponents> CompositeKey<T> {
+    /// Get the `KeyCodec` layout matching this key type.
+    pub fn codec() -> KeyCodec {
+        T::codec()
+    }
+}
+
+impl<T: KeyComponents> Value for CompositeKey<T> {
+    type SelfType<'a>
+        = T
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        T::decode(data).unwrap_or_else(|err| panic!("CompositeKey data is invalid: {}", err))
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        let mut encoded = Vec::new();
+        value.encode_into(&mut encoded);
+        encoded
+    }
+
+    fn type_name() -> TypeName {
+        let components: Vec<String> = T::codec()
+            .components()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        TypeName::new(&format!(
+            "redb_extras::encoding::CompositeKey<({})>",
+            components.join(", ")
+        ))
+    }
+}
+
+impl<T: KeyComponents> Key for CompositeKey<T> {
+    fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+        data1.cmp(data2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redb::{Database, ReadableDatabase, TableDefinition};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn encoded_keys_sort_by_component() {
+        let codec = KeyCodec::new().bytes().i64();
+        let keys = [
+            (b"a".as_slice(), 5),
+            (b"a", 6),
+            (b"a\0", -1),
+            (b"a\0\0", -1),
+            (b"a\x01", -1),
+            (b"ab", i64::MIN),
+            (b"b", -3),
+        ];
+        let encoded: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|(bytes, number)| codec.encode(&[(*bytes).into(), (*number).into()]).unwrap())
+            .collect();
+        for pair in encoded.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+        for ((bytes, number), key) in keys.iter().zip(&encoded) {
+            assert_eq!(
+                codec.decode(key).unwrap(),
+                vec![KeyPart::from(*bytes), KeyPart::from(*number)]
+            );
+        }
+    }
+
+    #[test]
+    fn codec_checks_layout() {
+        let codec = KeyCodec::new().str().u64();
+        assert!(matches!(
+            codec.encode(&["name".into()]),
+            Err(EncodingError::SchemaMismatch(_))
+        ));
+        assert!(matches!(
+            codec.encode(&[7u64.into(), "name".into()]),
+            Err(EncodingError::SchemaMismatch(_))
+        ));
+
+        let prefix = codec.encode_prefix(&["name".into()]).unwrap();
+        let key = codec.encode(&["name".into(), 7u64.into()]).unwrap();
+        assert!(key.starts_with(&prefix));
+        assert!(matches!(
+            codec.decode(&prefix),
+            Err(EncodingError::Malformed(_))
+        ));
+        assert!(matches!(
+            codec.decode(&[key.as_slice(), b"x"].concat()),
+            Err(EncodingError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn composite_keys_match_codec() {
+        const EVENTS: TableDefinition<CompositeKey<(String, u64)>, u64> =
+            TableDefinition::new("events");
+        let file = NamedTempFile::new().unwrap();
+        let db = Database::create(file.path()).unwrap();
+
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut events = write_txn.open_table(EVENTS).unwrap();
+            for (name, sequence) in [("b", 1), ("a", 2), ("a", 1), ("ab", 0)] {
+                events.insert((name.to_string(), sequence), 0).unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let events = read_txn.open_table(EVENTS).unwrap();
+        let keys: Vec<(String, u64)> = events
+            .range(("a".to_string(), 2)..)
+            .unwrap()
+            .map(|entry| entry.unwrap().0.value())
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("a".to_string(), 2),
+                ("ab".to_string(), 0),
+                ("b".to_string(), 1)
+            ]
+        );
+
+        let codec = CompositeKey::<(String, u64)>::codec();
+        assert_eq!(codec, KeyCodec::new().str().u64());
+        let key = ("a".to_string(), 2u64);
+        assert_eq!(
+            CompositeKey::<(String, u64)>::as_bytes(&key),
+            codec.encode(&["a".into(), 2u64.into()]).unwrap()
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Order-preserving concatenation of byte strings.
+//!
+//! Length-prefixed keys such as the partitioned tables' segment keys sort
+//! by component length first, so `"b"` sorts before `"aa"`. Escaping keeps
+//! the lexicographic order instead: every `0x00` in a component is written
+//! as `0x00 0xFF` and each component ends with `0x00 0x01`. The terminator
+//! sorts below every escaped byte, so comparing concatenations bytewise
+//! compares their components one by one, and a component sorts before the
+//! longer components it's a prefix of.
+
+use crate::encoding::EncodingError;
+
+/// Escape byte, and the bytes that may follow it.
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xFF;
+const TERMINATOR: u8 = 0x01;
+
+/// Concatenates byte strings so the result sorts by component.
+///
+/// # Arguments
+/// * `components` - The byte strings, in order of significance
+///
+/// # Returns
+/// The escaped and terminated components
+pub fn concat(components: &[&[u8]]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for component in components {
+        encode_escaped(component, &mut encoded);
+    }
+    encoded
+}
+
+/// Splits a concatenation written by `concat` back into its components.
+///
+/// # Returns
+/// The components, or `Malformed` if `encoded` has an invalid escape or
+/// an unterminated component
+pub fn split(encoded: &[u8]) -> Result<Vec<Vec<u8>>, EncodingError> {
+    let mut data = encoded;
+    let mut components = Vec::new();
+    while !data.is_empty() {
+        components.push(decode_escaped(&mut data)?);
+    }
+    Ok(components)
+}
+
+/// Appends an escaped and terminated component to `out`.
+pub fn encode_escaped(bytes: &[u8], out: &mut Vec<u8>) {
+    for &byte in bytes {
+        out.push(byte);
+        if byte == ESCAPE {
+            out.push(ESCAPED_ZERO);
+        }
+    }
+    out.extend_from_slice(&[ESCAPE, TERMINATOR]);
+}
+
+/// Decodes a component from the front of `data` and advances past it.
+///
+/// # Returns
+/// The unescaped component, or `Malformed` if it has an invalid escape or
+/// no terminator
+pub fn decode_escaped(data: &mut &[u8]) -> Result<Vec<u8>, EncodingError> {
+    let mut decoded = Vec::new();
+    let mut rest = data.iter().enumerate();
+    while let Some((index, &byte)) = rest.next() {
+        if byte != ESCAPE {
+            decoded.push(byte);
+            continue;
+        }
+        match rest.next() {
+            Some((_, &ESCAPED_ZERO)) => decoded.push(ESCAPE),
+            Some((_, &TERMINATOR)) => {
+                *data = &data[index + 2..];
+                return Ok(decoded);
+            }
+            _ => {
+                return Err(EncodingError::Malformed(format!(
+                    "invalid escape at byte {}",
+                    index
+                )))
+            }
+        }
+    }
+    Err(EncodingError::Malformed(
+        "unterminated byte string component".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::partition::table::encode_shard_key;
+
+    #[test]
+    fn concatenations_sort_by_component() {
+        let keys: [[&[u8]; 2]; 6] = [
+            [b"", b"z"],
+            [b"a", b"\xFF"],
+            [b"a\0", b""],
+            [b"a\0\xFF", b""],
+            [b"aa", b"a"],
+            [b"b", b""],
+        ];
+        let encoded: Vec<Vec<u8>> = keys.iter().map(|key| concat(key)).collect();
+        for pair in encoded.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+        for (key, encoded) in keys.iter().zip(&encoded) {
+            assert_eq!(split(encoded).unwrap(), key.to_vec());
+        }
+
+        // Length prefixes sort "b" before "aa"
+        assert!(encode_shard_key(b"b", 0).unwrap() < encode_shard_key(b"aa", 0).unwrap());
+        assert!(concat(&[b"aa"]) < concat(&[b"b"]));
+
+        assert!(split(b"a\0").is_err());
+        assert!(split(b"a\0\x02").is_err());
+        assert!(split(b"a").is_err());
+        assert_eq!(split(b"").unwrap(), Vec::<Vec<u8>>::new());
+    }
+}
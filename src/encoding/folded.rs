@@ -0,0 +1,178 @@
+//! Case-insensitive string keys that keep the original spelling.
+//!
+//! A `FoldedKey` is encoded as the lowercased string, escaped and
+//! terminated as in `escape`, followed by the original string. Keys
+//! sort by their folded form first, so every spelling of a name is stored
+//! next to the others and can be found with a range starting at
+//! `FoldedStr::lower_bound`, while the original stays readable. Folding uses
+//! Rust's Unicode lowercasing; it doesn't apply Unicode normalization, so
+//! composed and decomposed forms of the same text fold differently.
+
+use crate::encoding::escape::{decode_escaped, encode_escaped};
+use redb::{Key, TypeName, Value};
+use std::cmp::Ordering;
+
+/// Folds a string to the form keys are ordered by.
+pub fn fold(text: &str) -> String {
+    text.to_lowercase()
+}
+
+/// A string together with its folded form, as stored by `FoldedKey`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoldedStr<'a> {
+    folded: String,
+    original: &'a str,
+}
+
+impl<'a> FoldedStr<'a> {
+    /// Creates a key for `original`.
+    pub fn new(original: &'a str) -> Self {
+        Self {
+            folded: fold(original),
+            original,
+        }
+    }
+
+    /// Creates a range bound sorting before every key whose folded form is
+    /// at least the folded `prefix`.
+    ///
+    /// Ranging from the bound and taking entries while their folded form
+    /// starts with `fold(prefix)` visits every key with that prefix, in
+    /// any spelling.
+    pub fn lower_bound(prefix: &str) -> Self {
+        Self {
+            folded: fold(prefix),
+            original: "",
+        }
+    }
+
+    /// Gets the folded form the key is ordered by.
+    pub fn folded(&self) -> &str {
+        &self.folded
+    }
+
+    /// Gets the original string.
+    pub fn original(&self) -> &'a str {
+        self.original
+    }
+}
+
+/// A redb key storing strings ordered case-insensitively.
+///
+/// Distinct spellings of a string are distinct keys, sorted next to each
+/// other by their original bytes. Values are read and written as
+/// `FoldedStr`, e.g. `table.insert(FoldedStr::new("Alice"), value)`.
+#[derive(Debug)]
+pub struct FoldedKey;
+
+impl Value for FoldedKey {
+    type SelfType<'a>
+        = FoldedStr<'a>
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        let mut original = data;
+        let folded = decode_escaped(&mut original)
+            .ok()
+            .and_then(|folded| String::from_utf8(folded).ok())
+            .expect("FoldedKey data has an invalid folded form");
+        FoldedStr {
+            folded,
+            original: std::str::from_utf8(original)
+                .expect("FoldedKey data has an invalid original"),
+        }
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        let mut encoded = Vec::with_capacity(value.folded.len() + 2 + value.original.len());
+        encode_escaped(value.folded.as_bytes(), &mut encoded);
+        encoded.extend_from_slice(value.original.as_bytes());
+        encoded
+    }
+
+    fn type_name() -> TypeName {
+        TypeName::new("redb_extras::encoding::FoldedKey")
+    }
+}
+
+impl Key for FoldedKey {
+    fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+        data1.cmp(data2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redb::{Database, ReadableDatabase, ReadableTable, TableDefinition};
+    use tempfile::NamedTempFile;
+
+    const USERS: TableDefinition<FoldedKey, u64> = TableDefinition::new("users");
+
+    fn originals_from<T>(table: &T, prefix: &str) -> Vec<String>
+    where
+        T: ReadableTable<FoldedKey, u64>,
+    {
+        let folded_prefix = fold(prefix);
+        table
+            .range(FoldedStr::lower_bound(prefix)..)
+            .unwrap()
+            .map(|entry| entry.unwrap().0.value().original().to_string())
+            .take_while(|original| fold(original).starts_with(&folded_prefix))
+            .collect()
+    }
+
+    #[test]
+    fn lookups_ignore_case() {
+        let file = NamedTempFile::new().unwrap();
+        let db = Database::create(file.path()).unwrap();
+
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut users = write_txn.open_table(USERS).unwrap();
+            for (id, name) in ["bob", "ALICE", "Alfred", "alice", "Ål", "Al\0x", "Alice"]
+                .into_iter()
+                .enumerate()
+            {
+                users.insert(FoldedStr::new(name), id as u64).unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let users = read_txn.open_table(USERS).unwrap();
+        assert_eq!(
+            originals_from(&users, "aLiCe"),
+            vec!["ALICE", "Alice", "alice"]
+        );
+        assert_eq!(
+            originals_from(&users, "AL"),
+            vec!["Al\0x", "Alfred", "ALICE", "Alice", "alice"]
+        );
+        assert_eq!(originals_from(&users, "ål"), vec!["Ål"]);
+        assert!(originals_from(&users, "carol").is_empty());
+
+        let key = FoldedStr::new("Alice");
+        assert_eq!(users.get(&key).unwrap().unwrap().value(), 6);
+        assert_eq!(FoldedKey::from_bytes(&FoldedKey::as_bytes(&key)), key);
+        assert_eq!(key.folded(), "alice");
+        assert!(users.get(FoldedStr::new("alicE")).unwrap().is_none());
+    }
+}
@@ -0,0 +1,316 @@
+//! Time-ordered 128-bit ids.
+//!
+//! A `Ulid` holds a 48-bit Unix timestamp in milliseconds followed by 80
+//! random bits, stored big-endian so ids sort by creation time. It is
+//! laid out like a ULID and prints as one, as 26 Crockford base32 chars.
+//!
+//! `UlidGenerator` hands out strictly increasing ids: ids drawn within the
+//! same millisecond, or after the clock went backwards, increment the
+//! previous id instead of drawing new random bits. The random bits come from
+//! a per-generator seed hashed with xxh3; they are unique enough for keys
+//! but not suitable as secrets.
+
+use crate::encoding::EncodingError;
+use redb::{Key, TypeName, Value};
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use xxhash_rust::xxh3::xxh3_128_with_seed;
+
+/// Bits of the random part.
+const RANDOM_BITS: u32 = 80;
+/// Crockford base32 alphabet.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+/// Length of the text form.
+const TEXT_LEN: usize = 26;
+
+/// A 128-bit id ordered by its millisecond timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ulid(u128);
+
+impl Ulid {
+    /// Largest timestamp an id can hold, in milliseconds.
+    pub const MAX_TIMESTAMP: u64 = (1 << (128 - RANDOM_BITS)) - 1;
+
+    /// Largest random part an id can hold.
+    pub const MAX_RANDOM: u128 = (1 << RANDOM_BITS) - 1;
+
+    /// Creates an id from its parts.
+    ///
+    /// # Arguments
+    /// * `timestamp_ms` - Unix timestamp in milliseconds
+    /// * `random` - Random part, of which the low 80 bits are kept
+    ///
+    /// # Panics
+    /// If `timestamp_ms` exceeds `MAX_TIMESTAMP`
+    pub fn from_parts(timestamp_ms: u64, random: u128) -> Self {
+        assert!(
+            timestamp_ms <= Self::MAX_TIMESTAMP,
+            "Ulid timestamp {} exceeds 48 bits",
+            timestamp_ms
+        );
+        Self(((timestamp_ms as u128) << RANDOM_BITS) | (random & Self::MAX_RANDOM))
+    }
+
+    /// Gets the timestamp in milliseconds.
+    pub fn timestamp_ms(&self) -> u64 {
+        (self.0 >> RANDOM_BITS) as u64
+    }
+
+    /// Gets the random part.
+    pub fn random(&self) -> u128 {
+        self.0 & Self::MAX_RANDOM
+    }
+
+    /// Gets the id as a number.
+    pub fn as_u128(&self) -> u128 {
+        self.0
+    }
+
+    /// Encodes the id as 16 big-endian bytes, which sort like the id.
+    pub fn to_bytes(&self) -> [u8; 16] {
+        self.0.to_be_bytes()
+    }
+
+    /// Decodes an id from the bytes written by `to_bytes`.
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(u128::from_be_bytes(bytes))
+    }
+}
+
+impl From<u128> for Ulid {
+    fn from(value: u128) -> Self {
+        Self(value)
+    }
+}
+
+impl fmt::Display for Ulid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut text = [0u8; TEXT_LEN];
+        for (index, char) in text.iter_mut().enumerate() {
+            let shift = 5 * (TEXT_LEN - 1 - index);
+            *char = ALPHABET[((self.0 >> shift) & 0x1F) as usize];
+        }
+        f.write_str(std::str::from_utf8(&text).expect("base32 is ascii"))
+    }
+}
+
+impl FromStr for Ulid {
+    type Err = EncodingError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || EncodingError::Malformed(format!("invalid ulid '{}'", text));
+
+        if text.len() != TEXT_LEN {
+            return Err(invalid());
+        }
+        let mut value: u128 = 0;
+        for (index, char) in text.bytes().enumerate() {
+            let digit = ALPHABET
+                .iter()
+                .position(|c| *c == char.to_ascii_uppercase())
+                .ok_or_else(invalid)? as u128;
+            // The first char only carries the top 3 bits
+            if index == 0 && digit > 7 {
+                return Err(invalid());
+            }
+            value = (value << 5) | digit;
+        }
+        Ok(Self(value))
+    }
+}
+
+/// A redb key storing a `Ulid` as its 16 big-endian bytes.
+impl Value for Ulid {
+    type SelfType<'a>
+        = Ulid
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = [u8; 16]
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        Some(16)
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        let bytes = data
+            .try_into()
+            .unwrap_or_else(|_| panic!("Ulid data has {} bytes, expected 16", data.len()));
+        Ulid::from_bytes(bytes)
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        value.to_bytes()
+    }
+
+    fn type_name() -> TypeName {
+        TypeName::new("redb_extras::encoding::Ulid")
+    }
+}
+
+impl Key for Ulid {
+    fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+        data1.cmp(data2)
+    }
+}
+
+/// Generator of strictly increasing `Ulid`s.
+///
+/// Share one generator, e.g. behind a mutex, among the writers of a table
+/// so their ids never collide or go backwards.
+#[derive(Debug)]
+pub struct UlidGenerator {
+    seed: u64,
+    draws: u64,
+    last: Option<Ulid>,
+}
+
+impl UlidGenerator {
+    /// Creates a generator with a random seed.
+    pub fn new() -> Self {
+        Self::with_seed(RandomState::new().build_hasher().finish())
+    }
+
+    /// Creates a generator whose random parts are derived from `seed`.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            seed,
+            draws: 0,
+            last: None,
+        }
+    }
+
+    /// Generates an id for the current time.
+    pub fn generate(&mut self) -> Ulid {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(0);
+        self.generate_at(timestamp_ms.min(Ulid::MAX_TIMESTAMP))
+    }
+
+    /// Generates an id for the given time.
+    ///
+    /// # Arguments
+    /// * `timestamp_ms` - Unix timestamp in milliseconds
+    ///
+    /// # Returns
+    /// An id greater than every id generated before, with `timestamp_ms` as
+    /// its timestamp unless an earlier id already reached it
+    ///
+    /// # Panics
+    /// If `timestamp_ms` exceeds `Ulid::MAX_TIMESTAMP`
+    pub fn generate_at(&mut self, timestamp_ms: u64) -> Ulid {
+        let id = match self.last {
+            Some(last) if last.timestamp_ms() >= timestamp_ms => Ulid(
+                last.0
+                    .checked_add(1)
+                    .expect("Ulid space exhausted by the generator"),
+            ),
+            _ => {
+                self.draws += 1;
+                let random = xxh3_128_with_seed(&self.draws.to_be_bytes(), self.seed);
+                Ulid::from_parts(timestamp_ms, random)
+            }
+        };
+        self.last = Some(id);
+        id
+    }
+}
+
+impl Default for UlidGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redb::{Database, ReadableDatabase, ReadableTable, TableDefinition};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn ids_round_trip() {
+        let id = Ulid::from_parts(1_700_000_000_000, 0xABCD_EF01_2345_6789_0123);
+        assert_eq!(id.timestamp_ms(), 1_700_000_000_000);
+        assert_eq!(id.random(), 0xABCD_EF01_2345_6789_0123);
+        assert_eq!(Ulid::from_bytes(id.to_bytes()), id);
+
+        let text = id.to_string();
+        assert_eq!(text.len(), 26);
+        assert_eq!(text.parse::<Ulid>().unwrap(), id);
+        assert_eq!(text.to_lowercase().parse::<Ulid>().unwrap(), id);
+        assert_eq!(
+            Ulid::from(u128::MAX).to_string(),
+            "7ZZZZZZZZZZZZZZZZZZZZZZZZZ"
+        );
+        assert!("8ZZZZZZZZZZZZZZZZZZZZZZZZZ".parse::<Ulid>().is_err());
+        assert!("01ARZ3NDEKTSV4RRFFQ69G5FAU".parse::<Ulid>().is_err());
+        assert!("01ARZ3NDEKTSV4RRFFQ69G5FA".parse::<Ulid>().is_err());
+    }
+
+    #[test]
+    fn generator_is_monotonic() {
+        let mut generator = UlidGenerator::with_seed(7);
+        let first = generator.generate_at(1_000);
+        let same_ms = generator.generate_at(1_000);
+        let clock_back = generator.generate_at(999);
+        let later = generator.generate_at(1_001);
+        assert_eq!(same_ms.as_u128(), first.as_u128() + 1);
+        assert_eq!(clock_back.as_u128(), same_ms.as_u128() + 1);
+        assert!(clock_back < later);
+        assert_eq!(later.timestamp_ms(), 1_001);
+
+        let mut other = UlidGenerator::with_seed(8);
+        assert_ne!(other.generate_at(1_000), first);
+
+        let ids: Vec<Ulid> = (0..100).map(|_| generator.generate()).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn ids_iterate_in_creation_order() {
+        const EVENTS: TableDefinition<Ulid, u64> = TableDefinition::new("events");
+        let file = NamedTempFile::new().unwrap();
+        let db = Database::create(file.path()).unwrap();
+
+        let mut generator = UlidGenerator::new();
+        let ids: Vec<Ulid> = [5_000, 1_000_000, 1_000_000, 3 << 40]
+            .into_iter()
+            .map(|timestamp_ms| generator.generate_at(timestamp_ms))
+            .collect();
+
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut events = write_txn.open_table(EVENTS).unwrap();
+            for (index, id) in ids.iter().enumerate().rev() {
+                events.insert(id, index as u64).unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let events = read_txn.open_table(EVENTS).unwrap();
+        let stored: Vec<Ulid> = events
+            .iter()
+            .unwrap()
+            .map(|entry| entry.unwrap().0.value())
+            .collect();
+        assert_eq!(stored, ids);
+    }
+}
@@ -0,0 +1,161 @@
+//! Borrowed views into partitioned table keys.
+//!
+//! Segment keys are `[key_len][base_key][shard][segment]` and shard keys,
+//! which key meta and tombstone rows, are `[key_len][base_key][shard]`, with
+//! the length, shard and segment big-endian. The functions here read single
+//! fields straight out of the encoded bytes, so scans and external tools can
+//! inspect keys without decoding or copying them.
+
+use crate::encoding::EncodingError;
+
+/// Size of the base key length prefix.
+const LEN_PREFIX: usize = 4;
+/// Size of the shard and segment fields.
+const ID_LEN: usize = 2;
+
+/// Gets the base key of a segment or shard key.
+///
+/// # Arguments
+/// * `key` - The encoded segment or shard key
+///
+/// # Returns
+/// The base key, borrowed from `key`, or `Malformed` if `key` is neither
+pub fn base_key_of(key: &[u8]) -> Result<&[u8], EncodingError> {
+    split(key).map(|(base_key, _)| base_key)
+}
+
+/// Gets the shard of a segment or shard key.
+///
+/// # Arguments
+/// * `key` - The encoded segment or shard key
+///
+/// # Returns
+/// The shard, or `Malformed` if `key` is neither
+pub fn shard_of(key: &[u8]) -> Result<u16, EncodingError> {
+    split(key).map(|(_, ids)| u16::from_be_bytes([ids[0], ids[1]]))
+}
+
+/// Gets the segment of a segment key.
+///
+/// # Arguments
+/// * `segment_key` - The encoded segment key
+///
+/// # Returns
+/// The segment, or `Malformed` if `segment_key` isn't a segment key
+pub fn segment_of(segment_key: &[u8]) -> Result<u16, EncodingError> {
+    decode_segment_key(segment_key).map(|(_, _, segment)| segment)
+}
+
+/// Decodes a segment key written by `encode_segment_key`.
+///
+/// # Arguments
+/// * `segment_key` - The encoded segment key
+///
+/// # Returns
+/// The base key, borrowed from `segment_key`, with its shard and segment, or
+/// `Malformed` if `segment_key` isn't a segment key
+pub fn decode_segment_key(segment_key: &[u8]) -> Result<(&[u8], u16, u16), EncodingError> {
+    match split(segment_key)? {
+        (base_key, [shard_high, shard_low, high, low]) => Ok((
+            base_key,
+            u16::from_be_bytes([*shard_high, *shard_low]),
+            u16::from_be_bytes([*high, *low]),
+        )),
+        _ => Err(EncodingError::Malformed(format!(
+            "shard key of {} bytes has no segment",
+            segment_key.len()
+        ))),
+    }
+}
+
+/// Decodes a shard key written by `encode_shard_key`, which keys meta and
+/// tombstone rows.
+///
+/// # Arguments
+/// * `meta_key` - The encoded shard key
+///
+/// # Returns
+/// The base key, borrowed from `meta_key`, and its shard, or `Malformed` if
+/// `meta_key` isn't a shard key
+pub fn decode_meta_key(meta_key: &[u8]) -> Result<(&[u8], u16), EncodingError> {
+    match split(meta_key)? {
+        (base_key, [high, low]) => Ok((base_key, u16::from_be_bytes([*high, *low]))),
+        _ => Err(EncodingError::Malformed(format!(
+            "segment key of {} bytes isn't a shard key",
+            meta_key.len()
+        ))),
+    }
+}
+
+/// Splits an encoded key into its base key and the shard, or shard and
+/// segment, fields following it.
+pub(crate) fn split(key: &[u8]) -> Result<(&[u8], &[u8]), EncodingError> {
+    let invalid =
+        || EncodingError::Malformed(format!("{} bytes aren't a segment or shard key", key.len()));
+
+    let len_bytes: [u8; LEN_PREFIX] = key
+        .get(..LEN_PREFIX)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(invalid)?;
+    let key_len = u32::from_be_bytes(len_bytes) as usize;
+    let ids_len = (key.len() - LEN_PREFIX)
+        .checked_sub(key_len)
+        .ok_or_else(invalid)?;
+    if ids_len != ID_LEN && ids_len != 2 * ID_LEN {
+        return Err(invalid());
+    }
+
+    Ok(key[LEN_PREFIX..].split_at(key_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::partition::table::{encode_segment_key, encode_shard_key};
+
+    #[test]
+    fn fields_are_read_in_place() {
+        let segment_key = encode_segment_key(b"user:1", 7, 300).unwrap();
+        let shard_key = encode_shard_key(b"user:1", 7).unwrap();
+
+        assert_eq!(base_key_of(&segment_key).unwrap(), b"user:1");
+        assert_eq!(shard_of(&segment_key).unwrap(), 7);
+        assert_eq!(segment_of(&segment_key).unwrap(), 300);
+        assert_eq!(base_key_of(&shard_key).unwrap(), b"user:1");
+        assert_eq!(shard_of(&shard_key).unwrap(), 7);
+        assert!(matches!(
+            segment_of(&shard_key),
+            Err(EncodingError::Malformed(_))
+        ));
+
+        let empty = encode_segment_key(b"", 0, 0).unwrap();
+        assert_eq!(base_key_of(&empty).unwrap(), b"");
+
+        for malformed in [
+            &b"abc"[..],
+            &segment_key[..9],
+            &[segment_key.as_slice(), b"x"].concat(),
+        ] {
+            assert!(base_key_of(malformed).is_err());
+            assert!(shard_of(malformed).is_err());
+            assert!(segment_of(malformed).is_err());
+        }
+    }
+
+    #[test]
+    fn keys_decode_to_all_fields() {
+        let segment_key = encode_segment_key(b"key", 3, 7).unwrap();
+        assert_eq!(
+            decode_segment_key(&segment_key).unwrap(),
+            (b"key".as_slice(), 3, 7)
+        );
+        let meta_key = encode_shard_key(b"key", 3).unwrap();
+        assert_eq!(decode_meta_key(&meta_key).unwrap(), (b"key".as_slice(), 3));
+
+        // Truncated keys, and keys of the other kind, are rejected
+        assert!(decode_segment_key(&segment_key[..5]).is_err());
+        assert!(decode_segment_key(&meta_key).is_err());
+        assert!(decode_meta_key(&segment_key).is_err());
+        assert!(decode_meta_key(&[0, 0]).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+//! Key and value encoding utilities.
+//!
+//! Byte encodings for building custom redb keys whose raw bytes sort the
+//! same way as the values they encode, so keys can be compared, ranged over
+//! and prefixed without decoding them, plus a versioned envelope for values
+//! whose encoding evolves.
+
+use crate::error::ErrorKind;
+use std::fmt;
+
+/// Errors specific to the key encodings.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum EncodingError {
+    /// Components don't match the codec's layout
+    SchemaMismatch(String),
+
+    /// Encoded data is truncated or not in the expected format
+    Malformed(String),
+
+    /// Value has a version or format that can't be read
+    UnsupportedVersion(u8),
+}
+
+impl EncodingError {
+    /// Classifies the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            EncodingError::SchemaMismatch(_) => ErrorKind::InvalidInput,
+            EncodingError::Malformed(_) => ErrorKind::Corruption,
+            EncodingError::UnsupportedVersion(_) => ErrorKind::ConfigMismatch,
+        }
+    }
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodingError::SchemaMismatch(msg) => write!(f, "Key schema mismatch: {}", msg),
+            EncodingError::Malformed(msg) => write!(f, "Malformed encoding: {}", msg),
+            EncodingError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported value version: {}", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EncodingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+/// A stable name for a type stored through serde.
+///
+/// redb persists the type name of each table's values and refuses to open the
+/// table under a different one. `SerdeValue` and `CborValue` build their
+/// names from this one rather than `std::any::type_name`, whose output can
+/// change between compiler versions or when the type moves to another module.
+#[cfg(any(feature = "serde", feature = "cbor"))]
+pub trait StableName {
+    /// Name of the type, e.g. `"User"`
+    const NAME: &'static str;
+}
+
+#[cfg(feature = "cbor")]
+pub mod cbor;
+pub mod composite;
+pub mod escape;
+pub mod folded;
+pub mod ids;
+pub mod key;
+pub mod order;
+pub mod prefix;
+#[cfg(feature = "serde")]
+pub mod serialized;
+pub mod versioned;
+
+// Re-export main types for public API
+#[cfg(feature = "cbor")]
+pub use cbor::CborValue;
+pub use composite::{ComponentKind, CompositeKey, KeyCodec, KeyComponent, KeyComponents, KeyPart};
+pub use folded::{fold, FoldedKey, FoldedStr};
+pub use ids::{Ulid, UlidGenerator};
+pub use key::{base_key_of, decode_meta_key, decode_segment_key, segment_of, shard_of};
+pub use order::{BeU128, BeU32, BeU64, OrderedKey, OrderedNumber};
+pub use prefix::prefix_range;
+#[cfg(feature = "serde")]
+pub use serialized::SerdeValue;
+pub use versioned::{Versioned, VersionedValue};
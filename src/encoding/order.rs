@@ -0,0 +1,298 @@
+//! Order-preserving encodings for signed integers and floats.
+//!
+//! redb compares integer keys numerically, but their little-endian bytes
+//! don't sort that way, so numbers embedded in composite or raw byte keys
+//! need an encoding of their own. The encodings here are big-endian with:
+//!
+//! - signed integers: the sign bit flipped, so negatives sort before
+//!   positives;
+//! - floats: the sign bit flipped for positive values and every bit flipped
+//!   for negative ones, so larger magnitudes of negatives sort first.
+//!
+//! Floats sort as `-NaN < -inf < ... < -0.0 < 0.0 < ... < inf < NaN`, i.e.
+//! by IEEE 754 total order, and decode back to the exact same bits.
+//! Unsigned integers need no transformation and are stored big-endian as is,
+//! e.g. by the `BeU64` key.
+
+use redb::{Key, TypeName, Value};
+use std::cmp::Ordering;
+use std::fmt::Debug;
+
+macro_rules! ordered_int {
+    ($ty:ty, $unsigned:ty, $encode:ident, $decode:ident) => {
+        #[doc = concat!("Encodes an `", stringify!($ty), "` so byte order matches numeric order.")]
+        pub fn $encode(value: $ty) -> [u8; std::mem::size_of::<$ty>()] {
+            ((value as $unsigned) ^ (1 << (<$unsigned>::BITS - 1))).to_be_bytes()
+        }
+
+        #[doc = concat!("Decodes an `", stringify!($ty), "` encoded by `", stringify!($encode), "`.")]
+        pub fn $decode(bytes: [u8; std::mem::size_of::<$ty>()]) -> $ty {
+            (<$unsigned>::from_be_bytes(bytes) ^ (1 << (<$unsigned>::BITS - 1))) as $ty
+        }
+    };
+}
+
+ordered_int!(i8, u8, encode_i8, decode_i8);
+ordered_int!(i16, u16, encode_i16, decode_i16);
+ordered_int!(i32, u32, encode_i32, decode_i32);
+ordered_int!(i64, u64, encode_i64, decode_i64);
+
+macro_rules! ordered_float {
+    ($ty:ty, $bits:ty, $encode:ident, $decode:ident) => {
+        #[doc = concat!("Encodes an `", stringify!($ty), "` so byte order matches IEEE 754 total order.")]
+        pub fn $encode(value: $ty) -> [u8; std::mem::size_of::<$ty>()] {
+            let bits = value.to_bits();
+            let sign = 1 << (<$bits>::BITS - 1);
+            let ordered = if bits & sign == 0 { bits ^ sign } else { !bits };
+            ordered.to_be_bytes()
+        }
+
+        #[doc = concat!("Decodes an `", stringify!($ty), "` encoded by `", stringify!($encode), "`.")]
+        pub fn $decode(bytes: [u8; std::mem::size_of::<$ty>()]) -> $ty {
+            let ordered = <$bits>::from_be_bytes(bytes);
+            let sign = 1 << (<$bits>::BITS - 1);
+            let bits = if ordered & sign == 0 {
+                !ordered
+            } else {
+                ordered ^ sign
+            };
+            <$ty>::from_bits(bits)
+        }
+    };
+}
+
+ordered_float!(f32, u32, encode_f32, decode_f32);
+ordered_float!(f64, u64, encode_f64, decode_f64);
+
+/// Numbers with an order-preserving byte encoding.
+pub trait OrderedNumber: Copy + Debug + 'static {
+    /// Encoded form, whose bytes sort in numeric order.
+    type Bytes: AsRef<[u8]> + Debug;
+
+    /// Encoded size in bytes.
+    const WIDTH: usize;
+
+    /// Name of the number type in `OrderedKey`'s redb type name.
+    const NAME: &'static str;
+
+    /// Encode the number.
+    fn to_ordered_bytes(self) -> Self::Bytes;
+
+    /// Decode a number from exactly `WIDTH` bytes.
+    ///
+    /// # Panics
+    /// If `bytes` is not `WIDTH` bytes long
+    fn from_ordered_bytes(bytes: &[u8]) -> Self;
+}
+
+macro_rules! ordered_number {
+    ($ty:ty, $encode:path, $decode:path) => {
+        impl OrderedNumber for $ty {
+            type Bytes = [u8; std::mem::size_of::<$ty>()];
+
+            const WIDTH: usize = std::mem::size_of::<$ty>();
+
+            const NAME: &'static str = stringify!($ty);
+
+            fn to_ordered_bytes(self) -> Self::Bytes {
+                $encode(self)
+            }
+
+            fn from_ordered_bytes(bytes: &[u8]) -> Self {
+                let bytes = bytes.try_into().unwrap_or_else(|_| {
+                    panic!(
+                        "{} data has {} bytes, expected {}",
+                        stringify!($ty),
+                        bytes.len(),
+                        Self::WIDTH
+                    )
+                });
+                $decode(bytes)
+            }
+        }
+    };
+}
+
+ordered_number!(i8, encode_i8, decode_i8);
+ordered_number!(i16, encode_i16, decode_i16);
+ordered_number!(i32, encode_i32, decode_i32);
+ordered_number!(i64, encode_i64, decode_i64);
+ordered_number!(f32, encode_f32, decode_f32);
+ordered_number!(f64, encode_f64, decode_f64);
+ordered_number!(u32, u32::to_be_bytes, u32::from_be_bytes);
+ordered_number!(u64, u64::to_be_bytes, u64::from_be_bytes);
+ordered_number!(u128, u128::to_be_bytes, u128::from_be_bytes);
+
+/// A redb key storing a number in its order-preserving encoding.
+///
+/// Keys compare by their raw bytes, so a table keyed by `OrderedKey<f64>`
+/// iterates in IEEE 754 total order and external tools reading the raw
+/// keys see them in the same order. Values are read and written as the
+/// plain number, e.g. `table.insert(-1.5, value)`.
+#[derive(Debug, Clone, Copy)]
+pub struct OrderedKey<T>(pub T);
+
+impl<T: OrderedNumber> Value for OrderedKey<T> {
+    type SelfType<'a>
+        = T
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = T::Bytes
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        Some(T::WIDTH)
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        T::from_ordered_bytes(data)
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        value.to_ordered_bytes()
+    }
+
+    fn type_name() -> TypeName {
+        TypeName::new(&format!("redb_extras::encoding::OrderedKey<{}>", T::NAME))
+    }
+}
+
+impl<T: OrderedNumber> Key for OrderedKey<T> {
+    fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+        data1.cmp(data2)
+    }
+}
+
+/// A `u32` key stored big-endian, so raw keys sort numerically.
+pub type BeU32 = OrderedKey<u32>;
+
+/// A `u64` key stored big-endian, so raw keys sort numerically.
+pub type BeU64 = OrderedKey<u64>;
+
+/// A `u128` key stored big-endian, so raw keys sort numerically.
+pub type BeU128 = OrderedKey<u128>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redb::{Database, ReadableDatabase, ReadableTable, TableDefinition};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn integers_sort_numerically() {
+        let values = [i64::MIN, -300, -1, 0, 1, 255, 256, i64::MAX];
+        for pair in values.windows(2) {
+            assert!(encode_i64(pair[0]) < encode_i64(pair[1]));
+        }
+        for value in values {
+            assert_eq!(decode_i64(encode_i64(value)), value);
+        }
+        assert!(encode_i8(-1) < encode_i8(0));
+        assert_eq!(decode_i16(encode_i16(i16::MIN)), i16::MIN);
+        assert_eq!(decode_i32(encode_i32(-7)), -7);
+    }
+
+    #[test]
+    fn floats_sort_by_total_order() {
+        let values = [
+            f64::NEG_INFINITY,
+            -1e300,
+            -1.5,
+            -f64::MIN_POSITIVE,
+            -0.0,
+            0.0,
+            f64::MIN_POSITIVE,
+            1.5,
+            1e300,
+            f64::INFINITY,
+            f64::NAN,
+        ];
+        for pair in values.windows(2) {
+            assert!(encode_f64(pair[0]) < encode_f64(pair[1]));
+        }
+        for value in values {
+            assert_eq!(decode_f64(encode_f64(value)).to_bits(), value.to_bits());
+        }
+        assert!(encode_f32(-2.0) < encode_f32(-1.0));
+        assert_eq!(decode_f32(encode_f32(0.25)), 0.25);
+    }
+
+    #[test]
+    fn ordered_keys_iterate_in_numeric_order() {
+        const READINGS: TableDefinition<OrderedKey<f64>, &str> = TableDefinition::new("readings");
+        let file = NamedTempFile::new().unwrap();
+        let db = Database::create(file.path()).unwrap();
+
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut readings = write_txn.open_table(READINGS).unwrap();
+            for (key, value) in [(2.5, "warm"), (-10.0, "freezing"), (0.0, "zero")] {
+                readings.insert(key, value).unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let readings = read_txn.open_table(READINGS).unwrap();
+        let keys: Vec<f64> = readings
+            .range(-5.0..)
+            .unwrap()
+            .map(|entry| entry.unwrap().0.value())
+            .collect();
+        assert_eq!(keys, vec![0.0, 2.5]);
+    }
+
+    #[test]
+    fn big_endian_keys_share_prefixes() {
+        const BLOCKS: TableDefinition<BeU64, ()> = TableDefinition::new("blocks");
+        let file = NamedTempFile::new().unwrap();
+        let db = Database::create(file.path()).unwrap();
+
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut blocks = write_txn.open_table(BLOCKS).unwrap();
+            for height in [0x0102, 0x01FF, 0x0200, 1, u64::MAX] {
+                blocks.insert(height, ()).unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let blocks = read_txn.open_table(BLOCKS).unwrap();
+        let heights: Vec<u64> = blocks
+            .iter()
+            .unwrap()
+            .map(|entry| entry.unwrap().0.value())
+            .collect();
+        assert_eq!(heights, vec![1, 0x0102, 0x01FF, 0x0200, u64::MAX]);
+
+        // Keys below 2^16 share six leading zero bytes
+        assert_eq!(BeU64::as_bytes(&0x01FF)[..6], [0; 6]);
+        assert_eq!(BeU32::as_bytes(&1), [0, 0, 0, 1]);
+        assert_eq!(BeU128::from_bytes(&BeU128::as_bytes(&u128::MAX)), u128::MAX);
+        assert_eq!(BeU128::fixed_width(), Some(16));
+    }
+
+    #[test]
+    fn type_names_are_stable() {
+        // Type names are persisted with each table, so they must not change
+        assert_eq!(
+            OrderedKey::<f64>::type_name().name(),
+            "redb_extras::encoding::OrderedKey<f64>"
+        );
+        assert_eq!(
+            BeU64::type_name().name(),
+            "redb_extras::encoding::OrderedKey<u64>"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Key ranges covering every key with a given prefix.
+
+use std::ops::Bound;
+
+/// Computes the range of byte keys starting with `prefix`.
+///
+/// The end bound is the smallest key greater than every key with the
+/// prefix: the prefix with its trailing `0xFF` bytes dropped and its last
+/// remaining byte incremented. A prefix made only of `0xFF` bytes, or an
+/// empty one, has no such key, so its range is unbounded above.
+///
+/// # Arguments
+/// * `prefix` - The key prefix
+///
+/// # Returns
+/// The inclusive start key and the exclusive end bound
+pub fn prefix_range(prefix: &[u8]) -> (Vec<u8>, Bound<Vec<u8>>) {
+    let end = match prefix.iter().rposition(|byte| *byte != 0xFF) {
+        Some(last) => {
+            let mut end = prefix[..=last].to_vec();
+            end[last] += 1;
+            Bound::Excluded(end)
+        }
+        None => Bound::Unbounded,
+    };
+    (prefix.to_vec(), end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redb::{Database, ReadableDatabase, TableDefinition};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn end_bound_carries_past_0xff() {
+        assert_eq!(
+            prefix_range(b"ab"),
+            (b"ab".to_vec(), Bound::Excluded(b"ac".to_vec()))
+        );
+        assert_eq!(
+            prefix_range(&[0x01, 0xFF, 0xFF]),
+            (vec![0x01, 0xFF, 0xFF], Bound::Excluded(vec![0x02]))
+        );
+        assert_eq!(prefix_range(&[0xFF, 0xFF]).1, Bound::Unbounded);
+        assert_eq!(prefix_range(&[]), (vec![], Bound::Unbounded));
+    }
+
+    #[test]
+    fn ranges_visit_exactly_the_prefixed_keys() {
+        const KEYS: TableDefinition<&[u8], ()> = TableDefinition::new("keys");
+        let file = NamedTempFile::new().unwrap();
+        let db = Database::create(file.path()).unwrap();
+
+        let keys: [&[u8]; 7] = [
+            &[0x01, 0xFE],
+            &[0x01, 0xFF],
+            &[0x01, 0xFF, 0x00],
+            &[0x01, 0xFF, 0xFF, 0xFF],
+            &[0x02],
+            &[0xFF, 0xFF],
+            &[0xFF, 0xFF, 0x01],
+        ];
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(KEYS).unwrap();
+            for key in keys {
+                table.insert(key, ()).unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let table = read_txn.open_table(KEYS).unwrap();
+        for prefix in [&[0x01, 0xFF][..], &[0xFF, 0xFF], &[0x01], &[]] {
+            let (start, end) = prefix_range(prefix);
+            let end = match &end {
+                Bound::Excluded(end) => Bound::Excluded(end.as_slice()),
+                _ => Bound::Unbounded,
+            };
+            let found: Vec<Vec<u8>> = table
+                .range::<&[u8]>((Bound::Included(start.as_slice()), end))
+                .unwrap()
+                .map(|entry| entry.unwrap().0.value().to_vec())
+                .collect();
+            let expected: Vec<Vec<u8>> = keys
+                .iter()
+                .filter(|key| key.starts_with(prefix))
+                .map(|key| key.to_vec())
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+}
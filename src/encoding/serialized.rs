@@ -0,0 +1,150 @@
+//! Values stored through serde.
+//!
+//! `SerdeValue<T>` stores any `T: Serialize + DeserializeOwned` as a
+//! format byte followed by its bincode encoding, so structs can be kept in
+//! redb tables without writing a `Value` impl for each. The format byte
+//! leaves room to change the serializer without breaking existing rows.
+//! Changes to `T` itself still have to be compatible with bincode, e.g. by
+//! wrapping the value in `Versioned` and migrating older payloads.
+
+use crate::encoding::{EncodingError, StableName};
+use redb::{TypeName, Value};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+/// Format byte of bincode-encoded values.
+const BINCODE: u8 = 1;
+
+/// A redb value storing a `T` through serde.
+///
+/// Values are read and written as the plain `T`, e.g.
+/// `table.insert(key, &user)`. `T` names itself through `StableName`, which
+/// becomes part of the table's persisted type name.
+#[derive(Debug)]
+pub struct SerdeValue<T>(PhantomData<T>);
+
+impl<T: Serialize + DeserializeOwned> SerdeValue<T> {
+    /// Encodes a value as stored in the table.
+    ///
+    /// # Returns
+    /// The format byte followed by the serialized value, or `Malformed` if
+    /// `value` can't be serialized
+    pub fn encode(value: &T) -> Result<Vec<u8>, EncodingError> {
+        let mut encoded = vec![BINCODE];
+        bincode::serialize_into(&mut encoded, value)
+            .map_err(|err| EncodingError::Malformed(format!("serialization failed: {}", err)))?;
+        Ok(encoded)
+    }
+
+    /// Decodes a value encoded by `encode`.
+    ///
+    /// # Returns
+    /// The value, or an error if the format byte is unknown or the data
+    /// doesn't deserialize as a `T`
+    pub fn decode(data: &[u8]) -> Result<T, EncodingError> {
+        match data.split_first() {
+            Some((&BINCODE, payload)) => bincode::deserialize(payload).map_err(|err| {
+                EncodingError::Malformed(format!("deserialization failed: {}", err))
+            }),
+            Some((&format, _)) => Err(EncodingError::UnsupportedVersion(format)),
+            None => Err(EncodingError::Malformed("empty serde value".to_string())),
+        }
+    }
+}
+
+impl<T> Value for SerdeValue<T>
+where
+    T: Serialize + DeserializeOwned + StableName + Debug + 'static,
+{
+    type SelfType<'a>
+        = T
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        Self::decode(data).unwrap_or_else(|err| panic!("SerdeValue data is invalid: {}", err))
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        Self::encode(value).unwrap_or_else(|err| panic!("SerdeValue can't encode value: {}", err))
+    }
+
+    fn type_name() -> TypeName {
+        TypeName::new(&format!("redb_extras::encoding::SerdeValue<{}>", T::NAME))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redb::{Database, ReadableDatabase, TableDefinition};
+    use serde::Deserialize;
+    use tempfile::NamedTempFile;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        tags: Vec<String>,
+        age: Option<u8>,
+    }
+
+    impl StableName for User {
+        const NAME: &'static str = "User";
+    }
+
+    #[test]
+    fn structs_round_trip() {
+        const USERS: TableDefinition<u64, SerdeValue<User>> = TableDefinition::new("users");
+        let file = NamedTempFile::new().unwrap();
+        let db = Database::create(file.path()).unwrap();
+
+        let user = User {
+            name: "ada".to_string(),
+            tags: vec!["admin".to_string()],
+            age: Some(36),
+        };
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut users = write_txn.open_table(USERS).unwrap();
+            users.insert(1, &user).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let users = read_txn.open_table(USERS).unwrap();
+        assert_eq!(users.get(1).unwrap().unwrap().value(), user);
+
+        let encoded = SerdeValue::encode(&user).unwrap();
+        assert_eq!(encoded[0], BINCODE);
+        assert!(matches!(
+            SerdeValue::<User>::decode(&[9, 0]),
+            Err(EncodingError::UnsupportedVersion(9))
+        ));
+        assert!(matches!(
+            SerdeValue::<User>::decode(&encoded[..encoded.len() - 1]),
+            Err(EncodingError::Malformed(_))
+        ));
+        assert_eq!(
+            SerdeValue::<User>::type_name().name(),
+            "redb_extras::encoding::SerdeValue<User>"
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! Versioned value envelopes.
+//!
+//! `Versioned<V, VERSION>` stores a value of type `V` behind a version
+//! byte. When the encoding of `V` changes, bump `VERSION` and register a
+//! migration from the previous version: rows written before are upgraded
+//! one version at a time when they're read, so tables don't have to be
+//! rewritten up front. Rows already at `VERSION` are read in place.
+//!
+//! Migrations are closures over the encoded payload, registered once per
+//! process with `Versioned::register_migration`, since redb decodes values
+//! without any handle to pass them through. They're keyed by the value type
+//! and the version they upgrade, so they survive later bumps of `VERSION`.
+//!
+//! redb can't report errors from value decoding, so a row that can't be
+//! brought to `VERSION` (a missing migration, or a version newer than the
+//! reader) is still returned, and its `value()` and `payload()` report the
+//! error instead of panicking inside the read.
+
+use crate::encoding::EncodingError;
+use redb::{TypeName, Value};
+use std::any::TypeId;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// Upgrades a payload from one version to the next.
+type Migration = Arc<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+/// Registered migrations, by value type and the version they upgrade.
+type MigrationRegistry = RwLock<HashMap<(TypeId, u8), Migration>>;
+
+fn migrations() -> &'static MigrationRegistry {
+    static MIGRATIONS: OnceLock<MigrationRegistry> = OnceLock::new();
+    MIGRATIONS.get_or_init(Default::default)
+}
+
+/// A redb value storing a `V` behind a version byte.
+///
+/// The type name doesn't include the version, so bumping `VERSION` keeps
+/// existing tables openable. Values are read and written as
+/// `VersionedValue`s, e.g. `table.insert(key, VersionedValue::new(&value))`.
+#[derive(Debug)]
+pub struct Versioned<V, const VERSION: u8 = 1>(PhantomData<V>);
+
+impl<V: Value + 'static, const VERSION: u8> Versioned<V, VERSION> {
+    /// Registers the migration upgrading payloads written at version
+    /// `from` to version `from + 1`, replacing any previous one.
+    ///
+    /// Migrations are shared by every `Versioned<V, _>` with the same `V`,
+    /// whatever its `VERSION`, so they only need registering once as the
+    /// version keeps growing.
+    ///
+    /// # Arguments
+    /// * `from` - The version the migration reads
+    /// * `migrate` - Converts a payload of version `from` to the next version
+    pub fn register_migration<F>(from: u8, migrate: F)
+    where
+        F: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        migrations()
+            .write()
+            .expect("migration registry poisoned")
+            .insert((TypeId::of::<V>(), from), Arc::new(migrate));
+    }
+
+    /// Decodes a stored envelope, migrating older payloads to `VERSION`.
+    ///
+    /// # Arguments
+    /// * `data` - The stored bytes
+    ///
+    /// # Returns
+    /// The value at the current version, or an error if the data is empty,
+    /// newer than `VERSION`, or a migration on the way is missing
+    pub fn decode(data: &[u8]) -> Result<VersionedValue<'_, V>, EncodingError> {
+        let (&version, payload) = data
+            .split_first()
+            .ok_or_else(|| EncodingError::Malformed("empty versioned value".to_string()))?;
+        if version > VERSION {
+            return Err(EncodingError::UnsupportedVersion(version));
+        }
+
+        let mut payload = Cow::Borrowed(payload);
+        for from in version..VERSION {
+            let migration = migrations()
+                .read()
+                .expect("migration registry poisoned")
+                .get(&(TypeId::of::<V>(), from))
+                .cloned()
+                .ok_or(EncodingError::UnsupportedVersion(from))?;
+            payload = Cow::Owned(migration(&payload));
+        }
+        Ok(VersionedValue::from_payload(payload))
+    }
+}
+
+/// Contents of a `VersionedValue`.
+enum Contents<'a> {
+    /// Payload at the current version
+    Current(Cow<'a, [u8]>),
+    /// Stored bytes that couldn't be brought to the current version, kept
+    /// as-is so writing the value back doesn't lose them
+    Undecodable {
+        stored: &'a [u8],
+        error: EncodingError,
+    },
+}
+
+/// A value read from or written to a `Versioned` table, holding the
+/// payload at the current version.
+pub struct VersionedValue<'a, V> {
+    contents: Contents<'a>,
+    _value: PhantomData<V>,
+}
+
+impl<'a, V: Value> VersionedValue<'a, V> {
+    /// Creates an envelope for writing `value`.
+    pub fn new(value: &V::SelfType<'_>) -> VersionedValue<'static, V> {
+        VersionedValue::from_payload(Cow::Owned(V::as_bytes(value).as_ref().to_vec()))
+    }
+
+    fn from_payload(payload: Cow<'a, [u8]>) -> Self {
+        Self {
+            contents: Contents::Current(payload),
+            _value: PhantomData,
+        }
+    }
+
+    /// Decodes the value.
+    ///
+    /// # Returns
+    /// The value, or the error that kept the stored row from being migrated
+    /// to the current version
+    pub fn value(&self) -> Result<V::SelfType<'_>, EncodingError> {
+        self.payload().map(V::from_bytes)
+    }
+
+    /// Gets the encoded value, without the version byte.
+    ///
+    /// # Returns
+    /// The payload, or the error that kept the stored row from being migrated
+    /// to the current version
+    pub fn payload(&self) -> Result<&[u8], EncodingError> {
+        match &self.contents {
+            Contents::Current(payload) => Ok(payload),
+            Contents::Undecodable { error, .. } => Err(error.clone()),
+        }
+    }
+}
+
+impl<V> fmt::Debug for VersionedValue<'_, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.contents {
+            Contents::Current(payload) => f
+                .debug_struct("VersionedValue")
+                .field("payload", payload)
+                .finish(),
+            Contents::Undecodable { stored, error } => f
+                .debug_struct("VersionedValue")
+                .field("stored", stored)
+                .field("error", error)
+                .finish(),
+        }
+    }
+}
+
+impl<V: Value + 'static, const VERSION: u8> Value for Versioned<V, VERSION> {
+    type SelfType<'a>
+        = VersionedValue<'a, V>
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        Self::decode(data).unwrap_or_else(|error| VersionedValue {
+            contents: Contents::Undecodable {
+                stored: data,
+                error,
+            },
+            _value: PhantomData,
+        })
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        match &value.contents {
+            Contents::Current(payload) => {
+                let mut encoded = Vec::with_capacity(1 + payload.len());
+                encoded.push(VERSION);
+                encoded.extend_from_slice(payload);
+                encoded
+            }
+            Contents::Undecodable { stored, .. } => stored.to_vec(),
+        }
+    }
+
+    fn type_name() -> TypeName {
+        TypeName::new(&format!(
+            "redb_extras::encoding::Versioned<{}>",
+            V::type_name().name()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redb::{Database, ReadableDatabase, TableDefinition};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn old_rows_are_migrated_on_read() {
+        // Version 1 stored a name, version 2 a "name:score" pair
+        const V1: TableDefinition<u64, Versioned<&str>> = TableDefinition::new("players");
+        const V2: TableDefinition<u64, Versioned<&str, 2>> = TableDefinition::new("players");
+        let file = NamedTempFile::new().unwrap();
+        let db = Database::create(file.path()).unwrap();
+
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut players = write_txn.open_table(V1).unwrap();
+            players.insert(1, VersionedValue::new(&"ada")).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        assert!(matches!(
+            Versioned::<&str, 2>::decode(&[1, b'a']),
+            Err(EncodingError::UnsupportedVersion(1))
+        ));
+        Versioned::<&str, 2>::register_migration(1, |name| [name, b":0"].concat());
+
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut players = write_txn.open_table(V2).unwrap();
+            players.insert(2, VersionedValue::new(&"bob:7")).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let players = read_txn.open_table(V2).unwrap();
+        assert_eq!(
+            players.get(1).unwrap().unwrap().value().value().unwrap(),
+            "ada:0"
+        );
+        assert_eq!(
+            players.get(2).unwrap().unwrap().value().value().unwrap(),
+            "bob:7"
+        );
+
+        // Rows newer than the reader are rejected
+        assert!(matches!(
+            Versioned::<&str>::decode(&[2, b'a']),
+            Err(EncodingError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            Versioned::<&str>::decode(&[]),
+            Err(EncodingError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn migrations_survive_version_bumps() {
+        // Each test uses its own value type, since the registry is global
+        Versioned::<u32, 2>::register_migration(1, |old| [old, &[0]].concat());
+        Versioned::<u32, 3>::register_migration(2, |old| old[..4].to_vec());
+
+        let v1 = [1, 7, 0, 0, 0];
+        let value = Versioned::<u32, 3>::decode(&v1).unwrap();
+        assert_eq!(value.value().unwrap(), 7);
+    }
+
+    #[test]
+    fn missing_migrations_are_reported_on_read() {
+        const V1: TableDefinition<u64, Versioned<u16>> = TableDefinition::new("counters");
+        const V2: TableDefinition<u64, Versioned<u16, 2>> = TableDefinition::new("counters");
+        let file = NamedTempFile::new().unwrap();
+        let db = Database::create(file.path()).unwrap();
+
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut counters = write_txn.open_table(V1).unwrap();
+            counters.insert(1, VersionedValue::new(&5)).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let counters = read_txn.open_table(V2).unwrap();
+        let stored = counters.get(1).unwrap().unwrap();
+        assert!(matches!(
+            stored.value().value(),
+            Err(EncodingError::UnsupportedVersion(1))
+        ));
+
+        // Writing an unreadable row back keeps its original bytes
+        let stored = [1, 5, 0];
+        let value = Versioned::<u16, 2>::from_bytes(&stored);
+        assert_eq!(Versioned::<u16, 2>::as_bytes(&value), stored);
+    }
+}
@@ -8,11 +8,221 @@ use std::fmt;
 /// Result type alias for convenience
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Broad class of an error, for deciding how to react to it.
+///
+/// Transaction-retry loops should retry only `Transient` errors; the other
+/// kinds fail the same way until the data, configuration or input changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The database is busy or hit an I/O error; the operation may succeed
+    /// in a new transaction, possibly after reopening the database
+    Transient,
+
+    /// Stored data is corrupted or can't be decoded
+    Corruption,
+
+    /// Persisted configuration, table types or formats disagree with the
+    /// ones in use
+    ConfigMismatch,
+
+    /// A table the operation needs doesn't exist
+    NotFound,
+
+    /// The arguments or the way the API was used are invalid
+    InvalidInput,
+
+    /// Any other failure, not expected to go away on retry
+    Other,
+}
+
+impl ErrorKind {
+    /// Classifies a redb error.
+    pub fn of_redb(err: &redb::Error) -> Self {
+        match err {
+            redb::Error::DatabaseAlreadyOpen
+            | redb::Error::TransactionInProgress
+            | redb::Error::ReadTransactionStillInUse(_)
+            | redb::Error::Io(_)
+            | redb::Error::PreviousIo
+            | redb::Error::LockPoisoned(_) => ErrorKind::Transient,
+            redb::Error::Corrupted(_) => ErrorKind::Corruption,
+            redb::Error::UpgradeRequired(_)
+            | redb::Error::TableTypeMismatch { .. }
+            | redb::Error::TableIsMultimap(_)
+            | redb::Error::TableIsNotMultimap(_)
+            | redb::Error::TypeDefinitionChanged { .. } => ErrorKind::ConfigMismatch,
+            redb::Error::TableDoesNotExist(_) => ErrorKind::NotFound,
+            redb::Error::InvalidSavepoint
+            | redb::Error::ValueTooLarge(_)
+            | redb::Error::TableExists(_)
+            | redb::Error::TableAlreadyOpen(_, _) => ErrorKind::InvalidInput,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// Whether retrying the operation may succeed.
+    pub fn is_retryable(self) -> bool {
+        self == ErrorKind::Transient
+    }
+}
+
+/// The entity a failed operation was working on.
+///
+/// Fields are set when the operation knows them, e.g. a segment read sets
+/// the table, base key, shard and segment, while a bucket scan sets the
+/// table and bucket.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ErrorContext {
+    /// Name of the table or bucketed table
+    pub table: Option<String>,
+    /// Encoded base key
+    pub key: Option<Vec<u8>>,
+    /// Shard of the key
+    pub shard: Option<u16>,
+    /// Segment within the shard
+    pub segment: Option<u16>,
+    /// Bucket of a bucketed table
+    pub bucket: Option<u64>,
+}
+
+impl ErrorContext {
+    /// Whether no field is set.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut fields = Vec::new();
+        if let Some(table) = &self.table {
+            fields.push(format!("table '{}'", table));
+        }
+        if let Some(key) = &self.key {
+            let hex: String = key.iter().map(|byte| format!("{:02x}", byte)).collect();
+            fields.push(format!("key 0x{}", hex));
+        }
+        if let Some(shard) = self.shard {
+            fields.push(format!("shard {}", shard));
+        }
+        if let Some(segment) = self.segment {
+            fields.push(format!("segment {}", segment));
+        }
+        if let Some(bucket) = self.bucket {
+            fields.push(format!("bucket {}", bucket));
+        }
+        write!(f, "{}", fields.join(", "))
+    }
+}
+
+/// A redb error together with the operation that failed.
+///
+/// The module error types wrap this instead of the error's message, so
+/// callers can still inspect the original redb error, e.g. to retry on
+/// I/O errors, through `redb_error` or `source`, and the entity it hit
+/// through `context`. Both are boxed to keep the error enums embedding
+/// this small.
+#[derive(Debug)]
+pub struct DatabaseError {
+    operation: String,
+    context: Box<ErrorContext>,
+    source: Box<redb::Error>,
+}
+
+impl DatabaseError {
+    /// Creates an error for a failed operation.
+    ///
+    /// # Arguments
+    /// * `operation` - What was being done, e.g. "Failed to open segment table"
+    /// * `source` - The redb error, of any of redb's error types
+    pub fn new(operation: impl Into<String>, source: impl Into<redb::Error>) -> Self {
+        Self {
+            operation: operation.into(),
+            context: Box::default(),
+            source: Box::new(source.into()),
+        }
+    }
+
+    /// Records the table the operation worked on.
+    pub fn table(mut self, table: impl Into<String>) -> Self {
+        self.context.table = Some(table.into());
+        self
+    }
+
+    /// Records the encoded base key the operation worked on.
+    pub fn key(mut self, key: &[u8]) -> Self {
+        self.context.key = Some(key.to_vec());
+        self
+    }
+
+    /// Records the shard the operation worked on.
+    pub fn shard(mut self, shard: u16) -> Self {
+        self.context.shard = Some(shard);
+        self
+    }
+
+    /// Records the segment the operation worked on.
+    pub fn segment(mut self, segment: u16) -> Self {
+        self.context.segment = Some(segment);
+        self
+    }
+
+    /// Records the bucket the operation worked on.
+    pub fn bucket(mut self, bucket: u64) -> Self {
+        self.context.bucket = Some(bucket);
+        self
+    }
+
+    /// Gets the operation that failed.
+    pub fn operation(&self) -> &str {
+        &self.operation
+    }
+
+    /// Gets the entity the operation worked on.
+    pub fn context(&self) -> &ErrorContext {
+        &self.context
+    }
+
+    /// Gets the underlying redb error.
+    pub fn redb_error(&self) -> &redb::Error {
+        &self.source
+    }
+
+    /// Consumes the error and returns the underlying redb error.
+    pub fn into_redb_error(self) -> redb::Error {
+        *self.source
+    }
+
+    /// Classifies the underlying redb error.
+    pub fn kind(&self) -> ErrorKind {
+        ErrorKind::of_redb(&self.source)
+    }
+}
+
+impl std::error::Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.context.is_empty() {
+            write!(f, "{}: {}", self.operation, self.source)
+        } else {
+            write!(f, "{} ({}): {}", self.operation, self.context, self.source)
+        }
+    }
+}
+
 /// Main error type exposed to users of the crate.
 ///
 /// This provides a simple interface for facade users while wrapping more specific
 /// internal error types for debugging and advanced usage.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Errors from the partition layer (generic storage mechanics)
     Partition(crate::partition::PartitionError),
@@ -26,11 +236,35 @@ pub enum Error {
     /// Errors from the database copy utilities
     DbCopy(crate::dbcopy::DbCopyError),
 
+    /// Errors from the key encodings
+    Encoding(crate::encoding::EncodingError),
+
     /// Invalid input parameters
     InvalidInput(String),
 
-    /// Transaction-related errors
-    TransactionFailed(String),
+    /// Errors from redb outside of any of the layers above
+    Redb(redb::Error),
+}
+
+impl Error {
+    /// Classifies the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Partition(err) => err.kind(),
+            Error::Roaring(err) => err.kind(),
+            Error::Bucket(err) => err.kind(),
+            Error::DbCopy(err) => err.kind(),
+            Error::Encoding(err) => err.kind(),
+            Error::InvalidInput(_) => ErrorKind::InvalidInput,
+            Error::Redb(err) => ErrorKind::of_redb(err),
+        }
+    }
+
+    /// Whether the error is transient, so retrying the operation in a new
+    /// transaction may succeed.
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
 }
 
 impl From<crate::partition::PartitionError> for Error {
@@ -57,21 +291,52 @@ impl From<crate::dbcopy::DbCopyError> for Error {
     }
 }
 
+impl From<crate::encoding::EncodingError> for Error {
+    fn from(err: crate::encoding::EncodingError) -> Self {
+        Error::Encoding(err)
+    }
+}
+
+impl From<redb::Error> for Error {
+    fn from(err: redb::Error) -> Self {
+        Error::Redb(err)
+    }
+}
+
 impl From<redb::StorageError> for Error {
     fn from(err: redb::StorageError) -> Self {
-        Error::TransactionFailed(format!("Storage error: {}", err))
+        Error::Redb(err.into())
+    }
+}
+
+impl From<redb::TableError> for Error {
+    fn from(err: redb::TableError) -> Self {
+        Error::Redb(err.into())
+    }
+}
+
+impl From<redb::TransactionError> for Error {
+    fn from(err: redb::TransactionError) -> Self {
+        Error::Redb(err.into())
+    }
+}
+
+impl From<redb::CommitError> for Error {
+    fn from(err: redb::CommitError) -> Self {
+        Error::Redb(err.into())
     }
 }
 
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            Error::Partition(err) => err.source(),
-            Error::Roaring(err) => err.source(),
-            Error::Bucket(err) => err.source(),
-            Error::DbCopy(err) => err.source(),
+            Error::Partition(err) => Some(err),
+            Error::Roaring(err) => Some(err),
+            Error::Bucket(err) => Some(err),
+            Error::DbCopy(err) => Some(err),
+            Error::Encoding(err) => Some(err),
+            Error::Redb(err) => Some(err),
             Error::InvalidInput(_) => None,
-            Error::TransactionFailed(_) => None,
         }
     }
 }
@@ -83,8 +348,61 @@ impl fmt::Display for Error {
             Error::Roaring(err) => write!(f, "Roaring error: {}", err),
             Error::Bucket(err) => write!(f, "Bucket error: {}", err),
             Error::DbCopy(err) => write!(f, "Database copy error: {}", err),
+            Error::Encoding(err) => write!(f, "Encoding error: {}", err),
             Error::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
-            Error::TransactionFailed(msg) => write!(f, "Transaction failed: {}", msg),
+            Error::Redb(err) => write!(f, "Database error: {}", err),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_is_shown_and_kept() {
+        let err = DatabaseError::new(
+            "Failed to read segment",
+            redb::Error::Corrupted("bad page".to_string()),
+        )
+        .table("users")
+        .key(b"ab")
+        .shard(3)
+        .segment(1);
+        assert_eq!(
+            err.to_string(),
+            "Failed to read segment (table 'users', key 0x6162, shard 3, segment 1): \
+             DB corrupted: bad page"
+        );
+        assert_eq!(err.context().key.as_deref(), Some(&b"ab"[..]));
+        assert_eq!(err.context().bucket, None);
+
+        let err = DatabaseError::new("Failed to commit", redb::Error::DatabaseAlreadyOpen);
+        assert!(err.context().is_empty());
+        assert!(!err.to_string().contains('('));
+    }
+
+    #[test]
+    fn errors_are_classified() {
+        assert!(Error::from(redb::Error::PreviousIo).is_retryable());
+        let io = std::io::Error::new(std::io::ErrorKind::Other, "disk unplugged");
+        assert!(Error::from(redb::StorageError::Io(io)).is_retryable());
+
+        let corrupted = Error::from(crate::partition::PartitionError::DatabaseError(
+            DatabaseError::new("Failed to read segment", redb::Error::Corrupted("x".into())),
+        ));
+        assert_eq!(corrupted.kind(), ErrorKind::Corruption);
+        assert!(!corrupted.is_retryable());
+
+        let mismatch = Error::from(crate::partition::PartitionError::ConfigMismatch(
+            "shard_count".to_string(),
+        ));
+        assert_eq!(mismatch.kind(), ErrorKind::ConfigMismatch);
+        let missing = Error::from(redb::TableError::TableDoesNotExist("t".to_string()));
+        assert_eq!(missing.kind(), ErrorKind::NotFound);
+        assert_eq!(
+            Error::InvalidInput("abort".to_string()).kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+}
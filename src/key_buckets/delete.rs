@@ -0,0 +1,273 @@
+//! Deleting a sequence range for one base key.
+//!
+//! The buckets covering a sequence range are computed with the same key
+//! builder used to write them, and each bucket entry of the base key is
+//! removed with a point delete.
+
+use crate::error::DatabaseError;
+use crate::key_buckets::iterator::{bucket_bounds, multimap_bucket_span, table_bucket_span};
+use crate::key_buckets::key::{BucketScheme, BucketedKey};
+use crate::key_buckets::BucketError;
+use redb::{Key, MultimapTable, ReadableMultimapTable, Table, Value};
+use std::borrow::Borrow;
+use std::ops::{Bound, RangeBounds};
+
+fn delete_error(base_key: &[u8], bucket: u64, err: impl Into<redb::Error>) -> BucketError {
+    BucketError::DatabaseError(
+        DatabaseError::new("Failed to delete bucket range", err)
+            .key(base_key)
+            .bucket(bucket),
+    )
+}
+
+/// Extension trait for deleting a sequence range on bucketed tables.
+pub trait BucketDeleteExt<K>
+where
+    K: Key + 'static,
+{
+    /// Removes every bucket entry of a base key that overlaps a sequence range.
+    ///
+    /// Buckets at the ends of the range may also cover sequences outside it.
+    /// They are removed whole, since the sequence of individual values isn't
+    /// known; see `delete_range_exact` on multimaps to trim them instead.
+    /// Unbounded ends resolve to the stored buckets as for `bucket_range`.
+    ///
+    /// # Arguments
+    /// * `key_builder` - The builder the keys were created with
+    /// * `base_key` - The base key to delete from
+    /// * `range` - The sequence range to delete
+    ///
+    /// # Returns
+    /// Number of values removed
+    fn delete_range<'k>(
+        &mut self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+    ) -> Result<u64, BucketError>;
+}
+
+impl<K, V> BucketDeleteExt<K> for Table<'_, BucketedKey<K>, V>
+where
+    K: Key + 'static,
+    V: Value + 'static,
+{
+    fn delete_range<'k>(
+        &mut self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+    ) -> Result<u64, BucketError> {
+        let Some((start_bucket, end_bucket)) =
+            bucket_bounds(key_builder, &range, || table_bucket_span(&*self))?
+        else {
+            return Ok(0);
+        };
+
+        let base_key = K::as_bytes(base_key.borrow()).as_ref().to_vec();
+        let mut removed = 0;
+        for bucket in start_bucket..=end_bucket {
+            let key = BucketedKey::new(K::from_bytes(&base_key), bucket);
+            if self
+                .remove(&key)
+                .map_err(|err| delete_error(&base_key, bucket, err))?
+                .is_some()
+            {
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+impl<K, V> BucketDeleteExt<K> for MultimapTable<'_, BucketedKey<K>, V>
+where
+    K: Key + 'static,
+    V: Key + 'static,
+{
+    fn delete_range<'k>(
+        &mut self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+    ) -> Result<u64, BucketError> {
+        let Some((start_bucket, end_bucket)) =
+            bucket_bounds(key_builder, &range, || multimap_bucket_span(&*self))?
+        else {
+            return Ok(0);
+        };
+
+        let base_key = K::as_bytes(base_key.borrow()).as_ref().to_vec();
+        let mut removed = 0;
+        for bucket in start_bucket..=end_bucket {
+            let key = BucketedKey::new(K::from_bytes(&base_key), bucket);
+            removed += self
+                .remove_all(&key)
+                .map_err(|err| delete_error(&base_key, bucket, err))?
+                .len();
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Extension trait for exact sequence range deletion on bucketed multimaps.
+pub trait BucketMultimapDeleteExt<K, V>
+where
+    K: Key + 'static,
+    V: Key + 'static,
+{
+    /// Removes the values of a base key stored within an exact sequence range.
+    ///
+    /// Like `delete_range`, but values in the boundary buckets are only
+    /// removed if `sequence_of` places them inside the range. Interior
+    /// buckets are removed whole without decoding their values.
+    ///
+    /// # Arguments
+    /// * `key_builder` - The builder the keys were created with
+    /// * `base_key` - The base key to delete from
+    /// * `range` - The sequence range to delete
+    /// * `sequence_of` - Returns the sequence a value was stored at
+    ///
+    /// # Returns
+    /// Number of values removed
+    fn delete_range_exact<'k>(
+        &mut self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+        sequence_of: impl Fn(&V) -> u64,
+    ) -> Result<u64, BucketError>;
+}
+
+impl<K, V> BucketMultimapDeleteExt<K, V> for MultimapTable<'_, BucketedKey<K>, V>
+where
+    K: Key + 'static,
+    V: Key + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+{
+    fn delete_range_exact<'k>(
+        &mut self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+        sequence_of: impl Fn(&V) -> u64,
+    ) -> Result<u64, BucketError> {
+        let Some((start_bucket, end_bucket)) =
+            bucket_bounds(key_builder, &range, || multimap_bucket_span(&*self))?
+        else {
+            return Ok(0);
+        };
+
+        let in_range = |sequence: u64| {
+            let after_start = match range.start_bound() {
+                Bound::Included(&start) => sequence >= start,
+                Bound::Excluded(&start) => sequence > start,
+                Bound::Unbounded => true,
+            };
+            let before_end = match range.end_bound() {
+                Bound::Included(&end) => sequence <= end,
+                Bound::Excluded(&end) => sequence < end,
+                Bound::Unbounded => true,
+            };
+            after_start && before_end
+        };
+
+        let base_key = K::as_bytes(base_key.borrow()).as_ref().to_vec();
+        let mut removed = 0;
+        for bucket in start_bucket..=end_bucket {
+            let key = BucketedKey::new(K::from_bytes(&base_key), bucket);
+            if bucket != start_bucket && bucket != end_bucket {
+                removed += self
+                    .remove_all(&key)
+                    .map_err(|err| delete_error(&base_key, bucket, err))?
+                    .len();
+                continue;
+            }
+
+            // Boundary bucket: collect the encoded values to remove first
+            let mut doomed: Vec<Vec<u8>> = Vec::new();
+            for value in self
+                .get(&key)
+                .map_err(|err| delete_error(&base_key, bucket, err))?
+            {
+                let guard = value.map_err(|err| delete_error(&base_key, bucket, err))?;
+                let value = guard.value();
+                let bytes = V::as_bytes(&value).as_ref().to_vec();
+                if in_range(sequence_of(&V::from(value))) {
+                    doomed.push(bytes);
+                }
+            }
+
+            for bytes in doomed {
+                if self
+                    .remove(&key, V::from_bytes(&bytes))
+                    .map_err(|err| delete_error(&base_key, bucket, err))?
+                {
+                    removed += 1;
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_buckets::KeyBuilder;
+    use redb::{Database, MultimapTableDefinition, ReadableTableMetadata, TableDefinition};
+    use tempfile::NamedTempFile;
+
+    const BALANCES: TableDefinition<'static, BucketedKey<u64>, u64> =
+        TableDefinition::new("balances");
+    const TRADES: MultimapTableDefinition<'static, BucketedKey<u64>, u64> =
+        MultimapTableDefinition::new("trades");
+
+    #[test]
+    fn test_delete_range() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+
+        let write_txn = db.begin_write()?;
+        {
+            let mut balances = write_txn.open_table(BALANCES)?;
+            let mut trades = write_txn.open_multimap_table(TRADES)?;
+            for sequence in (0..500).step_by(50) {
+                for account in [1u64, 2] {
+                    balances.insert(key_builder.bucketed_key(account, sequence), sequence)?;
+                    trades.insert(key_builder.bucketed_key(account, sequence), sequence)?;
+                }
+            }
+
+            // Buckets 1 and 2 overlap 150..=220, including the boundary values 100 and 250
+            assert_eq!(balances.delete_range(&key_builder, 1u64, 150..=220)?, 2);
+            assert_eq!(balances.len()?, 8);
+            assert_eq!(trades.delete_range(&key_builder, 1u64, 150..=220)?, 4);
+
+            // Exact deletion keeps boundary values outside the range
+            assert_eq!(
+                trades.delete_range_exact(&key_builder, 2u64, 150..=320, |value| *value)?,
+                4
+            );
+            let remaining: Vec<u64> = trades
+                .iter()?
+                .filter_map(|entry| {
+                    let (key, values) = entry.ok()?;
+                    (key.value().base_key == 2).then(|| values.map(|v| v.unwrap().value()))
+                })
+                .flatten()
+                .collect();
+            assert_eq!(remaining, vec![0, 50, 100, 350, 400, 450]);
+
+            // Open-ended ranges stop at the stored buckets
+            assert_eq!(trades.delete_range(&key_builder, 2u64, ..)?, 6);
+        }
+        write_txn.commit()?;
+
+        Ok(())
+    }
+}
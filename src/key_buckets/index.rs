@@ -0,0 +1,209 @@
+//! Reverse index over a bucketed table.
+//!
+//! Finding where a value was stored normally means iterating every bucket of
+//! every base key. `IndexedBucketTable` keeps a companion multimap from each
+//! value to the bucketed keys holding it, updated on every write made through
+//! it, so that question becomes a point lookup.
+
+use crate::error::DatabaseError;
+use crate::key_buckets::key::{BucketScheme, BucketedKey};
+use crate::key_buckets::BucketError;
+use redb::{
+    Key, MultimapTable, MultimapTableDefinition, MultimapValue, ReadableMultimapTable,
+    ReadableTableMetadata, Table, TableDefinition, WriteTransaction,
+};
+use std::borrow::Borrow;
+
+fn index_error(err: impl Into<redb::Error>) -> BucketError {
+    BucketError::DatabaseError(DatabaseError::new("Failed to update bucket index", err))
+}
+
+/// A bucketed table with a reverse index from values to bucketed keys.
+///
+/// Writes must go through `insert` and `remove` to keep the index in sync;
+/// entries written to the table directly are not indexed. Readers can open
+/// the index definition as a regular multimap and look values up with `get`.
+pub struct IndexedBucketTable<'txn, K, V>
+where
+    K: Key + 'static,
+    V: Key + 'static,
+{
+    table: Table<'txn, BucketedKey<K>, V>,
+    index: MultimapTable<'txn, V, BucketedKey<K>>,
+}
+
+impl<'txn, K, V> IndexedBucketTable<'txn, K, V>
+where
+    K: Key + 'static,
+    V: Key + 'static,
+{
+    /// Opens a bucketed table together with its index.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `table` - The bucketed table
+    /// * `index` - The multimap holding the reverse index
+    ///
+    /// # Returns
+    /// The opened table pair
+    pub fn open(
+        txn: &'txn WriteTransaction,
+        table: TableDefinition<'static, BucketedKey<K>, V>,
+        index: MultimapTableDefinition<'static, V, BucketedKey<K>>,
+    ) -> Result<Self, BucketError> {
+        Ok(Self {
+            table: txn.open_table(table).map_err(index_error)?,
+            index: txn.open_multimap_table(index).map_err(index_error)?,
+        })
+    }
+
+    /// Stores a value for a base key at a sequence and indexes it.
+    ///
+    /// A value previously stored in the same bucket is replaced and its
+    /// index entry removed.
+    ///
+    /// # Arguments
+    /// * `key_builder` - The builder used for the table
+    /// * `base_key` - The base key to store under
+    /// * `sequence` - The sequence the value belongs to
+    /// * `value` - The value to store
+    pub fn insert<'k, 'v>(
+        &mut self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        sequence: u64,
+        value: impl Borrow<V::SelfType<'v>>,
+    ) -> Result<(), BucketError> {
+        let base_key = K::as_bytes(base_key.borrow()).as_ref().to_vec();
+        let key = BucketedKey::new(K::from_bytes(&base_key), key_builder.bucket_for(sequence));
+
+        if let Some(previous) = self
+            .table
+            .insert(&key, value.borrow())
+            .map_err(index_error)?
+        {
+            self.index
+                .remove(previous.value(), &key)
+                .map_err(index_error)?;
+        }
+        self.index.insert(value, &key).map_err(index_error)?;
+
+        Ok(())
+    }
+
+    /// Removes the value stored for a base key in a sequence's bucket.
+    ///
+    /// # Arguments
+    /// * `key_builder` - The builder used for the table
+    /// * `base_key` - The base key to remove from
+    /// * `sequence` - Any sequence within the bucket to remove
+    ///
+    /// # Returns
+    /// Whether a value was removed
+    pub fn remove<'k>(
+        &mut self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        sequence: u64,
+    ) -> Result<bool, BucketError> {
+        let base_key = K::as_bytes(base_key.borrow()).as_ref().to_vec();
+        let key = BucketedKey::new(K::from_bytes(&base_key), key_builder.bucket_for(sequence));
+
+        match self.table.remove(&key).map_err(index_error)? {
+            Some(previous) => {
+                self.index
+                    .remove(previous.value(), &key)
+                    .map_err(index_error)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the bucketed keys currently holding a value.
+    pub fn find<'v>(
+        &self,
+        value: impl Borrow<V::SelfType<'v>>,
+    ) -> Result<MultimapValue<'_, BucketedKey<K>>, BucketError> {
+        self.index.get(value).map_err(index_error)
+    }
+
+    /// Get the underlying bucketed table, e.g. for bucket iteration.
+    pub fn table(&self) -> &Table<'txn, BucketedKey<K>, V> {
+        &self.table
+    }
+
+    /// Get the underlying index table.
+    pub fn index(&self) -> &MultimapTable<'txn, V, BucketedKey<K>> {
+        &self.index
+    }
+
+    /// Number of values stored in the bucketed table.
+    pub fn len(&self) -> Result<u64, BucketError> {
+        self.table.len().map_err(index_error)
+    }
+
+    /// Whether the bucketed table is empty.
+    pub fn is_empty(&self) -> Result<bool, BucketError> {
+        self.table.is_empty().map_err(index_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_buckets::KeyBuilder;
+    use redb::{Database, ReadableDatabase};
+    use tempfile::NamedTempFile;
+
+    const ORDERS: TableDefinition<'static, BucketedKey<u64>, &str> = TableDefinition::new("orders");
+    const ORDERS_BY_ID: MultimapTableDefinition<'static, &str, BucketedKey<u64>> =
+        MultimapTableDefinition::new("orders_by_id");
+
+    fn located(
+        keys: MultimapValue<'_, BucketedKey<u64>>,
+    ) -> Result<Vec<(u64, u64)>, Box<dyn std::error::Error>> {
+        let mut found = Vec::new();
+        for key in keys {
+            let key = key?.value();
+            found.push((key.base_key, key.bucket));
+        }
+        Ok(found)
+    }
+
+    #[test]
+    fn test_indexed_bucket_table() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+
+        let write_txn = db.begin_write()?;
+        {
+            let mut orders = IndexedBucketTable::open(&write_txn, ORDERS, ORDERS_BY_ID)?;
+            orders.insert(&key_builder, 1u64, 50, "a1")?;
+            orders.insert(&key_builder, 1u64, 150, "a2")?;
+            orders.insert(&key_builder, 2u64, 120, "a2")?;
+            orders.insert(&key_builder, 2u64, 320, "b1")?;
+            assert_eq!(located(orders.find("a2")?)?, vec![(1, 1), (2, 1)]);
+
+            // Overwriting a bucket moves the index entry
+            orders.insert(&key_builder, 1u64, 199, "c1")?;
+            assert_eq!(located(orders.find("a2")?)?, vec![(2, 1)]);
+            assert_eq!(located(orders.find("c1")?)?, vec![(1, 1)]);
+
+            assert!(orders.remove(&key_builder, 2u64, 300)?);
+            assert!(!orders.remove(&key_builder, 2u64, 500)?);
+            assert!(located(orders.find("b1")?)?.is_empty());
+            assert_eq!(orders.len()?, 3);
+        }
+        write_txn.commit()?;
+
+        // Readers use the index as a plain multimap
+        let read_txn = db.begin_read()?;
+        let index = read_txn.open_multimap_table(ORDERS_BY_ID)?;
+        assert_eq!(located(index.get("a1")?)?, vec![(1, 0)]);
+        assert_eq!(index.get("b1")?.len(), 0);
+
+        Ok(())
+    }
+}
@@ -2,10 +2,183 @@
 //!
 //! Provides efficient iteration over bucket ranges for specific base keys.
 
-use crate::key_buckets::key::{BucketedKey, KeyBuilder};
+use crate::error::DatabaseError;
+use crate::key_buckets::key::{BucketScheme, BucketedKey};
+use crate::key_buckets::merged::{MergedBucketIterator, MergedBucketMultimapIterator};
 use crate::key_buckets::BucketError;
-use redb::{ReadOnlyMultimapTable, ReadOnlyTable};
-use std::collections::VecDeque;
+use redb::{
+    Key, MultimapValue, ReadOnlyMultimapTable, ReadOnlyTable, ReadableMultimapTable, ReadableTable,
+};
+use std::borrow::Borrow;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+/// Extracts the sequence a multimap value was stored at.
+type SequenceOf<V> = Box<dyn Fn(&V) -> u64>;
+
+/// Computes the inclusive bucket range covering a sequence range.
+///
+/// Unbounded ends are resolved with `stored`, which returns the first and
+/// last bucket present in the table. Returns None when the range is empty or
+/// lies outside the stored buckets.
+pub(super) fn bucket_bounds<R, F>(
+    key_builder: &impl BucketScheme,
+    range: &R,
+    stored: F,
+) -> Result<Option<(u64, u64)>, BucketError>
+where
+    R: RangeBounds<u64>,
+    F: FnOnce() -> Result<Option<(u64, u64)>, BucketError>,
+{
+    if let (
+        Bound::Included(&start) | Bound::Excluded(&start),
+        Bound::Included(&end) | Bound::Excluded(&end),
+    ) = (range.start_bound(), range.end_bound())
+    {
+        if start > end {
+            return Err(BucketError::InvalidRange { start, end });
+        }
+    }
+
+    let start = match range.start_bound() {
+        Bound::Included(&start) => Some(start),
+        Bound::Excluded(&start) => match start.checked_add(1) {
+            Some(start) => Some(start),
+            None => return Ok(None),
+        },
+        Bound::Unbounded => None,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => Some(end),
+        Bound::Excluded(&end) => match end.checked_sub(1) {
+            Some(end) => Some(end),
+            None => return Ok(None),
+        },
+        Bound::Unbounded => None,
+    };
+
+    let (first_stored, last_stored) = if start.is_none() || end.is_none() {
+        match stored()? {
+            Some(stored) => stored,
+            None => return Ok(None),
+        }
+    } else {
+        (0, u64::MAX)
+    };
+
+    let start_bucket = start.map_or(first_stored, |start| key_builder.bucket_for(start));
+    let end_bucket = end.map_or(last_stored, |end| key_builder.bucket_for(end));
+
+    if start.zip(end).is_some_and(|(start, end)| start > end) || start_bucket > end_bucket {
+        return Ok(None);
+    }
+    Ok(Some((start_bucket, end_bucket)))
+}
+
+/// Converts a database error from a bucket lookup.
+pub(super) fn lookup_error(err: impl Into<redb::Error>) -> BucketError {
+    BucketError::IterationError(DatabaseError::new(
+        "Database error during point lookup",
+        err,
+    ))
+}
+
+/// Returns the first and last bucket stored in a table, if any.
+pub(super) fn table_bucket_span<K, V>(
+    table: &impl ReadableTable<BucketedKey<K>, V>,
+) -> Result<Option<(u64, u64)>, BucketError>
+where
+    K: Key + 'static,
+    V: redb::Value + 'static,
+{
+    let first = table.first().map_err(lookup_error)?;
+    let last = table.last().map_err(lookup_error)?;
+    Ok(first
+        .zip(last)
+        .map(|((first, _), (last, _))| (first.value().bucket(), last.value().bucket())))
+}
+
+/// Returns the first and last bucket stored in a multimap table, if any.
+pub(super) fn multimap_bucket_span<K, V>(
+    table: &impl ReadableMultimapTable<BucketedKey<K>, V>,
+) -> Result<Option<(u64, u64)>, BucketError>
+where
+    K: Key + 'static,
+    V: redb::Key + 'static,
+{
+    let mut keys = table.iter().map_err(lookup_error)?;
+    let first = keys.next().transpose().map_err(lookup_error)?;
+    let last = keys.next_back().transpose().map_err(lookup_error)?;
+    Ok(first.map(|(first, _)| {
+        let first = first.value().bucket();
+        let last = last.map_or(first, |(last, _)| last.value().bucket());
+        (first, last)
+    }))
+}
+
+/// Position of a paused `BucketRangeIterator`.
+///
+/// Holds the front and back buckets still to be visited, so iteration can
+/// continue in a later read transaction. Serialize it with `to_bytes` to hand
+/// it to a client as a pagination cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumeToken {
+    /// Next bucket to visit from the front
+    pub front_bucket: u64,
+    /// Next bucket to visit from the back
+    pub back_bucket: u64,
+}
+
+impl ResumeToken {
+    /// Encoded size of a token.
+    pub const SIZE: usize = 16;
+
+    /// Token of an iteration with no buckets left.
+    const EXHAUSTED: Self = Self {
+        front_bucket: 1,
+        back_bucket: 0,
+    };
+
+    /// Whether the iteration has no buckets left to visit.
+    pub fn is_exhausted(&self) -> bool {
+        self.front_bucket > self.back_bucket
+    }
+
+    /// Encodes the token as the front then back bucket, big-endian.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[..8].copy_from_slice(&self.front_bucket.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.back_bucket.to_be_bytes());
+        bytes
+    }
+
+    /// Decodes a token produced by `to_bytes`.
+    ///
+    /// # Arguments
+    /// * `bytes` - The encoded token
+    ///
+    /// # Returns
+    /// The token or error if `bytes` has the wrong length
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BucketError> {
+        if bytes.len() != Self::SIZE {
+            return Err(BucketError::SerializationError(format!(
+                "resume token must be {} bytes, got {}",
+                Self::SIZE,
+                bytes.len()
+            )));
+        }
+
+        let (front, back) = bytes.split_at(8);
+        let mut front_bytes = [0u8; 8];
+        let mut back_bytes = [0u8; 8];
+        front_bytes.copy_from_slice(front);
+        back_bytes.copy_from_slice(back);
+        Ok(Self {
+            front_bucket: u64::from_be_bytes(front_bytes),
+            back_bucket: u64::from_be_bytes(back_bytes),
+        })
+    }
+}
 
 /// Iterator over a range of buckets for a specific base key.
 ///
@@ -13,52 +186,87 @@ use std::collections::VecDeque;
 /// requested sequence range, yielding only values that match the base key.
 ///
 /// Implements `DoubleEndedIterator` for reverse iteration.
-pub struct BucketRangeIterator<V>
+pub struct BucketRangeIterator<K, V, T = ReadOnlyTable<BucketedKey<K>, V>>
 where
+    K: Key + 'static,
     V: redb::Value + 'static,
     for<'b> V: From<V::SelfType<'b>>,
 {
-    table: ReadOnlyTable<BucketedKey<u64>, V>,
-    base_key: u64,
+    table: T,
+    _types: PhantomData<fn() -> (K, V)>,
+    base_key: Vec<u8>,
     start_bucket: u64,
     end_bucket: u64,
-    front_bucket: i64,
-    back_bucket: i64,
+    // Next buckets to visit from either end; meaningless once finished
+    front_bucket: u64,
+    back_bucket: u64,
     finished: bool,
 }
 
-impl<V> BucketRangeIterator<V>
+impl<K, V, T> BucketRangeIterator<K, V, T>
 where
+    K: Key + 'static,
     V: redb::Value + 'static,
     for<'b> V: From<V::SelfType<'b>>,
+    T: Borrow<ReadOnlyTable<BucketedKey<K>, V>>,
 {
     /// Create a new bucket range iterator.
-    pub fn new(
-        table: ReadOnlyTable<BucketedKey<u64>, V>,
-        key_builder: &KeyBuilder,
-        base_key: u64,
-        start_sequence: u64,
-        end_sequence: u64,
+    pub fn new<'k>(
+        table: T,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
     ) -> Result<Self, BucketError> {
-        if start_sequence > end_sequence {
-            return Err(BucketError::InvalidRange {
-                start: start_sequence,
-                end: end_sequence,
-            });
-        }
+        let bounds = bucket_bounds(key_builder, &range, || table_bucket_span(table.borrow()))?;
+
+        // An empty range yields nothing and reports (0, 0) as its bucket range
+        let (start_bucket, end_bucket) = bounds.unwrap_or((0, 0));
+        Ok(Self {
+            table,
+            _types: PhantomData,
+            base_key: K::as_bytes(base_key.borrow()).as_ref().to_vec(),
+            start_bucket,
+            end_bucket,
+            front_bucket: start_bucket,
+            back_bucket: end_bucket,
+            finished: bounds.is_none(),
+        })
+    }
 
-        let bucket_size = key_builder.bucket_size();
-        let start_bucket = start_sequence / bucket_size;
-        let end_bucket = end_sequence / bucket_size;
+    /// Resume an iteration paused with `resume_token`.
+    ///
+    /// The table may come from a newer read transaction than the one the
+    /// token was taken in; buckets written in between are picked up. The
+    /// bucket range reported is the part left to visit.
+    ///
+    /// # Arguments
+    /// * `table` - The table to continue reading from
+    /// * `base_key` - The base key the paused iteration was reading
+    /// * `token` - Position returned by `resume_token`
+    ///
+    /// # Returns
+    /// Iterator over the buckets the paused iteration had not visited
+    pub fn resume<'k>(
+        table: T,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        token: ResumeToken,
+    ) -> Result<Self, BucketError> {
+        let finished = token.is_exhausted();
+        let (start_bucket, end_bucket) = if finished {
+            (0, 0)
+        } else {
+            (token.front_bucket, token.back_bucket)
+        };
 
         Ok(Self {
             table,
-            base_key,
+            _types: PhantomData,
+            base_key: K::as_bytes(base_key.borrow()).as_ref().to_vec(),
             start_bucket,
             end_bucket,
-            front_bucket: start_bucket as i64,
-            back_bucket: end_bucket as i64,
-            finished: false,
+            front_bucket: start_bucket,
+            back_bucket: end_bucket,
+            finished,
         })
     }
 
@@ -66,74 +274,87 @@ where
     pub fn bucket_range(&self) -> (u64, u64) {
         (self.start_bucket, self.end_bucket)
     }
+
+    /// Returns the current position, to continue later with `resume`.
+    pub fn resume_token(&self) -> ResumeToken {
+        if self.finished {
+            return ResumeToken::EXHAUSTED;
+        }
+
+        ResumeToken {
+            front_bucket: self.front_bucket,
+            back_bucket: self.back_bucket,
+        }
+    }
+
+    /// Looks up the value stored for the base key in a single bucket.
+    fn lookup(&self, bucket: u64) -> Result<Option<V>, BucketError> {
+        let key = BucketedKey::new(K::from_bytes(&self.base_key), bucket);
+        match self.table.borrow().get(&key) {
+            Ok(value_guard) => Ok(value_guard.map(|guard| V::from(guard.value()))),
+            Err(err) => Err(lookup_error(err)),
+        }
+    }
 }
 
-impl<V> Iterator for BucketRangeIterator<V>
+impl<K, V, T> Iterator for BucketRangeIterator<K, V, T>
 where
+    K: Key + 'static,
     V: redb::Value + 'static,
     for<'b> V: From<V::SelfType<'b>>,
+    T: Borrow<ReadOnlyTable<BucketedKey<K>, V>>,
 {
     type Item = Result<V, BucketError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.finished {
-            return None;
-        }
-
-        while self.front_bucket <= self.back_bucket {
-            let bucket = self.front_bucket as u64;
-            self.front_bucket += 1;
+        while !self.finished {
+            let bucket = self.front_bucket;
+            if bucket == self.back_bucket {
+                self.finished = true;
+            } else {
+                self.front_bucket += 1;
+            }
 
-            match self.table.get(&BucketedKey::new(self.base_key, bucket)) {
-                Ok(Some(value_guard)) => {
-                    return Some(Ok(V::from(value_guard.value())));
-                }
+            match self.lookup(bucket) {
+                Ok(Some(value)) => return Some(Ok(value)),
                 Ok(None) => continue,
                 Err(err) => {
                     self.finished = true;
-                    return Some(Err(BucketError::IterationError(format!(
-                        "Database error during point lookup: {}",
-                        err
-                    ))));
+                    return Some(Err(err));
                 }
             }
         }
 
-        self.finished = true;
         None
     }
 }
 
-impl<V> DoubleEndedIterator for BucketRangeIterator<V>
+impl<K, V, T> DoubleEndedIterator for BucketRangeIterator<K, V, T>
 where
+    K: Key + 'static,
     V: redb::Value + 'static,
     for<'b> V: From<V::SelfType<'b>>,
+    T: Borrow<ReadOnlyTable<BucketedKey<K>, V>>,
 {
     fn next_back(&mut self) -> Option<Self::Item> {
-        if self.finished {
-            return None;
-        }
-
-        while self.front_bucket <= self.back_bucket {
-            let bucket = self.back_bucket as u64;
-            self.back_bucket -= 1;
+        while !self.finished {
+            let bucket = self.back_bucket;
+            if bucket == self.front_bucket {
+                self.finished = true;
+            } else {
+                self.back_bucket -= 1;
+            }
 
-            match self.table.get(&BucketedKey::new(self.base_key, bucket)) {
-                Ok(Some(value_guard)) => {
-                    return Some(Ok(V::from(value_guard.value())));
-                }
+            match self.lookup(bucket) {
+                Ok(Some(value)) => return Some(Ok(value)),
                 Ok(None) => continue,
                 Err(err) => {
                     self.finished = true;
-                    return Some(Err(BucketError::IterationError(format!(
-                        "Database error during point lookup: {}",
-                        err
-                    ))));
+                    return Some(Err(err));
                 }
             }
         }
 
-        self.finished = true;
         None
     }
 }
@@ -141,7 +362,9 @@ where
 /// Iterator over a range of buckets for a specific base key in multimap tables.
 ///
 /// This iterator flattens the multimap values, yielding each value in order
-/// across the requested bucket range via per-bucket point lookups.
+/// across the requested bucket range via per-bucket point lookups. Values are
+/// streamed from the table, so only the current bucket's cursor is held in
+/// memory regardless of how many values a bucket contains.
 ///
 /// Implements `DoubleEndedIterator` to iterate buckets and values in reverse.
 ///
@@ -153,7 +376,8 @@ where
 ///     MultimapTableDefinition::new("bucketed_values");
 ///
 /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-/// let db = Database::create("example.redb")?;
+/// let temp_file = tempfile::NamedTempFile::new()?;
+/// let db = Database::create(temp_file.path())?;
 /// let key_builder = KeyBuilder::new(100)?;
 ///
 /// let write_txn = db.begin_write()?;
@@ -173,184 +397,218 @@ where
 /// # Ok(())
 /// # }
 /// ```
-pub struct BucketRangeMultimapIterator<V>
+pub struct BucketRangeMultimapIterator<K, V, T = ReadOnlyMultimapTable<BucketedKey<K>, V>>
 where
+    K: Key + 'static,
     V: redb::Key + 'static,
     for<'b> V: From<V::SelfType<'b>>,
 {
-    table: ReadOnlyMultimapTable<BucketedKey<u64>, V>,
-    base_key: u64,
+    table: T,
+    _types: PhantomData<fn() -> (K, V)>,
+    base_key: Vec<u8>,
     start_bucket: u64,
     end_bucket: u64,
-    front_bucket: i64,
-    back_bucket: i64,
+    // Next buckets to open from either end, until every bucket is open
+    front_bucket: u64,
+    back_bucket: u64,
+    buckets_opened: bool,
     finished: bool,
-    front_values: Option<VecDeque<V>>,
-    back_values: Option<VecDeque<V>>,
+    front_values: Option<MultimapValue<'static, V>>,
+    back_values: Option<MultimapValue<'static, V>>,
+    sequences: (u64, u64),
+    sequence_of: Option<SequenceOf<V>>,
 }
 
-impl<V> BucketRangeMultimapIterator<V>
+impl<K, V, T> BucketRangeMultimapIterator<K, V, T>
 where
+    K: Key + 'static,
     V: redb::Key + 'static,
     for<'b> V: From<V::SelfType<'b>>,
+    T: Borrow<ReadOnlyMultimapTable<BucketedKey<K>, V>>,
 {
     /// Create a new bucket range iterator for a multimap table.
-    pub fn new(
-        table: ReadOnlyMultimapTable<BucketedKey<u64>, V>,
-        key_builder: &KeyBuilder,
-        base_key: u64,
-        start_sequence: u64,
-        end_sequence: u64,
+    pub fn new<'k>(
+        table: T,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
     ) -> Result<Self, BucketError> {
-        if start_sequence > end_sequence {
-            return Err(BucketError::InvalidRange {
-                start: start_sequence,
-                end: end_sequence,
-            });
-        }
+        let bounds = bucket_bounds(key_builder, &range, || multimap_bucket_span(table.borrow()))?;
 
-        let bucket_size = key_builder.bucket_size();
-        let start_bucket = start_sequence / bucket_size;
-        let end_bucket = end_sequence / bucket_size;
+        // Only used with `with_sequence_of`; an empty range never yields
+        let first_sequence = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let last_sequence = match range.end_bound() {
+            Bound::Included(&end) => end,
+            Bound::Excluded(&end) => end.saturating_sub(1),
+            Bound::Unbounded => u64::MAX,
+        };
 
+        // An empty range yields nothing and reports (0, 0) as its bucket range
+        let (start_bucket, end_bucket) = bounds.unwrap_or((0, 0));
         Ok(Self {
             table,
-            base_key,
+            _types: PhantomData,
+            base_key: K::as_bytes(base_key.borrow()).as_ref().to_vec(),
             start_bucket,
             end_bucket,
-            front_bucket: start_bucket as i64,
-            back_bucket: end_bucket as i64,
-            finished: false,
+            front_bucket: start_bucket,
+            back_bucket: end_bucket,
+            buckets_opened: bounds.is_none(),
+            finished: bounds.is_none(),
             front_values: None,
             back_values: None,
+            sequences: (first_sequence, last_sequence),
+            sequence_of: None,
         })
     }
 
+    /// Trims the iteration to the exact sequence range.
+    ///
+    /// Buckets at either end of the range usually also hold values for
+    /// sequences just outside it. When every value encodes its own sequence,
+    /// `sequence_of` extracts it and values outside the requested range are
+    /// skipped instead of yielded.
+    ///
+    /// # Arguments
+    /// * `sequence_of` - Returns the sequence a value was stored at
+    ///
+    /// # Returns
+    /// The iterator, filtering values by sequence
+    pub fn with_sequence_of(mut self, sequence_of: impl Fn(&V) -> u64 + 'static) -> Self {
+        self.sequence_of = Some(Box::new(sequence_of));
+        self
+    }
+
+    /// Whether a value lies within the requested sequence range.
+    fn in_sequence_range(&self, value: &V) -> bool {
+        match &self.sequence_of {
+            Some(sequence_of) => {
+                let (first, last) = self.sequences;
+                (first..=last).contains(&sequence_of(value))
+            }
+            None => true,
+        }
+    }
+
+    /// Yields the next value in sequence range from the front or back.
+    fn next_in_range(&mut self, from_back: bool) -> Option<Result<V, BucketError>> {
+        loop {
+            match self.advance(from_back)? {
+                Ok(value) if !self.in_sequence_range(&value) => continue,
+                result => return Some(result),
+            }
+        }
+    }
+
     /// Get the bucket range.
     pub fn bucket_range(&self) -> (u64, u64) {
         (self.start_bucket, self.end_bucket)
     }
-}
 
-impl<V> Iterator for BucketRangeMultimapIterator<V>
-where
-    V: redb::Key + 'static,
-    for<'b> V: From<V::SelfType<'b>>,
-{
-    type Item = Result<V, BucketError>;
+    /// Opens the values stored for the base key in a single bucket.
+    fn lookup(&self, bucket: u64) -> Result<MultimapValue<'static, V>, BucketError> {
+        let key = BucketedKey::new(K::from_bytes(&self.base_key), bucket);
+        self.table.borrow().get(&key).map_err(lookup_error)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Takes the next value from an open bucket, closing it once drained.
+    fn pull(
+        values: &mut Option<MultimapValue<'static, V>>,
+        from_back: bool,
+    ) -> Option<Result<V, BucketError>> {
+        let open = values.as_mut()?;
+        let next = if from_back {
+            open.next_back()
+        } else {
+            open.next()
+        };
+
+        match next {
+            Some(Ok(guard)) => Some(Ok(V::from(guard.value()))),
+            Some(Err(err)) => Some(Err(lookup_error(err))),
+            None => {
+                *values = None;
+                None
+            }
+        }
+    }
+
+    /// Yields the next value from the front or back of the remaining range.
+    fn advance(&mut self, from_back: bool) -> Option<Result<V, BucketError>> {
         if self.finished {
             return None;
         }
 
         loop {
-            if let Some(values) = self.front_values.as_mut() {
-                if let Some(value) = values.pop_front() {
-                    return Some(Ok(value));
-                }
-                self.front_values = None;
+            let (current, other) = if from_back {
+                (&mut self.back_values, &mut self.front_values)
+            } else {
+                (&mut self.front_values, &mut self.back_values)
+            };
+            if let Some(result) = Self::pull(current, from_back) {
+                self.finished = result.is_err();
+                return Some(result);
             }
 
-            if self.front_bucket > self.back_bucket {
-                self.finished = true;
-                return None;
+            if self.buckets_opened {
+                // Both ends met inside a bucket opened by the other end
+                let result = Self::pull(other, from_back);
+                self.finished = !matches!(result, Some(Ok(_)));
+                return result;
             }
 
-            let bucket = self.front_bucket as u64;
-            self.front_bucket += 1;
-
-            match self.table.get(&BucketedKey::new(self.base_key, bucket)) {
-                Ok(values) => {
-                    let mut collected = VecDeque::new();
-                    for value_result in values {
-                        match value_result {
-                            Ok(value_guard) => {
-                                collected.push_back(V::from(value_guard.value()));
-                            }
-                            Err(err) => {
-                                self.finished = true;
-                                return Some(Err(BucketError::IterationError(format!(
-                                    "Database error during point lookup: {}",
-                                    err
-                                ))));
-                            }
-                        }
-                    }
-                    if collected.is_empty() {
-                        continue;
-                    }
-                    self.front_values = Some(collected);
-                }
+            let bucket = if from_back {
+                self.back_bucket
+            } else {
+                self.front_bucket
+            };
+            if self.front_bucket == self.back_bucket {
+                self.buckets_opened = true;
+            } else if from_back {
+                self.back_bucket -= 1;
+            } else {
+                self.front_bucket += 1;
+            }
+
+            match self.lookup(bucket) {
+                Ok(values) if from_back => self.back_values = Some(values),
+                Ok(values) => self.front_values = Some(values),
                 Err(err) => {
                     self.finished = true;
-                    return Some(Err(BucketError::IterationError(format!(
-                        "Database error during point lookup: {}",
-                        err
-                    ))));
+                    return Some(Err(err));
                 }
             }
         }
     }
 }
 
-impl<V> DoubleEndedIterator for BucketRangeMultimapIterator<V>
+impl<K, V, T> Iterator for BucketRangeMultimapIterator<K, V, T>
 where
+    K: Key + 'static,
     V: redb::Key + 'static,
     for<'b> V: From<V::SelfType<'b>>,
+    T: Borrow<ReadOnlyMultimapTable<BucketedKey<K>, V>>,
 {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        if self.finished {
-            return None;
-        }
-
-        loop {
-            if let Some(values) = self.back_values.as_mut() {
-                if let Some(value) = values.pop_back() {
-                    return Some(Ok(value));
-                }
-                self.back_values = None;
-            }
+    type Item = Result<V, BucketError>;
 
-            if self.front_bucket > self.back_bucket {
-                self.finished = true;
-                return None;
-            }
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_in_range(false)
+    }
+}
 
-            let bucket = self.back_bucket as u64;
-            self.back_bucket -= 1;
-
-            match self.table.get(&BucketedKey::new(self.base_key, bucket)) {
-                Ok(values) => {
-                    let mut collected = VecDeque::new();
-                    for value_result in values {
-                        match value_result {
-                            Ok(value_guard) => {
-                                collected.push_back(V::from(value_guard.value()));
-                            }
-                            Err(err) => {
-                                self.finished = true;
-                                return Some(Err(BucketError::IterationError(format!(
-                                    "Database error during point lookup: {}",
-                                    err
-                                ))));
-                            }
-                        }
-                    }
-                    if collected.is_empty() {
-                        continue;
-                    }
-                    self.back_values = Some(collected);
-                }
-                Err(err) => {
-                    self.finished = true;
-                    return Some(Err(BucketError::IterationError(format!(
-                        "Database error during point lookup: {}",
-                        err
-                    ))));
-                }
-            }
-        }
+impl<K, V, T> DoubleEndedIterator for BucketRangeMultimapIterator<K, V, T>
+where
+    K: Key + 'static,
+    V: redb::Key + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+    T: Borrow<ReadOnlyMultimapTable<BucketedKey<K>, V>>,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_in_range(true)
     }
 }
 
@@ -359,34 +617,114 @@ where
 /// Bucket iteration uses per-bucket point lookups for the requested
 /// sequence range, skipping buckets that have no stored value.
 ///
-/// This consumes the table handle so the iterator can own it.
-pub trait BucketIterExt<V>
+/// `bucket_range_bounds` accepts any `RangeBounds<u64>` over sequences.
+/// Unbounded ends extend to the first or last bucket stored in the table, so
+/// `..` visits every bucket that holds data for any base key.
+///
+/// Implemented for owned table handles, which the iterator takes over, and
+/// for references, whose iterators borrow the table so it can be queried
+/// again in the same transaction.
+pub trait BucketIterExt<K, V>: Sized
 where
+    K: Key + 'static,
     V: redb::Value + 'static,
     for<'b> V: From<V::SelfType<'b>>,
 {
-    fn bucket_range(
+    /// Iterates the buckets covering `start_sequence..=end_sequence`.
+    ///
+    /// Returns `BucketError::InvalidRange` when `start_sequence` is greater
+    /// than `end_sequence`.
+    fn bucket_range<'k>(
         self,
-        key_builder: &KeyBuilder,
-        base_key: u64,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
         start_sequence: u64,
         end_sequence: u64,
-    ) -> Result<BucketRangeIterator<V>, BucketError>;
+    ) -> Result<BucketRangeIterator<K, V, Self>, BucketError> {
+        if start_sequence > end_sequence {
+            return Err(BucketError::InvalidRange {
+                start: start_sequence,
+                end: end_sequence,
+            });
+        }
+        self.bucket_range_bounds(key_builder, base_key, start_sequence..=end_sequence)
+    }
+
+    /// Iterates the buckets covering any range of sequences.
+    fn bucket_range_bounds<'k>(
+        self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+    ) -> Result<BucketRangeIterator<K, V, Self>, BucketError>;
+
+    /// Iterates the values of several base keys merged in bucket order.
+    fn merged_bucket_range<'k, I>(
+        self,
+        key_builder: &impl BucketScheme,
+        base_keys: I,
+        range: impl RangeBounds<u64>,
+    ) -> Result<MergedBucketIterator<K, V, Self>, BucketError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<K::SelfType<'k>>;
 }
 
-impl<V> BucketIterExt<V> for ReadOnlyTable<BucketedKey<u64>, V>
+impl<K, V> BucketIterExt<K, V> for ReadOnlyTable<BucketedKey<K>, V>
 where
+    K: Key + 'static,
     V: redb::Value + 'static,
     for<'b> V: From<V::SelfType<'b>>,
 {
-    fn bucket_range(
+    fn bucket_range_bounds<'k>(
         self,
-        key_builder: &KeyBuilder,
-        base_key: u64,
-        start_sequence: u64,
-        end_sequence: u64,
-    ) -> Result<BucketRangeIterator<V>, BucketError> {
-        BucketRangeIterator::new(self, key_builder, base_key, start_sequence, end_sequence)
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+    ) -> Result<BucketRangeIterator<K, V, Self>, BucketError> {
+        BucketRangeIterator::new(self, key_builder, base_key, range)
+    }
+
+    fn merged_bucket_range<'k, I>(
+        self,
+        key_builder: &impl BucketScheme,
+        base_keys: I,
+        range: impl RangeBounds<u64>,
+    ) -> Result<MergedBucketIterator<K, V, Self>, BucketError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<K::SelfType<'k>>,
+    {
+        MergedBucketIterator::new(self, key_builder, base_keys, range)
+    }
+}
+
+impl<K, V> BucketIterExt<K, V> for &ReadOnlyTable<BucketedKey<K>, V>
+where
+    K: Key + 'static,
+    V: redb::Value + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+{
+    fn bucket_range_bounds<'k>(
+        self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+    ) -> Result<BucketRangeIterator<K, V, Self>, BucketError> {
+        BucketRangeIterator::new(self, key_builder, base_key, range)
+    }
+
+    fn merged_bucket_range<'k, I>(
+        self,
+        key_builder: &impl BucketScheme,
+        base_keys: I,
+        range: impl RangeBounds<u64>,
+    ) -> Result<MergedBucketIterator<K, V, Self>, BucketError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<K::SelfType<'k>>,
+    {
+        MergedBucketIterator::new(self, key_builder, base_keys, range)
     }
 }
 
@@ -395,47 +733,133 @@ where
 /// Returns a flattened iterator over values for the base key within the
 /// requested bucket range, using per-bucket point lookups.
 ///
-/// This consumes the table handle so the iterator can own it.
-pub trait BucketMultimapIterExt<V>
+/// Unbounded `bucket_range_bounds` ends resolve to the stored buckets as for
+/// `BucketIterExt`.
+///
+/// Implemented for owned table handles, which the iterator takes over, and
+/// for references, whose iterators borrow the table so it can be queried
+/// again in the same transaction.
+pub trait BucketMultimapIterExt<K, V>: Sized
 where
+    K: Key + 'static,
     V: redb::Key + 'static,
     for<'b> V: From<V::SelfType<'b>>,
 {
-    fn bucket_range(
+    /// Iterates the buckets covering `start_sequence..=end_sequence`.
+    ///
+    /// Returns `BucketError::InvalidRange` when `start_sequence` is greater
+    /// than `end_sequence`.
+    fn bucket_range<'k>(
         self,
-        key_builder: &KeyBuilder,
-        base_key: u64,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
         start_sequence: u64,
         end_sequence: u64,
-    ) -> Result<BucketRangeMultimapIterator<V>, BucketError>;
+    ) -> Result<BucketRangeMultimapIterator<K, V, Self>, BucketError> {
+        if start_sequence > end_sequence {
+            return Err(BucketError::InvalidRange {
+                start: start_sequence,
+                end: end_sequence,
+            });
+        }
+        self.bucket_range_bounds(key_builder, base_key, start_sequence..=end_sequence)
+    }
+
+    /// Iterates the buckets covering any range of sequences.
+    fn bucket_range_bounds<'k>(
+        self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+    ) -> Result<BucketRangeMultimapIterator<K, V, Self>, BucketError>;
+
+    /// Iterates the values of several base keys merged in bucket order.
+    fn merged_bucket_range<'k, I>(
+        self,
+        key_builder: &impl BucketScheme,
+        base_keys: I,
+        range: impl RangeBounds<u64>,
+    ) -> Result<MergedBucketMultimapIterator<K, V, Self>, BucketError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<K::SelfType<'k>>;
 }
 
-impl<V> BucketMultimapIterExt<V> for ReadOnlyMultimapTable<BucketedKey<u64>, V>
+impl<K, V> BucketMultimapIterExt<K, V> for ReadOnlyMultimapTable<BucketedKey<K>, V>
 where
+    K: Key + 'static,
     V: redb::Key + 'static,
     for<'b> V: From<V::SelfType<'b>>,
 {
-    fn bucket_range(
+    fn bucket_range_bounds<'k>(
         self,
-        key_builder: &KeyBuilder,
-        base_key: u64,
-        start_sequence: u64,
-        end_sequence: u64,
-    ) -> Result<BucketRangeMultimapIterator<V>, BucketError> {
-        BucketRangeMultimapIterator::new(self, key_builder, base_key, start_sequence, end_sequence)
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+    ) -> Result<BucketRangeMultimapIterator<K, V, Self>, BucketError> {
+        BucketRangeMultimapIterator::new(self, key_builder, base_key, range)
+    }
+
+    fn merged_bucket_range<'k, I>(
+        self,
+        key_builder: &impl BucketScheme,
+        base_keys: I,
+        range: impl RangeBounds<u64>,
+    ) -> Result<MergedBucketMultimapIterator<K, V, Self>, BucketError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<K::SelfType<'k>>,
+    {
+        MergedBucketMultimapIterator::new(self, key_builder, base_keys, range)
+    }
+}
+
+impl<K, V> BucketMultimapIterExt<K, V> for &ReadOnlyMultimapTable<BucketedKey<K>, V>
+where
+    K: Key + 'static,
+    V: redb::Key + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+{
+    fn bucket_range_bounds<'k>(
+        self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+    ) -> Result<BucketRangeMultimapIterator<K, V, Self>, BucketError> {
+        BucketRangeMultimapIterator::new(self, key_builder, base_key, range)
+    }
+
+    fn merged_bucket_range<'k, I>(
+        self,
+        key_builder: &impl BucketScheme,
+        base_keys: I,
+        range: impl RangeBounds<u64>,
+    ) -> Result<MergedBucketMultimapIterator<K, V, Self>, BucketError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<K::SelfType<'k>>,
+    {
+        MergedBucketMultimapIterator::new(self, key_builder, base_keys, range)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use redb::{Database, MultimapTableDefinition, ReadableDatabase, TableDefinition};
+    use crate::key_buckets::KeyBuilder;
+    use redb::{
+        Database, MultimapTableDefinition, ReadableDatabase, ReadableTable, TableDefinition,
+    };
     use tempfile::NamedTempFile;
 
     const TEST_TABLE: TableDefinition<'static, BucketedKey<u64>, String> =
         TableDefinition::new("test_table");
     const TEST_MULTIMAP: MultimapTableDefinition<'static, BucketedKey<u64>, u64> =
         MultimapTableDefinition::new("test_multimap");
+    const HASH_TABLE: TableDefinition<'static, BucketedKey<&[u8]>, u64> =
+        TableDefinition::new("hash_table");
+    const NAME_MULTIMAP: MultimapTableDefinition<'static, BucketedKey<&str>, u64> =
+        MultimapTableDefinition::new("name_multimap");
 
     #[test]
     fn test_basic_functionality() -> Result<(), Box<dyn std::error::Error>> {
@@ -477,8 +901,7 @@ mod tests {
                 read_txn.open_table(TEST_TABLE)?,
                 &key_builder,
                 123u64,
-                0,
-                199,
+                0..=199,
             )?;
             assert_eq!(iter.bucket_range(), (0, 1));
 
@@ -487,10 +910,18 @@ mod tests {
                 read_txn.open_table(TEST_TABLE)?,
                 &key_builder,
                 123u64,
-                200,
-                100,
+                (Bound::Included(200), Bound::Included(100)),
             );
             assert!(invalid_iter.is_err());
+            assert!(matches!(
+                read_txn
+                    .open_table(TEST_TABLE)?
+                    .bucket_range(&key_builder, 123u64, 200, 100),
+                Err(BucketError::InvalidRange {
+                    start: 200,
+                    end: 100
+                })
+            ));
         }
 
         // Test value iteration and base key filtering
@@ -500,8 +931,7 @@ mod tests {
                 read_txn.open_table(TEST_TABLE)?,
                 &key_builder,
                 123u64,
-                0,
-                299,
+                0..=299,
             )?;
             let values: Vec<String> = iter.collect::<Result<_, _>>()?;
             assert_eq!(
@@ -517,8 +947,7 @@ mod tests {
                 read_txn.open_table(TEST_TABLE)?,
                 &key_builder,
                 123u64,
-                0,
-                299,
+                0..=299,
             )?;
             let values: Vec<String> = iter.rev().collect::<Result<_, _>>()?;
             assert_eq!(
@@ -572,8 +1001,7 @@ mod tests {
                 read_txn.open_multimap_table(TEST_MULTIMAP)?,
                 &key_builder,
                 123u64,
-                0,
-                199,
+                0..=199,
             )?;
             assert_eq!(iter.bucket_range(), (0, 1));
 
@@ -584,8 +1012,7 @@ mod tests {
                 read_txn.open_multimap_table(TEST_MULTIMAP)?,
                 &key_builder,
                 123u64,
-                0,
-                199,
+                0..=199,
             )?;
             let values: Vec<u64> = iter.rev().collect::<Result<_, _>>()?;
             assert_eq!(values, vec![40u64, 30u64, 20u64, 10u64]);
@@ -602,4 +1029,409 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_multimap_meets_inside_bucket() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+
+        {
+            let write_txn = db.begin_write()?;
+            {
+                let mut multimap = write_txn.open_multimap_table(TEST_MULTIMAP)?;
+                for value in 0..1000u64 {
+                    multimap.insert(key_builder.bucketed_key(7u64, 150), value)?;
+                }
+                multimap.insert(key_builder.bucketed_key(7u64, 50), 5000u64)?;
+            }
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        let mut iter = read_txn.open_multimap_table(TEST_MULTIMAP)?.bucket_range(
+            &key_builder,
+            7u64,
+            0,
+            199,
+        )?;
+
+        // Both ends stream from the same bucket once the front one is drained
+        let mut front = vec![iter.next().unwrap()?];
+        let mut back = Vec::new();
+        loop {
+            match (iter.next_back(), iter.next()) {
+                (None, None) => break,
+                (b, f) => {
+                    back.extend(b.transpose()?);
+                    front.extend(f.transpose()?);
+                }
+            }
+        }
+
+        assert_eq!(front[0], 5000);
+        assert_eq!(back[0], 999);
+        front.extend(back.into_iter().rev());
+        assert_eq!(front.len(), 1001);
+        assert!(front[1..].iter().copied().eq(0..1000));
+        assert!(iter.next().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multimap_sequence_trimming() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+
+        // Each value is the sequence it was stored at
+        {
+            let write_txn = db.begin_write()?;
+            {
+                let mut multimap = write_txn.open_multimap_table(TEST_MULTIMAP)?;
+                for sequence in (0..400).step_by(25) {
+                    multimap.insert(key_builder.bucketed_key(9u64, sequence), sequence)?;
+                }
+            }
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        let untrimmed = read_txn
+            .open_multimap_table(TEST_MULTIMAP)?
+            .bucket_range_bounds(&key_builder, 9u64, 130..260)?
+            .count();
+        assert_eq!(untrimmed, 8);
+
+        let trimmed: Vec<u64> = read_txn
+            .open_multimap_table(TEST_MULTIMAP)?
+            .bucket_range_bounds(&key_builder, 9u64, 130..260)?
+            .with_sequence_of(|value| *value)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(trimmed, vec![150, 175, 200, 225, 250]);
+
+        let trimmed: Vec<u64> = read_txn
+            .open_multimap_table(TEST_MULTIMAP)?
+            .bucket_range_bounds(&key_builder, 9u64, 330..)?
+            .with_sequence_of(|value| *value)
+            .rev()
+            .collect::<Result<_, _>>()?;
+        assert_eq!(trimmed, vec![375, 350]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_slice_and_str_base_keys() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+        let hash = [0xabu8; 32];
+        let prefix = &hash[..31];
+
+        {
+            let write_txn = db.begin_write()?;
+            {
+                let mut table = write_txn.open_table(HASH_TABLE)?;
+                table.insert(key_builder.bucketed_key(&hash[..], 50), 1u64)?;
+                table.insert(key_builder.bucketed_key(&hash[..], 250), 3u64)?;
+                // A prefix of the hash must not be confused with the hash itself
+                table.insert(key_builder.bucketed_key(prefix, 50), 99u64)?;
+
+                let mut multimap = write_txn.open_multimap_table(NAME_MULTIMAP)?;
+                multimap.insert(key_builder.bucketed_key("alice", 10), 1u64)?;
+                multimap.insert(key_builder.bucketed_key("alice", 10), 2u64)?;
+                multimap.insert(key_builder.bucketed_key("alice", 110), 3u64)?;
+                multimap.insert(key_builder.bucketed_key("alicia", 10), 4u64)?;
+            }
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+
+        // Stored order within a bucket follows the base key bytes
+        let keys: Vec<Vec<u8>> = read_txn
+            .open_table(HASH_TABLE)?
+            .iter()?
+            .map(|entry| entry.map(|(key, _)| key.value().base_key().to_vec()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(keys, vec![prefix.to_vec(), hash.to_vec(), hash.to_vec()]);
+
+        let values: Vec<u64> = read_txn
+            .open_table(HASH_TABLE)?
+            .bucket_range(&key_builder, &hash[..], 0, 299)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![1u64, 3u64]);
+
+        let values: Vec<u64> = read_txn
+            .open_multimap_table(NAME_MULTIMAP)?
+            .bucket_range(&key_builder, "alice", 0, 199)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![1u64, 2u64, 3u64]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbounded_ranges() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+
+        {
+            let write_txn = db.begin_write()?;
+            {
+                let mut table = write_txn.open_table(TEST_TABLE)?;
+                for sequence in [150u64, 350, 550] {
+                    table.insert(
+                        key_builder.bucketed_key(1u64, sequence),
+                        sequence.to_string(),
+                    )?;
+                }
+                // Another base key widens the stored bucket span
+                table.insert(key_builder.bucketed_key(2u64, 950), "other".to_string())?;
+
+                let mut multimap = write_txn.open_multimap_table(TEST_MULTIMAP)?;
+                multimap.insert(key_builder.bucketed_key(1u64, 250), 1u64)?;
+                multimap.insert(key_builder.bucketed_key(1u64, 250), 2u64)?;
+                multimap.insert(key_builder.bucketed_key(1u64, 450), 3u64)?;
+            }
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        let collect = |range: (Bound<u64>, Bound<u64>)| -> Result<_, BucketError> {
+            let iter = read_txn
+                .open_table(TEST_TABLE)
+                .unwrap()
+                .bucket_range_bounds(&key_builder, 1u64, range)?;
+            let buckets = iter.bucket_range();
+            let values: Vec<String> = iter.collect::<Result<_, _>>()?;
+            Ok((buckets, values))
+        };
+
+        let (buckets, values) = collect((Bound::Unbounded, Bound::Unbounded))?;
+        assert_eq!(buckets, (1, 9));
+        assert_eq!(values, vec!["150", "350", "550"]);
+
+        let (_, values) = collect((Bound::Included(300), Bound::Unbounded))?;
+        assert_eq!(values, vec!["350", "550"]);
+
+        let (buckets, values) = collect((Bound::Unbounded, Bound::Excluded(500)))?;
+        assert_eq!(buckets, (1, 4));
+        assert_eq!(values, vec!["150", "350"]);
+
+        // Exclusive bounds that leave no sequences yield nothing
+        let (_, values) = collect((Bound::Excluded(200), Bound::Excluded(201)))?;
+        assert!(values.is_empty());
+        let (_, values) = collect((Bound::Unbounded, Bound::Excluded(0)))?;
+        assert!(values.is_empty());
+
+        let values: Vec<u64> = read_txn
+            .open_multimap_table(TEST_MULTIMAP)?
+            .bucket_range_bounds(&key_builder, 1u64, ..)?
+            .rev()
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![3u64, 2u64, 1u64]);
+
+        // An empty table has no buckets to resolve the range from
+        let read_txn = {
+            let write_txn = db.begin_write()?;
+            write_txn.delete_table(TEST_TABLE)?;
+            write_txn.open_table(TEST_TABLE)?;
+            write_txn.commit()?;
+            db.begin_read()?
+        };
+        let iter =
+            read_txn
+                .open_table(TEST_TABLE)?
+                .bucket_range_bounds(&key_builder, 1u64, 200..)?;
+        assert_eq!(iter.count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_token() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+
+        let write_txn = db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TEST_TABLE)?;
+            for sequence in (0..500).step_by(100) {
+                table.insert(
+                    key_builder.bucketed_key(1u64, sequence),
+                    sequence.to_string(),
+                )?;
+            }
+        }
+        write_txn.commit()?;
+
+        // Take one value from each end, then pause
+        let token = {
+            let read_txn = db.begin_read()?;
+            let mut iter =
+                read_txn
+                    .open_table(TEST_TABLE)?
+                    .bucket_range_bounds(&key_builder, 1u64, ..)?;
+            assert_eq!(iter.next().transpose()?, Some("0".to_string()));
+            assert_eq!(iter.next_back().transpose()?, Some("400".to_string()));
+            ResumeToken::from_bytes(&iter.resume_token().to_bytes())?
+        };
+        assert_eq!(
+            token,
+            ResumeToken {
+                front_bucket: 1,
+                back_bucket: 3
+            }
+        );
+
+        // Writes between pages are seen by the resumed iteration
+        let write_txn = db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TEST_TABLE)?;
+            table.insert(key_builder.bucketed_key(1u64, 250), "250".to_string())?;
+        }
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        let mut iter = BucketRangeIterator::resume(read_txn.open_table(TEST_TABLE)?, 1u64, token)?;
+        assert_eq!(iter.bucket_range(), (1, 3));
+        let values: Vec<String> = iter.by_ref().collect::<Result<_, _>>()?;
+        assert_eq!(values, vec!["100", "250", "300"]);
+        assert!(iter.resume_token().is_exhausted());
+
+        let resumed = BucketRangeIterator::resume(
+            read_txn.open_table(TEST_TABLE)?,
+            1u64,
+            iter.resume_token(),
+        )?;
+        assert_eq!(resumed.count(), 0);
+        assert!(ResumeToken::from_bytes(&[0; 8]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_buckets_beyond_i64_max() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(1)?;
+        let middle = i64::MAX as u64;
+        let top = [u64::MAX - 2, u64::MAX - 1, u64::MAX];
+
+        let write_txn = db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TEST_TABLE)?;
+            let mut multimap = write_txn.open_multimap_table(TEST_MULTIMAP)?;
+            for sequence in [middle, middle + 1].into_iter().chain(top) {
+                table.insert(
+                    key_builder.bucketed_key(1u64, sequence),
+                    sequence.to_string(),
+                )?;
+                multimap.insert(key_builder.bucketed_key(1u64, sequence), sequence)?;
+            }
+        }
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        let table = read_txn.open_table(TEST_TABLE)?;
+        let mut iter = (&table).bucket_range_bounds(&key_builder, 1u64, u64::MAX - 2..)?;
+        assert_eq!(iter.next().transpose()?, Some(top[0].to_string()));
+        let token = iter.resume_token();
+        assert_eq!(
+            token,
+            ResumeToken {
+                front_bucket: u64::MAX - 1,
+                back_bucket: u64::MAX
+            }
+        );
+
+        let mut resumed = BucketRangeIterator::resume(&table, 1u64, token)?;
+        assert_eq!(resumed.next_back().transpose()?, Some(top[2].to_string()));
+        assert_eq!(resumed.next().transpose()?, Some(top[1].to_string()));
+        assert!(resumed.next().is_none());
+        assert!(resumed.resume_token().is_exhausted());
+
+        let multimap = read_txn.open_multimap_table(TEST_MULTIMAP)?;
+        let values: Vec<u64> = multimap
+            .bucket_range_bounds(&key_builder, 1u64, u64::MAX - 2..)?
+            .rev()
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![top[2], top[1], top[0]]);
+
+        // Ranges crossing i64::MAX are visited from both ends
+        let range = (&table).bucket_range(&key_builder, 1u64, middle, middle + 1)?;
+        let values: Vec<String> = range.rev().collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![(middle + 1).to_string(), middle.to_string()]);
+        let token = ResumeToken {
+            front_bucket: middle,
+            back_bucket: middle + 1,
+        };
+        let resumed = BucketRangeIterator::resume(&table, 1u64, token)?;
+        assert_eq!(resumed.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_borrowing_iteration() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+
+        let write_txn = db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TEST_TABLE)?;
+            let mut multimap = write_txn.open_multimap_table(TEST_MULTIMAP)?;
+            for sequence in [10u64, 150, 250] {
+                table.insert(
+                    key_builder.bucketed_key(1u64, sequence),
+                    sequence.to_string(),
+                )?;
+                table.insert(key_builder.bucketed_key(2u64, sequence), "two".to_string())?;
+                multimap.insert(key_builder.bucketed_key(1u64, sequence), sequence)?;
+            }
+        }
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        let table = read_txn.open_table(TEST_TABLE)?;
+        let first: Vec<String> = (&table)
+            .bucket_range_bounds(&key_builder, 1u64, ..200)?
+            .collect::<Result<_, _>>()?;
+        let second = (&table)
+            .bucket_range_bounds(&key_builder, 2u64, ..)?
+            .count();
+        assert_eq!(first, vec!["10", "150"]);
+        assert_eq!(second, 3);
+
+        let merged = (&table)
+            .merged_bucket_range(&key_builder, [1u64, 2], 200..)?
+            .count();
+        assert_eq!(merged, 2);
+        // The table is still usable after the borrowing iterators are gone
+        assert_eq!(
+            table.bucket_range_bounds(&key_builder, 1u64, ..)?.count(),
+            3
+        );
+
+        let multimap = read_txn.open_multimap_table(TEST_MULTIMAP)?;
+        let values: Vec<u64> = (&multimap)
+            .bucket_range_bounds(&key_builder, 1u64, 100..)?
+            .rev()
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![250, 150]);
+        assert_eq!(
+            (&multimap)
+                .merged_bucket_range(&key_builder, [1u64], ..)?
+                .count(),
+            3
+        );
+
+        Ok(())
+    }
 }
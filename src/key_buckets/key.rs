@@ -1,19 +1,95 @@
 //! Bucketed key implementations.
 //!
-//! Provides KeyBuilder for configuration and BucketedKey for storage.
+//! Provides KeyBuilder for configuration and BucketedKey for storage, plus
+//! helpers for bucketing unix-millisecond timestamps.
 
+use crate::error::DatabaseError;
 use crate::key_buckets::BucketError;
-use redb::{Key, Value};
+use redb::{
+    Key, ReadTransaction, ReadableTable, TableDefinition, TableError, Value, WriteTransaction,
+};
 use std::cmp::Ordering;
-use std::fmt::Debug;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Milliseconds in an hour.
+pub(crate) const HOUR_MILLIS: u64 = 60 * 60 * 1000;
+
+/// Milliseconds in a day.
+pub(crate) const DAY_MILLIS: u64 = 24 * HOUR_MILLIS;
+
+/// Table definition for persisted key builder configurations (one row per name)
+pub const BUCKET_CONFIG_TABLE: TableDefinition<&'static str, &'static [u8]> =
+    TableDefinition::new("redb_extras_bucket_configs");
+
+/// Encoded size of a persisted configuration: bucket size + shift.
+const CONFIG_LEN: usize = 8 + 8;
+
+/// Converts a point in time to milliseconds since the unix epoch.
+///
+/// # Arguments
+/// * `time` - The point in time to convert
+///
+/// # Returns
+/// Milliseconds since the epoch or error if the time is before the epoch
+pub fn unix_millis(time: SystemTime) -> Result<u64, BucketError> {
+    let elapsed = time.duration_since(UNIX_EPOCH).map_err(|e| {
+        BucketError::InvalidTimestamp(format!("{:?} is before the unix epoch", e.duration()))
+    })?;
+
+    u64::try_from(elapsed.as_millis()).map_err(|_| {
+        BucketError::InvalidTimestamp(format!("{:?} after the unix epoch overflows", elapsed))
+    })
+}
+
+/// Maps sequences to buckets.
+///
+/// Implemented by `KeyBuilder` for fixed-size buckets and `TieredKeyBuilder`
+/// for buckets that grow with age. Buckets must be non-decreasing in the
+/// sequence, which is what lets bucket iterators turn a sequence range into
+/// a contiguous bucket range.
+pub trait BucketScheme {
+    /// Get the bucket a sequence falls into.
+    fn bucket_for(&self, sequence: u64) -> u64;
+
+    /// Get the first sequence that falls into a bucket.
+    fn bucket_start(&self, bucket: u64) -> u64;
+
+    /// Get the last sequence that falls into a bucket.
+    fn bucket_end(&self, bucket: u64) -> u64 {
+        if bucket >= self.bucket_for(u64::MAX) {
+            u64::MAX
+        } else {
+            self.bucket_start(bucket + 1) - 1
+        }
+    }
+
+    /// Create a bucketed key from the given base key and sequence.
+    fn bucketed_key<K: Key>(&self, base_key: K, sequence: u64) -> BucketedKey<K> {
+        BucketedKey {
+            base_key,
+            bucket: self.bucket_for(sequence),
+        }
+    }
+
+    /// Create a tagged bucketed key from the given tag, base key and sequence.
+    fn tagged_key<K: Key>(&self, tag: u16, base_key: K, sequence: u64) -> TaggedBucketedKey<K> {
+        self.bucketed_key((tag, base_key), sequence)
+    }
+}
 
 /// Builder for creating bucketed keys with consistent configuration.
 ///
 /// KeyBuilder holds the bucket configuration and can be reused to create
 /// bucketed keys for any base key type and sequence.
-#[derive(Debug, Clone)]
+///
+/// For time series, sequences are unix milliseconds: `from_duration`,
+/// `hourly` and `daily` size buckets in milliseconds, and `with_utc_offset`
+/// moves bucket boundaries to a local calendar (for example local midnight).
+/// `with_offset` moves them to any other sequence.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct KeyBuilder {
     bucket_size: u64,
+    shift: u64,
 }
 
 impl KeyBuilder {
@@ -28,12 +104,83 @@ impl KeyBuilder {
         if bucket_size == 0 {
             return Err(BucketError::InvalidBucketSize(bucket_size));
         }
-        Ok(Self { bucket_size })
+        Ok(Self {
+            bucket_size,
+            shift: 0,
+        })
+    }
+
+    /// Create a KeyBuilder whose buckets span the given duration of unix milliseconds.
+    ///
+    /// # Arguments
+    /// * `duration` - Time span of each bucket (must be at least one millisecond)
+    ///
+    /// # Returns
+    /// Configured KeyBuilder or error if the duration is shorter than a millisecond
+    pub fn from_duration(duration: Duration) -> Result<Self, BucketError> {
+        Self::new(u64::try_from(duration.as_millis()).unwrap_or(u64::MAX))
+    }
+
+    /// Create a KeyBuilder with one bucket per UTC hour of unix milliseconds.
+    pub fn hourly() -> Self {
+        Self {
+            bucket_size: HOUR_MILLIS,
+            shift: 0,
+        }
+    }
+
+    /// Create a KeyBuilder with one bucket per UTC day of unix milliseconds.
+    pub fn daily() -> Self {
+        Self {
+            bucket_size: DAY_MILLIS,
+            shift: 0,
+        }
+    }
+
+    /// Aligns bucket boundaries to a local calendar instead of UTC.
+    ///
+    /// Sequences are treated as unix milliseconds, and buckets start at
+    /// multiples of the bucket size in local time. With `daily`, buckets run
+    /// from local midnight to local midnight; with `hourly`, zones with a
+    /// half-hour offset get buckets starting at half past the UTC hour.
+    ///
+    /// # Arguments
+    /// * `offset_secs` - Local time offset from UTC in seconds, east positive
+    ///
+    /// # Returns
+    /// KeyBuilder with shifted bucket boundaries
+    pub fn with_utc_offset(mut self, offset_secs: i64) -> Self {
+        let offset_millis = i128::from(offset_secs) * 1000;
+        self.shift = offset_millis.rem_euclid(i128::from(self.bucket_size)) as u64;
+        self
+    }
+
+    /// Aligns bucket boundaries to an arbitrary sequence.
+    ///
+    /// Buckets start at `offset` plus multiples of the bucket size instead of
+    /// at multiples of the bucket size, e.g. to follow epochs that don't
+    /// start at sequence 0. Sequences before the first boundary fall into a
+    /// shorter bucket 0. Replaces any UTC offset set before.
+    ///
+    /// # Arguments
+    /// * `offset` - Any sequence at which a bucket starts
+    ///
+    /// # Returns
+    /// KeyBuilder with shifted bucket boundaries
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        self.shift = (self.bucket_size - offset % self.bucket_size) % self.bucket_size;
+        self
+    }
+
+    /// Get the first sequence at or after 0 where a bucket starts.
+    pub fn offset(&self) -> u64 {
+        (self.bucket_size - self.shift) % self.bucket_size
     }
 
     /// Create a bucketed key from the given base key and sequence.
     ///
-    /// The bucket is calculated as `sequence / bucket_size` using integer division.
+    /// The bucket is calculated as `sequence / bucket_size` using integer
+    /// division, after applying any offset.
     ///
     /// # Arguments
     /// * `base_key` - The base key (any type implementing redb::Key)
@@ -42,14 +189,167 @@ impl KeyBuilder {
     /// # Returns
     /// BucketedKey with bucket as prefix and base_key as secondary component
     pub fn bucketed_key<K: Key>(&self, base_key: K, sequence: u64) -> BucketedKey<K> {
-        let bucket = sequence / self.bucket_size;
-        BucketedKey { base_key, bucket }
+        BucketedKey {
+            base_key,
+            bucket: self.bucket_for(sequence),
+        }
+    }
+
+    /// Create a bucketed key from the given base key and point in time.
+    ///
+    /// # Arguments
+    /// * `base_key` - The base key (any type implementing redb::Key)
+    /// * `time` - The point in time to bucket
+    ///
+    /// # Returns
+    /// BucketedKey or error if the time is before the unix epoch
+    pub fn bucketed_key_at<K: Key>(
+        &self,
+        base_key: K,
+        time: SystemTime,
+    ) -> Result<BucketedKey<K>, BucketError> {
+        Ok(self.bucketed_key(base_key, unix_millis(time)?))
+    }
+
+    /// Get the bucket a sequence falls into.
+    pub fn bucket_for(&self, sequence: u64) -> u64 {
+        ((u128::from(sequence) + u128::from(self.shift)) / u128::from(self.bucket_size)) as u64
+    }
+
+    /// Get the bucket a point in time falls into.
+    ///
+    /// # Returns
+    /// The bucket or error if the time is before the unix epoch
+    pub fn bucket_at(&self, time: SystemTime) -> Result<u64, BucketError> {
+        Ok(self.bucket_for(unix_millis(time)?))
+    }
+
+    /// Get the first sequence that falls into a bucket.
+    ///
+    /// With an offset the first bucket starts before sequence 0, in which
+    /// case 0 is returned.
+    pub fn bucket_start(&self, bucket: u64) -> u64 {
+        let start = u128::from(bucket) * u128::from(self.bucket_size);
+        start
+            .saturating_sub(u128::from(self.shift))
+            .min(u128::from(u64::MAX)) as u64
     }
 
     /// Get the configured bucket size.
     pub fn bucket_size(&self) -> u64 {
         self.bucket_size
     }
+
+    /// Records this configuration under a name, or checks it against the recorded one.
+    ///
+    /// Processes that share bucketed tables should persist the builder they
+    /// use, so one started with a different bucket size or UTC offset fails
+    /// instead of silently writing keys into the wrong buckets.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `name` - Name the configuration is stored under, typically the table name
+    ///
+    /// # Returns
+    /// Ok if the configuration was recorded or matches, `ConfigMismatch` otherwise
+    pub fn persist(&self, txn: &WriteTransaction, name: &str) -> Result<(), BucketError> {
+        let mut table = txn.open_table(BUCKET_CONFIG_TABLE).map_err(|e| {
+            BucketError::DatabaseError(DatabaseError::new("Failed to open bucket config table", e))
+        })?;
+
+        let stored = table
+            .get(name)
+            .map_err(|e| {
+                BucketError::DatabaseError(DatabaseError::new("Failed to read bucket config", e))
+            })?
+            .map(|guard| Self::decode(guard.value()))
+            .transpose()?;
+
+        match stored {
+            Some(stored) if stored != *self => Err(BucketError::ConfigMismatch(format!(
+                "'{}' was persisted with bucket size {} and shift {} but is used with bucket size {} and shift {}",
+                name, stored.bucket_size, stored.shift, self.bucket_size, self.shift
+            ))),
+            Some(_) => Ok(()),
+            None => {
+                table
+                    .insert(name, self.encode().as_slice())
+                    .map_err(|e| {
+                        BucketError::DatabaseError(DatabaseError::new("Failed to write bucket config", e))
+                    })?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Loads a configuration recorded with `persist`.
+    ///
+    /// # Arguments
+    /// * `txn` - The read transaction
+    /// * `name` - Name the configuration was stored under
+    ///
+    /// # Returns
+    /// The stored builder, or None if nothing was persisted under the name
+    pub fn load(txn: &ReadTransaction, name: &str) -> Result<Option<Self>, BucketError> {
+        let table = match txn.open_table(BUCKET_CONFIG_TABLE) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => {
+                return Err(BucketError::DatabaseError(DatabaseError::new(
+                    "Failed to open bucket config table",
+                    e,
+                )))
+            }
+        };
+
+        table
+            .get(name)
+            .map_err(|e| {
+                BucketError::DatabaseError(DatabaseError::new("Failed to read bucket config", e))
+            })?
+            .map(|guard| Self::decode(guard.value()))
+            .transpose()
+    }
+
+    /// Encodes the configuration as \\[bucket_size u64 BE\\]\\[shift u64 BE\\].
+    fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(CONFIG_LEN);
+        encoded.extend_from_slice(&self.bucket_size.to_be_bytes());
+        encoded.extend_from_slice(&self.shift.to_be_bytes());
+        encoded
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, BucketError> {
+        if data.len() != CONFIG_LEN {
+            return Err(BucketError::SerializationError(format!(
+                "Invalid bucket config length: expected {}, got {}",
+                CONFIG_LEN,
+                data.len()
+            )));
+        }
+
+        let (size_bytes, shift_bytes) = data.split_at(8);
+        let bucket_size = u64::from_be_bytes(size_bytes.try_into().unwrap());
+        let shift = u64::from_be_bytes(shift_bytes.try_into().unwrap());
+        if bucket_size == 0 || shift >= bucket_size {
+            return Err(BucketError::SerializationError(format!(
+                "Invalid bucket config: bucket size {} with shift {}",
+                bucket_size, shift
+            )));
+        }
+
+        Ok(Self { bucket_size, shift })
+    }
+}
+
+impl BucketScheme for KeyBuilder {
+    fn bucket_for(&self, sequence: u64) -> u64 {
+        KeyBuilder::bucket_for(self, sequence)
+    }
+
+    fn bucket_start(&self, bucket: u64) -> u64 {
+        KeyBuilder::bucket_start(self, bucket)
+    }
 }
 
 /// A bucketed key that implements redb::Key for storage.
@@ -57,13 +357,19 @@ impl KeyBuilder {
 /// BucketedKey stores a base key along with its computed bucket.
 /// The bucket serves as the primary sort key (prefix) while the base key
 /// provides secondary sorting within each bucket.
+///
+/// Any `redb::Key` can be used as the base key. The bucket is encoded as
+/// 8 little-endian bytes followed by the base key's own encoding, and keys
+/// within a bucket are ordered with the base key's `compare`. For `&[u8]`
+/// and `&str` base keys, such as hash identifiers, that is plain lexicographic
+/// byte order, with a key sorting before any longer key it prefixes.
 #[derive(Debug, Clone)]
-pub struct BucketedKey<K: Key> {
+pub struct BucketedKey<K> {
     pub base_key: K,
     pub bucket: u64,
 }
 
-impl<K: Key> BucketedKey<K> {
+impl<K> BucketedKey<K> {
     /// Create a new BucketedKey directly.
     ///
     /// Note: Typically you should use KeyBuilder::bucketed_key() instead
@@ -83,10 +389,47 @@ impl<K: Key> BucketedKey<K> {
     }
 }
 
-// For now, we'll implement a simple version that works with u64 base keys
-impl Value for BucketedKey<u64> {
+/// A bucketed key with a record-type tag between the bucket and the base key.
+///
+/// Lets several record types share one bucketed table: keys sort by bucket,
+/// then tag, then base key, so entries of one type are never visited when
+/// iterating another. Since the tag is part of the base key, the bucket
+/// iterators, statistics and pruning work on tagged tables directly, with
+/// `(tag, base_key)` passed as the base key.
+///
+/// Encoded as the 8-byte bucket, the 2-byte little-endian tag and the base
+/// key's own encoding, following redb's layout for `(u16, K)` tuples.
+pub type TaggedBucketedKey<K> = BucketedKey<(u16, K)>;
+
+impl<K> BucketedKey<(u16, K)> {
+    /// Get the record-type tag of a tagged key.
+    pub fn tag(&self) -> u16 {
+        self.base_key.0
+    }
+}
+
+/// Encoded size of the bucket prefix.
+const BUCKET_BYTES: usize = 8;
+
+/// Splits encoded key data into the bucket and the encoded base key.
+fn split_bucket(data: &[u8]) -> (u64, &[u8]) {
+    if data.len() < BUCKET_BYTES {
+        panic!(
+            "BucketedKey data too short: expected at least {} bytes, got {}",
+            BUCKET_BYTES,
+            data.len()
+        );
+    }
+
+    let (bucket, base_key) = data.split_at(BUCKET_BYTES);
+    let mut bucket_bytes = [0u8; BUCKET_BYTES];
+    bucket_bytes.copy_from_slice(bucket);
+    (u64::from_le_bytes(bucket_bytes), base_key)
+}
+
+impl<K: Key + 'static> Value for BucketedKey<K> {
     type SelfType<'a>
-        = BucketedKey<u64>
+        = BucketedKey<K::SelfType<'a>>
     where
         Self: 'a;
 
@@ -96,31 +439,19 @@ impl Value for BucketedKey<u64> {
         Self: 'a;
 
     fn fixed_width() -> Option<usize> {
-        Some(16) // 8 bytes bucket + 8 bytes u64 base key
+        // 8 bytes bucket + the base key's width
+        K::fixed_width().map(|width| BUCKET_BYTES + width)
     }
 
     fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
     where
         Self: 'a,
     {
-        if data.len() < 16 {
-            panic!(
-                "BucketedKey data too short: expected at least 16 bytes, got {}",
-                data.len()
-            );
+        let (bucket, base_key) = split_bucket(data);
+        BucketedKey {
+            base_key: K::from_bytes(base_key),
+            bucket,
         }
-
-        // Read bucket (first 8 bytes, little-endian)
-        let bucket = u64::from_le_bytes([
-            data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
-        ]);
-
-        // Read base key (next 8 bytes, little-endian)
-        let base_key = u64::from_le_bytes([
-            data[8], data[9], data[10], data[11], data[12], data[13], data[14], data[15],
-        ]);
-
-        BucketedKey { base_key, bucket }
     }
 
     fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
@@ -128,53 +459,33 @@ impl Value for BucketedKey<u64> {
         Self: 'a,
         Self: 'b,
     {
-        // Serialize bucket as 8-byte little-endian
-        let bucket_bytes = value.bucket.to_le_bytes();
+        let base_key_bytes = K::as_bytes(&value.base_key);
+        let base_key_bytes = base_key_bytes.as_ref();
 
-        // Serialize base key as 8-byte little-endian
-        let base_key_bytes = value.base_key.to_le_bytes();
-
-        // Concatenate bucket + base key
-        let mut result = Vec::with_capacity(16);
-        result.extend_from_slice(&bucket_bytes);
-        result.extend_from_slice(&base_key_bytes);
+        // Concatenate bucket (8-byte little-endian) + base key
+        let mut result = Vec::with_capacity(BUCKET_BYTES + base_key_bytes.len());
+        result.extend_from_slice(&value.bucket.to_le_bytes());
+        result.extend_from_slice(base_key_bytes);
 
         result
     }
 
     fn type_name() -> redb::TypeName {
-        redb::TypeName::new("redb_extras::key_buckets::BucketedKey<u64>")
+        redb::TypeName::new(&format!(
+            "redb_extras::key_buckets::BucketedKey<{}>",
+            K::type_name().name()
+        ))
     }
 }
 
-impl Key for BucketedKey<u64> {
+impl<K: Key + 'static> Key for BucketedKey<K> {
     fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
-        // Extract bucket from both keys (first 8 bytes)
-        if data1.len() < 16 || data2.len() < 16 {
-            panic!("BucketedKey data too short for comparison");
-        }
+        let (bucket1, base1) = split_bucket(data1);
+        let (bucket2, base2) = split_bucket(data2);
 
-        let bucket1 = u64::from_le_bytes([
-            data1[0], data1[1], data1[2], data1[3], data1[4], data1[5], data1[6], data1[7],
-        ]);
-        let bucket2 = u64::from_le_bytes([
-            data2[0], data2[1], data2[2], data2[3], data2[4], data2[5], data2[6], data2[7],
-        ]);
-
-        // First compare bucket
+        // First compare bucket, then base keys within the same bucket
         match bucket1.cmp(&bucket2) {
-            Ordering::Equal => {
-                // If buckets equal, compare base keys
-                let base1 = u64::from_le_bytes([
-                    data1[8], data1[9], data1[10], data1[11], data1[12], data1[13], data1[14],
-                    data1[15],
-                ]);
-                let base2 = u64::from_le_bytes([
-                    data2[8], data2[9], data2[10], data2[11], data2[12], data2[13], data2[14],
-                    data2[15],
-                ]);
-                base1.cmp(&base2)
-            }
+            Ordering::Equal => K::compare(base1, base2),
             other => other,
         }
     }
@@ -219,11 +530,11 @@ mod tests {
         let key = builder.bucketed_key(123u64, 1500); // bucket 1
 
         // Serialize to bytes
-        let bytes: Vec<u8> = BucketedKey::as_bytes(&key);
+        let bytes: Vec<u8> = BucketedKey::<u64>::as_bytes(&key);
         assert_eq!(bytes.len(), 16);
 
         // Deserialize back
-        let deserialized: BucketedKey<u64> = BucketedKey::from_bytes(&bytes);
+        let deserialized = BucketedKey::<u64>::from_bytes(&bytes);
         assert_eq!(deserialized.bucket(), 1);
         assert_eq!(deserialized.base_key(), &123u64);
     }
@@ -238,9 +549,9 @@ mod tests {
         let key3 = builder.bucketed_key(456u64, 500); // bucket 0, different base
 
         // Serialize for comparison
-        let bytes1: Vec<u8> = BucketedKey::as_bytes(&key1);
-        let bytes2: Vec<u8> = BucketedKey::as_bytes(&key2);
-        let bytes3: Vec<u8> = BucketedKey::as_bytes(&key3);
+        let bytes1: Vec<u8> = BucketedKey::<u64>::as_bytes(&key1);
+        let bytes2: Vec<u8> = BucketedKey::<u64>::as_bytes(&key2);
+        let bytes3: Vec<u8> = BucketedKey::<u64>::as_bytes(&key3);
 
         // Bucket should be primary sort key
         assert_eq!(
@@ -262,4 +573,258 @@ mod tests {
             Ordering::Greater
         );
     }
+
+    #[test]
+    fn test_tuple_base_key() {
+        type Composite = BucketedKey<(u64, &'static str)>;
+        assert_eq!(Composite::fixed_width(), None);
+        assert_eq!(
+            Composite::type_name().name(),
+            "redb_extras::key_buckets::BucketedKey<(u64,&str)>"
+        );
+
+        let builder = KeyBuilder::new(1000).unwrap();
+        let key1 = builder.bucketed_key((7u64, "alpha"), 1500);
+        let key2 = builder.bucketed_key((7u64, "beta"), 1500);
+        let key3 = builder.bucketed_key((3u64, "zeta"), 2500);
+
+        let bytes1 = Composite::as_bytes(&key1);
+        let bytes2 = Composite::as_bytes(&key2);
+        let bytes3 = Composite::as_bytes(&key3);
+
+        let decoded = Composite::from_bytes(&bytes2);
+        assert_eq!(decoded.bucket(), 1);
+        assert_eq!(decoded.base_key(), &(7u64, "beta"));
+
+        assert_eq!(Composite::compare(&bytes1, &bytes2), Ordering::Less);
+        assert_eq!(Composite::compare(&bytes2, &bytes3), Ordering::Less);
+    }
+
+    #[test]
+    fn test_byte_slice_and_str_base_keys() {
+        let builder = KeyBuilder::new(1000).unwrap();
+
+        // Within a bucket, slices order lexicographically, prefixes first
+        let slices: [&[u8]; 4] = [b"", b"ab", b"abc", b"b"];
+        let encoded: Vec<Vec<u8>> = slices
+            .iter()
+            .map(|slice| BucketedKey::<&[u8]>::as_bytes(&builder.bucketed_key(*slice, 1500)))
+            .collect();
+        for pair in encoded.windows(2) {
+            assert_eq!(
+                BucketedKey::<&[u8]>::compare(&pair[0], &pair[1]),
+                Ordering::Less
+            );
+        }
+
+        // The bucket still dominates the base key
+        let earlier = BucketedKey::<&[u8]>::as_bytes(&builder.bucketed_key(&b"zz"[..], 500));
+        assert_eq!(
+            BucketedKey::<&[u8]>::compare(&earlier, &encoded[0]),
+            Ordering::Less
+        );
+
+        let key = builder.bucketed_key("user-\u{e9}", 2500);
+        let bytes = BucketedKey::<&str>::as_bytes(&key);
+        let decoded = BucketedKey::<&str>::from_bytes(&bytes);
+        assert_eq!(decoded.bucket(), 2);
+        assert_eq!(*decoded.base_key(), "user-\u{e9}");
+
+        let other = BucketedKey::<&str>::as_bytes(&builder.bucketed_key("user-a", 2500));
+        assert_eq!(BucketedKey::<&str>::compare(&other, &bytes), Ordering::Less);
+    }
+
+    #[test]
+    fn test_time_based_buckets() {
+        let hourly = KeyBuilder::from_duration(Duration::from_secs(3600)).unwrap();
+        assert_eq!(hourly.bucket_size(), KeyBuilder::hourly().bucket_size());
+        assert!(KeyBuilder::from_duration(Duration::from_micros(10)).is_err());
+
+        // 2024-01-01T00:00:00Z
+        let midnight = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+        let daily = KeyBuilder::daily();
+        let day = daily.bucket_at(midnight).unwrap();
+        assert_eq!(
+            daily
+                .bucket_at(midnight + Duration::from_secs(86_399))
+                .unwrap(),
+            day
+        );
+        assert_eq!(
+            daily
+                .bucket_at(midnight + Duration::from_secs(86_400))
+                .unwrap(),
+            day + 1
+        );
+
+        let key = hourly
+            .bucketed_key_at(7u64, midnight + Duration::from_secs(5400))
+            .unwrap();
+        assert_eq!(key.bucket(), hourly.bucket_at(midnight).unwrap() + 1);
+
+        assert!(daily
+            .bucket_at(UNIX_EPOCH - Duration::from_secs(1))
+            .is_err());
+        assert_eq!(unix_millis(midnight).unwrap(), 1_704_067_200_000);
+    }
+
+    #[test]
+    fn test_calendar_aligned_buckets() {
+        // UTC+2: local midnight is 22:00 UTC the previous day
+        let local = KeyBuilder::daily().with_utc_offset(2 * 3600);
+        let utc_midnight = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+        let before = utc_midnight - Duration::from_secs(2 * 3600 + 1);
+        let after = utc_midnight - Duration::from_secs(2 * 3600);
+        assert_eq!(
+            local.bucket_at(after).unwrap(),
+            local.bucket_at(before).unwrap() + 1
+        );
+        assert_eq!(
+            local.bucket_at(after).unwrap(),
+            local.bucket_at(utc_midnight).unwrap()
+        );
+
+        // UTC-5:30 hours start at half past the UTC hour
+        let hourly = KeyBuilder::hourly().with_utc_offset(-(5 * 3600 + 1800));
+        let half_past = utc_midnight + Duration::from_secs(1800);
+        assert_eq!(
+            hourly.bucket_at(half_past).unwrap(),
+            hourly
+                .bucket_at(half_past - Duration::from_millis(1))
+                .unwrap()
+                + 1
+        );
+    }
+
+    #[test]
+    fn test_offset_aligned_buckets() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::key_buckets::BucketIterExt;
+        use redb::{Database, ReadableDatabase};
+
+        const EPOCHS: TableDefinition<'static, BucketedKey<u64>, u64> =
+            TableDefinition::new("epochs");
+
+        // Epochs of 100 starting at sequence 30
+        let epochs = KeyBuilder::new(100)?.with_offset(30);
+        assert_eq!(epochs.offset(), 30);
+        assert_eq!(KeyBuilder::new(100)?.with_offset(230), epochs);
+        assert_eq!(epochs.bucket_for(0), 0);
+        assert_eq!(epochs.bucket_for(29), 0);
+        assert_eq!(epochs.bucket_for(30), 1);
+        assert_eq!(epochs.bucket_for(129), 1);
+        assert_eq!(epochs.bucket_for(130), 2);
+        assert_eq!(epochs.bucket_start(2), 130);
+        assert_eq!(epochs.bucket_start(0), 0);
+        assert_eq!(epochs.bucket_end(1), 129);
+        assert_eq!(epochs.bucket_end(epochs.bucket_for(u64::MAX)), u64::MAX);
+        assert_eq!(KeyBuilder::new(100)?.with_offset(300).offset(), 0);
+
+        let temp_file = tempfile::NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let write_txn = db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(EPOCHS)?;
+            for sequence in [10u64, 40, 140, 240] {
+                table.insert(epochs.bucketed_key(1u64, sequence), sequence)?;
+            }
+        }
+        write_txn.commit()?;
+
+        // Range math uses the same boundaries
+        let read_txn = db.begin_read()?;
+        let values: Vec<u64> = read_txn
+            .open_table(EPOCHS)?
+            .bucket_range_bounds(&epochs, 1u64, 30..130)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![40]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_persist_and_load() -> Result<(), Box<dyn std::error::Error>> {
+        use redb::{Database, ReadableDatabase};
+
+        let temp_file = tempfile::NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        assert_eq!(KeyBuilder::load(&db.begin_read()?, "events")?, None);
+
+        let local = KeyBuilder::daily().with_utc_offset(3600);
+        let write_txn = db.begin_write()?;
+        local.persist(&write_txn, "events")?;
+        local.persist(&write_txn, "events")?;
+        let err = KeyBuilder::daily()
+            .persist(&write_txn, "events")
+            .unwrap_err();
+        assert!(matches!(err, BucketError::ConfigMismatch(_)), "{}", err);
+        KeyBuilder::hourly().persist(&write_txn, "samples")?;
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        assert_eq!(KeyBuilder::load(&read_txn, "events")?, Some(local));
+        assert_eq!(
+            KeyBuilder::load(&read_txn, "samples")?,
+            Some(KeyBuilder::hourly())
+        );
+        assert_eq!(KeyBuilder::load(&read_txn, "other")?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tagged_keys() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::key_buckets::BucketIterExt;
+        use redb::{Database, ReadableDatabase};
+
+        const RECORDS: TableDefinition<'static, TaggedBucketedKey<u64>, String> =
+            TableDefinition::new("records");
+        const ORDER: u16 = 1;
+        const PAYMENT: u16 = 2;
+
+        let key_builder = KeyBuilder::new(100)?;
+        let key = key_builder.tagged_key(PAYMENT, 42u64, 150);
+        assert_eq!(
+            (key.bucket(), key.tag(), key.base_key().1),
+            (1, PAYMENT, 42)
+        );
+
+        let bytes = TaggedBucketedKey::<u64>::as_bytes(&key);
+        assert_eq!(bytes.len(), 8 + 2 + 8);
+        assert_eq!(&bytes[8..10], &PAYMENT.to_le_bytes());
+        assert_eq!(TaggedBucketedKey::<u64>::from_bytes(&bytes).tag(), PAYMENT);
+
+        let temp_file = tempfile::NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let write_txn = db.begin_write()?;
+        {
+            let mut records = write_txn.open_table(RECORDS)?;
+            records.insert(
+                key_builder.tagged_key(ORDER, 42u64, 10),
+                "order 1".to_string(),
+            )?;
+            records.insert(
+                key_builder.tagged_key(PAYMENT, 42u64, 20),
+                "payment 1".to_string(),
+            )?;
+            records.insert(
+                key_builder.tagged_key(ORDER, 42u64, 110),
+                "order 2".to_string(),
+            )?;
+        }
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        let orders: Vec<String> = read_txn
+            .open_table(RECORDS)?
+            .bucket_range_bounds(&key_builder, (ORDER, 42u64), ..)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(orders, vec!["order 1", "order 2"]);
+
+        // Within a bucket, tags sort before base keys
+        let records = read_txn.open_table(RECORDS)?;
+        let (first, _) = records.first()?.unwrap();
+        assert_eq!(first.value().tag(), ORDER);
+
+        Ok(())
+    }
 }
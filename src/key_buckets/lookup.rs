@@ -0,0 +1,116 @@
+//! Batched point lookups.
+//!
+//! Several sequences usually share a bucket, so looking each of them up
+//! separately reads the same entry again and again. `get_many` groups the
+//! requested sequences by bucket first and reads every bucket once.
+
+use crate::key_buckets::iterator::lookup_error;
+use crate::key_buckets::key::{BucketScheme, BucketedKey};
+use crate::key_buckets::BucketError;
+use redb::{Key, ReadableTable, Value};
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+
+/// Extension trait for batched lookups on bucketed tables.
+pub trait BucketLookupExt<K, V>
+where
+    K: Key + 'static,
+    V: Value + 'static,
+{
+    /// Looks up the values stored for a base key at several sequences.
+    ///
+    /// Sequences are grouped by bucket and each bucket is read once; all
+    /// sequences in a bucket map to that bucket's value.
+    ///
+    /// # Arguments
+    /// * `key_builder` - The builder the keys were created with
+    /// * `base_key` - The base key to look up
+    /// * `sequences` - The sequences to look up, in any order
+    ///
+    /// # Returns
+    /// Map from each sequence whose bucket holds a value to that value
+    fn get_many<'k>(
+        &self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        sequences: impl IntoIterator<Item = u64>,
+    ) -> Result<BTreeMap<u64, V>, BucketError>;
+}
+
+impl<K, V, T> BucketLookupExt<K, V> for T
+where
+    K: Key + 'static,
+    V: Value + Clone + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+    T: ReadableTable<BucketedKey<K>, V>,
+{
+    fn get_many<'k>(
+        &self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        sequences: impl IntoIterator<Item = u64>,
+    ) -> Result<BTreeMap<u64, V>, BucketError> {
+        let mut by_bucket: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for sequence in sequences {
+            by_bucket
+                .entry(key_builder.bucket_for(sequence))
+                .or_default()
+                .push(sequence);
+        }
+
+        let base_key = K::as_bytes(base_key.borrow()).as_ref().to_vec();
+        let mut found = BTreeMap::new();
+        for (bucket, sequences) in by_bucket {
+            let key = BucketedKey::new(K::from_bytes(&base_key), bucket);
+            let Some(guard) = self.get(&key).map_err(lookup_error)? else {
+                continue;
+            };
+
+            let value = V::from(guard.value());
+            for sequence in sequences {
+                found.insert(sequence, value.clone());
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_buckets::KeyBuilder;
+    use redb::{Database, ReadableDatabase, TableDefinition};
+    use tempfile::NamedTempFile;
+
+    const PRICES: TableDefinition<'static, BucketedKey<&str>, u64> = TableDefinition::new("prices");
+
+    #[test]
+    fn test_get_many() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+
+        let write_txn = db.begin_write()?;
+        {
+            let mut prices = write_txn.open_table(PRICES)?;
+            prices.insert(key_builder.bucketed_key("btc", 0), 10)?;
+            prices.insert(key_builder.bucketed_key("btc", 200), 30)?;
+            prices.insert(key_builder.bucketed_key("eth", 100), 99)?;
+
+            // Works on writable tables too
+            let found = prices.get_many(&key_builder, "eth", [150])?;
+            assert_eq!(found.get(&150), Some(&99));
+        }
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        let prices = read_txn.open_table(PRICES)?;
+        let found = prices.get_many(&key_builder, "btc", [250, 5, 150, 99, 201])?;
+        let expected: BTreeMap<u64, u64> = [(5, 10), (99, 10), (201, 30), (250, 30)].into();
+        assert_eq!(found, expected);
+        assert!(prices.get_many(&key_builder, "sol", [5])?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+//! Merged iteration over several base keys.
+//!
+//! All base keys share the same buckets, so merging their values in bucket
+//! order only needs one pass over the bucket range with a point lookup per
+//! base key and bucket, instead of one iterator per key and a merge on the
+//! caller's side.
+
+use crate::key_buckets::iterator::{
+    bucket_bounds, lookup_error, multimap_bucket_span, table_bucket_span,
+};
+use crate::key_buckets::key::{BucketScheme, BucketedKey};
+use crate::key_buckets::BucketError;
+use redb::{Key, MultimapValue, ReadOnlyMultimapTable, ReadOnlyTable};
+use std::borrow::Borrow;
+use std::marker::PhantomData;
+use std::ops::RangeBounds;
+
+/// Position of a merged iteration: the current bucket and base key.
+struct MergeCursor {
+    /// Encoded base keys with their index in the caller's list, in key order
+    base_keys: Vec<(usize, Vec<u8>)>,
+    /// Bucket being visited, None once the range is exhausted
+    bucket: Option<u64>,
+    end_bucket: u64,
+    /// Index into `base_keys` of the next lookup in the current bucket
+    position: usize,
+}
+
+impl MergeCursor {
+    fn new<'k, K, I>(base_keys: I, bounds: Option<(u64, u64)>) -> Self
+    where
+        K: Key + 'static,
+        I: IntoIterator,
+        I::Item: Borrow<K::SelfType<'k>>,
+    {
+        let mut encoded: Vec<(usize, Vec<u8>)> = Vec::new();
+        for (index, base_key) in base_keys.into_iter().enumerate() {
+            let bytes = K::as_bytes(base_key.borrow()).as_ref().to_vec();
+            if encoded.iter().all(|(_, existing)| *existing != bytes) {
+                encoded.push((index, bytes));
+            }
+        }
+        encoded.sort_by(|(_, a), (_, b)| K::compare(a, b));
+
+        let (start_bucket, end_bucket) = bounds.unwrap_or((0, 0));
+        Self {
+            bucket: bounds.filter(|_| !encoded.is_empty()).map(|_| start_bucket),
+            base_keys: encoded,
+            end_bucket,
+            position: 0,
+        }
+    }
+
+    /// Returns the next bucket and base key to look up, advancing the cursor.
+    fn advance(&mut self) -> Option<(u64, usize, &[u8])> {
+        let bucket = self.bucket?;
+        let (index, base_key) = &self.base_keys[self.position];
+
+        self.position += 1;
+        if self.position == self.base_keys.len() {
+            self.position = 0;
+            self.bucket = (bucket < self.end_bucket).then(|| bucket + 1);
+        }
+
+        Some((bucket, *index, base_key))
+    }
+
+    fn finish(&mut self) {
+        self.bucket = None;
+    }
+}
+
+/// Iterator over the values of several base keys in bucket order.
+///
+/// Yields `(index, value)` pairs, where `index` is the position of the base
+/// key in the list given to `merged_bucket_range`. Values are ordered by
+/// bucket, and within a bucket by base key in table order. Duplicate base
+/// keys are visited once, under the index of their first occurrence.
+pub struct MergedBucketIterator<K, V, T = ReadOnlyTable<BucketedKey<K>, V>>
+where
+    K: Key + 'static,
+    V: redb::Value + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+{
+    table: T,
+    _types: PhantomData<fn() -> (K, V)>,
+    cursor: MergeCursor,
+}
+
+impl<K, V, T> MergedBucketIterator<K, V, T>
+where
+    K: Key + 'static,
+    V: redb::Value + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+    T: Borrow<ReadOnlyTable<BucketedKey<K>, V>>,
+{
+    /// Create a new merged iterator.
+    pub fn new<'k, I>(
+        table: T,
+        key_builder: &impl BucketScheme,
+        base_keys: I,
+        range: impl RangeBounds<u64>,
+    ) -> Result<Self, BucketError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<K::SelfType<'k>>,
+    {
+        let bounds = bucket_bounds(key_builder, &range, || table_bucket_span(table.borrow()))?;
+        Ok(Self {
+            table,
+            _types: PhantomData,
+            cursor: MergeCursor::new::<K, I>(base_keys, bounds),
+        })
+    }
+}
+
+impl<K, V, T> Iterator for MergedBucketIterator<K, V, T>
+where
+    K: Key + 'static,
+    V: redb::Value + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+    T: Borrow<ReadOnlyTable<BucketedKey<K>, V>>,
+{
+    type Item = Result<(usize, V), BucketError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (bucket, index, base_key) = self.cursor.advance()?;
+            let found = {
+                let key = BucketedKey::new(K::from_bytes(base_key), bucket);
+                self.table
+                    .borrow()
+                    .get(&key)
+                    .map(|guard| guard.map(|guard| V::from(guard.value())))
+            };
+
+            match found {
+                Ok(Some(value)) => return Some(Ok((index, value))),
+                Ok(None) => continue,
+                Err(err) => {
+                    self.cursor.finish();
+                    return Some(Err(lookup_error(err)));
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the values of several base keys in bucket order, for multimap tables.
+///
+/// Yields `(index, value)` pairs like `MergedBucketIterator`, with all values
+/// of one base key in a bucket before those of the next base key. Values are
+/// streamed from the table one bucket and base key at a time.
+pub struct MergedBucketMultimapIterator<K, V, T = ReadOnlyMultimapTable<BucketedKey<K>, V>>
+where
+    K: Key + 'static,
+    V: redb::Key + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+{
+    table: T,
+    _types: PhantomData<fn() -> (K, V)>,
+    cursor: MergeCursor,
+    values: Option<(usize, MultimapValue<'static, V>)>,
+}
+
+impl<K, V, T> MergedBucketMultimapIterator<K, V, T>
+where
+    K: Key + 'static,
+    V: redb::Key + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+    T: Borrow<ReadOnlyMultimapTable<BucketedKey<K>, V>>,
+{
+    /// Create a new merged iterator for a multimap table.
+    pub fn new<'k, I>(
+        table: T,
+        key_builder: &impl BucketScheme,
+        base_keys: I,
+        range: impl RangeBounds<u64>,
+    ) -> Result<Self, BucketError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<K::SelfType<'k>>,
+    {
+        let bounds = bucket_bounds(key_builder, &range, || multimap_bucket_span(table.borrow()))?;
+        Ok(Self {
+            table,
+            _types: PhantomData,
+            cursor: MergeCursor::new::<K, I>(base_keys, bounds),
+            values: None,
+        })
+    }
+}
+
+impl<K, V, T> Iterator for MergedBucketMultimapIterator<K, V, T>
+where
+    K: Key + 'static,
+    V: redb::Key + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+    T: Borrow<ReadOnlyMultimapTable<BucketedKey<K>, V>>,
+{
+    type Item = Result<(usize, V), BucketError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((index, values)) = self.values.as_mut() {
+                match values.next() {
+                    Some(Ok(guard)) => return Some(Ok((*index, V::from(guard.value())))),
+                    Some(Err(err)) => {
+                        self.values = None;
+                        self.cursor.finish();
+                        return Some(Err(lookup_error(err)));
+                    }
+                    None => self.values = None,
+                }
+            }
+
+            let (bucket, index, base_key) = self.cursor.advance()?;
+            let found = {
+                let key = BucketedKey::new(K::from_bytes(base_key), bucket);
+                self.table.borrow().get(&key)
+            };
+
+            match found {
+                Ok(values) => self.values = Some((index, values)),
+                Err(err) => {
+                    self.cursor.finish();
+                    return Some(Err(lookup_error(err)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::key_buckets::{BucketIterExt, BucketMultimapIterExt, BucketedKey, KeyBuilder};
+    use redb::{Database, MultimapTableDefinition, ReadableDatabase, TableDefinition};
+    use tempfile::NamedTempFile;
+
+    const EVENTS: TableDefinition<'static, BucketedKey<u64>, u64> =
+        TableDefinition::new("merged_events");
+    const TRANSFERS: MultimapTableDefinition<'static, BucketedKey<&str>, u64> =
+        MultimapTableDefinition::new("merged_transfers");
+
+    #[test]
+    fn test_merged_bucket_range() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+
+        let write_txn = db.begin_write()?;
+        {
+            let mut events = write_txn.open_table(EVENTS)?;
+            for (account, sequence) in [(1u64, 50), (2, 150), (3, 20), (1, 250), (4, 120)] {
+                events.insert(key_builder.bucketed_key(account, sequence), sequence)?;
+            }
+
+            let mut transfers = write_txn.open_multimap_table(TRANSFERS)?;
+            transfers.insert(key_builder.bucketed_key("bob", 10), 1)?;
+            transfers.insert(key_builder.bucketed_key("bob", 10), 2)?;
+            transfers.insert(key_builder.bucketed_key("alice", 10), 3)?;
+            transfers.insert(key_builder.bucketed_key("alice", 110), 4)?;
+            transfers.insert(key_builder.bucketed_key("carol", 110), 5)?;
+        }
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        let merged: Vec<(usize, u64)> = read_txn
+            .open_table(EVENTS)?
+            .merged_bucket_range(&key_builder, [3u64, 1, 2, 1], ..)?
+            .collect::<Result<_, _>>()?;
+        // Bucket order first, then base key order, labelled by position
+        assert_eq!(merged, vec![(1, 50), (0, 20), (2, 150), (1, 250)]);
+
+        let merged: Vec<(usize, u64)> = read_txn
+            .open_table(EVENTS)?
+            .merged_bucket_range(&key_builder, [2u64, 4], 100..200)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(merged, vec![(0, 150), (1, 120)]);
+
+        let none: [u64; 0] = [];
+        assert_eq!(
+            read_txn
+                .open_table(EVENTS)?
+                .merged_bucket_range(&key_builder, none, ..)?
+                .count(),
+            0
+        );
+
+        let merged: Vec<(usize, u64)> = read_txn
+            .open_multimap_table(TRANSFERS)?
+            .merged_bucket_range(&key_builder, ["bob", "alice"], 0..=199)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(merged, vec![(1, 3), (0, 1), (0, 2), (1, 4)]);
+
+        Ok(())
+    }
+}
@@ -8,10 +8,12 @@
 //! scans only the buckets in the requested sequence range without filtering
 //! through unrelated base keys.
 
+use crate::error::{DatabaseError, ErrorKind};
 use std::fmt;
 
 /// Errors specific to the bucket layer.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum BucketError {
     /// Invalid bucket size configuration
     InvalidBucketSize(u64),
@@ -23,7 +25,38 @@ pub enum BucketError {
     SerializationError(String),
 
     /// Iteration over bucket range failed
-    IterationError(String),
+    IterationError(DatabaseError),
+
+    /// Timestamp cannot be mapped to a bucket
+    InvalidTimestamp(String),
+
+    /// Database operation on a bucketed table failed
+    DatabaseError(DatabaseError),
+
+    /// Two bucket configurations cannot be converted into each other
+    IncompatibleBuckets(String),
+
+    /// Persisted bucket configuration differs from the one in use
+    ConfigMismatch(String),
+
+    /// Tier layout of a tiered key builder is invalid
+    InvalidTiers(String),
+}
+
+impl BucketError {
+    /// Classifies the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            BucketError::InvalidBucketSize(_)
+            | BucketError::InvalidRange { .. }
+            | BucketError::InvalidTimestamp(_)
+            | BucketError::IncompatibleBuckets(_)
+            | BucketError::InvalidTiers(_) => ErrorKind::InvalidInput,
+            BucketError::SerializationError(_) => ErrorKind::Corruption,
+            BucketError::IterationError(err) | BucketError::DatabaseError(err) => err.kind(),
+            BucketError::ConfigMismatch(_) => ErrorKind::ConfigMismatch,
+        }
+    }
 }
 
 impl fmt::Display for BucketError {
@@ -42,8 +75,23 @@ impl fmt::Display for BucketError {
             BucketError::SerializationError(msg) => {
                 write!(f, "Serialization error: {}", msg)
             }
-            BucketError::IterationError(msg) => {
-                write!(f, "Bucket iteration error: {}", msg)
+            BucketError::IterationError(err) => {
+                write!(f, "Bucket iteration error: {}", err)
+            }
+            BucketError::InvalidTimestamp(msg) => {
+                write!(f, "Invalid timestamp: {}", msg)
+            }
+            BucketError::DatabaseError(err) => {
+                write!(f, "Bucket database error: {}", err)
+            }
+            BucketError::IncompatibleBuckets(msg) => {
+                write!(f, "Incompatible bucket configurations: {}", msg)
+            }
+            BucketError::ConfigMismatch(msg) => {
+                write!(f, "Bucket configuration mismatch: {}", msg)
+            }
+            BucketError::InvalidTiers(msg) => {
+                write!(f, "Invalid bucket tiers: {}", msg)
             }
         }
     }
@@ -51,15 +99,41 @@ impl fmt::Display for BucketError {
 
 impl std::error::Error for BucketError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        None
+        match self {
+            BucketError::IterationError(err) | BucketError::DatabaseError(err) => Some(err),
+            _ => None,
+        }
     }
 }
 
+pub mod delete;
+pub mod index;
 pub mod iterator;
 pub mod key;
+pub mod lookup;
+pub mod merged;
+pub mod ordered;
+pub mod prune;
+pub mod rebucket;
+pub mod stats;
+pub mod summarize;
+pub mod tiered;
 
 // Re-export main types for public API
+pub use delete::{BucketDeleteExt, BucketMultimapDeleteExt};
+pub use index::IndexedBucketTable;
 pub use iterator::{
     BucketIterExt, BucketMultimapIterExt, BucketRangeIterator, BucketRangeMultimapIterator,
+    ResumeToken,
+};
+pub use key::{
+    unix_millis, BucketScheme, BucketedKey, KeyBuilder, TaggedBucketedKey, BUCKET_CONFIG_TABLE,
 };
-pub use key::{BucketedKey, KeyBuilder};
+pub use lookup::BucketLookupExt;
+pub use merged::{MergedBucketIterator, MergedBucketMultimapIterator};
+pub use ordered::{migrate_to_ordered, OrderedBucketedKey};
+pub use prune::BucketPruneExt;
+pub use rebucket::rebucket;
+pub use stats::{BucketStat, BucketStatsExt};
+pub use summarize::summarize_bucket;
+pub use tiered::TieredKeyBuilder;
@@ -0,0 +1,268 @@
+//! Order-preserving bucketed key encoding.
+//!
+//! `BucketedKey` stores its bucket little-endian, so the raw bytes of a key
+//! don't sort the way redb orders them. That's invisible through redb, but
+//! external tools that dump or range over raw keys see buckets shuffled.
+//! `OrderedBucketedKey` stores the bucket big-endian instead, so byte order
+//! follows bucket order. Combined with a base key whose encoding is already
+//! byte-ordered, such as `&[u8]`, `&str` or `[u8; 8]` holding
+//! `u64::to_be_bytes`, raw byte order matches key order exactly.
+//!
+//! The two encodings are not compatible; `migrate_to_ordered` rewrites an
+//! existing table into the ordered form.
+
+use crate::error::DatabaseError;
+use crate::key_buckets::key::BucketedKey;
+use crate::key_buckets::BucketError;
+use redb::{Key, ReadableTable, TableDefinition, Value, WriteTransaction};
+use std::cmp::Ordering;
+
+/// Encoded size of the bucket prefix.
+const BUCKET_BYTES: usize = 8;
+
+/// A bucketed key whose bucket is encoded big-endian.
+///
+/// Orders exactly like `BucketedKey`: by bucket first, then by the base
+/// key's `compare`. Create one from a `BucketedKey` with `From`, e.g.
+/// `key_builder.bucketed_key(id, sequence).into()`.
+#[derive(Debug, Clone)]
+pub struct OrderedBucketedKey<K> {
+    pub base_key: K,
+    pub bucket: u64,
+}
+
+impl<K> OrderedBucketedKey<K> {
+    /// Create a new OrderedBucketedKey directly.
+    pub fn new(base_key: K, bucket: u64) -> Self {
+        Self { base_key, bucket }
+    }
+
+    /// Get reference to the base key.
+    pub fn base_key(&self) -> &K {
+        &self.base_key
+    }
+
+    /// Get the bucket number.
+    pub fn bucket(&self) -> u64 {
+        self.bucket
+    }
+}
+
+impl<K> From<BucketedKey<K>> for OrderedBucketedKey<K> {
+    fn from(key: BucketedKey<K>) -> Self {
+        Self::new(key.base_key, key.bucket)
+    }
+}
+
+impl<K> From<OrderedBucketedKey<K>> for BucketedKey<K> {
+    fn from(key: OrderedBucketedKey<K>) -> Self {
+        BucketedKey::new(key.base_key, key.bucket)
+    }
+}
+
+/// Splits encoded key data into the bucket and the encoded base key.
+fn split_bucket(data: &[u8]) -> (u64, &[u8]) {
+    if data.len() < BUCKET_BYTES {
+        panic!(
+            "OrderedBucketedKey data too short: expected at least {} bytes, got {}",
+            BUCKET_BYTES,
+            data.len()
+        );
+    }
+
+    let (bucket, base_key) = data.split_at(BUCKET_BYTES);
+    let mut bucket_bytes = [0u8; BUCKET_BYTES];
+    bucket_bytes.copy_from_slice(bucket);
+    (u64::from_be_bytes(bucket_bytes), base_key)
+}
+
+impl<K: Key + 'static> Value for OrderedBucketedKey<K> {
+    type SelfType<'a>
+        = OrderedBucketedKey<K::SelfType<'a>>
+    where
+        Self: 'a;
+
+    type AsBytes<'a>
+        = Vec<u8>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        K::fixed_width().map(|width| BUCKET_BYTES + width)
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        let (bucket, base_key) = split_bucket(data);
+        OrderedBucketedKey {
+            base_key: K::from_bytes(base_key),
+            bucket,
+        }
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        let base_key_bytes = K::as_bytes(&value.base_key);
+        let base_key_bytes = base_key_bytes.as_ref();
+
+        // Concatenate bucket (8-byte big-endian) + base key
+        let mut result = Vec::with_capacity(BUCKET_BYTES + base_key_bytes.len());
+        result.extend_from_slice(&value.bucket.to_be_bytes());
+        result.extend_from_slice(base_key_bytes);
+
+        result
+    }
+
+    fn type_name() -> redb::TypeName {
+        redb::TypeName::new(&format!(
+            "redb_extras::key_buckets::OrderedBucketedKey<{}>",
+            K::type_name().name()
+        ))
+    }
+}
+
+impl<K: Key + 'static> Key for OrderedBucketedKey<K> {
+    fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+        let (bucket1, base1) = split_bucket(data1);
+        let (bucket2, base2) = split_bucket(data2);
+
+        match bucket1.cmp(&bucket2) {
+            Ordering::Equal => K::compare(base1, base2),
+            other => other,
+        }
+    }
+}
+
+fn migrate_error(err: impl Into<redb::Error>) -> BucketError {
+    BucketError::DatabaseError(DatabaseError::new("Failed to migrate to ordered keys", err))
+}
+
+/// Rewrites a `BucketedKey` table into an `OrderedBucketedKey` table.
+///
+/// Every entry of `source` is copied to `target` with the same base key,
+/// bucket and value, and `source` is deleted afterwards. Since both key
+/// types order entries the same way, the target ends up with the same
+/// entries in the same order. Run it in its own transaction; for very large
+/// tables the whole copy is held in that one transaction.
+///
+/// # Arguments
+/// * `txn` - The write transaction
+/// * `source` - Table using the little-endian encoding
+/// * `target` - Table using the big-endian encoding, must be different from `source`
+///
+/// # Returns
+/// Number of entries migrated
+pub fn migrate_to_ordered<K, V>(
+    txn: &WriteTransaction,
+    source: TableDefinition<'static, BucketedKey<K>, V>,
+    target: TableDefinition<'static, OrderedBucketedKey<K>, V>,
+) -> Result<u64, BucketError>
+where
+    K: Key + 'static,
+    V: Value + 'static,
+{
+    let mut migrated = 0;
+    {
+        let source_table = txn.open_table(source).map_err(migrate_error)?;
+        let mut target_table = txn.open_table(target).map_err(migrate_error)?;
+
+        for entry in source_table.iter().map_err(migrate_error)? {
+            let (key_guard, value_guard) = entry.map_err(migrate_error)?;
+            let key = key_guard.value();
+            target_table
+                .insert(
+                    OrderedBucketedKey::new(key.base_key, key.bucket),
+                    value_guard.value(),
+                )
+                .map_err(migrate_error)?;
+            migrated += 1;
+        }
+    }
+
+    txn.delete_table(source).map_err(migrate_error)?;
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_buckets::KeyBuilder;
+    use redb::{Database, ReadableDatabase, TableHandle};
+    use tempfile::NamedTempFile;
+
+    const LEGACY: TableDefinition<'static, BucketedKey<[u8; 8]>, u64> =
+        TableDefinition::new("legacy");
+    const ORDERED: TableDefinition<'static, OrderedBucketedKey<[u8; 8]>, u64> =
+        TableDefinition::new("ordered");
+
+    #[test]
+    fn test_ordered_encoding() {
+        let low = OrderedBucketedKey::<[u8; 8]>::as_bytes(&OrderedBucketedKey::new(
+            7u64.to_be_bytes(),
+            255,
+        ));
+        let high = OrderedBucketedKey::<[u8; 8]>::as_bytes(&OrderedBucketedKey::new(
+            1u64.to_be_bytes(),
+            256,
+        ));
+        assert!(low < high);
+        assert_eq!(
+            OrderedBucketedKey::<[u8; 8]>::compare(&low, &high),
+            Ordering::Less
+        );
+
+        // The little-endian encoding gets the same pair backwards
+        let low = BucketedKey::<[u8; 8]>::as_bytes(&BucketedKey::new(7u64.to_be_bytes(), 255));
+        let high = BucketedKey::<[u8; 8]>::as_bytes(&BucketedKey::new(1u64.to_be_bytes(), 256));
+        assert!(low > high);
+    }
+
+    #[test]
+    fn test_migrate_to_ordered() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+
+        let write_txn = db.begin_write()?;
+        {
+            let mut legacy = write_txn.open_table(LEGACY)?;
+            for sequence in (0..1000u64).step_by(10) {
+                let id = (sequence % 3).to_be_bytes();
+                legacy.insert(key_builder.bucketed_key(id, sequence), sequence)?;
+            }
+        }
+        write_txn.commit()?;
+
+        let write_txn = db.begin_write()?;
+        assert_eq!(migrate_to_ordered(&write_txn, LEGACY, ORDERED)?, 30);
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        assert!(read_txn
+            .list_tables()?
+            .all(|table| table.name() != LEGACY.name()));
+
+        // Raw key bytes now sort in key order
+        let raw: Vec<Vec<u8>> = read_txn
+            .open_table(ORDERED)?
+            .iter()?
+            .map(|entry| {
+                entry.map(|(key, _)| OrderedBucketedKey::<[u8; 8]>::as_bytes(&key.value()))
+            })
+            .collect::<Result<_, _>>()?;
+        assert_eq!(raw.len(), 30);
+        assert!(raw.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let ordered = read_txn.open_table(ORDERED)?;
+        let key: OrderedBucketedKey<[u8; 8]> =
+            key_builder.bucketed_key(2u64.to_be_bytes(), 980).into();
+        assert_eq!(ordered.get(key)?.unwrap().value(), 980);
+
+        Ok(())
+    }
+}
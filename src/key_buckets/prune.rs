@@ -0,0 +1,151 @@
+//! Bucket retention.
+//!
+//! Bucketed keys sort by bucket first, so every entry older than a cutoff
+//! bucket sits at the front of the table. Pruning pops entries off the front
+//! until it reaches the cutoff, touching only the entries it deletes.
+
+use crate::error::DatabaseError;
+use crate::key_buckets::key::{BucketScheme, BucketedKey};
+use crate::key_buckets::BucketError;
+use redb::{Key, MultimapTable, ReadableMultimapTable, ReadableTable, Table, Value};
+
+/// Extension trait for sliding-window retention on bucketed tables.
+pub trait BucketPruneExt {
+    /// Removes every entry whose bucket is older than the cutoff's bucket.
+    ///
+    /// Entries in the bucket containing `cutoff_sequence` and later buckets
+    /// are kept, for every base key.
+    ///
+    /// # Arguments
+    /// * `key_builder` - The builder the keys were created with
+    /// * `cutoff_sequence` - The oldest sequence whose bucket is retained
+    ///
+    /// # Returns
+    /// Number of values removed
+    fn prune_before(
+        &mut self,
+        key_builder: &impl BucketScheme,
+        cutoff_sequence: u64,
+    ) -> Result<u64, BucketError>;
+}
+
+fn prune_error(err: impl Into<redb::Error>) -> BucketError {
+    BucketError::DatabaseError(DatabaseError::new("Failed to prune buckets", err))
+}
+
+impl<K, V> BucketPruneExt for Table<'_, BucketedKey<K>, V>
+where
+    K: Key + 'static,
+    V: Value + 'static,
+{
+    fn prune_before(
+        &mut self,
+        key_builder: &impl BucketScheme,
+        cutoff_sequence: u64,
+    ) -> Result<u64, BucketError> {
+        let cutoff_bucket = key_builder.bucket_for(cutoff_sequence);
+        let mut removed = 0;
+
+        loop {
+            let bucket = match self.first().map_err(prune_error)? {
+                Some((key, _)) => key.value().bucket(),
+                None => break,
+            };
+            if bucket >= cutoff_bucket {
+                break;
+            }
+
+            self.pop_first().map_err(prune_error)?;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+}
+
+impl<K, V> BucketPruneExt for MultimapTable<'_, BucketedKey<K>, V>
+where
+    K: Key + 'static,
+    V: Key + 'static,
+{
+    fn prune_before(
+        &mut self,
+        key_builder: &impl BucketScheme,
+        cutoff_sequence: u64,
+    ) -> Result<u64, BucketError> {
+        let cutoff_bucket = key_builder.bucket_for(cutoff_sequence);
+        let mut removed = 0;
+
+        loop {
+            let first = match self.iter().map_err(prune_error)?.next() {
+                Some(entry) => {
+                    let (key, _) = entry.map_err(prune_error)?;
+                    let key = key.value();
+                    (key.bucket() < cutoff_bucket).then(|| BucketedKey::<K>::as_bytes(&key))
+                }
+                None => None,
+            };
+            let Some(first) = first else {
+                break;
+            };
+
+            let values = self
+                .remove_all(BucketedKey::<K>::from_bytes(&first))
+                .map_err(prune_error)?;
+            removed += values.len();
+        }
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_buckets::KeyBuilder;
+    use redb::{
+        Database, MultimapTableDefinition, ReadableDatabase, ReadableTableMetadata, TableDefinition,
+    };
+    use tempfile::NamedTempFile;
+
+    const EVENTS: TableDefinition<'static, BucketedKey<&str>, u64> = TableDefinition::new("events");
+    const SAMPLES: MultimapTableDefinition<'static, BucketedKey<u64>, u64> =
+        MultimapTableDefinition::new("samples");
+
+    #[test]
+    fn test_prune_before() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+
+        let write_txn = db.begin_write()?;
+        {
+            let mut events = write_txn.open_table(EVENTS)?;
+            let mut samples = write_txn.open_multimap_table(SAMPLES)?;
+            for sequence in (0..500).step_by(50) {
+                for base_key in ["a", "b"] {
+                    events.insert(key_builder.bucketed_key(base_key, sequence), sequence)?;
+                }
+                samples.insert(key_builder.bucketed_key(1u64, sequence), sequence)?;
+                samples.insert(key_builder.bucketed_key(1u64, sequence), sequence + 1)?;
+            }
+
+            // Buckets 0 and 1 are dropped, the cutoff's own bucket is kept
+            assert_eq!(events.prune_before(&key_builder, 250)?, 4);
+            assert_eq!(samples.prune_before(&key_builder, 250)?, 8);
+            assert_eq!(events.prune_before(&key_builder, 250)?, 0);
+        }
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        let events = read_txn.open_table(EVENTS)?;
+        assert_eq!(events.len()?, 6);
+        let (first, _) = events.first()?.unwrap();
+        assert_eq!(first.value().bucket(), 2);
+
+        let samples = read_txn.open_multimap_table(SAMPLES)?;
+        assert_eq!(samples.len()?, 12);
+
+        Ok(())
+    }
+}
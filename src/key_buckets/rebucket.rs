@@ -0,0 +1,242 @@
+//! Rebucketing into coarser buckets.
+//!
+//! Tiered retention keeps recent data in fine buckets (per minute, say) and
+//! older data in coarse ones (per day). `rebucket` moves entries from a fine
+//! bucketed table into a coarse one, merging the values of all fine buckets
+//! that fall into the same coarse bucket with `MergeableValue`.
+
+use crate::error::DatabaseError;
+use crate::key_buckets::key::{BucketedKey, KeyBuilder};
+use crate::key_buckets::BucketError;
+use crate::MergeableValue;
+use redb::{Key, ReadableTable, TableDefinition, Value, WriteTransaction};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::ops::{Bound, RangeBounds};
+
+fn rebucket_error(err: impl Into<redb::Error>) -> BucketError {
+    BucketError::DatabaseError(DatabaseError::new("Failed to rebucket", err))
+}
+
+/// Checks that every fine bucket falls entirely into one coarse bucket.
+fn check_coarsens(small: &KeyBuilder, large: &KeyBuilder) -> Result<(), BucketError> {
+    if large.bucket_size() % small.bucket_size() != 0 {
+        return Err(BucketError::IncompatibleBuckets(format!(
+            "bucket size {} is not a multiple of {}",
+            large.bucket_size(),
+            small.bucket_size()
+        )));
+    }
+
+    // Coarse boundaries are a multiple of the fine size apart, so one aligned
+    // boundary means all of them are
+    let boundary = large.bucket_start(1);
+    if small.bucket_start(small.bucket_for(boundary)) != boundary {
+        return Err(BucketError::IncompatibleBuckets(format!(
+            "bucket boundaries of size {} and {} are not aligned",
+            large.bucket_size(),
+            small.bucket_size()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Whether an encoded base key lies within encoded range bounds.
+fn in_range<K: Key>(base_key: &[u8], start: &Bound<Vec<u8>>, end: &Bound<Vec<u8>>) -> bool {
+    let after_start = match start {
+        Bound::Included(start) => K::compare(base_key, start) != Ordering::Less,
+        Bound::Excluded(start) => K::compare(base_key, start) == Ordering::Greater,
+        Bound::Unbounded => true,
+    };
+    let before_end = match end {
+        Bound::Included(end) => K::compare(base_key, end) != Ordering::Greater,
+        Bound::Excluded(end) => K::compare(base_key, end) == Ordering::Less,
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+/// Moves entries from a fine bucketed table into a coarser one.
+///
+/// Every entry of `source` whose base key lies in `base_key_range` is removed
+/// and written to `target` under the coarse bucket covering its fine bucket.
+/// Values landing on the same coarse key, including one already stored in
+/// `target`, are combined with `MergeableValue::merge` in source order.
+///
+/// The coarse bucket size must be a multiple of the fine one with aligned
+/// boundaries (e.g. `hourly()` into `daily()` with the same UTC offset), so
+/// no fine bucket is split between two coarse buckets. `source` and `target`
+/// must be different tables.
+///
+/// # Arguments
+/// * `txn` - The write transaction
+/// * `source` - Table keyed with `key_builder_small`
+/// * `target` - Table keyed with `key_builder_large`
+/// * `key_builder_small` - The fine bucket configuration
+/// * `key_builder_large` - The coarse bucket configuration
+/// * `base_key_range` - Base keys to rebucket, `..` for all of them
+///
+/// # Returns
+/// Number of source entries moved
+pub fn rebucket<'k, K, V>(
+    txn: &WriteTransaction,
+    source: TableDefinition<'static, BucketedKey<K>, V>,
+    target: TableDefinition<'static, BucketedKey<K>, V>,
+    key_builder_small: &KeyBuilder,
+    key_builder_large: &KeyBuilder,
+    base_key_range: impl RangeBounds<K::SelfType<'k>>,
+) -> Result<u64, BucketError>
+where
+    K: Key + 'static,
+    V: Value + MergeableValue + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+    for<'b> V: Borrow<V::SelfType<'b>>,
+{
+    check_coarsens(key_builder_small, key_builder_large)?;
+
+    let encode = |bound: Bound<&K::SelfType<'k>>| match bound {
+        Bound::Included(key) => Bound::Included(K::as_bytes(key).as_ref().to_vec()),
+        Bound::Excluded(key) => Bound::Excluded(K::as_bytes(key).as_ref().to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let start = encode(base_key_range.start_bound());
+    let end = encode(base_key_range.end_bound());
+
+    let mut source_table = txn.open_table(source).map_err(rebucket_error)?;
+    let mut target_table = txn.open_table(target).map_err(rebucket_error)?;
+
+    let extracted = source_table
+        .extract_if(|key, _| in_range::<K>(K::as_bytes(&key.base_key).as_ref(), &start, &end))
+        .map_err(rebucket_error)?;
+
+    let mut moved = 0;
+    for entry in extracted {
+        let (key_guard, value_guard) = entry.map_err(rebucket_error)?;
+        let key = key_guard.value();
+        let coarse_bucket =
+            key_builder_large.bucket_for(key_builder_small.bucket_start(key.bucket()));
+        let coarse_key = BucketedKey::new(key.base_key, coarse_bucket);
+
+        let existing = target_table
+            .get(&coarse_key)
+            .map_err(rebucket_error)?
+            .map(|guard| V::from(guard.value()));
+        let merged = V::merge(existing, V::from(value_guard.value()));
+        target_table
+            .insert(&coarse_key, merged)
+            .map_err(rebucket_error)?;
+
+        moved += 1;
+    }
+
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redb::{Database, ReadableDatabase, ReadableTableMetadata};
+    use tempfile::NamedTempFile;
+
+    #[derive(Debug, PartialEq)]
+    struct Total(u64);
+
+    impl Value for Total {
+        type SelfType<'a> = Total;
+        type AsBytes<'a> = [u8; 8];
+
+        fn fixed_width() -> Option<usize> {
+            Some(8)
+        }
+
+        fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+        where
+            Self: 'a,
+        {
+            Total(u64::from_le_bytes(data.try_into().unwrap()))
+        }
+
+        fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+        where
+            Self: 'b,
+        {
+            value.0.to_le_bytes()
+        }
+
+        fn type_name() -> redb::TypeName {
+            redb::TypeName::new("rebucket_test::Total")
+        }
+    }
+
+    impl MergeableValue for Total {
+        fn merge(existing: Option<Self>, incoming: Self) -> Self {
+            Total(existing.map_or(0, |total| total.0) + incoming.0)
+        }
+    }
+
+    const MINUTES: TableDefinition<'static, BucketedKey<&str>, Total> =
+        TableDefinition::new("minutes");
+    const HOURS: TableDefinition<'static, BucketedKey<&str>, Total> = TableDefinition::new("hours");
+
+    const MINUTE: u64 = 60 * 1000;
+
+    #[test]
+    fn test_rebucket() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let minutely = KeyBuilder::new(MINUTE)?;
+        let hourly = KeyBuilder::hourly();
+
+        let write_txn = db.begin_write()?;
+        {
+            let mut minutes = write_txn.open_table(MINUTES)?;
+            for minute in 0..90 {
+                for name in ["a", "b"] {
+                    minutes.insert(minutely.bucketed_key(name, minute * MINUTE), Total(1))?;
+                }
+            }
+            // An earlier pass already rolled up part of the first hour
+            write_txn
+                .open_table(HOURS)?
+                .insert(hourly.bucketed_key("a", 0), Total(100))?;
+        }
+
+        assert_eq!(
+            rebucket(&write_txn, MINUTES, HOURS, &minutely, &hourly, "a"..="a")?,
+            90
+        );
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        let hours = read_txn.open_table(HOURS)?;
+        assert_eq!(
+            hours.get(hourly.bucketed_key("a", 0))?.unwrap().value(),
+            Total(160)
+        );
+        assert_eq!(
+            hours
+                .get(hourly.bucketed_key("a", 60 * MINUTE))?
+                .unwrap()
+                .value(),
+            Total(30)
+        );
+        assert!(hours.get(hourly.bucketed_key("b", 0))?.is_none());
+        assert_eq!(read_txn.open_table(MINUTES)?.len()?, 90);
+
+        // Fine buckets that straddle coarse ones are rejected
+        let write_txn = db.begin_write()?;
+        let misaligned = KeyBuilder::new(7 * MINUTE)?;
+        assert!(matches!(
+            rebucket(&write_txn, MINUTES, HOURS, &misaligned, &hourly, ..),
+            Err(BucketError::IncompatibleBuckets(_))
+        ));
+        let shifted = KeyBuilder::hourly().with_utc_offset(30);
+        assert!(matches!(
+            rebucket(&write_txn, MINUTES, HOURS, &minutely, &shifted, ..),
+            Err(BucketError::IncompatibleBuckets(_))
+        ));
+
+        Ok(())
+    }
+}
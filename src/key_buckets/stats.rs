@@ -0,0 +1,280 @@
+//! Per-bucket occupancy statistics.
+//!
+//! Reports how much data a base key has in each bucket of a sequence range
+//! without decoding any stored values, e.g. to show gaps in a time series.
+
+use crate::key_buckets::iterator::{
+    bucket_bounds, lookup_error, multimap_bucket_span, table_bucket_span,
+};
+use crate::key_buckets::key::{BucketScheme, BucketedKey};
+use crate::key_buckets::BucketError;
+use redb::{Key, ReadOnlyMultimapTable, ReadOnlyTable, Value};
+use std::borrow::Borrow;
+use std::ops::{RangeBounds, RangeInclusive};
+
+/// Occupancy of a single bucket for one base key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BucketStat {
+    /// The bucket number
+    pub bucket: u64,
+    /// Number of values stored in the bucket, at most 1 for regular tables
+    pub value_count: u64,
+}
+
+impl BucketStat {
+    /// Whether the bucket holds any value for the base key.
+    pub fn is_occupied(&self) -> bool {
+        self.value_count > 0
+    }
+}
+
+/// Extension trait for bucket occupancy statistics on read-only tables.
+///
+/// Returns one entry per bucket in the requested sequence range, including
+/// empty buckets, in bucket order. Range bounds resolve as for `bucket_range`.
+pub trait BucketStatsExt<K>
+where
+    K: Key + 'static,
+{
+    fn bucket_stats<'k>(
+        &self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+    ) -> Result<Vec<BucketStat>, BucketError>;
+
+    /// Counts the values a base key has stored within a sequence range.
+    ///
+    /// Sums the per-bucket counts of `bucket_stats`, so no value is decoded.
+    /// Whole buckets are counted, including values of boundary buckets that
+    /// fall outside the range.
+    fn count_range<'k>(
+        &self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+    ) -> Result<u64, BucketError> {
+        let stats = self.bucket_stats(key_builder, base_key, range)?;
+        Ok(stats.iter().map(|stat| stat.value_count).sum())
+    }
+
+    /// Finds the sequence range covered by the buckets a base key occupies.
+    ///
+    /// Probes buckets with point lookups inward from both ends of the
+    /// table's stored buckets until the first and last occupied bucket of
+    /// the base key are found. The range runs from the start of the first to
+    /// the end of the last of those buckets, so it can be used to validate
+    /// requested ranges or display the available history.
+    ///
+    /// # Arguments
+    /// * `key_builder` - The builder the keys were created with
+    /// * `base_key` - The base key to look up
+    ///
+    /// # Returns
+    /// The covered sequences, or None if the base key has no values
+    fn sequence_bounds<'k>(
+        &self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+    ) -> Result<Option<RangeInclusive<u64>>, BucketError>;
+}
+
+/// Finds the first and last bucket in `span` for which `occupied` holds.
+fn probe_bounds<F>(
+    span: Option<(u64, u64)>,
+    mut occupied: F,
+) -> Result<Option<(u64, u64)>, BucketError>
+where
+    F: FnMut(u64) -> Result<bool, BucketError>,
+{
+    let Some((first_stored, last_stored)) = span else {
+        return Ok(None);
+    };
+
+    let mut first = None;
+    for bucket in first_stored..=last_stored {
+        if occupied(bucket)? {
+            first = Some(bucket);
+            break;
+        }
+    }
+    let Some(first) = first else {
+        return Ok(None);
+    };
+
+    for bucket in (first..=last_stored).rev() {
+        if occupied(bucket)? {
+            return Ok(Some((first, bucket)));
+        }
+    }
+    Ok(Some((first, first)))
+}
+
+/// Converts an inclusive bucket range into the sequences it covers.
+fn covered_sequences(
+    key_builder: &impl BucketScheme,
+    buckets: Option<(u64, u64)>,
+) -> Option<RangeInclusive<u64>> {
+    buckets.map(|(first, last)| key_builder.bucket_start(first)..=key_builder.bucket_end(last))
+}
+
+/// Collects a stat for every bucket in `bounds` using `count` per bucket.
+fn collect_stats<F>(
+    bounds: Option<(u64, u64)>,
+    mut count: F,
+) -> Result<Vec<BucketStat>, BucketError>
+where
+    F: FnMut(u64) -> Result<u64, BucketError>,
+{
+    let Some((start_bucket, end_bucket)) = bounds else {
+        return Ok(Vec::new());
+    };
+
+    let mut stats = Vec::new();
+    for bucket in start_bucket..=end_bucket {
+        stats.push(BucketStat {
+            bucket,
+            value_count: count(bucket)?,
+        });
+    }
+    Ok(stats)
+}
+
+impl<K, V> BucketStatsExt<K> for ReadOnlyTable<BucketedKey<K>, V>
+where
+    K: Key + 'static,
+    V: Value + 'static,
+{
+    fn bucket_stats<'k>(
+        &self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+    ) -> Result<Vec<BucketStat>, BucketError> {
+        let bounds = bucket_bounds(key_builder, &range, || table_bucket_span(self))?;
+        let base_key = K::as_bytes(base_key.borrow()).as_ref().to_vec();
+
+        collect_stats(bounds, |bucket| {
+            let key = BucketedKey::new(K::from_bytes(&base_key), bucket);
+            let guard = self.get(&key).map_err(lookup_error)?;
+            Ok(u64::from(guard.is_some()))
+        })
+    }
+
+    fn sequence_bounds<'k>(
+        &self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+    ) -> Result<Option<RangeInclusive<u64>>, BucketError> {
+        let base_key = K::as_bytes(base_key.borrow()).as_ref().to_vec();
+        let buckets = probe_bounds(table_bucket_span(self)?, |bucket| {
+            let key = BucketedKey::new(K::from_bytes(&base_key), bucket);
+            Ok(self.get(&key).map_err(lookup_error)?.is_some())
+        })?;
+        Ok(covered_sequences(key_builder, buckets))
+    }
+}
+
+impl<K, V> BucketStatsExt<K> for ReadOnlyMultimapTable<BucketedKey<K>, V>
+where
+    K: Key + 'static,
+    V: Key + 'static,
+{
+    fn bucket_stats<'k>(
+        &self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+        range: impl RangeBounds<u64>,
+    ) -> Result<Vec<BucketStat>, BucketError> {
+        let bounds = bucket_bounds(key_builder, &range, || multimap_bucket_span(self))?;
+        let base_key = K::as_bytes(base_key.borrow()).as_ref().to_vec();
+
+        collect_stats(bounds, |bucket| {
+            let key = BucketedKey::new(K::from_bytes(&base_key), bucket);
+            Ok(self.get(&key).map_err(lookup_error)?.len())
+        })
+    }
+
+    fn sequence_bounds<'k>(
+        &self,
+        key_builder: &impl BucketScheme,
+        base_key: impl Borrow<K::SelfType<'k>>,
+    ) -> Result<Option<RangeInclusive<u64>>, BucketError> {
+        let base_key = K::as_bytes(base_key.borrow()).as_ref().to_vec();
+        let buckets = probe_bounds(multimap_bucket_span(self)?, |bucket| {
+            let key = BucketedKey::new(K::from_bytes(&base_key), bucket);
+            Ok(!self.get(&key).map_err(lookup_error)?.is_empty())
+        })?;
+        Ok(covered_sequences(key_builder, buckets))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_buckets::KeyBuilder;
+    use redb::{Database, MultimapTableDefinition, ReadableDatabase, TableDefinition};
+    use tempfile::NamedTempFile;
+
+    const READINGS: TableDefinition<'static, BucketedKey<u64>, u64> =
+        TableDefinition::new("readings");
+    const SAMPLES: MultimapTableDefinition<'static, BucketedKey<&str>, u64> =
+        MultimapTableDefinition::new("samples");
+
+    #[test]
+    fn test_bucket_stats() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+
+        let write_txn = db.begin_write()?;
+        {
+            let mut readings = write_txn.open_table(READINGS)?;
+            readings.insert(key_builder.bucketed_key(1u64, 50), 1)?;
+            readings.insert(key_builder.bucketed_key(1u64, 250), 2)?;
+            readings.insert(key_builder.bucketed_key(2u64, 150), 3)?;
+
+            let mut samples = write_txn.open_multimap_table(SAMPLES)?;
+            for value in 0..3 {
+                samples.insert(key_builder.bucketed_key("a", 120), value)?;
+            }
+            samples.insert(key_builder.bucketed_key("a", 320), 9)?;
+        }
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        let readings = read_txn.open_table(READINGS)?;
+        let stats = readings.bucket_stats(&key_builder, 1u64, 0..400)?;
+        let counts: Vec<(u64, u64)> = stats.iter().map(|s| (s.bucket, s.value_count)).collect();
+        assert_eq!(counts, vec![(0, 1), (1, 0), (2, 1), (3, 0)]);
+        assert!(!stats[1].is_occupied());
+
+        // Open ends stop at the stored buckets
+        assert_eq!(readings.bucket_stats(&key_builder, 2u64, ..)?.len(), 3);
+
+        let samples = read_txn.open_multimap_table(SAMPLES)?;
+        let counts: Vec<u64> = samples
+            .bucket_stats(&key_builder, "a", 100..)?
+            .iter()
+            .map(|s| s.value_count)
+            .collect();
+        assert_eq!(counts, vec![3, 0, 1]);
+        assert!(samples.bucket_stats(&key_builder, "b", 0..0)?.is_empty());
+
+        assert_eq!(readings.count_range(&key_builder, 1u64, ..)?, 2);
+        assert_eq!(readings.count_range(&key_builder, 1u64, 100..200)?, 0);
+        assert_eq!(samples.count_range(&key_builder, "a", ..)?, 4);
+        assert_eq!(samples.count_range(&key_builder, "b", ..)?, 0);
+
+        assert_eq!(readings.sequence_bounds(&key_builder, 1u64)?, Some(0..=299));
+        assert_eq!(
+            readings.sequence_bounds(&key_builder, 2u64)?,
+            Some(100..=199)
+        );
+        assert_eq!(readings.sequence_bounds(&key_builder, 3u64)?, None);
+        assert_eq!(samples.sequence_bounds(&key_builder, "a")?, Some(100..=399));
+        assert_eq!(samples.sequence_bounds(&key_builder, "b")?, None);
+
+        Ok(())
+    }
+}
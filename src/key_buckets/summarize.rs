@@ -0,0 +1,185 @@
+//! Bucket rollups.
+//!
+//! Dashboards usually want one figure per bucket rather than every raw
+//! value. `summarize_bucket` folds the values a multimap holds for a base key
+//! in one bucket into a single row of a rollup table, using
+//! `MergeableValue` to combine them.
+
+use crate::error::DatabaseError;
+use crate::key_buckets::key::BucketedKey;
+use crate::key_buckets::BucketError;
+use crate::MergeableValue;
+use redb::{
+    Key, MultimapTableDefinition, ReadableMultimapTable, TableDefinition, WriteTransaction,
+};
+use std::borrow::Borrow;
+
+fn summarize_error(err: impl Into<redb::Error>) -> BucketError {
+    BucketError::DatabaseError(DatabaseError::new("Failed to summarize bucket", err))
+}
+
+/// Folds all values of a base key in one bucket into a summary row.
+///
+/// The values `source` holds under the bucketed key are combined in value
+/// order with `MergeableValue::merge`, starting from no existing value, and
+/// the result is written to `target` under the same bucketed key. Any
+/// previous summary is replaced, so a bucket can be summarized again as it
+/// fills up. An empty bucket removes the summary row. `source` is left
+/// unchanged.
+///
+/// # Arguments
+/// * `txn` - The write transaction
+/// * `base_key` - The base key to summarize
+/// * `bucket` - The bucket number, e.g. from `BucketScheme::bucket_for`
+/// * `source` - Multimap holding the raw values
+/// * `target` - Rollup table receiving the summary
+///
+/// # Returns
+/// Number of values folded into the summary
+pub fn summarize_bucket<'k, K, V>(
+    txn: &WriteTransaction,
+    base_key: impl Borrow<K::SelfType<'k>>,
+    bucket: u64,
+    source: MultimapTableDefinition<'static, BucketedKey<K>, V>,
+    target: TableDefinition<'static, BucketedKey<K>, V>,
+) -> Result<u64, BucketError>
+where
+    K: Key + 'static,
+    V: Key + MergeableValue + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+    for<'b> V: Borrow<V::SelfType<'b>>,
+{
+    let source_table = txn.open_multimap_table(source).map_err(summarize_error)?;
+    let mut target_table = txn.open_table(target).map_err(summarize_error)?;
+
+    let base_key = K::as_bytes(base_key.borrow()).as_ref().to_vec();
+    let key = BucketedKey::new(K::from_bytes(&base_key), bucket);
+
+    let mut summary: Option<V> = None;
+    let mut folded = 0;
+    for value in source_table.get(&key).map_err(summarize_error)? {
+        let value = V::from(value.map_err(summarize_error)?.value());
+        summary = Some(V::merge(summary, value));
+        folded += 1;
+    }
+
+    match summary {
+        Some(summary) => {
+            target_table
+                .insert(&key, summary)
+                .map_err(summarize_error)?;
+        }
+        None => {
+            target_table.remove(&key).map_err(summarize_error)?;
+        }
+    }
+
+    Ok(folded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_buckets::KeyBuilder;
+    use redb::{Database, ReadableDatabase, Value};
+    use tempfile::NamedTempFile;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    struct Total(u64);
+
+    impl Value for Total {
+        type SelfType<'a> = Total;
+        type AsBytes<'a> = [u8; 8];
+
+        fn fixed_width() -> Option<usize> {
+            Some(8)
+        }
+
+        fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+        where
+            Self: 'a,
+        {
+            Total(u64::from_le_bytes(data.try_into().unwrap()))
+        }
+
+        fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
+        where
+            Self: 'b,
+        {
+            value.0.to_le_bytes()
+        }
+
+        fn type_name() -> redb::TypeName {
+            redb::TypeName::new("summarize_test::Total")
+        }
+    }
+
+    impl Key for Total {
+        fn compare(data1: &[u8], data2: &[u8]) -> std::cmp::Ordering {
+            Self::from_bytes(data1).cmp(&Self::from_bytes(data2))
+        }
+    }
+
+    impl MergeableValue for Total {
+        fn merge(existing: Option<Self>, incoming: Self) -> Self {
+            Total(existing.map_or(0, |total| total.0) + incoming.0)
+        }
+    }
+
+    const SALES: MultimapTableDefinition<'static, BucketedKey<&str>, Total> =
+        MultimapTableDefinition::new("sales");
+    const SALES_ROLLUP: TableDefinition<'static, BucketedKey<&str>, Total> =
+        TableDefinition::new("sales_rollup");
+
+    #[test]
+    fn test_summarize_bucket() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let key_builder = KeyBuilder::new(100)?;
+
+        let write_txn = db.begin_write()?;
+        {
+            let mut sales = write_txn.open_multimap_table(SALES)?;
+            for amount in [5, 7, 30] {
+                sales.insert(key_builder.bucketed_key("shop", 120), Total(amount))?;
+            }
+            sales.insert(key_builder.bucketed_key("shop", 250), Total(1))?;
+        }
+
+        let bucket = key_builder.bucket_for(120);
+        assert_eq!(
+            summarize_bucket(&write_txn, "shop", bucket, SALES, SALES_ROLLUP)?,
+            3
+        );
+        // Summarizing again replaces the row instead of adding to it
+        assert_eq!(
+            summarize_bucket(&write_txn, "shop", bucket, SALES, SALES_ROLLUP)?,
+            3
+        );
+        assert_eq!(
+            summarize_bucket(&write_txn, "shop", 0, SALES, SALES_ROLLUP)?,
+            0
+        );
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        let rollup = read_txn.open_table(SALES_ROLLUP)?;
+        assert_eq!(
+            rollup
+                .get(key_builder.bucketed_key("shop", 120))?
+                .unwrap()
+                .value(),
+            Total(42)
+        );
+        assert!(rollup.get(key_builder.bucketed_key("shop", 0))?.is_none());
+        assert_eq!(
+            read_txn
+                .open_multimap_table(SALES)?
+                .get(key_builder.bucketed_key("shop", 120))?
+                .len(),
+            3
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+//! Tiered bucket sizes.
+//!
+//! With fixed-size buckets, iterating a base key's whole history costs one
+//! point lookup per bucket, which grows linearly with the history. A tiered
+//! layout uses small buckets for recent sequences and progressively larger
+//! ones for older sequences, so the number of buckets covering the history
+//! grows only slowly.
+//!
+//! Buckets are numbered consecutively across tiers, so bucketed keys still
+//! sort by sequence and the regular bucket iterators, statistics and pruning
+//! work unchanged when given a `TieredKeyBuilder`.
+
+use crate::key_buckets::key::BucketScheme;
+use crate::key_buckets::BucketError;
+
+/// A range of sequences sharing one bucket size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Tier {
+    /// First sequence of the tier
+    start: u64,
+    /// Bucket size within the tier
+    size: u64,
+    /// Bucket number of the tier's first bucket
+    first_bucket: u64,
+}
+
+/// Builder for bucketed keys whose bucket size depends on the sequence.
+///
+/// The layout is fixed when the builder is created: every process writing
+/// or reading a table must use the same tiers, just like with `KeyBuilder`.
+/// To keep coarsening data as it ages, move to a layout with a later horizon
+/// and rewrite the existing entries under it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TieredKeyBuilder {
+    tiers: Vec<Tier>,
+}
+
+impl TieredKeyBuilder {
+    /// Create a TieredKeyBuilder from explicit tiers.
+    ///
+    /// Each tier is a `(start, bucket_size)` pair and covers sequences from
+    /// its start up to the next tier's start; the last tier is unbounded.
+    /// Buckets are aligned to the start of their tier, and a tier's last
+    /// bucket is cut short if its span isn't a multiple of the bucket size.
+    ///
+    /// # Arguments
+    /// * `tiers` - Tiers in ascending start order, the first starting at 0
+    ///
+    /// # Returns
+    /// Configured TieredKeyBuilder or error if the tiers are invalid
+    pub fn new(tiers: impl IntoIterator<Item = (u64, u64)>) -> Result<Self, BucketError> {
+        let mut built: Vec<Tier> = Vec::new();
+
+        for (start, size) in tiers {
+            if size == 0 {
+                return Err(BucketError::InvalidBucketSize(size));
+            }
+
+            let first_bucket = match built.last() {
+                None if start != 0 => {
+                    return Err(BucketError::InvalidTiers(format!(
+                        "first tier starts at {} instead of 0",
+                        start
+                    )))
+                }
+                None => 0,
+                Some(previous) if start <= previous.start => {
+                    return Err(BucketError::InvalidTiers(format!(
+                        "tier starting at {} is not after the tier starting at {}",
+                        start, previous.start
+                    )))
+                }
+                Some(previous) => {
+                    let span = start - previous.start;
+                    previous.first_bucket
+                        + span / previous.size
+                        + u64::from(span % previous.size != 0)
+                }
+            };
+
+            built.push(Tier {
+                start,
+                size,
+                first_bucket,
+            });
+        }
+
+        if built.is_empty() {
+            return Err(BucketError::InvalidTiers("no tiers given".to_string()));
+        }
+
+        Ok(Self { tiers: built })
+    }
+
+    /// Create a TieredKeyBuilder whose bucket sizes grow exponentially with age.
+    ///
+    /// Sequences from `horizon` on use buckets of `base_size`. Going back in
+    /// time, each older tier holds `buckets_per_tier` buckets, each `factor`
+    /// times larger than those of the tier after it. The oldest tier extends
+    /// down to sequence 0.
+    ///
+    /// # Arguments
+    /// * `horizon` - First sequence bucketed at the finest size
+    /// * `base_size` - Bucket size from the horizon on
+    /// * `factor` - Growth of the bucket size per tier (must be > 1)
+    /// * `buckets_per_tier` - Number of buckets in each older tier (must be > 0)
+    /// * `tiers` - Maximum number of tiers older than the horizon
+    ///
+    /// # Returns
+    /// Configured TieredKeyBuilder or error if a parameter is invalid
+    pub fn exponential(
+        horizon: u64,
+        base_size: u64,
+        factor: u64,
+        buckets_per_tier: u64,
+        tiers: u32,
+    ) -> Result<Self, BucketError> {
+        if factor < 2 || buckets_per_tier == 0 {
+            return Err(BucketError::InvalidTiers(format!(
+                "growth factor {} with {} buckets per tier",
+                factor, buckets_per_tier
+            )));
+        }
+
+        // Built newest first, then reversed into ascending order
+        let mut layout = vec![(horizon, base_size)];
+        let mut size = base_size;
+        for _ in 0..tiers {
+            let start = layout.last().map_or(0, |(start, _)| *start);
+            if start == 0 {
+                break;
+            }
+            size = size.saturating_mul(factor);
+            let span = size.saturating_mul(buckets_per_tier);
+            layout.push((start.saturating_sub(span), size));
+        }
+        if let Some(oldest) = layout.last_mut() {
+            oldest.0 = 0;
+        }
+
+        layout.reverse();
+        layout.dedup_by_key(|(start, _)| *start);
+        Self::new(layout)
+    }
+
+    /// Get the first sequence that falls into a bucket.
+    pub fn bucket_start(&self, bucket: u64) -> u64 {
+        let tier = &self.tiers[self
+            .tiers
+            .partition_point(|tier| tier.first_bucket <= bucket)
+            - 1];
+        let offset = u128::from(bucket - tier.first_bucket) * u128::from(tier.size);
+        (u128::from(tier.start) + offset).min(u128::from(u64::MAX)) as u64
+    }
+
+    /// Get the size of the bucket a sequence falls into.
+    pub fn bucket_size_for(&self, sequence: u64) -> u64 {
+        self.tier_for(sequence).size
+    }
+
+    /// Returns the tier containing a sequence.
+    fn tier_for(&self, sequence: u64) -> &Tier {
+        // The first tier starts at 0, so there is always one at or before the sequence
+        &self.tiers[self.tiers.partition_point(|tier| tier.start <= sequence) - 1]
+    }
+}
+
+impl BucketScheme for TieredKeyBuilder {
+    fn bucket_for(&self, sequence: u64) -> u64 {
+        let tier = self.tier_for(sequence);
+        tier.first_bucket + (sequence - tier.start) / tier.size
+    }
+
+    fn bucket_start(&self, bucket: u64) -> u64 {
+        TieredKeyBuilder::bucket_start(self, bucket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_buckets::{BucketIterExt, BucketedKey};
+    use redb::{Database, ReadableDatabase, TableDefinition};
+    use tempfile::NamedTempFile;
+
+    const EVENTS: TableDefinition<'static, BucketedKey<u64>, u64> =
+        TableDefinition::new("tiered_events");
+
+    #[test]
+    fn test_explicit_tiers() {
+        let tiered = TieredKeyBuilder::new([(0, 100), (1000, 10)]).unwrap();
+        assert_eq!(tiered.bucket_for(0), 0);
+        assert_eq!(tiered.bucket_for(999), 9);
+        assert_eq!(tiered.bucket_for(1000), 10);
+        assert_eq!(tiered.bucket_for(1015), 11);
+        assert_eq!(tiered.bucket_start(11), 1010);
+        assert_eq!(tiered.bucket_size_for(50), 100);
+
+        // A short last bucket keeps the numbering consecutive
+        let uneven = TieredKeyBuilder::new([(0, 300), (1000, 10)]).unwrap();
+        assert_eq!(uneven.bucket_for(999), 3);
+        assert_eq!(uneven.bucket_for(1000), 4);
+
+        assert!(TieredKeyBuilder::new([(5, 100)]).is_err());
+        assert!(TieredKeyBuilder::new([(0, 100), (0, 10)]).is_err());
+        assert!(TieredKeyBuilder::new([(0, 0)]).is_err());
+        assert!(TieredKeyBuilder::new([]).is_err());
+    }
+
+    #[test]
+    fn test_exponential_tiers() -> Result<(), Box<dyn std::error::Error>> {
+        // Finest buckets of 10 from 10_000 on, then 4 buckets of 20, 40, ...
+        let tiered = TieredKeyBuilder::exponential(10_000, 10, 2, 4, 20)?;
+        assert_eq!(tiered.bucket_size_for(10_000), 10);
+        assert_eq!(tiered.bucket_size_for(9_999), 20);
+        assert_eq!(tiered.bucket_size_for(9_920), 20);
+        assert_eq!(tiered.bucket_size_for(9_919), 40);
+        assert_eq!(tiered.bucket_size_for(0), 1280);
+
+        // Buckets stay ordered across tier boundaries
+        let buckets: Vec<u64> = (0..10_100).map(|seq| tiered.bucket_for(seq)).collect();
+        assert!(buckets.windows(2).all(|pair| pair[1] - pair[0] <= 1));
+        assert!(buckets[10_099] < 50);
+
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let write_txn = db.begin_write()?;
+        {
+            let mut events = write_txn.open_table(EVENTS)?;
+            for sequence in (0..10_100).step_by(500) {
+                events.insert(tiered.bucketed_key(1u64, sequence), sequence)?;
+            }
+        }
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        let iter = read_txn
+            .open_table(EVENTS)?
+            .bucket_range_bounds(&tiered, 1u64, ..)?;
+        let (first, last) = iter.bucket_range();
+        assert!(last - first < 50);
+        // Several old sequences share a coarse bucket, keeping the latest value
+        let values: Vec<u64> = iter.collect::<Result<_, _>>()?;
+        assert_eq!(values.last(), Some(&10_000));
+        assert!(values.windows(2).all(|pair| pair[0] < pair[1]));
+
+        Ok(())
+    }
+}
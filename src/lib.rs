@@ -1,12 +1,19 @@
 pub mod dbcopy;
+pub mod encoding;
 pub mod error;
 pub mod key_buckets;
+pub mod multimap;
 pub mod partition;
 pub mod roaring;
 pub mod table_buckets;
 
 // Re-export common types for convenience
-pub use error::{Error, Result};
+pub use dbcopy::DbCopyError;
+pub use encoding::EncodingError;
+pub use error::{DatabaseError, Error, ErrorContext, ErrorKind, Result};
+pub use key_buckets::BucketError;
+pub use partition::PartitionError;
+pub use roaring::RoaringError;
 
 /// Trait for merging values when consolidating bucket tables.
 pub trait MergeableValue: Sized {
@@ -0,0 +1,18 @@
+//! Partitioned multimap module.
+//!
+//! Stores sets of small fixed-width values, such as hashes, per base key using
+//! the same sharding and segment rolling as partitioned roaring bitmaps. Each
+//! segment holds a sorted set of `N`-byte values instead of a bitmap, for
+//! workloads whose members aren't `u64` identifiers.
+
+use crate::partition::PartitionedTable;
+
+mod partitioned;
+mod value;
+
+// Re-export main types for public API
+pub use partitioned::{PartitionedMultimapRead, PartitionedMultimapWrite};
+pub use value::FixedSetValue;
+
+/// Partitioned table storing sets of `N`-byte values per base key.
+pub type PartitionedMultimapTable<K, const N: usize> = PartitionedTable<K, FixedSetValue<N>>;
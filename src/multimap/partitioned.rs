@@ -0,0 +1,358 @@
+//! Fixed-width set operations on partitioned tables.
+//!
+//! Values are routed to a shard by hashing their bytes, then added to that
+//! shard's head segment until it holds as many values as fit in
+//! `segment_max_bytes`, after which new segments are rolled. Every segment is
+//! a sorted set, and a value is stored in at most one segment of its shard.
+//! Removals rewrite the segments holding the value; tombstones are not used.
+
+use super::FixedSetValue;
+use crate::partition::scan::SegmentInfo;
+use crate::partition::table::{encode_base_key, SegmentTables};
+use crate::partition::{PartitionedRead, PartitionedTable, PartitionedWrite};
+use crate::Result;
+use redb::Key;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Read-only set operations on a partitioned multimap table.
+pub trait PartitionedMultimapRead<K: Key + 'static, const N: usize> {
+    /// Gets every value stored for the given key.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to retrieve
+    ///
+    /// # Returns
+    /// The values in ascending order, or empty if not found
+    fn get_values<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<Vec<[u8; N]>>;
+
+    /// Checks if a value is stored for the given key.
+    ///
+    /// Only the shard the value is routed to is read.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to check
+    /// * `value` - The value to check for
+    ///
+    /// # Returns
+    /// True if the value exists, false otherwise
+    fn contains_value<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        value: &[u8; N],
+    ) -> Result<bool>;
+
+    /// Gets the number of values stored for the given key.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to query
+    ///
+    /// # Returns
+    /// The number of values
+    fn get_value_count<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<u64> {
+        Ok(self.get_values(key)?.len() as u64)
+    }
+}
+
+/// Set write operations on a partitioned multimap table.
+pub trait PartitionedMultimapWrite<K: Key + 'static, const N: usize>:
+    PartitionedMultimapRead<K, N>
+{
+    /// Inserts a single value for the given key.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to modify
+    /// * `value` - The value to insert
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn insert_value<'k>(&self, key: impl Borrow<K::SelfType<'k>>, value: [u8; N]) -> Result<()> {
+        self.insert_values(key, std::iter::once(value))
+    }
+
+    /// Inserts multiple values for the given key.
+    ///
+    /// Values already stored are skipped. New values fill the head segment of
+    /// their shard first and roll into new segments once it is full.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to modify
+    /// * `values` - Iterator of values to insert
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn insert_values<'k, I>(&self, key: impl Borrow<K::SelfType<'k>>, values: I) -> Result<()>
+    where
+        I: IntoIterator<Item = [u8; N]>;
+
+    /// Removes a single value for the given key.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to modify
+    /// * `value` - The value to remove
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn remove_value<'k>(&self, key: impl Borrow<K::SelfType<'k>>, value: [u8; N]) -> Result<()> {
+        self.remove_values(key, std::iter::once(value))
+    }
+
+    /// Removes multiple values for the given key.
+    ///
+    /// Segments holding a removed value are rewritten, and deleted once empty.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to modify
+    /// * `values` - Iterator of values to remove
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn remove_values<'k, I>(&self, key: impl Borrow<K::SelfType<'k>>, values: I) -> Result<()>
+    where
+        I: IntoIterator<Item = [u8; N]>;
+
+    /// Removes every value stored for the given key.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to clear
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn clear_values<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<()>;
+}
+
+/// Decodes the set stored in each segment, keeping segment order.
+fn decode_segments<const N: usize>(
+    segments: Vec<SegmentInfo>,
+) -> Result<Vec<(SegmentInfo, FixedSetValue<N>)>> {
+    segments
+        .into_iter()
+        .map(|segment| {
+            let set = match &segment.segment_data {
+                Some(data) => FixedSetValue::decode(data)?,
+                None => FixedSetValue::empty(),
+            };
+            Ok((segment, set))
+        })
+        .collect()
+}
+
+/// Selects the shard a value is routed to.
+fn value_shard<'k, K: Key + 'static, V, const N: usize>(
+    table: &PartitionedTable<K, V>,
+    key: &K::SelfType<'k>,
+    value: &[u8; N],
+) -> Result<u16> {
+    table.select_shard(key, xxh3_64(value))
+}
+
+/// Groups values by the shard they are routed to.
+fn group_by_shard<'k, K, V, I, const N: usize>(
+    table: &PartitionedTable<K, V>,
+    key: &K::SelfType<'k>,
+    values: I,
+) -> Result<BTreeMap<u16, BTreeSet<[u8; N]>>>
+where
+    K: Key + 'static,
+    I: IntoIterator<Item = [u8; N]>,
+{
+    let mut groups: BTreeMap<u16, BTreeSet<[u8; N]>> = BTreeMap::new();
+    for value in values {
+        let shard = value_shard(table, key, &value)?;
+        groups.entry(shard).or_default().insert(value);
+    }
+    Ok(groups)
+}
+
+impl<K: Key + 'static, const N: usize> PartitionedMultimapRead<K, N>
+    for PartitionedRead<'_, K, FixedSetValue<N>>
+{
+    fn get_values<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<Vec<[u8; N]>> {
+        let base_key = encode_base_key::<K>(key.borrow());
+        let mut values = Vec::new();
+
+        for shard in 0..self.table().config().shard_count {
+            let segments = self.enumerate_shard_segments_raw(&base_key, shard)?;
+            for (_, set) in decode_segments::<N>(segments)? {
+                values.extend(set.into_values());
+            }
+        }
+
+        // Shards interleave, so the concatenation is only sorted per segment
+        values.sort_unstable();
+        Ok(values)
+    }
+
+    fn contains_value<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        value: &[u8; N],
+    ) -> Result<bool> {
+        let key = key.borrow();
+        let shard = value_shard(self.table(), key, value)?;
+        let segments = self.enumerate_shard_segments(key, shard)?;
+
+        Ok(decode_segments::<N>(segments)?
+            .iter()
+            .any(|(_, set)| set.contains(value)))
+    }
+}
+
+impl<K: Key + 'static, const N: usize> PartitionedMultimapRead<K, N>
+    for PartitionedWrite<'_, K, FixedSetValue<N>>
+{
+    fn get_values<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<Vec<[u8; N]>> {
+        let base_key = encode_base_key::<K>(key.borrow());
+        let tables = self.tables()?;
+        let mut values = Vec::new();
+
+        for shard in 0..self.table().config().shard_count {
+            let segments = tables.enumerate_shard_segments(&base_key, shard)?;
+            for (_, set) in decode_segments::<N>(segments)? {
+                values.extend(set.into_values());
+            }
+        }
+
+        values.sort_unstable();
+        Ok(values)
+    }
+
+    fn contains_value<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        value: &[u8; N],
+    ) -> Result<bool> {
+        let key = key.borrow();
+        let shard = value_shard(self.table(), key, value)?;
+        let segments = self.enumerate_shard_segments(key, shard)?;
+
+        Ok(decode_segments::<N>(segments)?
+            .iter()
+            .any(|(_, set)| set.contains(value)))
+    }
+}
+
+impl<K: Key + 'static, const N: usize> SegmentTables<'_, K, FixedSetValue<N>> {
+    /// Adds values to one shard, filling the head segment before rolling.
+    fn insert_shard_values(
+        &mut self,
+        key: &[u8],
+        shard: u16,
+        values: BTreeSet<[u8; N]>,
+    ) -> Result<()> {
+        let sets = decode_segments::<N>(self.enumerate_shard_segments(key, shard)?)?;
+        let stored: usize = sets.iter().map(|(_, set)| set.len()).sum();
+
+        let mut pending: Vec<[u8; N]> = values
+            .into_iter()
+            .filter(|value| !sets.iter().any(|(_, set)| set.contains(value)))
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let added = pending.len();
+        let capacity = FixedSetValue::<N>::capacity_for(self.table().config().segment_max_bytes);
+
+        if let Some((head, head_set)) = sets.last() {
+            let room = capacity.saturating_sub(head_set.len()).min(pending.len());
+            if room > 0 {
+                let mut head_set = head_set.clone();
+                for value in pending.drain(..room) {
+                    head_set.insert(value);
+                }
+                self.write_segment(&head.segment_key, &head_set.encode())?;
+            }
+        }
+
+        let chunks: Vec<Vec<u8>> = pending
+            .chunks(capacity)
+            .map(|chunk| chunk.iter().copied().collect::<FixedSetValue<N>>().encode())
+            .collect();
+        if !chunks.is_empty() {
+            self.append_segments(key, shard, &chunks)?;
+        }
+
+        self.set_member_count(key, shard, (stored + added) as u64)
+    }
+
+    /// Removes values from every segment of a shard, deleting emptied segments.
+    fn remove_shard_values(
+        &mut self,
+        key: &[u8],
+        shard: u16,
+        values: &BTreeSet<[u8; N]>,
+    ) -> Result<()> {
+        let mut remaining = 0;
+
+        for (segment, mut set) in decode_segments::<N>(self.enumerate_shard_segments(key, shard)?)?
+        {
+            let before = set.len();
+            for value in values {
+                set.remove(value);
+            }
+            remaining += set.len();
+
+            if set.len() == before {
+                continue;
+            }
+            if set.is_empty() {
+                self.delete_segment(&segment.segment_key)?;
+            } else {
+                self.write_segment(&segment.segment_key, &set.encode())?;
+            }
+        }
+
+        self.set_member_count(key, shard, remaining as u64)
+    }
+}
+
+impl<K: Key + 'static, const N: usize> PartitionedMultimapWrite<K, N>
+    for PartitionedWrite<'_, K, FixedSetValue<N>>
+{
+    fn insert_values<'k, I>(&self, key: impl Borrow<K::SelfType<'k>>, values: I) -> Result<()>
+    where
+        I: IntoIterator<Item = [u8; N]>,
+    {
+        let key = key.borrow();
+        let base_key = encode_base_key::<K>(key);
+        let mut tables = self.tables()?;
+
+        for (shard, shard_values) in group_by_shard(self.table(), key, values)? {
+            tables.record_logical_bytes((shard_values.len() * N) as u64);
+            tables.insert_shard_values(&base_key, shard, shard_values)?;
+        }
+
+        Ok(())
+    }
+
+    fn remove_values<'k, I>(&self, key: impl Borrow<K::SelfType<'k>>, values: I) -> Result<()>
+    where
+        I: IntoIterator<Item = [u8; N]>,
+    {
+        let key = key.borrow();
+        let base_key = encode_base_key::<K>(key);
+        let mut tables = self.tables()?;
+
+        for (shard, shard_values) in group_by_shard(self.table(), key, values)? {
+            tables.record_logical_bytes((shard_values.len() * N) as u64);
+            tables.remove_shard_values(&base_key, shard, &shard_values)?;
+        }
+
+        Ok(())
+    }
+
+    fn clear_values<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<()> {
+        let base_key = encode_base_key::<K>(key.borrow());
+        let mut tables = self.tables()?;
+
+        for shard in 0..self.table().config().shard_count {
+            for segment in tables.enumerate_shard_segments(&base_key, shard)? {
+                tables.delete_segment(&segment.segment_key)?;
+            }
+        }
+
+        Ok(())
+    }
+}
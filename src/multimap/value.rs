@@ -0,0 +1,189 @@
+//! Sorted fixed-width set value type for partitioned tables.
+//!
+//! Each segment stores a sorted, deduplicated set of `N`-byte values, encoded
+//! as a version byte followed by the values back to back.
+
+use crate::partition::PartitionError;
+use crate::Result;
+
+/// Storage format version written as the first byte of every segment.
+const FORMAT_VERSION: u8 = 1;
+
+/// Sorted set of fixed-width values stored in a single segment.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FixedSetValue<const N: usize> {
+    values: Vec<[u8; N]>,
+}
+
+impl<const N: usize> FixedSetValue<N> {
+    /// Creates an empty set.
+    pub fn empty() -> Self {
+        Self { values: Vec::new() }
+    }
+
+    /// Returns the values in ascending order.
+    pub fn values(&self) -> &[[u8; N]] {
+        &self.values
+    }
+
+    /// Consumes the set and returns the values in ascending order.
+    pub fn into_values(self) -> Vec<[u8; N]> {
+        self.values
+    }
+
+    /// Checks if a value is in the set.
+    pub fn contains(&self, value: &[u8; N]) -> bool {
+        self.values.binary_search(value).is_ok()
+    }
+
+    /// Inserts a value, keeping the set sorted.
+    ///
+    /// # Returns
+    /// True if the value was not present before
+    pub fn insert(&mut self, value: [u8; N]) -> bool {
+        match self.values.binary_search(&value) {
+            Ok(_) => false,
+            Err(index) => {
+                self.values.insert(index, value);
+                true
+            }
+        }
+    }
+
+    /// Removes a value from the set.
+    ///
+    /// # Returns
+    /// True if the value was present
+    pub fn remove(&mut self, value: &[u8; N]) -> bool {
+        match self.values.binary_search(value) {
+            Ok(index) => {
+                self.values.remove(index);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the number of values in the set.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns true if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Encodes the set into storage format.
+    ///
+    /// # Returns
+    /// Encoded bytes ready for storage
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(Self::encoded_size(self.values.len()));
+        encoded.push(FORMAT_VERSION);
+        for value in &self.values {
+            encoded.extend_from_slice(value);
+        }
+        encoded
+    }
+
+    /// Decodes storage bytes into a set.
+    ///
+    /// # Arguments
+    /// * `data` - The encoded segment bytes
+    ///
+    /// # Returns
+    /// Decoded set or error if the data is malformed
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let Some((&version, payload)) = data.split_first() else {
+            return Err(PartitionError::EncodingError("Empty set segment".to_string()).into());
+        };
+
+        if version != FORMAT_VERSION {
+            return Err(PartitionError::EncodingError(format!(
+                "Unsupported set segment version: {}",
+                version
+            ))
+            .into());
+        }
+
+        if N == 0 || payload.len() % N != 0 {
+            return Err(PartitionError::EncodingError(format!(
+                "Set segment length {} is not a multiple of {}",
+                payload.len(),
+                N
+            ))
+            .into());
+        }
+
+        let values: Vec<[u8; N]> = payload
+            .chunks_exact(N)
+            .map(|chunk| {
+                let mut value = [0u8; N];
+                value.copy_from_slice(chunk);
+                value
+            })
+            .collect();
+
+        if values.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(
+                PartitionError::EncodingError("Set segment is not sorted".to_string()).into(),
+            );
+        }
+
+        Ok(Self { values })
+    }
+
+    /// Gets the encoded size of a set holding `count` values.
+    pub fn encoded_size(count: usize) -> usize {
+        1 + count * N
+    }
+
+    /// Gets how many values fit in a segment of `max_bytes`.
+    ///
+    /// At least one value always fits, so values wider than a segment are
+    /// stored one per segment.
+    pub fn capacity_for(max_bytes: usize) -> usize {
+        (max_bytes.saturating_sub(1) / N.max(1)).max(1)
+    }
+}
+
+impl<const N: usize> FromIterator<[u8; N]> for FixedSetValue<N> {
+    /// Creates a set from an iterator of values, sorting and deduplicating them.
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = [u8; N]>,
+    {
+        let mut values: Vec<[u8; N]> = iter.into_iter().collect();
+        values.sort_unstable();
+        values.dedup();
+        Self { values }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_set_roundtrip() {
+        let set: FixedSetValue<4> = [[0, 0, 0, 2], [0, 0, 0, 1], [0, 0, 0, 2]]
+            .into_iter()
+            .collect();
+        assert_eq!(set.len(), 2);
+
+        let encoded = set.encode();
+        assert_eq!(encoded.len(), FixedSetValue::<4>::encoded_size(2));
+        assert_eq!(FixedSetValue::<4>::decode(&encoded).unwrap(), set);
+
+        assert!(FixedSetValue::<4>::decode(&[]).is_err());
+        assert!(FixedSetValue::<4>::decode(&[1, 0, 0]).is_err());
+        assert!(FixedSetValue::<4>::decode(&[1, 0, 0, 0, 2, 0, 0, 0, 1]).is_err());
+    }
+
+    #[test]
+    fn test_capacity_for() {
+        assert_eq!(FixedSetValue::<32>::capacity_for(1 + 32 * 4), 4);
+        assert_eq!(FixedSetValue::<32>::capacity_for(16), 1);
+    }
+}
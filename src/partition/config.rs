@@ -23,9 +23,17 @@ pub struct PartitionConfig {
 
     /// Whether to use a meta table for O(1) head segment discovery
     ///
-    /// With meta: Faster writes, additional storage overhead
+    /// With meta: Faster writes, cached per-shard member counts and
+    /// per-segment member bounds, additional storage overhead
     /// Without meta: Simpler, but requires scanning to find writable segment
     pub use_meta: bool,
+
+    /// Whether removals are recorded in a per-shard tombstone row
+    ///
+    /// With tombstones: Removals only touch a small tombstone row and are
+    /// folded into the segments at read and compaction time
+    /// Without tombstones: Removals rewrite every segment holding the member
+    pub use_tombstones: bool,
 }
 
 impl PartitionConfig {
@@ -55,25 +63,29 @@ impl PartitionConfig {
             shard_count,
             segment_max_bytes,
             use_meta,
+            use_tombstones: false,
         })
     }
 
+    /// Enables or disables tombstone rows for removals.
+    pub fn with_tombstones(mut self, use_tombstones: bool) -> Self {
+        self.use_tombstones = use_tombstones;
+        self
+    }
+}
+
+impl Default for PartitionConfig {
     /// Creates a default configuration suitable for most use cases.
-    pub fn default() -> Self {
+    fn default() -> Self {
         Self {
             shard_count: 16,              // Good balance for most workloads
             segment_max_bytes: 64 * 1024, // 64KB segments match roaring compression
             use_meta: true,               // Faster writes worth the overhead
+            use_tombstones: false,        // Removals rewrite segments by default
         }
     }
 }
 
-impl Default for PartitionConfig {
-    fn default() -> Self {
-        Self::default()
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,5 +119,14 @@ mod tests {
         assert_eq!(config.shard_count, 16);
         assert_eq!(config.segment_max_bytes, 64 * 1024);
         assert!(config.use_meta);
+        assert!(!config.use_tombstones);
+    }
+
+    #[test]
+    fn test_with_tombstones() {
+        let config = PartitionConfig::new(8, 1024, true)
+            .unwrap()
+            .with_tombstones(true);
+        assert!(config.use_tombstones);
     }
 }
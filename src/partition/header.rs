@@ -0,0 +1,262 @@
+//! Persisted configuration header.
+//!
+//! `ensure_table_exists` stores the parts of a `PartitionConfig` that decide
+//! where data lives and how it is read in a header row keyed by the table
+//! name. Opening the table again with a different shard count, segment size,
+//! tombstone setting, segment transform or storage format is reported as
+//! `PartitionError::ConfigMismatch` instead of silently misreading the data.
+
+use crate::partition::{PartitionConfig, PartitionError, SegmentTransform};
+use crate::Result;
+
+/// Version of the partition storage format written by this crate.
+///
+/// - 1: the rows of all partitioned tables share the `redb_extras_segments`,
+///   `redb_extras_meta` and `redb_extras_tombstones` tables, and the header
+///   records only the shard count and segment size
+/// - 2: each table name has its own segment, meta and tombstone tables, and
+///   the header also records the tombstone setting and segment transform
+///
+/// Version 1 data is rejected on open and can be moved with
+/// `PartitionedTable::adopt_legacy_storage`.
+pub const PARTITION_FORMAT_VERSION: u8 = 2;
+
+/// Encoded size of a format version 1 header: format version + shard count +
+/// segment size.
+const LEGACY_HEADER_LEN: usize = 1 + 2 + 8;
+
+/// Encoded size of the fixed part of a header: format version + shard count +
+/// segment size + tombstone flag + transform ID length.
+const HEADER_LEN: usize = 1 + 2 + 8 + 1 + 1;
+
+/// Configuration recorded when a partitioned table is first created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigHeader {
+    /// Storage format version
+    pub format_version: u8,
+    /// Number of shards data is distributed across
+    pub shard_count: u16,
+    /// Maximum segment size in bytes
+    pub segment_max_bytes: u64,
+    /// Whether removals are recorded as tombstones
+    pub use_tombstones: bool,
+    /// ID of the segment transform, if any
+    pub transform: Option<String>,
+}
+
+impl ConfigHeader {
+    /// Creates the header describing a configuration.
+    ///
+    /// # Arguments
+    /// * `config` - The partition configuration
+    /// * `transform` - The segment transform attached to the table, if any
+    pub fn from_config(config: &PartitionConfig, transform: Option<&dyn SegmentTransform>) -> Self {
+        Self {
+            format_version: PARTITION_FORMAT_VERSION,
+            shard_count: config.shard_count,
+            segment_max_bytes: config.segment_max_bytes as u64,
+            use_tombstones: config.use_tombstones,
+            transform: transform.map(|transform| truncate_id(transform.id()).to_string()),
+        }
+    }
+
+    /// Encodes the header as \\[version u8\\]\\[shard_count u16 BE\\]\\[segment_max_bytes u64 BE\\]
+    /// \\[use_tombstones u8\\]\\[transform_len u8\\]\\[transform\\].
+    ///
+    /// A transform length of 0 means no transform.
+    pub fn encode(&self) -> Vec<u8> {
+        let transform = truncate_id(self.transform.as_deref().unwrap_or_default()).as_bytes();
+
+        let mut encoded = Vec::with_capacity(HEADER_LEN + transform.len());
+        encoded.push(self.format_version);
+        encoded.extend_from_slice(&self.shard_count.to_be_bytes());
+        encoded.extend_from_slice(&self.segment_max_bytes.to_be_bytes());
+        encoded.push(u8::from(self.use_tombstones));
+        encoded.push(transform.len() as u8);
+        encoded.extend_from_slice(transform);
+        encoded
+    }
+
+    /// Decodes a header produced by `encode`.
+    ///
+    /// Format version 1 headers are decoded with tombstones disabled and no
+    /// transform, which were the only options that version supported.
+    ///
+    /// # Arguments
+    /// * `data` - The encoded header
+    ///
+    /// # Returns
+    /// The decoded header or error if the length is wrong
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() == LEGACY_HEADER_LEN && data[0] == 1 {
+            let mut size_bytes = [0u8; 8];
+            size_bytes.copy_from_slice(&data[3..11]);
+            return Ok(Self {
+                format_version: 1,
+                shard_count: u16::from_be_bytes([data[1], data[2]]),
+                segment_max_bytes: u64::from_be_bytes(size_bytes),
+                use_tombstones: false,
+                transform: None,
+            });
+        }
+
+        let transform_len = match data.get(HEADER_LEN - 1) {
+            Some(len) => *len as usize,
+            None => 0,
+        };
+        if data.len() < HEADER_LEN || data.len() != HEADER_LEN + transform_len {
+            return Err(PartitionError::EncodingError(format!(
+                "Invalid config header length: expected {}, got {}",
+                HEADER_LEN + transform_len,
+                data.len()
+            ))
+            .into());
+        }
+
+        let mut size_bytes = [0u8; 8];
+        size_bytes.copy_from_slice(&data[3..11]);
+
+        let transform = match &data[HEADER_LEN..] {
+            [] => None,
+            id => Some(String::from_utf8_lossy(id).into_owned()),
+        };
+
+        Ok(Self {
+            format_version: data[0],
+            shard_count: u16::from_be_bytes([data[1], data[2]]),
+            segment_max_bytes: u64::from_be_bytes(size_bytes),
+            use_tombstones: data[11] != 0,
+            transform,
+        })
+    }
+
+    /// Checks that a stored header matches the configuration a table is opened with.
+    ///
+    /// # Arguments
+    /// * `table` - Name of the partitioned table, used in the error message
+    /// * `expected` - Header of the runtime configuration
+    ///
+    /// # Returns
+    /// Ok if they match, `ConfigMismatch` naming the first differing field otherwise
+    pub fn check(&self, table: &str, expected: &Self) -> Result<()> {
+        let mismatch = if self.format_version != expected.format_version {
+            Some((
+                "format version",
+                self.format_version.to_string(),
+                expected.format_version.to_string(),
+            ))
+        } else if self.shard_count != expected.shard_count {
+            Some((
+                "shard_count",
+                self.shard_count.to_string(),
+                expected.shard_count.to_string(),
+            ))
+        } else if self.segment_max_bytes != expected.segment_max_bytes {
+            Some((
+                "segment_max_bytes",
+                self.segment_max_bytes.to_string(),
+                expected.segment_max_bytes.to_string(),
+            ))
+        } else if self.use_tombstones != expected.use_tombstones {
+            Some((
+                "use_tombstones",
+                self.use_tombstones.to_string(),
+                expected.use_tombstones.to_string(),
+            ))
+        } else if self.transform != expected.transform {
+            Some((
+                "transform",
+                self.transform.as_deref().unwrap_or("none").to_string(),
+                expected.transform.as_deref().unwrap_or("none").to_string(),
+            ))
+        } else {
+            None
+        };
+
+        match mismatch {
+            Some((field, stored, runtime)) => Err(PartitionError::ConfigMismatch(format!(
+                "table '{}' was created with {} {} but is opened with {}",
+                table, field, stored, runtime
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Cuts a transform ID to the 255 bytes a header can hold, on a char boundary.
+fn truncate_id(id: &str) -> &str {
+    let mut end = id.len().min(u8::MAX as usize);
+    while !id.is_char_boundary(end) {
+        end -= 1;
+    }
+    &id[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Reverse;
+
+    impl SegmentTransform for Reverse {
+        fn encode(&self, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.iter().rev().copied().collect())
+        }
+
+        fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+            self.encode(data)
+        }
+
+        fn id(&self) -> &str {
+            "reverse"
+        }
+    }
+
+    #[test]
+    fn test_config_header_roundtrip() {
+        let config = PartitionConfig::new(8, 4096, true).unwrap();
+        let header = ConfigHeader::from_config(&config, None);
+        assert_eq!(ConfigHeader::decode(&header.encode()).unwrap(), header);
+
+        let header = ConfigHeader::from_config(&config.with_tombstones(true), Some(&Reverse));
+        assert_eq!(header.transform.as_deref(), Some("reverse"));
+        assert_eq!(ConfigHeader::decode(&header.encode()).unwrap(), header);
+
+        assert!(ConfigHeader::decode(&[1, 0]).is_err());
+        let mut truncated = header.encode();
+        truncated.pop();
+        assert!(ConfigHeader::decode(&truncated).is_err());
+
+        // Format version 1 headers stop after the segment size
+        let legacy = ConfigHeader::decode(&header.encode()[..11]);
+        assert!(legacy.is_err());
+        let mut legacy = header.encode()[..11].to_vec();
+        legacy[0] = 1;
+        let legacy = ConfigHeader::decode(&legacy).unwrap();
+        assert_eq!(legacy.format_version, 1);
+        assert_eq!(legacy.shard_count, 8);
+        assert!(!legacy.use_tombstones && legacy.transform.is_none());
+    }
+
+    #[test]
+    fn test_config_header_check() {
+        let config = PartitionConfig::new(8, 4096, true).unwrap();
+        let stored = ConfigHeader::from_config(&config, None);
+        let same = ConfigHeader::from_config(&PartitionConfig::new(8, 4096, false).unwrap(), None);
+        assert!(stored.check("t", &same).is_ok());
+
+        let resharded =
+            ConfigHeader::from_config(&PartitionConfig::new(4, 4096, true).unwrap(), None);
+        let err = stored.check("t", &resharded).unwrap_err().to_string();
+        assert!(err.contains("shard_count 8"), "{}", err);
+
+        let tombstoned = ConfigHeader::from_config(&config.clone().with_tombstones(true), None);
+        let err = stored.check("t", &tombstoned).unwrap_err().to_string();
+        assert!(err.contains("use_tombstones false"), "{}", err);
+
+        let transformed = ConfigHeader::from_config(&config, Some(&Reverse));
+        let err = stored.check("t", &transformed).unwrap_err().to_string();
+        assert!(err.contains("transform none"), "{}", err);
+    }
+}
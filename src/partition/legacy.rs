@@ -0,0 +1,227 @@
+//! Storage written by format version 1.
+//!
+//! Version 1 kept the segments, meta entries and tombstones of every
+//! partitioned table in three shared tables, so the rows of different table
+//! names could not be told apart. Version 2 gives each table name its own
+//! tables. Opening a table whose data is still in the shared tables fails with
+//! `PartitionError::ConfigMismatch`; `adopt_legacy_storage` moves the rows over.
+
+use crate::partition::header::{ConfigHeader, PARTITION_FORMAT_VERSION};
+use crate::partition::table::{PartitionedTable, CONFIG_TABLE};
+use crate::partition::PartitionError;
+use crate::Result;
+use redb::{
+    Database, Key, ReadableTable, ReadableTableMetadata, TableDefinition, TableHandle,
+    WriteTransaction,
+};
+
+/// Shared segment table of format version 1
+const LEGACY_SEGMENT_TABLE: TableDefinition<&'static [u8], &'static [u8]> =
+    TableDefinition::new("redb_extras_segments");
+
+/// Shared meta table of format version 1
+const LEGACY_META_TABLE: TableDefinition<&'static [u8], &'static [u8]> =
+    TableDefinition::new("redb_extras_meta");
+
+/// Shared tombstone table of format version 1
+const LEGACY_TOMBSTONE_TABLE: TableDefinition<&'static [u8], &'static [u8]> =
+    TableDefinition::new("redb_extras_tombstones");
+
+type LegacyTable<'txn> = redb::Table<'txn, &'static [u8], &'static [u8]>;
+
+/// Counts the rows left in the shared tables of format version 1.
+///
+/// # Arguments
+/// * `txn` - The write transaction to inspect
+///
+/// # Returns
+/// Number of segment, meta and tombstone rows, 0 if the tables don't exist
+pub(crate) fn legacy_row_count(txn: &WriteTransaction) -> Result<u64> {
+    let names: Vec<String> = txn
+        .list_tables()
+        .map_err(|e| db_error("Failed to list tables", e))?
+        .map(|table| table.name().to_string())
+        .collect();
+
+    let mut rows = 0;
+    for definition in [
+        LEGACY_SEGMENT_TABLE,
+        LEGACY_META_TABLE,
+        LEGACY_TOMBSTONE_TABLE,
+    ] {
+        if names.iter().any(|name| name == definition.name()) {
+            let table = txn
+                .open_table(definition)
+                .map_err(|e| db_error("Failed to open legacy table", e))?;
+            rows += table
+                .len()
+                .map_err(|e| db_error("Failed to count legacy rows", e))?;
+        }
+    }
+    Ok(rows)
+}
+
+/// Builds the error returned when a table is opened over format version 1 data.
+pub(crate) fn legacy_error(table: &str) -> PartitionError {
+    PartitionError::ConfigMismatch(format!(
+        "table '{}' is opened with format version {} but the database holds format version 1 \
+         data in shared tables; move it with PartitionedTable::adopt_legacy_storage",
+        table, PARTITION_FORMAT_VERSION
+    ))
+}
+
+fn db_error(operation: &str, err: impl Into<redb::Error>) -> PartitionError {
+    PartitionError::DatabaseError(crate::error::DatabaseError::new(operation, err))
+}
+
+impl<K: Key + 'static, V> PartitionedTable<K, V> {
+    /// Moves format version 1 data into this table's own storage.
+    ///
+    /// Version 1 stored the rows of every table name in shared tables, so
+    /// they are all moved to this table. This is only correct for databases
+    /// that held a single partitioned table; if format version 1 headers of
+    /// other table names are present the call fails without changing
+    /// anything. Meta entries are dropped when this table doesn't use meta, and
+    /// tables with a segment transform are refused since version 1 payloads
+    /// were stored as-is.
+    ///
+    /// The shared tables are deleted and this table's header is rewritten for
+    /// the current format, all within one transaction.
+    ///
+    /// # Arguments
+    /// * `db` - The database instance
+    ///
+    /// # Returns
+    /// Number of rows moved, `ConfigMismatch` if the legacy data can't be
+    /// adopted by this table, or another error on failure
+    pub fn adopt_legacy_storage(&self, db: &Database) -> Result<u64> {
+        let txn = db.begin_write()?;
+
+        if legacy_row_count(&txn)? == 0 {
+            return Ok(0);
+        }
+        if self.transform().is_some() {
+            return Err(PartitionError::ConfigMismatch(format!(
+                "table '{}' has a segment transform but format version 1 payloads are untransformed",
+                self.name()
+            ))
+            .into());
+        }
+        self.check_legacy_headers(&txn)?;
+
+        let mut moved = 0;
+        {
+            let legacy_tombstones = txn
+                .open_table(LEGACY_TOMBSTONE_TABLE)
+                .map_err(|e| self.db_error("Failed to open legacy tombstone table", e))?;
+            if !self.config().use_tombstones
+                && !legacy_tombstones
+                    .is_empty()
+                    .map_err(|e| self.db_error("Failed to read legacy tombstone table", e))?
+            {
+                return Err(PartitionError::ConfigMismatch(format!(
+                    "table '{}' has pending tombstones but is opened with use_tombstones false",
+                    self.name()
+                ))
+                .into());
+            }
+
+            let legacy_segments = txn
+                .open_table(LEGACY_SEGMENT_TABLE)
+                .map_err(|e| self.db_error("Failed to open legacy segment table", e))?;
+            let mut segments = txn
+                .open_table(self.segment_table())
+                .map_err(|e| self.db_error("Failed to open segment table", e))?;
+            moved += self.move_rows(&legacy_segments, &mut segments)?;
+
+            if self.config().use_meta {
+                let legacy_meta = txn
+                    .open_table(LEGACY_META_TABLE)
+                    .map_err(|e| self.db_error("Failed to open legacy meta table", e))?;
+                let mut meta = txn
+                    .open_table(self.meta_table())
+                    .map_err(|e| self.db_error("Failed to open meta table", e))?;
+                moved += self.move_rows(&legacy_meta, &mut meta)?;
+            }
+
+            if self.config().use_tombstones {
+                let mut tombstones = txn
+                    .open_table(self.tombstone_table())
+                    .map_err(|e| self.db_error("Failed to open tombstone table", e))?;
+                moved += self.move_rows(&legacy_tombstones, &mut tombstones)?;
+            }
+        }
+
+        for definition in [
+            LEGACY_SEGMENT_TABLE,
+            LEGACY_META_TABLE,
+            LEGACY_TOMBSTONE_TABLE,
+        ] {
+            txn.delete_table(definition)
+                .map_err(|e| self.db_error("Failed to delete legacy table", e))?;
+        }
+        self.write_config_header(&txn)?;
+
+        txn.commit()
+            .map_err(|e| self.db_error("Failed to commit legacy storage move", e))?;
+
+        Ok(moved)
+    }
+
+    /// Fails unless the only format version 1 header, if any, is this table's
+    /// and matches its shard count and segment size.
+    fn check_legacy_headers(&self, txn: &WriteTransaction) -> Result<()> {
+        let table = txn
+            .open_table(CONFIG_TABLE)
+            .map_err(|e| self.db_error("Failed to open config table", e))?;
+        let entries = table
+            .iter()
+            .map_err(|e| self.db_error("Failed to read config headers", e))?;
+
+        for entry in entries {
+            let (name, header) =
+                entry.map_err(|e| self.db_error("Failed to read config header", e))?;
+            let header = ConfigHeader::decode(header.value())?;
+            if header.format_version >= PARTITION_FORMAT_VERSION {
+                continue;
+            }
+            if name.value() != self.name() {
+                return Err(PartitionError::ConfigMismatch(format!(
+                    "format version 1 data is shared with table '{}' and can't be adopted by '{}'",
+                    name.value(),
+                    self.name()
+                ))
+                .into());
+            }
+
+            // Version 1 headers only recorded the shard count and segment size
+            let expected = ConfigHeader::from_config(self.config(), None);
+            let legacy = ConfigHeader {
+                shard_count: header.shard_count,
+                segment_max_bytes: header.segment_max_bytes,
+                ..expected.clone()
+            };
+            legacy.check(self.name(), &expected)?;
+        }
+        Ok(())
+    }
+
+    /// Copies every row of a legacy table into one of this table's tables.
+    fn move_rows(
+        &self,
+        from: &LegacyTable<'_>,
+        to: &mut redb::Table<'_, &'static [u8], &'static [u8]>,
+    ) -> Result<u64> {
+        let mut moved = 0;
+        let rows = from
+            .iter()
+            .map_err(|e| self.db_error("Failed to read legacy table", e))?;
+        for row in rows {
+            let (key, value) = row.map_err(|e| self.db_error("Failed to read legacy row", e))?;
+            to.insert(key.value(), value.value())
+                .map_err(|e| self.db_error("Failed to move legacy row", e))?;
+            moved += 1;
+        }
+        Ok(moved)
+    }
+}
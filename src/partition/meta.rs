@@ -0,0 +1,162 @@
+//! Meta table entries.
+//!
+//! When `use_meta` is enabled, every (base_key, shard) pair with segments has a
+//! row in the meta table recording the head segment and a member count
+//! maintained by the value layer. The row key is the shard key
+//! (\\[key_len\\]\\[key\\]\\[shard\\]), shared with tombstone rows.
+//!
+//! Value layers whose members are `u64` ids can also record the smallest and
+//! largest member of each segment in a bounds row keyed by the segment key.
+//! Segment keys are two bytes longer than the shard key they extend, so the
+//! two kinds of rows never collide.
+
+use crate::partition::PartitionError;
+use crate::Result;
+
+/// Encoded size of a meta entry: head segment (2 bytes) + member count (8 bytes).
+const META_ENTRY_LEN: usize = 2 + 8;
+
+/// Encoded size of segment bounds: min member (8 bytes) + max member (8 bytes).
+const BOUNDS_LEN: usize = 8 + 8;
+
+/// Per-shard bookkeeping stored in the meta table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MetaEntry {
+    /// Highest segment ID currently stored for the shard
+    pub head_segment: u16,
+    /// Number of members stored in the shard, as maintained by the value layer
+    pub member_count: u64,
+}
+
+impl MetaEntry {
+    /// Creates a meta entry.
+    pub fn new(head_segment: u16, member_count: u64) -> Self {
+        Self {
+            head_segment,
+            member_count,
+        }
+    }
+
+    /// Encodes the entry as \\[head_segment u16 BE\\]\\[member_count u64 BE\\].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(META_ENTRY_LEN);
+        encoded.extend_from_slice(&self.head_segment.to_be_bytes());
+        encoded.extend_from_slice(&self.member_count.to_be_bytes());
+        encoded
+    }
+
+    /// Decodes an entry produced by `encode`.
+    ///
+    /// # Arguments
+    /// * `data` - The encoded entry
+    ///
+    /// # Returns
+    /// The decoded entry or error if the length is wrong
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() != META_ENTRY_LEN {
+            return Err(PartitionError::MetaOperationFailed(format!(
+                "Invalid meta entry length: expected {}, got {}",
+                META_ENTRY_LEN,
+                data.len()
+            ))
+            .into());
+        }
+
+        let head_segment = u16::from_be_bytes([data[0], data[1]]);
+        let mut count_bytes = [0u8; 8];
+        count_bytes.copy_from_slice(&data[2..]);
+
+        Ok(Self {
+            head_segment,
+            member_count: u64::from_be_bytes(count_bytes),
+        })
+    }
+}
+
+/// Smallest and largest member stored in a segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentBounds {
+    /// Smallest member in the segment
+    pub min: u64,
+    /// Largest member in the segment
+    pub max: u64,
+}
+
+impl SegmentBounds {
+    /// Creates segment bounds.
+    pub fn new(min: u64, max: u64) -> Self {
+        Self { min, max }
+    }
+
+    /// Checks if the inclusive range `start..=end` overlaps the bounds.
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        start <= self.max && end >= self.min
+    }
+
+    /// Encodes the bounds as \\[min u64 BE\\]\\[max u64 BE\\].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(BOUNDS_LEN);
+        encoded.extend_from_slice(&self.min.to_be_bytes());
+        encoded.extend_from_slice(&self.max.to_be_bytes());
+        encoded
+    }
+
+    /// Decodes bounds produced by `encode`.
+    ///
+    /// # Arguments
+    /// * `data` - The encoded bounds
+    ///
+    /// # Returns
+    /// The decoded bounds or error if the length is wrong
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() != BOUNDS_LEN {
+            return Err(PartitionError::MetaOperationFailed(format!(
+                "Invalid segment bounds length: expected {}, got {}",
+                BOUNDS_LEN,
+                data.len()
+            ))
+            .into());
+        }
+
+        let mut min = [0u8; 8];
+        let mut max = [0u8; 8];
+        min.copy_from_slice(&data[..8]);
+        max.copy_from_slice(&data[8..]);
+
+        Ok(Self {
+            min: u64::from_be_bytes(min),
+            max: u64::from_be_bytes(max),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_entry_roundtrip() {
+        let entry = MetaEntry::new(3, 1_000_000);
+        let encoded = entry.encode();
+        assert_eq!(encoded.len(), META_ENTRY_LEN);
+        assert_eq!(MetaEntry::decode(&encoded).unwrap(), entry);
+    }
+
+    #[test]
+    fn test_meta_entry_invalid_length() {
+        assert!(MetaEntry::decode(&[0, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_segment_bounds() {
+        let bounds = SegmentBounds::new(10, 20);
+        assert_eq!(SegmentBounds::decode(&bounds.encode()).unwrap(), bounds);
+        assert!(SegmentBounds::decode(&[0; 4]).is_err());
+
+        assert!(bounds.overlaps(0, 10));
+        assert!(bounds.overlaps(15, 15));
+        assert!(bounds.overlaps(20, u64::MAX));
+        assert!(!bounds.overlaps(0, 9));
+        assert!(!bounds.overlaps(21, 30));
+    }
+}
@@ -4,11 +4,13 @@
 //! that is independent of value types. It can be used with any value type that
 //! implements the necessary traits.
 
+use crate::error::{DatabaseError, ErrorKind};
 use std::fmt;
 
 /// Errors specific to the partition layer.
 /// These are concerned with generic storage mechanics and are independent of value types.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum PartitionError {
     /// Invalid shard count configuration
     InvalidShardCount(u16),
@@ -20,18 +22,48 @@ pub enum PartitionError {
     MetaOperationFailed(String),
 
     /// Segment scan failed
-    SegmentScanFailed(String),
+    SegmentScanFailed(DatabaseError),
 
     /// Database operation failed
-    DatabaseError(String),
+    DatabaseError(DatabaseError),
 
     /// Encoding operation failed
     EncodingError(String),
+
+    /// No segment IDs left in a shard
+    SegmentIdExhausted(u16),
+
+    /// Persisted config header disagrees with the runtime configuration
+    ConfigMismatch(String),
+}
+
+impl PartitionError {
+    /// Classifies the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            PartitionError::InvalidShardCount(_) | PartitionError::InvalidSegmentSize(_) => {
+                ErrorKind::InvalidInput
+            }
+            PartitionError::MetaOperationFailed(_) | PartitionError::EncodingError(_) => {
+                ErrorKind::Corruption
+            }
+            PartitionError::SegmentScanFailed(err) | PartitionError::DatabaseError(err) => {
+                err.kind()
+            }
+            PartitionError::SegmentIdExhausted(_) => ErrorKind::Other,
+            PartitionError::ConfigMismatch(_) => ErrorKind::ConfigMismatch,
+        }
+    }
 }
 
 impl std::error::Error for PartitionError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        None
+        match self {
+            PartitionError::SegmentScanFailed(err) | PartitionError::DatabaseError(err) => {
+                Some(err)
+            }
+            _ => None,
+        }
     }
 }
 
@@ -51,26 +83,45 @@ impl fmt::Display for PartitionError {
             PartitionError::MetaOperationFailed(msg) => {
                 write!(f, "Meta table operation failed: {}", msg)
             }
-            PartitionError::SegmentScanFailed(msg) => {
-                write!(f, "Segment scan failed: {}", msg)
+            PartitionError::SegmentScanFailed(err) => {
+                write!(f, "Segment scan failed: {}", err)
             }
-            PartitionError::DatabaseError(msg) => {
-                write!(f, "Database error: {}", msg)
+            PartitionError::DatabaseError(err) => {
+                write!(f, "Database error: {}", err)
             }
             PartitionError::EncodingError(ref err) => {
                 write!(f, "Encoding error: {}", err)
             }
+            PartitionError::SegmentIdExhausted(shard) => {
+                write!(f, "No segment IDs left in shard {}", shard)
+            }
+            PartitionError::ConfigMismatch(msg) => {
+                write!(f, "Partition config mismatch: {}", msg)
+            }
         }
     }
 }
 
 pub mod config;
+pub mod header;
+mod legacy;
+pub mod meta;
+pub mod observer;
 pub mod scan;
 pub mod shard;
+pub mod stats;
 pub mod table;
 pub mod traits;
+pub mod transform;
 
 // Re-export main types for public API
 pub use config::PartitionConfig;
+pub use header::{ConfigHeader, PARTITION_FORMAT_VERSION};
+pub use meta::{MetaEntry, SegmentBounds};
+pub use observer::PartitionObserver;
 pub use scan::{enumerate_segments, find_head_segment, SegmentInfo, SegmentIterator};
+pub use stats::WriteStats;
 pub use table::{PartitionedRead, PartitionedTable, PartitionedWrite};
+pub use transform::SegmentTransform;
+#[cfg(feature = "zstd")]
+pub use transform::ZstdTransform;
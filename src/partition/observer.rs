@@ -0,0 +1,61 @@
+//! Observer hooks for partition events.
+//!
+//! Applications can attach a `PartitionObserver` to a `PartitionedTable` to
+//! emit metrics or logs for storage events without wrapping every call site.
+//! All methods have empty default implementations so observers only need to
+//! implement the events they care about.
+
+/// Callbacks invoked by the partition layer when notable storage events happen.
+///
+/// Base keys are passed in their `redb::Key` byte encoding so a single observer
+/// can be shared across tables with different key types. Callbacks run inline
+/// inside the write transaction and should return quickly.
+pub trait PartitionObserver: Send + Sync {
+    /// Called when a new segment is opened after an existing head segment.
+    ///
+    /// # Arguments
+    /// * `table` - Name of the partitioned table
+    /// * `base_key` - Encoded base key
+    /// * `shard` - The shard that rolled
+    /// * `segment` - ID of the newly opened segment
+    fn on_segment_roll(&self, _table: &str, _base_key: &[u8], _shard: u16, _segment: u16) {}
+
+    /// Called after the segments of a shard have been compacted.
+    ///
+    /// # Arguments
+    /// * `table` - Name of the partitioned table
+    /// * `base_key` - Encoded base key
+    /// * `shard` - The compacted shard
+    /// * `segments_before` - Number of segments before compaction
+    /// * `segments_after` - Number of segments after compaction
+    fn on_compaction(
+        &self,
+        _table: &str,
+        _base_key: &[u8],
+        _shard: u16,
+        _segments_before: usize,
+        _segments_after: usize,
+    ) {
+    }
+
+    /// Called when a single write is larger than `segment_max_bytes`.
+    ///
+    /// Splittable values are divided across several segments afterwards;
+    /// other values are stored in a single oversized segment.
+    ///
+    /// # Arguments
+    /// * `table` - Name of the partitioned table
+    /// * `base_key` - Encoded base key
+    /// * `shard` - The shard being written
+    /// * `size` - Size of the write in bytes
+    /// * `max_size` - The configured `segment_max_bytes`
+    fn on_oversized_write(
+        &self,
+        _table: &str,
+        _base_key: &[u8],
+        _shard: u16,
+        _size: usize,
+        _max_size: usize,
+    ) {
+    }
+}
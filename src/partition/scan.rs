@@ -4,26 +4,23 @@
 //! when meta table is disabled. It uses redb's range scanning capabilities
 //! to efficiently find segments for a given base key and shard.
 
+use crate::encoding::key::{base_key_of, segment_of, shard_of};
+use crate::encoding::prefix_range;
+use crate::error::DatabaseError;
+use crate::partition::table::encode_shard_key;
 use crate::partition::PartitionError;
 use crate::Result;
 use redb::ReadableTable;
 use std::marker::PhantomData;
+use std::ops::Bound;
+
+/// Borrowed bounds of a segment scan.
+type ScanBounds<'k> = (Bound<&'k [u8]>, Bound<&'k [u8]>);
 
 /// Builds a segment prefix key for scanning all segments of a given (base_key, shard) pair.
 /// Segment keys have the format: [key_len][base_key][shard][segment]
 fn build_segment_prefix(base_key: &[u8], shard: u16) -> Result<Vec<u8>> {
-    let mut prefix = Vec::with_capacity(4 + base_key.len() + 2);
-
-    // Add key length (4 bytes big-endian)
-    prefix.extend_from_slice(&(base_key.len() as u32).to_be_bytes());
-
-    // Add base key
-    prefix.extend_from_slice(base_key);
-
-    // Add shard (2 bytes big-endian)
-    prefix.extend_from_slice(&shard.to_be_bytes());
-
-    Ok(prefix)
+    encode_shard_key(base_key, shard)
 }
 
 /// Information about a discovered segment.
@@ -80,12 +77,13 @@ where
 {
     let (start_key, end_key) = build_segment_scan_range(base_key, shard)?;
     let range = table
-        .range(start_key.as_slice()..end_key.as_slice())
+        .range::<&[u8]>(scan_bounds(&start_key, &end_key))
         .map_err(|e| {
-            crate::error::Error::Partition(PartitionError::SegmentScanFailed(format!(
-                "Failed to create range iterator: {}",
-                e
-            )))
+            PartitionError::SegmentScanFailed(
+                DatabaseError::new("Failed to create range iterator", e)
+                    .key(base_key)
+                    .shard(shard),
+            )
         })?;
 
     Ok(SegmentIterator {
@@ -96,6 +94,55 @@ where
     })
 }
 
+/// Lists the segment IDs and keys for a given base key and shard.
+///
+/// Like `enumerate_segments`, but segment data is not copied out, for callers
+/// that decide per segment whether its data is needed.
+///
+/// # Arguments
+/// * `table` - The redb table to scan
+/// * `base_key` - The base key to search for
+/// * `shard` - The shard identifier
+///
+/// # Returns
+/// (segment_id, segment_key) pairs in ascending segment ID order
+pub(crate) fn enumerate_segment_keys<T>(
+    table: &T,
+    base_key: &[u8],
+    shard: u16,
+) -> Result<Vec<(u16, Vec<u8>)>>
+where
+    T: ReadableTable<&'static [u8], &'static [u8]>,
+{
+    let (start_key, end_key) = build_segment_scan_range(base_key, shard)?;
+    let range = table
+        .range::<&[u8]>(scan_bounds(&start_key, &end_key))
+        .map_err(|e| {
+            PartitionError::SegmentScanFailed(
+                DatabaseError::new("Failed to create range iterator", e)
+                    .key(base_key)
+                    .shard(shard),
+            )
+        })?;
+
+    let mut keys = Vec::new();
+    for entry in range {
+        let (key_guard, _) = entry.map_err(|e| {
+            PartitionError::SegmentScanFailed(
+                DatabaseError::new("Database error during iteration", e)
+                    .key(base_key)
+                    .shard(shard),
+            )
+        })?;
+        let key = key_guard.value();
+        if validate_key_match(key, base_key, shard) {
+            keys.push((extract_segment_id(key)?, key.to_vec()));
+        }
+    }
+
+    Ok(keys)
+}
+
 /// Finds the head (highest-numbered) segment for a base key and shard.
 ///
 /// This function scans all segments for the given (base_key, shard) pair
@@ -113,10 +160,10 @@ pub fn find_head_segment<T>(table: &T, base_key: &[u8], shard: u16) -> Result<Op
 where
     T: ReadableTable<&'static [u8], &'static [u8]>,
 {
-    let mut iter = enumerate_segments(table, base_key, shard)?;
+    let iter = enumerate_segments(table, base_key, shard)?;
     let mut head_segment = None;
 
-    while let Some(segment_result) = iter.next() {
+    for segment_result in iter {
         let segment_info = segment_result?;
         head_segment = Some(segment_info.segment_id);
     }
@@ -134,24 +181,19 @@ where
 /// * `shard` - The shard identifier
 ///
 /// # Returns
-/// Tuple of (start_key, end_key) for range scanning
-fn build_segment_scan_range(base_key: &[u8], shard: u16) -> Result<(Vec<u8>, Vec<u8>)> {
-    let start_key = build_segment_prefix(base_key, shard)?;
-
-    // For the end key, increment the last byte of the prefix to create an
-    // exclusive upper bound that includes all keys with this prefix
-    let mut end_key = start_key.clone();
-    if let Some(last_byte) = end_key.last_mut() {
-        *last_byte = last_byte.saturating_add(1);
-    } else {
-        return Err(crate::error::Error::Partition(
-            PartitionError::SegmentScanFailed(
-                "Prefix key is empty, cannot create range".to_string(),
-            ),
-        ));
-    }
+/// Tuple of (start_key, end_bound) for range scanning
+fn build_segment_scan_range(base_key: &[u8], shard: u16) -> Result<(Vec<u8>, Bound<Vec<u8>>)> {
+    Ok(prefix_range(&build_segment_prefix(base_key, shard)?))
+}
 
-    Ok((start_key, end_key))
+/// Borrows a scan range built by `build_segment_scan_range` as range bounds.
+fn scan_bounds<'k>(start_key: &'k [u8], end: &'k Bound<Vec<u8>>) -> ScanBounds<'k> {
+    let end = match end {
+        Bound::Included(end) => Bound::Included(end.as_slice()),
+        Bound::Excluded(end) => Bound::Excluded(end.as_slice()),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (Bound::Included(start_key), end)
 }
 
 /// Extracts the segment ID from an encoded segment key.
@@ -165,17 +207,7 @@ fn build_segment_scan_range(base_key: &[u8], shard: u16) -> Result<(Vec<u8>, Vec
 /// # Returns
 /// The extracted segment ID
 fn extract_segment_id(encoded_key: &[u8]) -> Result<u16> {
-    if encoded_key.len() < 6 {
-        // Minimum: 4-byte length + 1-byte base_key + 2-byte shard
-        return Err(crate::error::Error::Partition(
-            PartitionError::SegmentScanFailed(
-                "Encoded key too short to contain segment ID".to_string(),
-            ),
-        ));
-    }
-
-    let segment_bytes = &encoded_key[encoded_key.len() - 2..];
-    Ok(u16::from_be_bytes([segment_bytes[0], segment_bytes[1]]))
+    segment_of(encoded_key).map_err(|e| PartitionError::EncodingError(e.to_string()).into())
 }
 
 /// Validates that an encoded key matches the expected base key and shard.
@@ -192,34 +224,8 @@ fn extract_segment_id(encoded_key: &[u8]) -> Result<u16> {
 /// # Returns
 /// true if the key matches, false otherwise
 fn validate_key_match(encoded_key: &[u8], expected_base_key: &[u8], expected_shard: u16) -> bool {
-    if encoded_key.len() < 4 {
-        return false;
-    }
-
-    // Extract and validate base key
-    let key_len = u32::from_be_bytes([
-        encoded_key[0],
-        encoded_key[1],
-        encoded_key[2],
-        encoded_key[3],
-    ]) as usize;
-
-    if encoded_key.len() < 4 + key_len + 4 {
-        // Not enough bytes for base_key + shard + segment
-        return false;
-    }
-
-    let base_key_slice = &encoded_key[4..4 + key_len];
-    if base_key_slice != expected_base_key {
-        return false;
-    }
-
-    // Extract and validate shard
-    let shard_start = 4 + key_len;
-    let shard_bytes = &encoded_key[shard_start..shard_start + 2];
-    let shard = u16::from_be_bytes([shard_bytes[0], shard_bytes[1]]);
-
-    shard == expected_shard
+    base_key_of(encoded_key).is_ok_and(|base_key| base_key == expected_base_key)
+        && shard_of(encoded_key).is_ok_and(|shard| shard == expected_shard)
 }
 
 /// Iterator over segments found during prefix scanning.
@@ -259,10 +265,11 @@ impl<'a> Iterator for SegmentIterator<'a> {
                     }
                 }
                 Some(Err(e)) => {
-                    return Some(Err(PartitionError::SegmentScanFailed(format!(
-                        "Database error during iteration: {}",
-                        e
-                    ))
+                    return Some(Err(PartitionError::SegmentScanFailed(
+                        DatabaseError::new("Database error during iteration", e)
+                            .key(&self.base_key)
+                            .shard(self.shard),
+                    )
                     .into()));
                 }
                 None => return None,
@@ -291,9 +298,20 @@ mod tests {
         assert_eq!(start, expected_prefix);
 
         // End should be start + 1 on the last byte
+        let Bound::Excluded(end) = end else {
+            panic!("expected an exclusive end bound");
+        };
         assert_eq!(end.len(), start.len());
         assert_eq!(end[..end.len() - 1], start[..start.len() - 1]);
         assert_eq!(end[end.len() - 1], start[start.len() - 1] + 1);
+
+        // A shard ending in 0xFF carries into the previous byte
+        let (start, end) = build_segment_scan_range(base_key, 0x01FF).unwrap();
+        let Bound::Excluded(end) = end else {
+            panic!("expected an exclusive end bound");
+        };
+        assert_eq!(end.len(), start.len() - 1);
+        assert_eq!(end[end.len() - 1], 0x02);
     }
 
     #[test]
@@ -369,10 +387,10 @@ mod tests {
         let read_txn = db.begin_read().unwrap();
         let table = read_txn.open_table(TEST_TABLE).unwrap();
 
-        let mut iter = enumerate_segments(&table, base_key, shard).unwrap();
+        let iter = enumerate_segments(&table, base_key, shard).unwrap();
         let mut segments = Vec::new();
 
-        while let Some(segment_result) = iter.next() {
+        for segment_result in iter {
             segments.push(segment_result.unwrap());
         }
 
@@ -28,7 +28,7 @@ pub fn select_shard(base_key: &[u8], element_id: u64, shard_count: u16) -> Resul
 
     // Combine base_key and element_id for hashing
     let mut hasher = xxh3_64(base_key);
-    hasher = xxh3_64(&element_id.to_be_bytes()) ^ hasher;
+    hasher ^= xxh3_64(&element_id.to_be_bytes());
 
     // Convert hash to shard index
     let shard = (hasher % shard_count as u64) as u16;
@@ -0,0 +1,52 @@
+//! Write amplification accounting for partitioned writes.
+//!
+//! Every row a `PartitionedWrite` stores in the segment, meta or tombstone
+//! tables is counted as physical bytes, while the value layer reports the size
+//! of the change it was asked to make as logical bytes. Comparing the two over
+//! a real workload shows how much `segment_max_bytes` costs in rewrites.
+
+/// Bytes written versus bytes changed by a write handle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WriteStats {
+    /// Size of the changes requested by the caller
+    ///
+    /// For raw segment writes this is the payload size; the roaring layer
+    /// counts 8 bytes per member inserted or removed. Maintenance such as
+    /// compaction adds no logical bytes.
+    pub logical_bytes: u64,
+    /// Key and value bytes stored in the segment, meta and tombstone tables
+    pub bytes_written: u64,
+    /// Number of segment rows inserted or overwritten
+    pub segments_written: u64,
+    /// Number of segment rows deleted
+    pub segments_deleted: u64,
+}
+
+impl WriteStats {
+    /// Ratio of physical bytes written to logical bytes changed.
+    ///
+    /// # Returns
+    /// The write amplification, or None if nothing logical was changed
+    pub fn write_amplification(&self) -> Option<f64> {
+        if self.logical_bytes == 0 {
+            return None;
+        }
+        Some(self.bytes_written as f64 / self.logical_bytes as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_amplification() {
+        let stats = WriteStats {
+            logical_bytes: 16,
+            bytes_written: 64,
+            ..WriteStats::default()
+        };
+        assert_eq!(stats.write_amplification(), Some(4.0));
+        assert_eq!(WriteStats::default().write_amplification(), None);
+    }
+}
@@ -3,13 +3,28 @@
 //! Provides the core storage infrastructure for sharded and segmented data
 //! that can work with any value type.
 
+use crate::encoding::key::decode_segment_key;
+use crate::error::DatabaseError;
 use crate::partition::config::PartitionConfig;
-use crate::partition::scan::{enumerate_segments, find_head_segment, SegmentInfo};
+use crate::partition::header::{ConfigHeader, PARTITION_FORMAT_VERSION};
+use crate::partition::legacy;
+use crate::partition::meta::{MetaEntry, SegmentBounds};
+use crate::partition::observer::PartitionObserver;
+use crate::partition::scan::{
+    enumerate_segment_keys, enumerate_segments, find_head_segment, SegmentInfo,
+};
 use crate::partition::shard::select_shard;
+use crate::partition::stats::WriteStats;
+use crate::partition::transform::SegmentTransform;
 use crate::partition::PartitionError;
 use crate::Result;
-use redb::{Database, ReadTransaction, ReadableTable, TableDefinition, WriteTransaction};
+use redb::{
+    Database, Key, ReadTransaction, ReadableTable, Table, TableDefinition, WriteTransaction,
+};
+use std::borrow::{Borrow, Cow};
+use std::cell::{RefCell, RefMut};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 /// Encodes a segment key with the format: \\[key_len\\]\\[key\\]\\[shard\\]\\[segment\\]
 pub fn encode_segment_key(key: &[u8], shard: u16, segment: u16) -> Result<Vec<u8>> {
@@ -30,18 +45,46 @@ pub fn encode_segment_key(key: &[u8], shard: u16, segment: u16) -> Result<Vec<u8
     Ok(encoded_key)
 }
 
+/// Encodes a shard key with the format: \\[key_len\\]\\[key\\]\\[shard\\]
+///
+/// This is the common prefix of every segment key for a (key, shard) pair and is
+/// used directly as the key for per-shard rows such as tombstones.
+pub fn encode_shard_key(key: &[u8], shard: u16) -> Result<Vec<u8>> {
+    let mut encoded_key = Vec::with_capacity(4 + key.len() + 2);
+
+    // Add key length (4 bytes big-endian)
+    encoded_key.extend_from_slice(&(key.len() as u32).to_be_bytes());
+
+    // Add base key
+    encoded_key.extend_from_slice(key);
+
+    // Add shard (2 bytes big-endian)
+    encoded_key.extend_from_slice(&shard.to_be_bytes());
+
+    Ok(encoded_key)
+}
+
 // Type aliases for complex return types
 type SegmentDataMap = HashMap<u16, Vec<(SegmentInfo, Option<Vec<u8>>)>>;
 type SegmentSimpleMap = HashMap<u16, Vec<(u16, Vec<u8>)>>;
 type SegmentResult = Option<(SegmentInfo, Vec<u8>)>;
 
-/// Table definition for segment data storage
-pub const SEGMENT_TABLE: TableDefinition<&'static [u8], &'static [u8]> =
-    TableDefinition::new("redb_extras_segments");
+/// Table definition for persisted config headers (one row per table name)
+pub const CONFIG_TABLE: TableDefinition<&'static str, &'static [u8]> =
+    TableDefinition::new("redb_extras_configs");
+
+/// Table definition for migration progress (last migrated base key per table name)
+pub const MIGRATION_TABLE: TableDefinition<&'static str, &'static [u8]> =
+    TableDefinition::new("redb_extras_migrations");
+
+/// Prefix of the per-table segment storage (segment data)
+const SEGMENT_TABLE_PREFIX: &str = "redb_extras_segments";
 
-/// Table definition for meta data storage (head segment tracking)
-pub const META_TABLE: TableDefinition<&'static [u8], &'static [u8]> =
-    TableDefinition::new("redb_extras_meta");
+/// Prefix of the per-table meta storage (head segment and member count tracking)
+const META_TABLE_PREFIX: &str = "redb_extras_meta";
+
+/// Prefix of the per-table tombstone storage (pending removals per key and shard)
+const TOMBSTONE_TABLE_PREFIX: &str = "redb_extras_tombstones";
 
 /// Generic partitioned table that stores values in sharded segments.
 ///
@@ -50,16 +93,35 @@ pub const META_TABLE: TableDefinition<&'static [u8], &'static [u8]> =
 /// - Sharding writes across multiple partitions
 /// - Segmenting large values to control write amplification
 /// - Optional meta table for O(1) head segment discovery
+/// - Optional tombstone rows that record removals without rewriting segments
+///
+/// The `K` parameter is the base key type. Base keys are serialized through
+/// their `redb::Key` encoding and then embedded in the length-prefixed segment
+/// key format, so any redb key type (`&[u8]`, `&str`, `u64`, tuples, ...) can be
+/// used without hand-serialization.
 ///
 /// The `V` parameter represents the value handler type that knows how to
 /// encode/decode and manipulate specific value types.
-pub struct PartitionedTable<V> {
+///
+/// An optional `PartitionObserver` can be attached with `with_observer` to be
+/// notified of segment rolls, compactions and oversized writes, and an optional
+/// `SegmentTransform` with `with_transform` to compress or encrypt payloads.
+///
+/// Segments, meta entries and tombstones live in redb tables derived from the
+/// table name, so partitioned tables sharing a database never see each
+/// other's rows.
+pub struct PartitionedTable<K: Key + 'static, V> {
     name: &'static str,
+    segment_table: String,
+    meta_table: String,
+    tombstone_table: String,
     config: PartitionConfig,
-    _phantom: std::marker::PhantomData<V>,
+    observer: Option<Arc<dyn PartitionObserver>>,
+    transform: Option<Arc<dyn SegmentTransform>>,
+    _phantom: std::marker::PhantomData<(K, V)>,
 }
 
-impl<V> PartitionedTable<V> {
+impl<K: Key + 'static, V> PartitionedTable<K, V> {
     /// Creates a new partitioned table with the given configuration.
     ///
     /// # Arguments
@@ -71,41 +133,182 @@ impl<V> PartitionedTable<V> {
     pub fn new(name: &'static str, config: PartitionConfig) -> Self {
         Self {
             name,
+            segment_table: format!("{}_{}", SEGMENT_TABLE_PREFIX, name),
+            meta_table: format!("{}_{}", META_TABLE_PREFIX, name),
+            tombstone_table: format!("{}_{}", TOMBSTONE_TABLE_PREFIX, name),
+            config,
+            observer: None,
+            transform: None,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    /// Attaches an observer that is notified of partition events.
+    ///
+    /// # Arguments
+    /// * `observer` - The observer to invoke on segment rolls, compactions and oversized writes
+    ///
+    /// # Returns
+    /// The table with the observer attached
+    pub fn with_observer(mut self, observer: Arc<dyn PartitionObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Attaches a transform applied to every segment and tombstone payload.
+    ///
+    /// `segment_max_bytes` is checked against payloads before they are
+    /// transformed, so segments are split the same way with or without it.
+    ///
+    /// # Arguments
+    /// * `transform` - The transform to encode payloads on write and decode them on read
+    ///
+    /// # Returns
+    /// The table with the transform attached
+    pub fn with_transform(mut self, transform: Arc<dyn SegmentTransform>) -> Self {
+        self.transform = Some(transform);
+        self
+    }
+
+    /// Creates a table with the same name, observer and transform but a different configuration.
+    pub(crate) fn with_config(&self, config: PartitionConfig) -> Self {
+        Self {
+            name: self.name,
+            segment_table: self.segment_table.clone(),
+            meta_table: self.meta_table.clone(),
+            tombstone_table: self.tombstone_table.clone(),
             config,
+            observer: self.observer.clone(),
+            transform: self.transform.clone(),
             _phantom: std::marker::PhantomData,
         }
     }
 
+    /// Gets the attached observer, if any.
+    pub fn observer(&self) -> Option<&dyn PartitionObserver> {
+        self.observer.as_deref()
+    }
+
+    /// Gets the attached segment transform, if any.
+    pub fn transform(&self) -> Option<&dyn SegmentTransform> {
+        self.transform.as_deref()
+    }
+
+    /// Applies the segment transform to a payload about to be stored.
+    fn encode_payload<'d>(&self, data: &'d [u8]) -> Result<Cow<'d, [u8]>> {
+        match &self.transform {
+            Some(transform) => Ok(Cow::Owned(transform.encode(data)?)),
+            None => Ok(Cow::Borrowed(data)),
+        }
+    }
+
+    /// Reverses the segment transform on a stored payload.
+    fn decode_payload(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match &self.transform {
+            Some(transform) => transform.decode(data),
+            None => Ok(data.to_vec()),
+        }
+    }
+
+    /// Decodes the payload carried by an enumerated segment.
+    fn decode_segment(&self, mut segment: SegmentInfo) -> Result<SegmentInfo> {
+        if let Some(data) = &segment.segment_data {
+            segment.segment_data = Some(self.decode_payload(data)?);
+        }
+        Ok(segment)
+    }
+
     /// Ensures required tables exist in the database.
     ///
-    /// This method creates the segment table and optionally the meta table
-    /// if they don't already exist.
+    /// This method creates the segment table and optionally the meta and
+    /// tombstone tables if they don't already exist.
+    ///
+    /// The first call for a table name records its shard count, segment size,
+    /// tombstone setting, segment transform and storage format version in a
+    /// header row. Later calls compare the header against this table's
+    /// configuration, so opening existing data with a config that would route
+    /// reads to the wrong shards or misread payloads fails early. Use
+    /// `migrate` to change the segment size or tombstone setting of existing data.
+    ///
+    /// Data written in format version 1 is also rejected rather than ignored;
+    /// `adopt_legacy_storage` moves it into this table's storage.
     ///
     /// # Arguments
     /// * `db` - The database instance
     ///
     /// # Returns
-    /// Ok on success, error on failure
+    /// Ok on success, `PartitionError::ConfigMismatch` if the stored header
+    /// disagrees with the configuration, or another error on failure
     pub fn ensure_table_exists(&self, db: &Database) -> Result<()> {
-        let txn = db
-            .begin_write()
-            .map_err(|e| PartitionError::DatabaseError(format!("Failed to begin write: {}", e)))?;
+        let txn = db.begin_write()?;
+
+        self.check_config_header(&txn)?;
 
         {
-            let _segment_table = txn.open_table(SEGMENT_TABLE).map_err(|e| {
-                PartitionError::DatabaseError(format!("Failed to open segment table: {}", e))
-            })?;
+            let _segment_table = txn
+                .open_table(self.segment_table())
+                .map_err(|e| self.db_error("Failed to open segment table", e))?;
 
             if self.config.use_meta {
-                let _meta_table = txn.open_table(META_TABLE).map_err(|e| {
-                    PartitionError::DatabaseError(format!("Failed to open meta table: {}", e))
-                })?;
+                let _meta_table = txn
+                    .open_table(self.meta_table())
+                    .map_err(|e| self.db_error("Failed to open meta table", e))?;
+            }
+
+            if self.config.use_tombstones {
+                let _tombstone_table = txn
+                    .open_table(self.tombstone_table())
+                    .map_err(|e| self.db_error("Failed to open tombstone table", e))?;
             }
         }
 
-        txn.commit().map_err(|e| {
-            PartitionError::DatabaseError(format!("Failed to commit table creation: {}", e))
-        })?;
+        txn.commit()
+            .map_err(|e| self.db_error("Failed to commit table creation", e))?;
+
+        Ok(())
+    }
+
+    /// Validates the persisted config header, recording it if there is none yet.
+    fn check_config_header(&self, txn: &WriteTransaction) -> Result<()> {
+        let expected = ConfigHeader::from_config(&self.config, self.transform());
+        let stored = {
+            let table = txn
+                .open_table(CONFIG_TABLE)
+                .map_err(|e| self.db_error("Failed to open config table", e))?;
+            let stored = table
+                .get(self.name)
+                .map_err(|e| self.db_error("Failed to read config header", e))?;
+            stored
+                .map(|guard| ConfigHeader::decode(guard.value()))
+                .transpose()?
+        };
+
+        match stored {
+            Some(stored) if stored.format_version < PARTITION_FORMAT_VERSION => {
+                Err(legacy::legacy_error(self.name).into())
+            }
+            Some(stored) => stored.check(self.name, &expected),
+            None if legacy::legacy_row_count(txn)? > 0 => {
+                Err(legacy::legacy_error(self.name).into())
+            }
+            None => self.write_config_header(txn),
+        }
+    }
+
+    /// Records this table's configuration as its persisted header.
+    pub(crate) fn write_config_header(&self, txn: &WriteTransaction) -> Result<()> {
+        let mut table = txn
+            .open_table(CONFIG_TABLE)
+            .map_err(|e| self.db_error("Failed to open config table", e))?;
+
+        table
+            .insert(
+                self.name,
+                ConfigHeader::from_config(&self.config, self.transform())
+                    .encode()
+                    .as_slice(),
+            )
+            .map_err(|e| self.db_error("Failed to write config header", e))?;
 
         Ok(())
     }
@@ -115,33 +318,145 @@ impl<V> PartitionedTable<V> {
         self.name
     }
 
+    /// Gets the definition of the table holding this table's segments.
+    pub fn segment_table(&self) -> TableDefinition<'_, &'static [u8], &'static [u8]> {
+        TableDefinition::new(&self.segment_table)
+    }
+
+    /// Gets the definition of the table holding this table's meta entries and segment bounds.
+    pub fn meta_table(&self) -> TableDefinition<'_, &'static [u8], &'static [u8]> {
+        TableDefinition::new(&self.meta_table)
+    }
+
+    /// Gets the definition of the table holding this table's tombstones.
+    pub fn tombstone_table(&self) -> TableDefinition<'_, &'static [u8], &'static [u8]> {
+        TableDefinition::new(&self.tombstone_table)
+    }
+
+    /// Wraps a redb error with this table's name.
+    pub(crate) fn db_error(&self, operation: &str, err: impl Into<redb::Error>) -> PartitionError {
+        PartitionError::DatabaseError(DatabaseError::new(operation, err).table(self.name))
+    }
+
+    /// Wraps a redb error with this table's name and the key and shard it hit.
+    pub(crate) fn shard_error(
+        &self,
+        operation: &str,
+        key: &[u8],
+        shard: u16,
+        err: impl Into<redb::Error>,
+    ) -> PartitionError {
+        PartitionError::DatabaseError(
+            DatabaseError::new(operation, err)
+                .table(self.name)
+                .key(key)
+                .shard(shard),
+        )
+    }
+
+    /// Wraps a redb error with this table's name and the key, shard and
+    /// segment named by `segment_key`.
+    pub(crate) fn segment_error(
+        &self,
+        operation: &str,
+        segment_key: &[u8],
+        err: impl Into<redb::Error>,
+    ) -> PartitionError {
+        let mut error = DatabaseError::new(operation, err).table(self.name);
+        if let Ok((key, shard, segment)) = decode_segment_key(segment_key) {
+            error = error.key(key).shard(shard).segment(segment);
+        }
+        PartitionError::DatabaseError(error)
+    }
+
     /// Returns the configuration.
     pub fn config(&self) -> &PartitionConfig {
         &self.config
     }
 
     /// Selects the appropriate shard for a given base key and element.
-    pub fn select_shard(&self, key: &[u8], element_id: u64) -> Result<u16> {
-        Ok(select_shard(key, element_id, self.config.shard_count)?)
+    pub fn select_shard<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        element_id: u64,
+    ) -> Result<u16> {
+        select_shard(
+            &encode_base_key::<K>(key.borrow()),
+            element_id,
+            self.config.shard_count,
+        )
+    }
+}
+
+/// Serializes a typed base key into the bytes embedded in segment keys.
+pub(crate) fn encode_base_key<'k, K: Key + 'static>(key: &K::SelfType<'k>) -> Vec<u8> {
+    K::as_bytes(key).as_ref().to_vec()
+}
+
+/// Enumerates the segments of a shard that may hold members in `start..=end`.
+///
+/// Segments whose recorded bounds lie outside the range are skipped without
+/// reading their data. Segments without bounds, or all segments when meta is
+/// disabled, are always returned.
+fn enumerate_segments_in<K, V, S, M>(
+    table: &PartitionedTable<K, V>,
+    segments: &S,
+    meta: Option<&M>,
+    key: &[u8],
+    shard: u16,
+    start: u64,
+    end: u64,
+) -> Result<Vec<SegmentInfo>>
+where
+    K: Key + 'static,
+    S: ReadableTable<&'static [u8], &'static [u8]>,
+    M: ReadableTable<&'static [u8], &'static [u8]>,
+{
+    let mut result = Vec::new();
+
+    for (segment_id, segment_key) in enumerate_segment_keys(segments, key, shard)? {
+        if let Some(meta) = meta {
+            let bounds = meta.get(segment_key.as_slice()).map_err(|e| {
+                table.segment_error("Failed to read segment bounds", &segment_key, e)
+            })?;
+            if let Some(bounds) = bounds {
+                if !SegmentBounds::decode(bounds.value())?.overlaps(start, end) {
+                    continue;
+                }
+            }
+        }
+
+        let data = segments
+            .get(segment_key.as_slice())
+            .map_err(|e| table.segment_error("Failed to read segment", &segment_key, e))?;
+        let Some(data) = data else {
+            continue;
+        };
+
+        let mut segment = SegmentInfo::new(segment_id, segment_key);
+        segment.segment_data = Some(table.decode_payload(data.value())?);
+        result.push(segment);
     }
+
+    Ok(result)
 }
 
 /// Read operations for partitioned tables.
 ///
 /// Provides read-only access to partitioned data without the ability to modify.
-pub struct PartitionedRead<'a, V> {
-    table: &'a PartitionedTable<V>,
+pub struct PartitionedRead<'a, K: Key + 'static, V> {
+    table: &'a PartitionedTable<K, V>,
     txn: &'a ReadTransaction,
 }
 
-impl<'a, V> PartitionedRead<'a, V> {
+impl<'a, K: Key + 'static, V> PartitionedRead<'a, K, V> {
     /// Creates a new read handle.
-    pub fn new(table: &'a PartitionedTable<V>, txn: &'a ReadTransaction) -> Self {
+    pub fn new(table: &'a PartitionedTable<K, V>, txn: &'a ReadTransaction) -> Self {
         Self { table, txn }
     }
 
     /// Gets the table reference.
-    pub fn table(&self) -> &PartitionedTable<V> {
+    pub fn table(&self) -> &PartitionedTable<K, V> {
         self.table
     }
 
@@ -155,23 +470,28 @@ impl<'a, V> PartitionedRead<'a, V> {
     ///
     /// # Returns
     /// HashMap where key is shard ID and value is vector of (segment_info, segment_data) tuples
-    pub fn collect_all_segments(&self, key: &[u8]) -> Result<SegmentDataMap> {
+    pub fn collect_all_segments<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+    ) -> Result<SegmentDataMap> {
+        let key = encode_base_key::<K>(key.borrow());
         let mut result = HashMap::new();
 
         // Open the segment table
-        let table = self.txn.open_table(SEGMENT_TABLE).map_err(|e| {
-            PartitionError::DatabaseError(format!("Failed to open segment table: {}", e))
-        })?;
+        let table = self
+            .txn
+            .open_table(self.table.segment_table())
+            .map_err(|e| self.table.db_error("Failed to open segment table", e))?;
 
         // Iterate through all shards
         for shard in 0..self.table.config.shard_count {
             let mut shard_segments = Vec::new();
 
             // Enumerate segments for this shard
-            let mut segment_iter = enumerate_segments(&table, key, shard)?;
+            let segment_iter = enumerate_segments(&table, &key, shard)?;
 
-            while let Some(segment_result) = segment_iter.next() {
-                let segment_info = segment_result?;
+            for segment_result in segment_iter {
+                let segment_info = self.table.decode_segment(segment_result?)?;
                 shard_segments.push((segment_info.clone(), segment_info.segment_data.clone()));
             }
 
@@ -193,23 +513,28 @@ impl<'a, V> PartitionedRead<'a, V> {
     ///
     /// # Returns
     /// HashMap where key is shard ID and value is vector of (segment_id, segment_data) tuples
-    pub fn enumerate_all_segments(&self, key: &[u8]) -> Result<SegmentSimpleMap> {
+    pub fn enumerate_all_segments<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+    ) -> Result<SegmentSimpleMap> {
+        let key = encode_base_key::<K>(key.borrow());
         let mut result = HashMap::new();
 
         // Open the segment table
-        let table = self.txn.open_table(SEGMENT_TABLE).map_err(|e| {
-            PartitionError::DatabaseError(format!("Failed to open segment table: {}", e))
-        })?;
+        let table = self
+            .txn
+            .open_table(self.table.segment_table())
+            .map_err(|e| self.table.db_error("Failed to open segment table", e))?;
 
         // Iterate through all shards
         for shard in 0..self.table.config.shard_count {
             let mut shard_segments = Vec::new();
 
             // Enumerate segments for this shard
-            let mut segment_iter = enumerate_segments(&table, key, shard)?;
+            let segment_iter = enumerate_segments(&table, &key, shard)?;
 
-            while let Some(segment_result) = segment_iter.next() {
-                let segment_info = segment_result?;
+            for segment_result in segment_iter {
+                let segment_info = self.table.decode_segment(segment_result?)?;
                 if let Some(data) = segment_info.segment_data {
                     shard_segments.push((segment_info.segment_id, data));
                 }
@@ -223,6 +548,125 @@ impl<'a, V> PartitionedRead<'a, V> {
         Ok(result)
     }
 
+    /// Enumerates the segments of a single shard for a given base key.
+    ///
+    /// # Arguments
+    /// * `key` - The key to search for
+    /// * `shard` - The shard ID
+    ///
+    /// # Returns
+    /// Segments in ascending segment ID order, including their data
+    pub fn enumerate_shard_segments<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        shard: u16,
+    ) -> Result<Vec<SegmentInfo>> {
+        self.enumerate_shard_segments_raw(&encode_base_key::<K>(key.borrow()), shard)
+    }
+
+    /// Enumerates the segments of a single shard for an already encoded base key.
+    pub(crate) fn enumerate_shard_segments_raw(
+        &self,
+        key: &[u8],
+        shard: u16,
+    ) -> Result<Vec<SegmentInfo>> {
+        let table = self
+            .txn
+            .open_table(self.table.segment_table())
+            .map_err(|e| self.table.db_error("Failed to open segment table", e))?;
+
+        enumerate_segments(&table, key, shard)?
+            .map(|segment| self.table.decode_segment(segment?))
+            .collect()
+    }
+
+    /// Enumerates the segments of a shard that may hold members in `start..=end`.
+    ///
+    /// Uses the per-segment bounds in the meta table to skip segments entirely
+    /// outside the range.
+    pub(crate) fn enumerate_segments_in_raw(
+        &self,
+        key: &[u8],
+        shard: u16,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<SegmentInfo>> {
+        let segments = self
+            .txn
+            .open_table(self.table.segment_table())
+            .map_err(|e| self.table.db_error("Failed to open segment table", e))?;
+
+        let meta = if self.table.config.use_meta {
+            Some(
+                self.txn
+                    .open_table(self.table.meta_table())
+                    .map_err(|e| self.table.db_error("Failed to open meta table", e))?,
+            )
+        } else {
+            None
+        };
+
+        enumerate_segments_in(self.table, &segments, meta.as_ref(), key, shard, start, end)
+    }
+
+    /// Reads the tombstone payload recorded for a (key, shard) pair.
+    ///
+    /// # Arguments
+    /// * `key` - The base key
+    /// * `shard` - The shard ID
+    ///
+    /// # Returns
+    /// The tombstone data, or None if tombstones are disabled or none exist
+    pub fn read_tombstone<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        shard: u16,
+    ) -> Result<Option<Vec<u8>>> {
+        self.read_tombstone_raw(&encode_base_key::<K>(key.borrow()), shard)
+    }
+
+    /// Reads the tombstone payload for an already encoded base key.
+    pub(crate) fn read_tombstone_raw(&self, key: &[u8], shard: u16) -> Result<Option<Vec<u8>>> {
+        if !self.table.config.use_tombstones {
+            return Ok(None);
+        }
+
+        let tombstone_key = encode_shard_key(key, shard)?;
+        let table = self
+            .txn
+            .open_table(self.table.tombstone_table())
+            .map_err(|e| self.table.db_error("Failed to open tombstone table", e))?;
+
+        let data = table.get(tombstone_key.as_slice()).map_err(|e| {
+            self.table
+                .shard_error("Failed to read tombstone", key, shard, e)
+        })?;
+
+        data.map(|guard| self.table.decode_payload(guard.value()))
+            .transpose()
+    }
+
+    /// Reads the meta entry for an already encoded base key.
+    pub(crate) fn read_meta_raw(&self, key: &[u8], shard: u16) -> Result<Option<MetaEntry>> {
+        if !self.table.config.use_meta {
+            return Ok(None);
+        }
+
+        let meta_key = encode_shard_key(key, shard)?;
+        let table = self
+            .txn
+            .open_table(self.table.meta_table())
+            .map_err(|e| self.table.db_error("Failed to open meta table", e))?;
+
+        let data = table.get(meta_key.as_slice()).map_err(|e| {
+            self.table
+                .shard_error("Failed to read meta entry", key, shard, e)
+        })?;
+
+        data.map(|guard| MetaEntry::decode(guard.value()))
+            .transpose()
+    }
+
     /// Reads data for a specific segment.
     ///
     /// If segment_info already contains data, it's returned directly.
@@ -240,37 +684,663 @@ impl<'a, V> PartitionedRead<'a, V> {
         }
 
         // Otherwise, read from the database
-        let table = self.txn.open_table(SEGMENT_TABLE).map_err(|e| {
-            PartitionError::DatabaseError(format!("Failed to open segment table: {}", e))
-        })?;
+        let table = self
+            .txn
+            .open_table(self.table.segment_table())
+            .map_err(|e| self.table.db_error("Failed to open segment table", e))?;
 
         match table.get(&*segment_info.segment_key) {
             Ok(Some(value_guard)) => {
-                let data = value_guard.value().to_vec();
+                let data = self.table.decode_payload(value_guard.value())?;
                 let mut info_with_data = segment_info.clone();
                 info_with_data.segment_data = Some(data.clone());
                 Ok(Some((info_with_data, data)))
             }
             Ok(None) => Ok(None),
-            Err(e) => {
-                Err(PartitionError::DatabaseError(format!("Failed to read segment: {}", e)).into())
+            Err(e) => Err(self
+                .table
+                .segment_error("Failed to read segment", &segment_info.segment_key, e)
+                .into()),
+        }
+    }
+}
+
+/// Segment, meta and tombstone tables opened together for write operations.
+///
+/// Held by `PartitionedWrite` for its whole lifetime so that operations that
+/// touch many segments (batches, compaction, removals) don't re-open tables.
+pub(crate) struct SegmentTables<'t, K: Key + 'static, V> {
+    table: &'t PartitionedTable<K, V>,
+    segments: Table<'t, &'static [u8], &'static [u8]>,
+    meta: Option<Table<'t, &'static [u8], &'static [u8]>>,
+    tombstones: Option<Table<'t, &'static [u8], &'static [u8]>>,
+    undo_log: Vec<UndoEntry>,
+    savepoint_depth: usize,
+    stats: WriteStats,
+}
+
+/// Which of the partition tables a row belongs to.
+#[derive(Debug, Clone, Copy)]
+enum TableKind {
+    Segments,
+    Meta,
+    Tombstones,
+}
+
+/// Previous state of a row modified while a savepoint is active.
+struct UndoEntry {
+    kind: TableKind,
+    key: Vec<u8>,
+    previous: Option<Vec<u8>>,
+}
+
+impl<'t, K: Key + 'static, V> SegmentTables<'t, K, V> {
+    /// Opens the segment table, and the meta and tombstone tables if enabled.
+    pub(crate) fn open(
+        table: &'t PartitionedTable<K, V>,
+        txn: &'t WriteTransaction,
+    ) -> Result<Self> {
+        let segments = txn
+            .open_table(table.segment_table())
+            .map_err(|e| table.db_error("Failed to open segment table", e))?;
+
+        let meta = if table.config.use_meta {
+            Some(
+                txn.open_table(table.meta_table())
+                    .map_err(|e| table.db_error("Failed to open meta table", e))?,
+            )
+        } else {
+            None
+        };
+
+        let tombstones = if table.config.use_tombstones {
+            Some(
+                txn.open_table(table.tombstone_table())
+                    .map_err(|e| table.db_error("Failed to open tombstone table", e))?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Self {
+            table,
+            segments,
+            meta,
+            tombstones,
+            undo_log: Vec::new(),
+            savepoint_depth: 0,
+            stats: WriteStats::default(),
+        })
+    }
+
+    /// Gets the partitioned table these tables belong to.
+    pub(crate) fn table(&self) -> &'t PartitionedTable<K, V> {
+        self.table
+    }
+
+    fn table_mut(
+        &mut self,
+        kind: TableKind,
+    ) -> Option<&mut Table<'t, &'static [u8], &'static [u8]>> {
+        match kind {
+            TableKind::Segments => Some(&mut self.segments),
+            TableKind::Meta => self.meta.as_mut(),
+            TableKind::Tombstones => self.tombstones.as_mut(),
+        }
+    }
+
+    /// Gets the write statistics accumulated so far.
+    pub(crate) fn stats(&self) -> WriteStats {
+        self.stats
+    }
+
+    /// Adds to the logical bytes changed, as reported by the value layer.
+    pub(crate) fn record_logical_bytes(&mut self, bytes: u64) {
+        self.stats.logical_bytes += bytes;
+    }
+
+    /// Inserts or removes (`None`) a row, recording its previous value when a
+    /// savepoint is active.
+    ///
+    /// # Returns
+    /// True if the row existed before
+    fn put(
+        &mut self,
+        kind: TableKind,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> std::result::Result<bool, redb::StorageError> {
+        let record = self.savepoint_depth > 0;
+        let Some(table) = self.table_mut(kind) else {
+            return Ok(false);
+        };
+
+        let previous = match value {
+            Some(value) => table
+                .insert(key, value)?
+                .map(|guard| guard.value().to_vec()),
+            None => table.remove(key)?.map(|guard| guard.value().to_vec()),
+        };
+        let existed = previous.is_some();
+
+        if let Some(value) = value {
+            self.stats.bytes_written += (key.len() + value.len()) as u64;
+        }
+        if matches!(kind, TableKind::Segments) {
+            match value {
+                Some(_) => self.stats.segments_written += 1,
+                None if existed => self.stats.segments_deleted += 1,
+                None => {}
+            }
+        }
+
+        if record {
+            self.undo_log.push(UndoEntry {
+                kind,
+                key: key.to_vec(),
+                previous,
+            });
+        }
+
+        Ok(existed)
+    }
+
+    /// Starts a savepoint and returns its position in the undo log.
+    pub(crate) fn begin_savepoint(&mut self) -> usize {
+        self.savepoint_depth += 1;
+        self.undo_log.len()
+    }
+
+    /// Keeps the changes made since a savepoint.
+    pub(crate) fn release_savepoint(&mut self) {
+        self.savepoint_depth -= 1;
+        if self.savepoint_depth == 0 {
+            self.undo_log.clear();
+        }
+    }
+
+    /// Reverts every change made since a savepoint, newest first.
+    ///
+    /// The savepoint ends even if restoring a row fails, so its undo entries
+    /// are dropped either way.
+    pub(crate) fn rollback_savepoint(&mut self, mark: usize) -> Result<()> {
+        self.savepoint_depth -= 1;
+        let result = self.undo_to(mark);
+        self.undo_log.truncate(mark);
+        result
+    }
+
+    /// Restores the rows recorded in the undo log after `mark`, newest first.
+    fn undo_to(&mut self, mark: usize) -> Result<()> {
+        while self.undo_log.len() > mark {
+            let Some(entry) = self.undo_log.pop() else {
+                break;
+            };
+            let Some(table) = self.table_mut(entry.kind) else {
+                continue;
+            };
+
+            let restored = match &entry.previous {
+                Some(previous) => table
+                    .insert(entry.key.as_slice(), previous.as_slice())
+                    .map(drop),
+                None => table.remove(entry.key.as_slice()).map(drop),
+            };
+            restored.map_err(|e| {
+                PartitionError::DatabaseError(
+                    DatabaseError::new(format!("Failed to roll back {:?} row", entry.kind), e)
+                        .table(self.table.name),
+                )
+            })?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn read_segment(&self, segment_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let data = self.segments.get(segment_key).map_err(|e| {
+            self.table
+                .segment_error("Failed to read segment", segment_key, e)
+        })?;
+
+        data.map(|guard| self.table.decode_payload(guard.value()))
+            .transpose()
+    }
+
+    /// Finds the head segment, using the meta table when enabled.
+    pub(crate) fn find_head_segment(&self, key: &[u8], shard: u16) -> Result<Option<u16>> {
+        match self.read_meta(key, shard)? {
+            Some(entry) => Ok(Some(entry.head_segment)),
+            None => self.scan_head_segment(key, shard),
+        }
+    }
+
+    /// Finds the head segment by scanning the segment table.
+    pub(crate) fn scan_head_segment(&self, key: &[u8], shard: u16) -> Result<Option<u16>> {
+        find_head_segment(&self.segments, key, shard)
+    }
+
+    pub(crate) fn enumerate_shard_segments(
+        &self,
+        key: &[u8],
+        shard: u16,
+    ) -> Result<Vec<SegmentInfo>> {
+        enumerate_segments(&self.segments, key, shard)?
+            .map(|segment| self.table.decode_segment(segment?))
+            .collect()
+    }
+
+    /// Enumerates the segments of a shard that may hold members in `start..=end`.
+    pub(crate) fn enumerate_segments_in(
+        &self,
+        key: &[u8],
+        shard: u16,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<SegmentInfo>> {
+        enumerate_segments_in(
+            self.table,
+            &self.segments,
+            self.meta.as_ref(),
+            key,
+            shard,
+            start,
+            end,
+        )
+    }
+
+    /// Writes a segment, advancing the meta head when the segment is new.
+    pub(crate) fn write_segment(&mut self, segment_key: &[u8], data: &[u8]) -> Result<()> {
+        let data = self.table.encode_payload(data)?;
+        self.put(TableKind::Segments, segment_key, Some(&data))
+            .map_err(|e| {
+                self.table
+                    .segment_error("Failed to write segment", segment_key, e)
+            })?;
+
+        if self.meta.is_some() {
+            // Bounds recorded for the previous contents no longer apply
+            self.remove_segment_bounds(segment_key)?;
+
+            let (key, shard, segment) = decode_segment_key(segment_key)?;
+            let entry = match self.read_meta(key, shard)? {
+                Some(entry) if entry.head_segment >= segment => return Ok(()),
+                Some(entry) => MetaEntry::new(segment, entry.member_count),
+                // No entry yet: older segments may predate the meta table
+                None => {
+                    let head = self.scan_head_segment(key, shard)?.unwrap_or(segment);
+                    MetaEntry::new(head.max(segment), 0)
+                }
+            };
+            self.write_meta(key, shard, &entry)?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes a segment, moving the meta head back when the head is removed.
+    pub(crate) fn delete_segment(&mut self, segment_key: &[u8]) -> Result<bool> {
+        let removed = self
+            .put(TableKind::Segments, segment_key, None)
+            .map_err(|e| {
+                self.table
+                    .segment_error("Failed to delete segment", segment_key, e)
+            })?;
+
+        if removed && self.meta.is_some() {
+            self.remove_segment_bounds(segment_key)?;
+
+            let (key, shard, segment) = decode_segment_key(segment_key)?;
+            if let Some(entry) = self.read_meta(key, shard)? {
+                if entry.head_segment == segment {
+                    match self.scan_head_segment(key, shard)? {
+                        Some(head) => {
+                            self.write_meta(key, shard, &MetaEntry::new(head, entry.member_count))?
+                        }
+                        None => self.remove_meta(key, shard)?,
+                    }
+                }
             }
         }
+
+        Ok(removed)
+    }
+
+    /// Reads the meta entry for a (key, shard) pair, if meta is enabled.
+    pub(crate) fn read_meta(&self, key: &[u8], shard: u16) -> Result<Option<MetaEntry>> {
+        let Some(meta) = &self.meta else {
+            return Ok(None);
+        };
+
+        let meta_key = encode_shard_key(key, shard)?;
+        let data = meta.get(meta_key.as_slice()).map_err(|e| {
+            self.table
+                .shard_error("Failed to read meta entry", key, shard, e)
+        })?;
+
+        data.map(|guard| MetaEntry::decode(guard.value()))
+            .transpose()
+    }
+
+    fn write_meta(&mut self, key: &[u8], shard: u16, entry: &MetaEntry) -> Result<()> {
+        let meta_key = encode_shard_key(key, shard)?;
+        self.put(TableKind::Meta, &meta_key, Some(&entry.encode()))
+            .map_err(|e| {
+                self.table
+                    .shard_error("Failed to write meta entry", key, shard, e)
+            })?;
+
+        Ok(())
+    }
+
+    fn remove_meta(&mut self, key: &[u8], shard: u16) -> Result<()> {
+        let meta_key = encode_shard_key(key, shard)?;
+        self.put(TableKind::Meta, &meta_key, None).map_err(|e| {
+            self.table
+                .shard_error("Failed to remove meta entry", key, shard, e)
+        })?;
+
+        Ok(())
+    }
+
+    /// Lists the keys of a shard's segments without reading their data.
+    pub(crate) fn segment_keys(&self, key: &[u8], shard: u16) -> Result<Vec<(u16, Vec<u8>)>> {
+        enumerate_segment_keys(&self.segments, key, shard)
+    }
+
+    /// Reads the member bounds recorded for a segment, if meta is enabled.
+    pub(crate) fn read_segment_bounds(&self, segment_key: &[u8]) -> Result<Option<SegmentBounds>> {
+        let Some(meta) = &self.meta else {
+            return Ok(None);
+        };
+
+        let data = meta.get(segment_key).map_err(|e| {
+            self.table
+                .segment_error("Failed to read segment bounds", segment_key, e)
+        })?;
+
+        data.map(|guard| SegmentBounds::decode(guard.value()))
+            .transpose()
+    }
+
+    /// Records the member bounds of a segment.
+    ///
+    /// Must be called after the segment is written, since writing a segment
+    /// clears its bounds. Does nothing when meta is disabled.
+    pub(crate) fn write_segment_bounds(
+        &mut self,
+        segment_key: &[u8],
+        bounds: &SegmentBounds,
+    ) -> Result<()> {
+        self.put(TableKind::Meta, segment_key, Some(&bounds.encode()))
+            .map_err(|e| {
+                self.table
+                    .segment_error("Failed to write segment bounds", segment_key, e)
+            })?;
+
+        Ok(())
+    }
+
+    fn remove_segment_bounds(&mut self, segment_key: &[u8]) -> Result<()> {
+        self.put(TableKind::Meta, segment_key, None).map_err(|e| {
+            self.table
+                .segment_error("Failed to remove segment bounds", segment_key, e)
+        })?;
+
+        Ok(())
+    }
+
+    /// Records the member count of a shard in its meta entry.
+    ///
+    /// Does nothing when meta is disabled or the shard has no segments.
+    pub(crate) fn set_member_count(&mut self, key: &[u8], shard: u16, count: u64) -> Result<()> {
+        if self.meta.is_none() {
+            return Ok(());
+        }
+
+        let head_segment = match self.read_meta(key, shard)? {
+            Some(entry) if entry.member_count == count => return Ok(()),
+            Some(entry) => entry.head_segment,
+            None => match self.scan_head_segment(key, shard)? {
+                Some(head) => head,
+                None => return Ok(()),
+            },
+        };
+
+        self.write_meta(key, shard, &MetaEntry::new(head_segment, count))
+    }
+
+    pub(crate) fn append_segments(
+        &mut self,
+        key: &[u8],
+        shard: u16,
+        chunks: &[Vec<u8>],
+    ) -> Result<Vec<u16>> {
+        let first_segment = match self.find_head_segment(key, shard)? {
+            Some(head) => head as usize + 1,
+            None => 0,
+        };
+
+        let max_bytes = self.table.config.segment_max_bytes;
+        let mut segment_ids = Vec::with_capacity(chunks.len());
+        for (offset, chunk) in chunks.iter().enumerate() {
+            let segment_id = u16::try_from(first_segment + offset)
+                .map_err(|_| PartitionError::SegmentIdExhausted(shard))?;
+            let segment_key = encode_segment_key(key, shard, segment_id)?;
+            self.write_segment(&segment_key, chunk)?;
+            segment_ids.push(segment_id);
+
+            if let Some(observer) = self.table.observer() {
+                if chunk.len() > max_bytes {
+                    observer.on_oversized_write(
+                        self.table.name,
+                        key,
+                        shard,
+                        chunk.len(),
+                        max_bytes,
+                    );
+                }
+                if segment_id > 0 {
+                    observer.on_segment_roll(self.table.name, key, shard, segment_id);
+                }
+            }
+        }
+
+        Ok(segment_ids)
+    }
+
+    pub(crate) fn update_head_segment(
+        &mut self,
+        key: &[u8],
+        shard: u16,
+        data: &[u8],
+    ) -> Result<(bool, u16)> {
+        let max_bytes = self.table.config.segment_max_bytes;
+
+        // Find current head segment
+        let head_segment = self.find_head_segment(key, shard)?;
+
+        if data.len() > max_bytes {
+            if let Some(observer) = self.table.observer() {
+                observer.on_oversized_write(self.table.name, key, shard, data.len(), max_bytes);
+            }
+        }
+
+        match head_segment {
+            Some(segment_id) => {
+                // Check if data fits in current segment
+                if data.len() <= max_bytes {
+                    // Update existing segment
+                    let segment_key = encode_segment_key(key, shard, segment_id)?;
+                    self.write_segment(&segment_key, data)?;
+                    Ok((false, segment_id))
+                } else {
+                    // Roll to new segment
+                    let new_segment_id = segment_id
+                        .checked_add(1)
+                        .ok_or(PartitionError::SegmentIdExhausted(shard))?;
+                    let new_segment_key = encode_segment_key(key, shard, new_segment_id)?;
+                    self.write_segment(&new_segment_key, data)?;
+                    if let Some(observer) = self.table.observer() {
+                        observer.on_segment_roll(self.table.name, key, shard, new_segment_id);
+                    }
+                    Ok((true, new_segment_id))
+                }
+            }
+            None => {
+                // No segments exist, create first one
+                let segment_key = encode_segment_key(key, shard, 0)?;
+                self.write_segment(&segment_key, data)?;
+                Ok((true, 0))
+            }
+        }
+    }
+
+    pub(crate) fn read_tombstone(&self, key: &[u8], shard: u16) -> Result<Option<Vec<u8>>> {
+        let Some(tombstones) = &self.tombstones else {
+            return Ok(None);
+        };
+
+        let tombstone_key = encode_shard_key(key, shard)?;
+        let data = tombstones.get(tombstone_key.as_slice()).map_err(|e| {
+            self.table
+                .shard_error("Failed to read tombstone", key, shard, e)
+        })?;
+
+        data.map(|guard| self.table.decode_payload(guard.value()))
+            .transpose()
+    }
+
+    pub(crate) fn write_tombstone(&mut self, key: &[u8], shard: u16, data: &[u8]) -> Result<()> {
+        if self.tombstones.is_none() {
+            return Err(crate::Error::InvalidInput(
+                "Tombstones are disabled for this table".to_string(),
+            ));
+        }
+
+        let tombstone_key = encode_shard_key(key, shard)?;
+        if data.is_empty() {
+            self.put(TableKind::Tombstones, &tombstone_key, None)
+                .map_err(|e| {
+                    self.table
+                        .shard_error("Failed to remove tombstone", key, shard, e)
+                })?;
+        } else {
+            let data = self.table.encode_payload(data)?;
+            self.put(TableKind::Tombstones, &tombstone_key, Some(&data))
+                .map_err(|e| {
+                    self.table
+                        .shard_error("Failed to write tombstone", key, shard, e)
+                })?;
+        }
+
+        Ok(())
     }
 }
 
 /// Write operations for partitioned tables.
 ///
 /// Provides read-write access to partitioned data with the ability to modify values.
-pub struct PartitionedWrite<'a, V> {
-    table: &'a PartitionedTable<V>,
-    txn: &'a mut WriteTransaction,
+///
+/// The segment, meta and tombstone tables are opened on first use and kept
+/// open for the lifetime of the handle, so individual operations don't pay for
+/// re-opening them. The handle borrows the transaction exclusively; drop it, or
+/// call `finish` to also get its `WriteStats`, before committing.
+pub struct PartitionedWrite<'a, K: Key + 'static, V> {
+    table: &'a PartitionedTable<K, V>,
+    txn: &'a WriteTransaction,
+    tables: RefCell<Option<SegmentTables<'a, K, V>>>,
 }
 
-impl<'a, V> PartitionedWrite<'a, V> {
+impl<'a, K: Key + 'static, V> PartitionedWrite<'a, K, V> {
     /// Creates a new write handle.
-    pub fn new(table: &'a PartitionedTable<V>, txn: &'a mut WriteTransaction) -> Self {
-        Self { table, txn }
+    pub fn new(table: &'a PartitionedTable<K, V>, txn: &'a mut WriteTransaction) -> Self {
+        Self {
+            table,
+            txn,
+            tables: RefCell::new(None),
+        }
+    }
+
+    /// Runs writes as a unit, rolling them back if the closure fails.
+    ///
+    /// While the closure runs, the previous value of every segment, meta and
+    /// tombstone row it touches is recorded. If it returns an error those rows
+    /// are restored, so a failed multi-segment write never leaves a
+    /// half-written key behind, and the error is returned. A panic in the
+    /// closure rolls its writes back as it unwinds. Savepoints can be nested;
+    /// an inner rollback only reverts the inner closure's writes.
+    ///
+    /// redb's own savepoints can only be taken before any table is opened in
+    /// the transaction and restore the last committed state, so they can't be
+    /// used around individual partition writes; this provides the same
+    /// guarantee within the handle. Observer callbacks fired by rolled back
+    /// writes are not retracted.
+    ///
+    /// # Arguments
+    /// * `f` - The writes to perform
+    ///
+    /// # Returns
+    /// The closure's result, or its error after rolling back
+    pub fn with_savepoint<R, F>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Self) -> Result<R>,
+    {
+        let mark = self.tables()?.begin_savepoint();
+        let mut guard = SavepointGuard {
+            write: self,
+            mark,
+            active: true,
+        };
+        let result = f(self);
+
+        let mut tables = self.tables()?;
+        guard.active = false;
+        match result {
+            Ok(value) => {
+                tables.release_savepoint();
+                Ok(value)
+            }
+            Err(err) => {
+                tables.rollback_savepoint(mark)?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Gets the write statistics accumulated by this handle so far.
+    ///
+    /// # Returns
+    /// Bytes written versus logical bytes changed since the handle was created
+    pub fn stats(&self) -> WriteStats {
+        self.tables
+            .borrow()
+            .as_ref()
+            .map(SegmentTables::stats)
+            .unwrap_or_default()
+    }
+
+    /// Closes the handle's tables so the transaction can be committed.
+    ///
+    /// Equivalent to dropping the handle, but returns what it wrote. Writes
+    /// rolled back by `with_savepoint` remain counted, since they were staged.
+    ///
+    /// # Returns
+    /// The write statistics for everything done through this handle
+    pub fn finish(self) -> WriteStats {
+        self.stats()
+    }
+
+    /// Gets the tables held by this handle, opening them on first use.
+    pub(crate) fn tables(&self) -> Result<RefMut<'_, SegmentTables<'a, K, V>>> {
+        let mut slot = self.tables.borrow_mut();
+        if slot.is_none() {
+            *slot = Some(SegmentTables::open(self.table, self.txn)?);
+        }
+
+        Ok(RefMut::map(slot, |slot| {
+            slot.as_mut().expect("segment tables are opened above")
+        }))
     }
 
     /// Reads segment data for the given segment info.
@@ -290,31 +1360,17 @@ impl<'a, V> PartitionedWrite<'a, V> {
         }
 
         // Otherwise, read from the database
-        let table = self.txn.open_table(SEGMENT_TABLE).map_err(|e| {
-            PartitionError::DatabaseError(format!("Failed to open segment table: {}", e))
-        })?;
+        let data = self.tables()?.read_segment(&segment_info.segment_key)?;
 
-        let result = match table.get(&*segment_info.segment_key) {
-            Ok(Some(value_guard)) => {
-                let data = value_guard.value().to_vec();
-                let mut info_with_data = segment_info.clone();
-                info_with_data.segment_data = Some(data.clone());
-                Ok(Some((info_with_data, data)))
-            }
-            Ok(None) => Ok(None),
-            Err(e) => Err(PartitionError::DatabaseError(format!(
-                "Failed to read segment: {}",
-                e
-            ))),
-        };
-
-        // Drop table before returning result
-        drop(table);
-        Ok(result?)
+        Ok(data.map(|data| {
+            let mut info_with_data = segment_info.clone();
+            info_with_data.segment_data = Some(data.clone());
+            (info_with_data, data)
+        }))
     }
 
     /// Gets the table reference.
-    pub fn table(&self) -> &PartitionedTable<V> {
+    pub fn table(&self) -> &PartitionedTable<K, V> {
         self.table
     }
 
@@ -329,12 +1385,13 @@ impl<'a, V> PartitionedWrite<'a, V> {
     ///
     /// # Returns
     /// The head segment ID, or None if no segments exist
-    pub fn find_head_segment_scan(&self, key: &[u8], shard: u16) -> Result<Option<u16>> {
-        let table = self.txn.open_table(SEGMENT_TABLE).map_err(|e| {
-            PartitionError::DatabaseError(format!("Failed to open segment table: {}", e))
-        })?;
-
-        Ok(find_head_segment(&table, key, shard)?)
+    pub fn find_head_segment_scan<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        shard: u16,
+    ) -> Result<Option<u16>> {
+        self.tables()?
+            .scan_head_segment(&encode_base_key::<K>(key.borrow()), shard)
     }
 
     /// Writes data to a specific segment.
@@ -348,15 +1405,76 @@ impl<'a, V> PartitionedWrite<'a, V> {
     /// # Returns
     /// Ok on success, error on failure
     pub fn write_segment_data(&self, segment_key: &[u8], data: &[u8]) -> Result<()> {
-        let mut table = self.txn.open_table(SEGMENT_TABLE).map_err(|e| {
-            PartitionError::DatabaseError(format!("Failed to open segment table: {}", e))
-        })?;
+        let mut tables = self.tables()?;
+        tables.record_logical_bytes(data.len() as u64);
+        tables.write_segment(segment_key, data)
+    }
 
-        table.insert(segment_key, data).map_err(|e| {
-            PartitionError::DatabaseError(format!("Failed to write segment: {}", e))
-        })?;
+    /// Deletes a specific segment.
+    ///
+    /// # Arguments
+    /// * `segment_key` - The encoded segment key
+    ///
+    /// # Returns
+    /// True if the segment existed
+    pub fn delete_segment_data(&self, segment_key: &[u8]) -> Result<bool> {
+        self.tables()?.delete_segment(segment_key)
+    }
 
-        Ok(())
+    /// Enumerates the segments of a single shard for a given base key.
+    ///
+    /// # Arguments
+    /// * `key` - The key to search for
+    /// * `shard` - The shard ID
+    ///
+    /// # Returns
+    /// Segments in ascending segment ID order, including their data
+    pub fn enumerate_shard_segments<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        shard: u16,
+    ) -> Result<Vec<SegmentInfo>> {
+        self.tables()?
+            .enumerate_shard_segments(&encode_base_key::<K>(key.borrow()), shard)
+    }
+
+    /// Reads the tombstone payload recorded for a (key, shard) pair.
+    ///
+    /// # Arguments
+    /// * `key` - The base key
+    /// * `shard` - The shard ID
+    ///
+    /// # Returns
+    /// The tombstone data, or None if tombstones are disabled or none exist
+    pub fn read_tombstone<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        shard: u16,
+    ) -> Result<Option<Vec<u8>>> {
+        self.tables()?
+            .read_tombstone(&encode_base_key::<K>(key.borrow()), shard)
+    }
+
+    /// Writes the tombstone payload for a (key, shard) pair.
+    ///
+    /// An empty payload removes the tombstone row entirely.
+    ///
+    /// # Arguments
+    /// * `key` - The base key
+    /// * `shard` - The shard ID
+    /// * `data` - The tombstone data
+    ///
+    /// # Returns
+    /// Ok on success, error if tombstones are disabled or the write fails
+    pub fn write_tombstone<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        shard: u16,
+        data: &[u8],
+    ) -> Result<()> {
+        let mut tables = self.tables()?;
+        tables.record_logical_bytes(data.len() as u64);
+        tables.write_tombstone(&encode_base_key::<K>(key.borrow()), shard, data)
     }
 
     /// Creates a new segment with the given data.
@@ -371,21 +1489,48 @@ impl<'a, V> PartitionedWrite<'a, V> {
     ///
     /// # Returns
     /// Ok on success, error on failure
-    pub fn create_new_segment(
+    pub fn create_new_segment<'k>(
         &self,
-        key: &[u8],
+        key: impl Borrow<K::SelfType<'k>>,
         shard: u16,
         segment_id: u16,
         data: &[u8],
     ) -> Result<()> {
-        let segment_key = encode_segment_key(key, shard, segment_id)?;
+        let segment_key =
+            encode_segment_key(&encode_base_key::<K>(key.borrow()), shard, segment_id)?;
         self.write_segment_data(&segment_key, data)
     }
 
+    /// Appends data chunks as new segments after the current head segment.
+    ///
+    /// Used when a value has to be split across several segments to honor
+    /// `segment_max_bytes`. Chunks are written to consecutive segment IDs,
+    /// starting at 0 when no segments exist yet.
+    ///
+    /// # Arguments
+    /// * `key` - The base key
+    /// * `shard` - The shard ID
+    /// * `chunks` - The segment data for each new segment
+    ///
+    /// # Returns
+    /// IDs of the created segments, in chunk order
+    pub fn append_segments<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        shard: u16,
+        chunks: &[Vec<u8>],
+    ) -> Result<Vec<u16>> {
+        let mut tables = self.tables()?;
+        tables.record_logical_bytes(chunks.iter().map(|chunk| chunk.len() as u64).sum());
+        tables.append_segments(&encode_base_key::<K>(key.borrow()), shard, chunks)
+    }
+
     /// Updates the head segment with new data, rolling if necessary.
     ///
     /// This method checks if the new data fits in the current head segment.
-    /// If it doesn't fit, a new segment is created.
+    /// If it doesn't fit, a new segment is created holding the data as-is.
+    /// Values that can be divided (such as roaring bitmaps) should be split
+    /// by the caller and written with `append_segments` instead.
     ///
     /// # Arguments
     /// * `key` - The base key
@@ -396,31 +1541,38 @@ impl<'a, V> PartitionedWrite<'a, V> {
     /// Tuple of (was_rolled, new_segment_id) where:
     /// - was_rolled: true if a new segment was created
     /// - new_segment_id: ID of the segment that now contains the data
-    pub fn update_head_segment(&self, key: &[u8], shard: u16, data: &[u8]) -> Result<(bool, u16)> {
-        // Find current head segment
-        let head_segment = self.find_head_segment_scan(key, shard)?;
+    pub fn update_head_segment<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        shard: u16,
+        data: &[u8],
+    ) -> Result<(bool, u16)> {
+        let mut tables = self.tables()?;
+        tables.record_logical_bytes(data.len() as u64);
+        tables.update_head_segment(&encode_base_key::<K>(key.borrow()), shard, data)
+    }
+}
 
-        match head_segment {
-            Some(segment_id) => {
-                // Check if data fits in current segment
-                if data.len() <= self.table.config.segment_max_bytes {
-                    // Update existing segment
-                    let segment_key = encode_segment_key(key, shard, segment_id)?;
-                    self.write_segment_data(&segment_key, data)?;
-                    Ok((false, segment_id))
-                } else {
-                    // Roll to new segment
-                    let new_segment_id = segment_id + 1;
-                    let new_segment_key = encode_segment_key(key, shard, new_segment_id)?;
-                    self.write_segment_data(&new_segment_key, data)?;
-                    Ok((true, new_segment_id))
-                }
-            }
-            None => {
-                // No segments exist, create first one
-                let segment_key = encode_segment_key(key, shard, 0)?;
-                self.write_segment_data(&segment_key, data)?;
-                Ok((true, 0))
+/// Ends a `with_savepoint` savepoint that didn't finish normally.
+///
+/// If the closure panics, or `with_savepoint` returns before ending the
+/// savepoint, its writes are rolled back on drop so the savepoint depth and
+/// undo log stay consistent for the rest of the handle's life.
+struct SavepointGuard<'w, 'a, K: Key + 'static, V> {
+    write: &'w PartitionedWrite<'a, K, V>,
+    mark: usize,
+    active: bool,
+}
+
+impl<K: Key + 'static, V> Drop for SavepointGuard<'_, '_, K, V> {
+    fn drop(&mut self) {
+        if !self.active {
+            return;
+        }
+        // The tables may still be borrowed if the closure panicked mid-write
+        if let Ok(mut tables) = self.write.tables.try_borrow_mut() {
+            if let Some(tables) = tables.as_mut() {
+                let _ = tables.rollback_savepoint(self.mark);
             }
         }
     }
@@ -434,7 +1586,7 @@ mod tests {
     #[test]
     fn test_partitioned_table_creation() {
         let config = PartitionConfig::default();
-        let table: PartitionedTable<()> = PartitionedTable::new("test_table", config);
+        let table: PartitionedTable<&[u8], ()> = PartitionedTable::new("test_table", config);
 
         assert_eq!(table.name(), "test_table");
         assert_eq!(table.config().shard_count, 16);
@@ -444,9 +1596,9 @@ mod tests {
     #[test]
     fn test_shard_selection() {
         let config = PartitionConfig::new(8, 1024, true).unwrap();
-        let table: PartitionedTable<()> = PartitionedTable::new("test", config);
+        let table: PartitionedTable<&[u8], ()> = PartitionedTable::new("test", config);
 
-        let key = b"test_key";
+        let key = b"test_key".as_slice();
         let element_id = 12345;
 
         let shard = table.select_shard(key, element_id).unwrap();
@@ -456,4 +1608,155 @@ mod tests {
         let shard2 = table.select_shard(key, element_id).unwrap();
         assert_eq!(shard, shard2);
     }
+
+    #[test]
+    fn test_typed_base_keys() {
+        use redb::ReadableDatabase;
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap();
+        let db = Database::create(temp_file.path()).unwrap();
+        let config = PartitionConfig::new(4, 1024, false).unwrap();
+        let table: PartitionedTable<u64, ()> = PartitionedTable::new("typed", config);
+        table.ensure_table_exists(&db).unwrap();
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            let shard = table.select_shard(42u64, 7).unwrap();
+            writer
+                .update_head_segment(42u64, shard, b"payload")
+                .unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let reader = PartitionedRead::new(&table, &read_txn);
+        let segments = reader.enumerate_all_segments(42u64).unwrap();
+        let shard = table.select_shard(42u64, 7).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[&shard], vec![(0, b"payload".to_vec())]);
+
+        // Keys are encoded through redb's u64 encoding, so other keys stay separate
+        assert!(reader.enumerate_all_segments(43u64).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_write_handle_keeps_tables_open() {
+        use redb::ReadableTableMetadata;
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap();
+        let db = Database::create(temp_file.path()).unwrap();
+        let config = PartitionConfig::new(1, 8, true).unwrap();
+        let table: PartitionedTable<&str, ()> = PartitionedTable::new("held", config);
+        table.ensure_table_exists(&db).unwrap();
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            assert_eq!(
+                writer.update_head_segment("k", 0, b"one").unwrap(),
+                (true, 0)
+            );
+            assert_eq!(
+                writer.update_head_segment("k", 0, b"two").unwrap(),
+                (false, 0)
+            );
+            assert_eq!(
+                writer.update_head_segment("k", 0, b"oversized").unwrap(),
+                (true, 1)
+            );
+            assert_eq!(writer.find_head_segment_scan("k", 0).unwrap(), Some(1));
+        }
+
+        // Tables are released together with the handle
+        let segments = write_txn.open_table(table.segment_table()).unwrap();
+        assert_eq!(segments.len().unwrap(), 2);
+        drop(segments);
+        write_txn.commit().unwrap();
+    }
+
+    #[test]
+    fn test_with_savepoint_rolls_back_on_error() {
+        use redb::{ReadableDatabase, ReadableTableMetadata};
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap();
+        let db = Database::create(temp_file.path()).unwrap();
+        let config = PartitionConfig::new(1, 8, true).unwrap();
+        let table: PartitionedTable<&str, ()> = PartitionedTable::new("savepoint", config);
+        table.ensure_table_exists(&db).unwrap();
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.update_head_segment("a", 0, b"before").unwrap();
+
+            let result: Result<()> = writer.with_savepoint(|w| {
+                w.update_head_segment("a", 0, b"changed")?;
+                w.update_head_segment("a", 0, b"rolled over")?;
+                w.update_head_segment("b", 0, b"new")?;
+
+                // A successful inner savepoint is still undone by the outer one
+                w.with_savepoint(|w| w.update_head_segment("c", 0, b"inner"))?;
+                Err(crate::Error::InvalidInput("abort".to_string()))
+            });
+            assert!(matches!(result, Err(crate::Error::InvalidInput(_))));
+
+            let kept = writer
+                .with_savepoint(|w| w.update_head_segment("d", 0, b"kept"))
+                .unwrap();
+            assert_eq!(kept, (true, 0));
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let reader = PartitionedRead::new(&table, &read_txn);
+        let a = reader.enumerate_all_segments("a").unwrap();
+        assert_eq!(a[&0], vec![(0, b"before".to_vec())]);
+        assert!(reader.enumerate_all_segments("b").unwrap().is_empty());
+        assert!(reader.enumerate_all_segments("c").unwrap().is_empty());
+        assert_eq!(reader.enumerate_all_segments("d").unwrap().len(), 1);
+
+        // Meta rows were rolled back together with the segments
+        let meta = read_txn.open_table(table.meta_table()).unwrap();
+        assert_eq!(meta.len().unwrap(), 2);
+    }
+    #[test]
+    fn test_with_savepoint_rolls_back_on_panic() {
+        use redb::ReadableDatabase;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap();
+        let db = Database::create(temp_file.path()).unwrap();
+        let config = PartitionConfig::new(1, 64, true).unwrap();
+        let table: PartitionedTable<&str, ()> = PartitionedTable::new("savepoint", config);
+        table.ensure_table_exists(&db).unwrap();
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            let panicked = catch_unwind(AssertUnwindSafe(|| {
+                writer.with_savepoint(|w| -> Result<()> {
+                    w.update_head_segment("a", 0, b"lost")?;
+                    panic!("writer failed");
+                })
+            }));
+            assert!(panicked.is_err());
+
+            {
+                let tables = writer.tables().unwrap();
+                assert_eq!(tables.savepoint_depth, 0);
+                assert!(tables.undo_log.is_empty());
+            }
+
+            // Later writes outside a savepoint aren't recorded for undo
+            writer.update_head_segment("b", 0, b"kept").unwrap();
+            assert!(writer.tables().unwrap().undo_log.is_empty());
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let reader = PartitionedRead::new(&table, &read_txn);
+        assert!(reader.enumerate_all_segments("a").unwrap().is_empty());
+        assert_eq!(reader.enumerate_all_segments("b").unwrap().len(), 1);
+    }
 }
@@ -0,0 +1,235 @@
+//! Incremental compaction of partitioned roaring bitmaps.
+//!
+//! `compact` rewrites every shard of one key inside the caller's transaction,
+//! and compacting a whole table that way means one very large transaction.
+//! A compaction plan instead lists the individual key/shard pairs that need
+//! compaction, so maintenance can run one small transaction per unit and be
+//! interleaved with live traffic.
+
+use super::RoaringValue;
+use crate::encoding::key::decode_segment_key;
+use crate::error::DatabaseError;
+use crate::partition::table::{encode_segment_key, encode_shard_key};
+use crate::partition::{PartitionError, PartitionedTable};
+use crate::Result;
+use redb::{Database, Key, ReadableDatabase, TableDefinition, TableHandle};
+use std::collections::VecDeque;
+use std::ops::Bound;
+
+/// Number of key/shard pairs inspected per read transaction by a compaction plan.
+pub const DEFAULT_PLAN_BATCH: usize = 256;
+
+/// A single key/shard that needs compaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionUnit {
+    /// The encoded base key
+    pub base_key: Vec<u8>,
+    /// The shard to compact
+    pub shard: u16,
+    /// Number of segments the shard had when the plan was made
+    pub segment_count: usize,
+    /// Whether the shard had pending tombstones when the plan was made
+    pub has_tombstone: bool,
+}
+
+/// Lazily enumerates the compaction units of a partitioned roaring table.
+///
+/// Created by `PartitionedTable::compaction_plan`. The segment table is read
+/// in batches of `DEFAULT_PLAN_BATCH` key/shard pairs, each from a fresh read
+/// transaction, so the plan never pins an old snapshot for long and writes
+/// made while it runs are picked up for keys it hasn't reached yet.
+pub struct CompactionPlan<'a> {
+    db: &'a Database,
+    name: &'static str,
+    segment_table: String,
+    tombstone_table: String,
+    shard_count: u16,
+    segment_max_bytes: usize,
+    use_tombstones: bool,
+    after: Option<(Vec<u8>, u16)>,
+    pending: VecDeque<CompactionUnit>,
+    exhausted: bool,
+}
+
+impl<K: Key + 'static> PartitionedTable<K, RoaringValue> {
+    /// Plans the compaction of the whole table as discrete units.
+    ///
+    /// Yields one unit, in key order, for every key/shard with pending
+    /// tombstones or with two adjacent segments small enough to be merged into
+    /// one. Shards whose segments are all close to `segment_max_bytes` are
+    /// already as compact as `compact` would leave them and are skipped.
+    /// Segment sizes are compared as stored, so with a compressing
+    /// `SegmentTransform` some units may not shrink. Each unit can be executed with
+    /// `PartitionedRoaringWrite::compact_unit`, typically one unit per write
+    /// transaction.
+    ///
+    /// # Arguments
+    /// * `db` - The database instance
+    ///
+    /// # Returns
+    /// Iterator of compaction units
+    pub fn compaction_plan<'a>(&self, db: &'a Database) -> CompactionPlan<'a> {
+        CompactionPlan {
+            db,
+            name: self.name(),
+            segment_table: self.segment_table().name().to_string(),
+            tombstone_table: self.tombstone_table().name().to_string(),
+            shard_count: self.config().shard_count,
+            segment_max_bytes: self.config().segment_max_bytes,
+            use_tombstones: self.config().use_tombstones,
+            after: None,
+            pending: VecDeque::new(),
+            exhausted: false,
+        }
+    }
+}
+
+/// Segments of one key/shard seen while scanning the segment table.
+struct ShardScan {
+    base_key: Vec<u8>,
+    shard: u16,
+    segment_count: usize,
+    last_size: usize,
+    mergeable: bool,
+}
+
+impl CompactionPlan<'_> {
+    /// Reads the next batch of key/shard pairs and queues those needing compaction.
+    fn fill(&mut self) -> Result<()> {
+        let txn = self.db.begin_read()?;
+
+        let groups = {
+            let definition = TableDefinition::<&[u8], &[u8]>::new(&self.segment_table);
+            let table = txn.open_table(definition).map_err(|e| {
+                PartitionError::DatabaseError(
+                    DatabaseError::new("Failed to open segment table", e).table(self.name),
+                )
+            })?;
+
+            // Segment keys sort by base key and shard first, so everything
+            // after the last segment of the previous pair belongs to later pairs
+            let start = match &self.after {
+                Some((base_key, shard)) => Some(encode_segment_key(base_key, *shard, u16::MAX)?),
+                None => None,
+            };
+            let lower = match &start {
+                Some(start) => Bound::Excluded(start.as_slice()),
+                None => Bound::Unbounded,
+            };
+            let range = table
+                .range::<&[u8]>((lower, Bound::Unbounded))
+                .map_err(|e| {
+                    PartitionError::SegmentScanFailed(
+                        DatabaseError::new("Failed to scan segment table", e).table(self.name),
+                    )
+                })?;
+
+            let mut groups: Vec<ShardScan> = Vec::new();
+            let mut complete = true;
+            for entry in range {
+                let (segment_key, segment_data) = entry.map_err(|e| {
+                    PartitionError::SegmentScanFailed(
+                        DatabaseError::new("Failed to read segment", e).table(self.name),
+                    )
+                })?;
+                let (base_key, shard, _) = decode_segment_key(segment_key.value())?;
+                let size = segment_data.value().len();
+
+                match groups.last_mut() {
+                    Some(group)
+                        if group.base_key.as_slice() == base_key && group.shard == shard =>
+                    {
+                        group.mergeable |= group.last_size + size <= self.segment_max_bytes;
+                        group.segment_count += 1;
+                        group.last_size = size;
+                    }
+                    _ => {
+                        if groups.len() == DEFAULT_PLAN_BATCH {
+                            complete = false;
+                            break;
+                        }
+                        groups.push(ShardScan {
+                            base_key: base_key.to_vec(),
+                            shard,
+                            segment_count: 1,
+                            last_size: size,
+                            mergeable: false,
+                        });
+                    }
+                }
+            }
+
+            self.exhausted = complete;
+            groups
+        };
+
+        if let Some(group) = groups.last() {
+            self.after = Some((group.base_key.clone(), group.shard));
+        }
+
+        let tombstones = if self.use_tombstones {
+            let definition = TableDefinition::<&[u8], &[u8]>::new(&self.tombstone_table);
+            Some(txn.open_table(definition).map_err(|e| {
+                PartitionError::DatabaseError(
+                    DatabaseError::new("Failed to open tombstone table", e).table(self.name),
+                )
+            })?)
+        } else {
+            None
+        };
+
+        for group in groups {
+            if group.shard >= self.shard_count {
+                continue;
+            }
+
+            let has_tombstone = match &tombstones {
+                Some(tombstones) => {
+                    let tombstone_key = encode_shard_key(&group.base_key, group.shard)?;
+                    tombstones
+                        .get(tombstone_key.as_slice())
+                        .map_err(|e| {
+                            PartitionError::DatabaseError(
+                                DatabaseError::new("Failed to read tombstone", e)
+                                    .table(self.name)
+                                    .key(&group.base_key)
+                                    .shard(group.shard),
+                            )
+                        })?
+                        .is_some()
+                }
+                None => false,
+            };
+
+            if group.mergeable || has_tombstone {
+                self.pending.push_back(CompactionUnit {
+                    base_key: group.base_key,
+                    shard: group.shard,
+                    segment_count: group.segment_count,
+                    has_tombstone,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for CompactionPlan<'_> {
+    type Item = Result<CompactionUnit>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(unit) = self.pending.pop_front() {
+                return Some(Ok(unit));
+            }
+            if self.exhausted {
+                return None;
+            }
+            if let Err(e) = self.fill() {
+                self.exhausted = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Resumable migration of partitioned roaring bitmaps to a new configuration.
+//!
+//! Changing `segment_max_bytes`, `use_meta` or `use_tombstones` on an existing
+//! table doesn't touch data that's already stored, so old and new layouts end
+//! up mixed. Migration rewrites every base key under the new configuration, a
+//! bounded number of keys per transaction. The last migrated key is committed
+//! together with each chunk, so an interrupted migration resumes where it
+//! stopped when `migrate` is called again.
+
+use super::RoaringValue;
+use crate::encoding::key::decode_segment_key;
+use crate::error::DatabaseError;
+use crate::partition::table::{encode_segment_key, SegmentTables, MIGRATION_TABLE};
+use crate::partition::{PartitionConfig, PartitionError, PartitionedTable};
+use crate::{Error, Result};
+use redb::{Database, Key, ReadableTable, TableDefinition, WriteTransaction};
+use roaring::RoaringTreemap;
+use std::ops::Bound;
+
+/// Number of base keys rewritten per transaction by `migrate`.
+pub const DEFAULT_MIGRATION_CHUNK: usize = 256;
+
+/// Summary of a completed migration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Number of base keys rewritten by this call
+    pub keys_migrated: u64,
+    /// Number of transactions committed by this call
+    pub transactions: u64,
+    /// Whether the call resumed an interrupted migration
+    pub resumed: bool,
+}
+
+impl<K: Key + 'static> PartitionedTable<K, RoaringValue> {
+    /// Rewrites all stored bitmaps under a new configuration.
+    ///
+    /// Uses chunks of `DEFAULT_MIGRATION_CHUNK` keys per transaction.
+    /// See `migrate_in_chunks` for details.
+    ///
+    /// # Arguments
+    /// * `db` - The database instance
+    /// * `new_config` - The configuration to migrate to
+    ///
+    /// # Returns
+    /// Summary of the work done by this call
+    pub fn migrate(
+        &mut self,
+        db: &Database,
+        new_config: PartitionConfig,
+    ) -> Result<MigrationReport> {
+        self.migrate_in_chunks(db, new_config, DEFAULT_MIGRATION_CHUNK)
+    }
+
+    /// Rewrites all stored bitmaps under a new configuration, `keys_per_txn` keys at a time.
+    ///
+    /// For every base key, all shards are unioned with pending tombstones
+    /// folded in, the old segments, meta entries and tombstones are deleted,
+    /// and the bitmap is written back split to the new `segment_max_bytes`
+    /// with meta entries if the new config uses them. Each key is rewritten
+    /// within a single transaction, so readers never see it half-migrated.
+    ///
+    /// Progress is recorded after every chunk; calling this again after an
+    /// interruption skips the keys that were already rewritten. Rewriting a
+    /// key is idempotent, so resuming with a different target config is safe.
+    /// On success the table's configuration and its persisted config header
+    /// are replaced with `new_config`.
+    ///
+    /// The shard count cannot be changed, since shard placement depends on it.
+    ///
+    /// # Arguments
+    /// * `db` - The database instance
+    /// * `new_config` - The configuration to migrate to
+    /// * `keys_per_txn` - Maximum number of base keys rewritten per transaction
+    ///
+    /// # Returns
+    /// Summary of the work done by this call
+    pub fn migrate_in_chunks(
+        &mut self,
+        db: &Database,
+        new_config: PartitionConfig,
+        keys_per_txn: usize,
+    ) -> Result<MigrationReport> {
+        if new_config.shard_count != self.config().shard_count {
+            return Err(Error::InvalidInput(format!(
+                "Cannot migrate from {} to {} shards",
+                self.config().shard_count,
+                new_config.shard_count
+            )));
+        }
+        if keys_per_txn == 0 {
+            return Err(Error::InvalidInput(
+                "Migration chunk size must be greater than zero".to_string(),
+            ));
+        }
+
+        let target = self.with_config(new_config.clone());
+        let mut report = MigrationReport::default();
+
+        loop {
+            let txn = db.begin_write()?;
+
+            let last_key = self.read_progress(&txn)?;
+            if report.transactions == 0 {
+                report.resumed = last_key.is_some();
+            }
+
+            let keys = next_base_keys(
+                &txn,
+                self.segment_table(),
+                last_key.as_deref(),
+                keys_per_txn,
+            )?;
+            for base_key in &keys {
+                self.migrate_key(&txn, &target, base_key)?;
+            }
+
+            let done = keys.len() < keys_per_txn;
+            self.write_progress(&txn, if done { None } else { keys.last() })?;
+            if done {
+                // Commit the new header together with the last chunk
+                target.write_config_header(&txn)?;
+            }
+
+            txn.commit()
+                .map_err(|e| self.db_error("Failed to commit migration chunk", e))?;
+
+            report.keys_migrated += keys.len() as u64;
+            report.transactions += 1;
+
+            if done {
+                break;
+            }
+        }
+
+        *self = target;
+        Ok(report)
+    }
+
+    /// Rewrites a single base key from this table's layout into the target layout.
+    fn migrate_key(&self, txn: &WriteTransaction, target: &Self, base_key: &[u8]) -> Result<()> {
+        let bitmaps = {
+            let mut old = SegmentTables::open(self, txn)?;
+            let mut bitmaps: Vec<(u16, RoaringTreemap)> = Vec::new();
+
+            for shard in 0..self.config().shard_count {
+                let segments = old.enumerate_shard_segments(base_key, shard)?;
+                let bitmap = old.shard_bitmap(base_key, shard)?;
+
+                for segment in &segments {
+                    old.delete_segment(&segment.segment_key)?;
+                }
+                if self.config().use_tombstones {
+                    old.write_tombstone(base_key, shard, &[])?;
+                }
+
+                if !bitmap.is_empty() {
+                    bitmaps.push((shard, bitmap));
+                }
+            }
+
+            bitmaps
+        };
+
+        let mut new = SegmentTables::open(target, txn)?;
+        for (shard, bitmap) in bitmaps {
+            new.append_bitmap(base_key, shard, &bitmap)?;
+            new.set_member_count(base_key, shard, bitmap.len())?;
+        }
+
+        Ok(())
+    }
+
+    fn read_progress(&self, txn: &WriteTransaction) -> Result<Option<Vec<u8>>> {
+        let table = txn
+            .open_table(MIGRATION_TABLE)
+            .map_err(|e| self.db_error("Failed to open migration table", e))?;
+
+        let progress = table
+            .get(self.name())
+            .map_err(|e| self.db_error("Failed to read migration progress", e))?;
+
+        Ok(progress.map(|guard| guard.value().to_vec()))
+    }
+
+    fn write_progress(&self, txn: &WriteTransaction, last_key: Option<&Vec<u8>>) -> Result<()> {
+        let mut table = txn
+            .open_table(MIGRATION_TABLE)
+            .map_err(|e| self.db_error("Failed to open migration table", e))?;
+
+        let result = match last_key {
+            Some(last_key) => table.insert(self.name(), last_key.as_slice()).map(drop),
+            None => table.remove(self.name()).map(drop),
+        };
+        result.map_err(|e| self.db_error("Failed to record migration progress", e))?;
+
+        Ok(())
+    }
+}
+
+/// Lists up to `limit` distinct base keys stored in `segments` after `after`, in key order.
+fn next_base_keys(
+    txn: &WriteTransaction,
+    segments: TableDefinition<&'static [u8], &'static [u8]>,
+    after: Option<&[u8]>,
+    limit: usize,
+) -> Result<Vec<Vec<u8>>> {
+    let table = txn.open_table(segments).map_err(|e| {
+        PartitionError::DatabaseError(DatabaseError::new("Failed to open segment table", e))
+    })?;
+
+    // Segment keys sort by base key first, so everything after the last
+    // segment of `after` belongs to later base keys
+    let start = after
+        .map(|after| encode_segment_key(after, u16::MAX, u16::MAX))
+        .transpose()?;
+    let lower = match &start {
+        Some(start) => Bound::Excluded(start.as_slice()),
+        None => Bound::Unbounded,
+    };
+    let range = table
+        .range::<&[u8]>((lower, Bound::Unbounded))
+        .map_err(|e| {
+            PartitionError::SegmentScanFailed(DatabaseError::new("Failed to scan segment table", e))
+        })?;
+
+    let mut keys: Vec<Vec<u8>> = Vec::new();
+    for entry in range {
+        let (segment_key, _) = entry.map_err(|e| {
+            PartitionError::SegmentScanFailed(DatabaseError::new("Failed to read segment key", e))
+        })?;
+        let (base_key, _, _) = decode_segment_key(segment_key.value())?;
+
+        if keys.last().map(Vec::as_slice) != Some(base_key) {
+            if keys.len() == limit {
+                break;
+            }
+            keys.push(base_key.to_vec());
+        }
+    }
+
+    Ok(keys)
+}
@@ -3,6 +3,7 @@
 //! This module provides roaring-specific value handling including encoding,
 //! decoding, and operations that require bitmap knowledge.
 
+use crate::error::ErrorKind;
 use crate::Result;
 use roaring::RoaringTreemap;
 use std::fmt;
@@ -10,6 +11,7 @@ use std::fmt;
 /// Errors specific to the roaring layer.
 /// These are concerned with bitmap operations and value-specific semantics.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum RoaringError {
     /// Failed to serialize/deserialize RoaringTreemap
     SerializationFailed(String),
@@ -24,6 +26,20 @@ pub enum RoaringError {
     SizeQueryFailed(String),
 }
 
+impl RoaringError {
+    /// Classifies the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            RoaringError::SerializationFailed(_) | RoaringError::InvalidBitmap(_) => {
+                ErrorKind::Corruption
+            }
+            RoaringError::CompactionFailed(_) | RoaringError::SizeQueryFailed(_) => {
+                ErrorKind::Other
+            }
+        }
+    }
+}
+
 impl fmt::Display for RoaringError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -178,8 +194,16 @@ pub trait RoaringValueTable<'txn, K>: RoaringValueReadOnlyTable<'txn, K> {
     fn remove_key(&mut self, key: K) -> Result<()>;
 }
 
+mod compaction;
 mod facade;
+mod migrate;
+mod partitioned;
+mod snapshot;
 mod value;
 
 // Re-export main types for public API
+pub use compaction::{CompactionPlan, CompactionUnit, DEFAULT_PLAN_BATCH};
+pub use migrate::{MigrationReport, DEFAULT_MIGRATION_CHUNK};
+pub use partitioned::{PartitionedRoaringRead, PartitionedRoaringWrite};
+pub use snapshot::RoaringSnapshot;
 pub use value::RoaringValue;
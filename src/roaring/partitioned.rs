@@ -0,0 +1,970 @@
+//! Roaring bitmap operations on partitioned tables.
+//!
+//! Bridges the generic partition layer and roaring bitmaps: members are routed
+//! to a shard, appended to that shard's head segment, and unioned back across
+//! segments and shards on read. When tombstones are enabled, removals are
+//! recorded in a per-shard tombstone bitmap and folded in at read and
+//! compaction time instead of rewriting the segments that hold the member.
+
+use super::{CompactionUnit, RoaringError, RoaringValue};
+use crate::partition::scan::SegmentInfo;
+use crate::partition::table::{encode_base_key, encode_segment_key, SegmentTables};
+use crate::partition::{PartitionedRead, PartitionedWrite, SegmentBounds};
+use crate::Result;
+use redb::Key;
+use roaring::{treemap, RoaringTreemap};
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+
+/// Logical size of one member, used for write amplification accounting.
+const MEMBER_BYTES: u64 = std::mem::size_of::<u64>() as u64;
+
+/// Read-only roaring bitmap operations on a partitioned table.
+pub trait PartitionedRoaringRead<K: Key + 'static> {
+    /// Gets the complete roaring bitmap for the given key.
+    ///
+    /// Segments of every shard are unioned and pending tombstones are subtracted.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to retrieve
+    ///
+    /// # Returns
+    /// The complete RoaringTreemap or empty if not found
+    fn get_bitmap<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<RoaringTreemap> {
+        let mut bitmap = RoaringTreemap::new();
+        for entry in self.iter_shard_bitmaps(key) {
+            let (_, shard_bitmap) = entry?;
+            bitmap |= shard_bitmap;
+        }
+        Ok(bitmap)
+    }
+
+    /// Gets the number of members from the per-shard counts in the meta table.
+    ///
+    /// With `use_meta` enabled, writes keep a member count next to each shard's
+    /// head segment, so counting only reads one small row per shard instead of
+    /// unioning every segment. Shards without a meta entry (for example data
+    /// written before meta was enabled) are counted by decoding them, and with
+    /// meta disabled this is equivalent to `get_member_count`.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to query
+    ///
+    /// # Returns
+    /// The number of members in the bitmap
+    fn cached_member_count<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<u64>;
+
+    /// Iterates the bitmap for the given key one shard at a time.
+    ///
+    /// Each shard is read and decoded only when the iterator reaches it, with
+    /// pending tombstones already subtracted, so consumers can process or
+    /// stream shards incrementally without materializing the full bitmap.
+    /// Shards holding no members are skipped. Since every member lives in
+    /// exactly one shard, the yielded bitmaps are disjoint.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to iterate
+    ///
+    /// # Returns
+    /// Iterator of (shard, bitmap) pairs in ascending shard order
+    fn iter_shard_bitmaps<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+    ) -> impl Iterator<Item = Result<(u16, RoaringTreemap)>> + '_;
+
+    /// Iterates the members of the bitmap for the given key within a range.
+    ///
+    /// With `use_meta` enabled, every segment records its smallest and largest
+    /// member, so segments entirely outside the range are skipped without
+    /// being read or decoded. Segments without recorded bounds are always read.
+    /// Pending tombstones are subtracted.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to iterate
+    /// * `range` - The range of members to include
+    ///
+    /// # Returns
+    /// Iterator of the members in the range, in ascending order
+    fn iter_members_in<'k, R>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        range: R,
+    ) -> Result<treemap::IntoIter>
+    where
+        R: RangeBounds<u64>;
+
+    /// Checks if a member exists in the bitmap for the given key.
+    ///
+    /// Only the shard the member is routed to is read.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to check
+    /// * `member` - The member to check for
+    ///
+    /// # Returns
+    /// True if the member exists, false otherwise
+    fn contains_member<'k>(&self, key: impl Borrow<K::SelfType<'k>>, member: u64) -> Result<bool>;
+
+    /// Gets the number of members in the bitmap for the given key.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to query
+    ///
+    /// # Returns
+    /// The number of members in the bitmap
+    fn get_member_count<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<u64> {
+        let bitmap = self.get_bitmap(key)?;
+        Ok(bitmap.len())
+    }
+}
+
+/// Roaring bitmap write operations on a partitioned table.
+pub trait PartitionedRoaringWrite<K: Key + 'static>: PartitionedRoaringRead<K> {
+    /// Inserts a single member into the bitmap for the given key.
+    ///
+    /// The member is appended to the head segment of its shard, rolling to a
+    /// new segment when the head would exceed `segment_max_bytes`. A pending
+    /// tombstone for the member is cleared.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to modify
+    /// * `member` - The member to insert
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn insert_member<'k>(&self, key: impl Borrow<K::SelfType<'k>>, member: u64) -> Result<()> {
+        self.insert_members(key, std::iter::once(member))
+    }
+
+    /// Removes a single member from the bitmap for the given key.
+    ///
+    /// With tombstones enabled only the shard's tombstone row is written;
+    /// otherwise every segment of the shard holding the member is rewritten.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to modify
+    /// * `member` - The member to remove
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn remove_member<'k>(&self, key: impl Borrow<K::SelfType<'k>>, member: u64) -> Result<()> {
+        self.remove_members(key, std::iter::once(member))
+    }
+
+    /// Inserts multiple members into the bitmap for the given key.
+    ///
+    /// Members are grouped per shard so each shard's head segment is rewritten once.
+    /// Batches too large for the head segment are split by member range across
+    /// as many new segments as needed to honor `segment_max_bytes`.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to modify
+    /// * `members` - Iterator of members to insert
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn insert_members<'k, I>(&self, key: impl Borrow<K::SelfType<'k>>, members: I) -> Result<()>
+    where
+        I: IntoIterator<Item = u64>;
+
+    /// Inserts members for several keys in one pass.
+    ///
+    /// All work is grouped per key and shard before anything is written, and
+    /// the segment table is opened a single time for the whole batch, so each
+    /// (key, shard) head segment is rewritten at most once. Prefer this over
+    /// repeated `insert_members` calls on ingestion paths.
+    ///
+    /// # Arguments
+    /// * `batch` - Iterator of (base key, members to insert) pairs
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn apply_batch<'k, B, M>(&self, batch: B) -> Result<()>
+    where
+        B: IntoIterator<Item = (K::SelfType<'k>, M)>,
+        M: IntoIterator<Item = u64>;
+
+    /// Removes multiple members from the bitmap for the given key.
+    ///
+    /// Members are grouped per shard so each shard is touched once.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to modify
+    /// * `members` - Iterator of members to remove
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn remove_members<'k, I>(&self, key: impl Borrow<K::SelfType<'k>>, members: I) -> Result<()>
+    where
+        I: IntoIterator<Item = u64>;
+
+    /// Removes every member within a range from the bitmap for the given key.
+    ///
+    /// Members of any shard may fall in the range, so every shard is visited,
+    /// but with `use_meta` enabled only segments whose recorded min/max
+    /// overlap the range are read and rewritten. Segments without recorded
+    /// bounds are always read. Matching members are removed from the
+    /// segments directly, also when tombstones are enabled, and dropped from
+    /// pending tombstones.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to modify
+    /// * `range` - The range of members to remove
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn remove_range<'k, R>(&self, key: impl Borrow<K::SelfType<'k>>, range: R) -> Result<()>
+    where
+        R: RangeBounds<u64>;
+
+    /// Clears all members from the bitmap for the given key.
+    ///
+    /// Deletes every segment and tombstone of every shard.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to clear
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn clear_bitmap<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<()>;
+
+    /// Compacts the segments of every shard for the given key.
+    ///
+    /// Segments are unioned, pending tombstones are folded in and the result
+    /// is rewritten from segment 0, split across as many segments as needed to
+    /// honor `segment_max_bytes`. Tombstone rows are removed afterwards.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to compact
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn compact<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<()>;
+
+    /// Compacts the single key/shard described by a compaction unit.
+    ///
+    /// Units come from `PartitionedTable::compaction_plan`. Running one unit per
+    /// transaction keeps maintenance transactions small, so compaction can be
+    /// interleaved with regular writes. Units may be stale by the time they
+    /// run; compacting a shard that no longer needs it only rewrites the same
+    /// members again.
+    ///
+    /// # Arguments
+    /// * `unit` - The key/shard to compact
+    ///
+    /// # Returns
+    /// Result indicating success or failure
+    fn compact_unit(&self, unit: &CompactionUnit) -> Result<()>;
+}
+
+/// Decodes and unions a sequence of encoded segments.
+pub(super) fn union_segments(segments: &[SegmentInfo]) -> Result<RoaringTreemap> {
+    let mut bitmap = RoaringTreemap::new();
+    for segment in segments {
+        if let Some(data) = &segment.segment_data {
+            bitmap |= RoaringValue::decode(data)?.into_bitmap();
+        }
+    }
+    Ok(bitmap)
+}
+
+/// Decodes an optional tombstone payload.
+pub(super) fn decode_tombstone(data: Option<Vec<u8>>) -> Result<RoaringTreemap> {
+    match data {
+        Some(data) => Ok(RoaringValue::decode(&data)?.into_bitmap()),
+        None => Ok(RoaringTreemap::new()),
+    }
+}
+
+/// Converts a range of members into inclusive bounds, or None if it is empty.
+fn inclusive_range<R: RangeBounds<u64>>(range: &R) -> Option<(u64, u64)> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start.checked_add(1)?,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end,
+        Bound::Excluded(&end) => end.checked_sub(1)?,
+        Bound::Unbounded => u64::MAX,
+    };
+    (start <= end).then_some((start, end))
+}
+
+/// Removes every member outside `start..=end` from a bitmap.
+fn retain_range(bitmap: &mut RoaringTreemap, start: u64, end: u64) {
+    bitmap.remove_range(..start);
+    if end < u64::MAX {
+        bitmap.remove_range(end + 1..);
+    }
+}
+
+/// Gets the smallest and largest member of a non-empty bitmap.
+fn bitmap_bounds(bitmap: &RoaringTreemap) -> Option<SegmentBounds> {
+    Some(SegmentBounds::new(bitmap.min()?, bitmap.max()?))
+}
+
+/// Groups members by the shard they are routed to.
+fn group_by_shard<'k, K, V, I>(
+    table: &crate::partition::PartitionedTable<K, V>,
+    key: &K::SelfType<'k>,
+    members: I,
+) -> Result<BTreeMap<u16, RoaringTreemap>>
+where
+    K: Key + 'static,
+    I: IntoIterator<Item = u64>,
+{
+    let mut groups: BTreeMap<u16, RoaringTreemap> = BTreeMap::new();
+    for member in members {
+        let shard = table.select_shard(key, member)?;
+        groups.entry(shard).or_default().insert(member);
+    }
+    Ok(groups)
+}
+
+impl<K: Key + 'static> PartitionedRead<'_, K, RoaringValue> {
+    /// Loads the bitmap of one shard with tombstones applied.
+    pub(super) fn load_shard_bitmap(&self, base_key: &[u8], shard: u16) -> Result<RoaringTreemap> {
+        let segments = self.enumerate_shard_segments_raw(base_key, shard)?;
+        if segments.is_empty() {
+            return Ok(RoaringTreemap::new());
+        }
+
+        let mut bitmap = union_segments(&segments)?;
+        bitmap -= decode_tombstone(self.read_tombstone_raw(base_key, shard)?)?;
+        Ok(bitmap)
+    }
+
+    /// Loads the members of one shard within `start..=end`, with tombstones applied.
+    fn load_shard_range(
+        &self,
+        base_key: &[u8],
+        shard: u16,
+        start: u64,
+        end: u64,
+    ) -> Result<RoaringTreemap> {
+        let segments = self.enumerate_segments_in_raw(base_key, shard, start, end)?;
+        if segments.is_empty() {
+            return Ok(RoaringTreemap::new());
+        }
+
+        let mut bitmap = union_segments(&segments)?;
+        retain_range(&mut bitmap, start, end);
+        bitmap -= decode_tombstone(self.read_tombstone_raw(base_key, shard)?)?;
+        Ok(bitmap)
+    }
+}
+
+/// Lazily yields the non-empty shard bitmaps of a key in shard order.
+fn iter_shards<'r, F>(
+    shard_count: u16,
+    mut load: F,
+) -> impl Iterator<Item = Result<(u16, RoaringTreemap)>> + 'r
+where
+    F: FnMut(u16) -> Result<RoaringTreemap> + 'r,
+{
+    (0..shard_count).filter_map(move |shard| match load(shard) {
+        Ok(bitmap) if bitmap.is_empty() => None,
+        Ok(bitmap) => Some(Ok((shard, bitmap))),
+        Err(e) => Some(Err(e)),
+    })
+}
+
+impl<K: Key + 'static> PartitionedRoaringRead<K> for PartitionedRead<'_, K, RoaringValue> {
+    fn iter_shard_bitmaps<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+    ) -> impl Iterator<Item = Result<(u16, RoaringTreemap)>> + '_ {
+        let base_key = encode_base_key::<K>(key.borrow());
+        iter_shards(self.table().config().shard_count, move |shard| {
+            self.load_shard_bitmap(&base_key, shard)
+        })
+    }
+
+    fn contains_member<'k>(&self, key: impl Borrow<K::SelfType<'k>>, member: u64) -> Result<bool> {
+        let key = key.borrow();
+        let shard = self.table().select_shard(key, member)?;
+
+        if decode_tombstone(self.read_tombstone(key, shard)?)?.contains(member) {
+            return Ok(false);
+        }
+
+        Ok(union_segments(&self.enumerate_shard_segments(key, shard)?)?.contains(member))
+    }
+
+    fn iter_members_in<'k, R>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        range: R,
+    ) -> Result<treemap::IntoIter>
+    where
+        R: RangeBounds<u64>,
+    {
+        let Some((start, end)) = inclusive_range(&range) else {
+            return Ok(RoaringTreemap::new().into_iter());
+        };
+
+        let base_key = encode_base_key::<K>(key.borrow());
+        let mut members = RoaringTreemap::new();
+        for shard in 0..self.table().config().shard_count {
+            members |= self.load_shard_range(&base_key, shard, start, end)?;
+        }
+        Ok(members.into_iter())
+    }
+
+    fn cached_member_count<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<u64> {
+        let base_key = encode_base_key::<K>(key.borrow());
+        let mut count = 0;
+        for shard in 0..self.table().config().shard_count {
+            count += match self.read_meta_raw(&base_key, shard)? {
+                Some(entry) => entry.member_count,
+                None => self.load_shard_bitmap(&base_key, shard)?.len(),
+            };
+        }
+        Ok(count)
+    }
+}
+
+impl<K: Key + 'static> PartitionedRoaringRead<K> for PartitionedWrite<'_, K, RoaringValue> {
+    fn iter_shard_bitmaps<'k>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+    ) -> impl Iterator<Item = Result<(u16, RoaringTreemap)>> + '_ {
+        let base_key = encode_base_key::<K>(key.borrow());
+        iter_shards(self.table().config().shard_count, move |shard| {
+            self.tables()?.shard_bitmap(&base_key, shard)
+        })
+    }
+
+    fn contains_member<'k>(&self, key: impl Borrow<K::SelfType<'k>>, member: u64) -> Result<bool> {
+        let key = key.borrow();
+        let shard = self.table().select_shard(key, member)?;
+
+        if decode_tombstone(self.read_tombstone(key, shard)?)?.contains(member) {
+            return Ok(false);
+        }
+
+        Ok(union_segments(&self.enumerate_shard_segments(key, shard)?)?.contains(member))
+    }
+
+    fn iter_members_in<'k, R>(
+        &self,
+        key: impl Borrow<K::SelfType<'k>>,
+        range: R,
+    ) -> Result<treemap::IntoIter>
+    where
+        R: RangeBounds<u64>,
+    {
+        let Some((start, end)) = inclusive_range(&range) else {
+            return Ok(RoaringTreemap::new().into_iter());
+        };
+
+        let base_key = encode_base_key::<K>(key.borrow());
+        let tables = self.tables()?;
+        let mut members = RoaringTreemap::new();
+        for shard in 0..self.table().config().shard_count {
+            members |= tables.shard_range_bitmap(&base_key, shard, start, end)?;
+        }
+        Ok(members.into_iter())
+    }
+
+    fn cached_member_count<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<u64> {
+        let base_key = encode_base_key::<K>(key.borrow());
+        let tables = self.tables()?;
+        let mut count = 0;
+        for shard in 0..self.table().config().shard_count {
+            count += match tables.read_meta(&base_key, shard)? {
+                Some(entry) => entry.member_count,
+                None => tables.shard_bitmap(&base_key, shard)?.len(),
+            };
+        }
+        Ok(count)
+    }
+}
+
+impl<K: Key + 'static> SegmentTables<'_, K, RoaringValue> {
+    /// Adds members to one shard, clearing any tombstones they had.
+    fn insert_shard_members(
+        &mut self,
+        key: &[u8],
+        shard: u16,
+        members: &RoaringTreemap,
+    ) -> Result<()> {
+        self.record_logical_bytes(members.len() * MEMBER_BYTES);
+        let count = self.member_count_before(key, shard)?;
+
+        let mut tombstone = RoaringTreemap::new();
+        if self.table().config().use_tombstones {
+            tombstone = decode_tombstone(self.read_tombstone(key, shard)?)?;
+            if !tombstone.is_disjoint(members) {
+                self.store_tombstone(key, shard, &(&tombstone - members))?;
+            }
+        }
+
+        let stored = self.append_to_head(key, shard, members)?;
+
+        // Tombstoned members were absent even if a segment still holds them
+        let added = members.len() - (stored - tombstone).len();
+        self.update_member_count(key, shard, count, added, 0)
+    }
+
+    /// Reads the member count of a shard before a write changes it.
+    ///
+    /// Returns `None` when meta is disabled, or when the shard has segments
+    /// but no meta entry and its count has to be recomputed after the write.
+    fn member_count_before(&self, key: &[u8], shard: u16) -> Result<Option<u64>> {
+        if !self.table().config().use_meta {
+            return Ok(None);
+        }
+
+        match self.read_meta(key, shard)? {
+            Some(entry) => Ok(Some(entry.member_count)),
+            None if self.scan_head_segment(key, shard)?.is_none() => Ok(Some(0)),
+            None => Ok(None),
+        }
+    }
+
+    /// Records the member count of a shard after a write added and removed members.
+    ///
+    /// Falls back to counting the shard bitmap when `before` is unknown.
+    fn update_member_count(
+        &mut self,
+        key: &[u8],
+        shard: u16,
+        before: Option<u64>,
+        added: u64,
+        removed: u64,
+    ) -> Result<()> {
+        if !self.table().config().use_meta {
+            return Ok(());
+        }
+
+        let count = match before {
+            Some(count) => (count + added).saturating_sub(removed),
+            None => self.shard_bitmap(key, shard)?.len(),
+        };
+        self.set_member_count(key, shard, count)
+    }
+
+    /// Finds which of `candidates` are stored in the segments of a shard.
+    ///
+    /// Segments whose recorded bounds miss the candidates are skipped without
+    /// being read, as is the `skip` segment. Tombstones are not applied.
+    fn stored_members(
+        &self,
+        key: &[u8],
+        shard: u16,
+        skip: Option<u16>,
+        candidates: &RoaringTreemap,
+    ) -> Result<RoaringTreemap> {
+        let mut stored = RoaringTreemap::new();
+        let (Some(min), Some(max)) = (candidates.min(), candidates.max()) else {
+            return Ok(stored);
+        };
+
+        // Newest first, since recent members are the likeliest to be repeated
+        for (segment, segment_key) in self.segment_keys(key, shard)?.into_iter().rev() {
+            if Some(segment) == skip {
+                continue;
+            }
+            if let Some(bounds) = self.read_segment_bounds(&segment_key)? {
+                if !bounds.overlaps(min, max) {
+                    continue;
+                }
+            }
+
+            if let Some(data) = self.read_segment(&segment_key)? {
+                stored |= RoaringValue::decode(&data)?.into_bitmap() & candidates;
+                if stored.len() == candidates.len() {
+                    break;
+                }
+            }
+        }
+
+        Ok(stored)
+    }
+
+    /// Loads the bitmap of one shard with tombstones applied.
+    pub(super) fn shard_bitmap(&self, key: &[u8], shard: u16) -> Result<RoaringTreemap> {
+        let segments = self.enumerate_shard_segments(key, shard)?;
+        if segments.is_empty() {
+            return Ok(RoaringTreemap::new());
+        }
+
+        let mut bitmap = union_segments(&segments)?;
+        bitmap -= decode_tombstone(self.read_tombstone(key, shard)?)?;
+        Ok(bitmap)
+    }
+
+    /// Loads the members of one shard within `start..=end`, with tombstones applied.
+    fn shard_range_bitmap(
+        &self,
+        key: &[u8],
+        shard: u16,
+        start: u64,
+        end: u64,
+    ) -> Result<RoaringTreemap> {
+        let segments = self.enumerate_segments_in(key, shard, start, end)?;
+        if segments.is_empty() {
+            return Ok(RoaringTreemap::new());
+        }
+
+        let mut bitmap = union_segments(&segments)?;
+        retain_range(&mut bitmap, start, end);
+        bitmap -= decode_tombstone(self.read_tombstone(key, shard)?)?;
+        Ok(bitmap)
+    }
+
+    /// Appends members to the head segment of a shard, rolling if it would overflow.
+    ///
+    /// Members already in the head are not written again. With meta enabled,
+    /// older segments are checked too so the member count stays exact.
+    ///
+    /// Returns the members that were already stored.
+    fn append_to_head(
+        &mut self,
+        key: &[u8],
+        shard: u16,
+        members: &RoaringTreemap,
+    ) -> Result<RoaringTreemap> {
+        let Some(head) = self.find_head_segment(key, shard)? else {
+            self.append_bitmap(key, shard, members)?;
+            return Ok(RoaringTreemap::new());
+        };
+
+        let head_key = encode_segment_key(key, shard, head)?;
+        let mut head_bitmap = match self.read_segment(&head_key)? {
+            Some(data) => RoaringValue::decode(&data)?.into_bitmap(),
+            None => RoaringTreemap::new(),
+        };
+
+        let mut stored = &head_bitmap & members;
+        if self.table().config().use_meta {
+            stored |= self.stored_members(key, shard, Some(head), &(members - &stored))?;
+        }
+
+        let fresh = members - &stored;
+        if fresh.is_empty() {
+            return Ok(stored);
+        }
+
+        head_bitmap |= &fresh;
+        let data = RoaringValue::encode_bitmap(&head_bitmap)?;
+        if data.len() <= self.table().config().segment_max_bytes {
+            self.write_segment(&head_key, &data)?;
+            self.record_bounds(&head_key, &head_bitmap)?;
+        } else {
+            self.append_bitmap(key, shard, &fresh)?;
+        }
+        Ok(stored)
+    }
+
+    /// Writes a bitmap into new segments, split by member range to fit `segment_max_bytes`.
+    ///
+    /// Returns the IDs of the created segments.
+    pub(super) fn append_bitmap(
+        &mut self,
+        key: &[u8],
+        shard: u16,
+        bitmap: &RoaringTreemap,
+    ) -> Result<Vec<u16>> {
+        let max_bytes = self.table().config().segment_max_bytes;
+        let parts = RoaringValue::split_bitmap(bitmap, max_bytes)?;
+
+        if parts.len() > 1 {
+            if let Some(observer) = self.table().observer() {
+                observer.on_oversized_write(
+                    self.table().name(),
+                    key,
+                    shard,
+                    RoaringValue::get_serialized_size_for(bitmap)?,
+                    max_bytes,
+                );
+            }
+        }
+
+        let chunks = parts
+            .iter()
+            .map(RoaringValue::encode_bitmap)
+            .collect::<Result<Vec<_>>>()?;
+        let segment_ids = self.append_segments(key, shard, &chunks)?;
+
+        for (part, segment_id) in parts.iter().zip(&segment_ids) {
+            self.record_bounds(&encode_segment_key(key, shard, *segment_id)?, part)?;
+        }
+
+        Ok(segment_ids)
+    }
+
+    /// Writes a non-empty bitmap to an existing segment and records its bounds.
+    fn write_bitmap_segment(&mut self, segment_key: &[u8], bitmap: &RoaringTreemap) -> Result<()> {
+        self.write_segment(segment_key, &RoaringValue::encode_bitmap(bitmap)?)?;
+        self.record_bounds(segment_key, bitmap)
+    }
+
+    /// Records the min/max member of a freshly written segment.
+    fn record_bounds(&mut self, segment_key: &[u8], bitmap: &RoaringTreemap) -> Result<()> {
+        match bitmap_bounds(bitmap) {
+            Some(bounds) => self.write_segment_bounds(segment_key, &bounds),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the members in `start..=end` from the segments of a shard.
+    ///
+    /// Segments whose recorded bounds don't overlap the range are skipped
+    /// without being read.
+    ///
+    /// Returns the number of live members removed.
+    fn remove_shard_range(&mut self, key: &[u8], shard: u16, start: u64, end: u64) -> Result<u64> {
+        let mut removed = RoaringTreemap::new();
+
+        for (_, segment_key) in self.segment_keys(key, shard)? {
+            if let Some(bounds) = self.read_segment_bounds(&segment_key)? {
+                if !bounds.overlaps(start, end) {
+                    continue;
+                }
+            }
+
+            let Some(data) = self.read_segment(&segment_key)? else {
+                continue;
+            };
+            let mut bitmap = RoaringValue::decode(&data)?.into_bitmap();
+            let before = bitmap.clone();
+            bitmap.remove_range(start..=end);
+            if bitmap.len() == before.len() {
+                continue;
+            }
+
+            removed |= before - &bitmap;
+            if bitmap.is_empty() {
+                self.delete_segment(&segment_key)?;
+            } else {
+                self.write_bitmap_segment(&segment_key, &bitmap)?;
+            }
+        }
+
+        if self.table().config().use_tombstones {
+            let mut tombstone = decode_tombstone(self.read_tombstone(key, shard)?)?;
+            // Tombstoned members were already gone before the range was removed
+            removed -= &tombstone;
+            let before = tombstone.len();
+            tombstone.remove_range(start..=end);
+            if tombstone.len() != before {
+                self.store_tombstone(key, shard, &tombstone)?;
+            }
+        }
+
+        Ok(removed.len())
+    }
+
+    /// Removes members from every segment of a shard, deleting emptied segments.
+    ///
+    /// Returns the members that were removed.
+    fn rewrite_without(
+        &mut self,
+        key: &[u8],
+        shard: u16,
+        members: &RoaringTreemap,
+    ) -> Result<RoaringTreemap> {
+        let mut removed = RoaringTreemap::new();
+        for segment in self.enumerate_shard_segments(key, shard)? {
+            let mut bitmap = union_segments(std::slice::from_ref(&segment))?;
+            if bitmap.is_disjoint(members) {
+                continue;
+            }
+
+            removed |= &bitmap & members;
+            bitmap -= members;
+            if bitmap.is_empty() {
+                self.delete_segment(&segment.segment_key)?;
+            } else {
+                self.write_bitmap_segment(&segment.segment_key, &bitmap)?;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Rewrites one shard into as few segments as possible with tombstones folded in.
+    ///
+    /// Shards with at most one segment and no pending tombstone are left as is.
+    fn compact_shard(&mut self, key: &[u8], shard: u16) -> Result<()> {
+        let segments = self.enumerate_shard_segments(key, shard)?;
+        let tombstone = decode_tombstone(self.read_tombstone(key, shard)?)?;
+        if segments.len() <= 1 && tombstone.is_empty() {
+            return Ok(());
+        }
+
+        let mut bitmap = union_segments(&segments)?;
+        bitmap -= tombstone;
+
+        for segment in &segments {
+            self.delete_segment(&segment.segment_key)?;
+        }
+        if self.table().config().use_tombstones {
+            self.write_tombstone(key, shard, &[])?;
+        }
+
+        // No segments remain, so the rewrite starts again at segment 0
+        let rewritten = self.append_bitmap(key, shard, &bitmap).map_err(|e| {
+            RoaringError::CompactionFailed(format!("Failed to rewrite shard {}: {}", shard, e))
+        })?;
+
+        self.set_member_count(key, shard, bitmap.len())?;
+
+        if let Some(observer) = self.table().observer() {
+            observer.on_compaction(
+                self.table().name(),
+                key,
+                shard,
+                segments.len(),
+                rewritten.len(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Writes a tombstone bitmap, removing the row when it becomes empty.
+    fn store_tombstone(
+        &mut self,
+        key: &[u8],
+        shard: u16,
+        tombstone: &RoaringTreemap,
+    ) -> Result<()> {
+        if tombstone.is_empty() {
+            self.write_tombstone(key, shard, &[])
+        } else {
+            self.write_tombstone(key, shard, &RoaringValue::encode_bitmap(tombstone)?)
+        }
+    }
+}
+
+impl<K: Key + 'static> PartitionedRoaringWrite<K> for PartitionedWrite<'_, K, RoaringValue> {
+    fn insert_members<'k, I>(&self, key: impl Borrow<K::SelfType<'k>>, members: I) -> Result<()>
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        let key = key.borrow();
+        let base_key = encode_base_key::<K>(key);
+        let mut tables = self.tables()?;
+
+        for (shard, shard_members) in group_by_shard(self.table(), key, members)? {
+            tables.insert_shard_members(&base_key, shard, &shard_members)?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_batch<'k, B, M>(&self, batch: B) -> Result<()>
+    where
+        B: IntoIterator<Item = (K::SelfType<'k>, M)>,
+        M: IntoIterator<Item = u64>,
+    {
+        // Group everything up front so each (key, shard) head is rewritten once
+        let mut grouped: BTreeMap<Vec<u8>, BTreeMap<u16, RoaringTreemap>> = BTreeMap::new();
+        for (key, members) in batch {
+            let shards = grouped.entry(encode_base_key::<K>(&key)).or_default();
+            for (shard, shard_members) in group_by_shard(self.table(), &key, members)? {
+                *shards.entry(shard).or_default() |= shard_members;
+            }
+        }
+
+        let mut tables = self.tables()?;
+        for (base_key, shards) in grouped {
+            for (shard, shard_members) in shards {
+                tables.insert_shard_members(&base_key, shard, &shard_members)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_members<'k, I>(&self, key: impl Borrow<K::SelfType<'k>>, members: I) -> Result<()>
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        let key = key.borrow();
+        let base_key = encode_base_key::<K>(key);
+        let mut tables = self.tables()?;
+
+        for (shard, shard_members) in group_by_shard(self.table(), key, members)? {
+            tables.record_logical_bytes(shard_members.len() * MEMBER_BYTES);
+            let count = tables.member_count_before(&base_key, shard)?;
+
+            let removed = if self.table().config().use_tombstones {
+                let mut tombstone = decode_tombstone(tables.read_tombstone(&base_key, shard)?)?;
+
+                // Only members the shard holds need a tombstone
+                let live = &shard_members - &tombstone;
+                let stored = tables.stored_members(&base_key, shard, None, &live)?;
+                if stored.is_empty() {
+                    continue;
+                }
+                tombstone |= &stored;
+                tables.store_tombstone(&base_key, shard, &tombstone)?;
+                stored.len()
+            } else {
+                tables
+                    .rewrite_without(&base_key, shard, &shard_members)?
+                    .len()
+            };
+            tables.update_member_count(&base_key, shard, count, 0, removed)?;
+        }
+
+        Ok(())
+    }
+
+    fn remove_range<'k, R>(&self, key: impl Borrow<K::SelfType<'k>>, range: R) -> Result<()>
+    where
+        R: RangeBounds<u64>,
+    {
+        let Some((start, end)) = inclusive_range(&range) else {
+            return Ok(());
+        };
+
+        let base_key = encode_base_key::<K>(key.borrow());
+        let mut tables = self.tables()?;
+
+        for shard in 0..self.table().config().shard_count {
+            let count = tables.member_count_before(&base_key, shard)?;
+            let removed = tables.remove_shard_range(&base_key, shard, start, end)?;
+            tables.record_logical_bytes(removed * MEMBER_BYTES);
+            tables.update_member_count(&base_key, shard, count, 0, removed)?;
+        }
+
+        Ok(())
+    }
+
+    fn clear_bitmap<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<()> {
+        let base_key = encode_base_key::<K>(key.borrow());
+        let mut tables = self.tables()?;
+
+        for shard in 0..self.table().config().shard_count {
+            for segment in tables.enumerate_shard_segments(&base_key, shard)? {
+                tables.delete_segment(&segment.segment_key)?;
+            }
+            if self.table().config().use_tombstones {
+                tables.write_tombstone(&base_key, shard, &[])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn compact<'k>(&self, key: impl Borrow<K::SelfType<'k>>) -> Result<()> {
+        let base_key = encode_base_key::<K>(key.borrow());
+        let mut tables = self.tables()?;
+
+        for shard in 0..self.table().config().shard_count {
+            tables.compact_shard(&base_key, shard)?;
+        }
+
+        Ok(())
+    }
+
+    fn compact_unit(&self, unit: &CompactionUnit) -> Result<()> {
+        self.tables()?.compact_shard(&unit.base_key, unit.shard)
+    }
+}
@@ -0,0 +1,156 @@
+//! Pinned read view over a partitioned roaring bitmap.
+//!
+//! A snapshot is bound to a single base key inside a read transaction. Shards
+//! are decoded lazily on first access and cached, so repeated queries against
+//! the same key don't re-read and re-decode every segment.
+
+use super::RoaringValue;
+use crate::partition::shard::{select_shard, validate_shard_index};
+use crate::partition::table::encode_base_key;
+use crate::partition::PartitionedRead;
+use crate::Result;
+use redb::Key;
+use roaring::RoaringTreemap;
+use std::borrow::Borrow;
+use std::cell::OnceCell;
+
+/// Read view over the bitmap of one base key with per-shard caching.
+///
+/// Created with `PartitionedRead::snapshot`. Since the underlying read
+/// transaction is itself a consistent snapshot, cached shards never go stale.
+pub struct RoaringSnapshot<'r, K: Key + 'static> {
+    reader: &'r PartitionedRead<'r, K, RoaringValue>,
+    base_key: Vec<u8>,
+    shards: Vec<OnceCell<RoaringTreemap>>,
+}
+
+impl<'r, K: Key + 'static> RoaringSnapshot<'r, K> {
+    /// Gets the bitmap stored in a single shard, with tombstones applied.
+    ///
+    /// The shard is decoded on first access and cached for the lifetime of
+    /// the snapshot.
+    ///
+    /// # Arguments
+    /// * `shard` - The shard ID
+    ///
+    /// # Returns
+    /// Reference to the cached shard bitmap
+    pub fn shard_bitmap(&self, shard: u16) -> Result<&RoaringTreemap> {
+        validate_shard_index(shard, self.reader.table().config().shard_count)?;
+        let cell = &self.shards[shard as usize];
+
+        if let Some(bitmap) = cell.get() {
+            return Ok(bitmap);
+        }
+
+        let bitmap = self.reader.load_shard_bitmap(&self.base_key, shard)?;
+        Ok(cell.get_or_init(|| bitmap))
+    }
+
+    /// Gets the complete bitmap, unioning every shard.
+    ///
+    /// # Returns
+    /// The complete RoaringTreemap or empty if not found
+    pub fn get_bitmap(&self) -> Result<RoaringTreemap> {
+        let mut bitmap = RoaringTreemap::new();
+        for shard in 0..self.reader.table().config().shard_count {
+            bitmap |= self.shard_bitmap(shard)?;
+        }
+        Ok(bitmap)
+    }
+
+    /// Checks if a member exists, decoding only the member's shard.
+    ///
+    /// # Arguments
+    /// * `member` - The member to check for
+    ///
+    /// # Returns
+    /// True if the member exists, false otherwise
+    pub fn contains_member(&self, member: u64) -> Result<bool> {
+        let shard = select_shard(
+            &self.base_key,
+            member,
+            self.reader.table().config().shard_count,
+        )?;
+        Ok(self.shard_bitmap(shard)?.contains(member))
+    }
+
+    /// Gets the number of members across all shards.
+    ///
+    /// # Returns
+    /// The number of members in the bitmap
+    pub fn get_member_count(&self) -> Result<u64> {
+        let mut count = 0;
+        for shard in 0..self.reader.table().config().shard_count {
+            count += self.shard_bitmap(shard)?.len();
+        }
+        Ok(count)
+    }
+}
+
+impl<K: Key + 'static> PartitionedRead<'_, K, RoaringValue> {
+    /// Creates a pinned read view over the bitmap of the given key.
+    ///
+    /// Nothing is read until the snapshot is queried.
+    ///
+    /// # Arguments
+    /// * `key` - The base key to pin
+    ///
+    /// # Returns
+    /// Snapshot that caches decoded shards across queries
+    pub fn snapshot<'r, 'k>(&'r self, key: impl Borrow<K::SelfType<'k>>) -> RoaringSnapshot<'r, K> {
+        let shard_count = self.table().config().shard_count as usize;
+        RoaringSnapshot {
+            reader: self,
+            base_key: encode_base_key::<K>(key.borrow()),
+            shards: (0..shard_count).map(|_| OnceCell::new()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::partition::{PartitionConfig, PartitionedTable, PartitionedWrite};
+    use crate::roaring::{PartitionedRoaringRead, PartitionedRoaringWrite};
+    use redb::{Database, ReadableDatabase};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_snapshot_matches_reader() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = Database::create(temp_file.path()).unwrap();
+        let config = PartitionConfig::new(4, 128, false)
+            .unwrap()
+            .with_tombstones(true);
+        let table: PartitionedTable<u64, RoaringValue> = PartitionedTable::new("snap", config);
+        table.ensure_table_exists(&db).unwrap();
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer
+                .insert_members(7u64, (0..500u64).map(|i| i * 3))
+                .unwrap();
+            writer.remove_members(7u64, [0, 3, 6]).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let reader = PartitionedRead::new(&table, &read_txn);
+        let snapshot = reader.snapshot(7u64);
+
+        assert_eq!(
+            snapshot.get_bitmap().unwrap(),
+            reader.get_bitmap(7u64).unwrap()
+        );
+        assert_eq!(snapshot.get_member_count().unwrap(), 497);
+        assert!(snapshot.contains_member(9).unwrap());
+        assert!(!snapshot.contains_member(3).unwrap());
+        assert!(!snapshot.contains_member(10).unwrap());
+        assert!(snapshot.shard_bitmap(4).is_err());
+
+        let empty = reader.snapshot(8u64);
+        assert_eq!(empty.get_member_count().unwrap(), 0);
+    }
+}
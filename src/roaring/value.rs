@@ -140,6 +140,47 @@ impl RoaringValue {
         Ok(1 + buf.len())
     }
 
+    /// Splits a RoaringTreemap into contiguous member ranges that each fit in `max_bytes`.
+    ///
+    /// The bitmap is halved by member rank until every part's serialized size
+    /// (including version prefix) is within the limit. A part holding a single
+    /// member cannot be split further and is returned as-is.
+    ///
+    /// # Arguments
+    /// * `bitmap` - The roaring bitmap to split
+    /// * `max_bytes` - Maximum serialized size of each part
+    ///
+    /// # Returns
+    /// Parts in ascending member order; empty if the bitmap is empty
+    pub fn split_bitmap(bitmap: &RoaringTreemap, max_bytes: usize) -> Result<Vec<RoaringTreemap>> {
+        let mut parts = Vec::new();
+        let mut pending = vec![bitmap.clone()];
+
+        while let Some(part) = pending.pop() {
+            if part.is_empty() {
+                continue;
+            }
+            if part.len() == 1 || Self::get_serialized_size_for(&part)? <= max_bytes {
+                parts.push(part);
+                continue;
+            }
+
+            let pivot = part.select(part.len() / 2).ok_or_else(|| {
+                RoaringError::InvalidBitmap("Split pivot out of range".to_string())
+            })?;
+            let mut upper = part.clone();
+            upper.remove_range(..pivot);
+            let mut lower = part;
+            lower.remove_range(pivot..);
+
+            // Lower half is popped first so parts come out in member order
+            pending.push(upper);
+            pending.push(lower);
+        }
+
+        Ok(parts)
+    }
+
     /// Creates a RoaringValue from a single value.
     pub fn from_single(value: u64) -> Self {
         let mut bitmap = RoaringTreemap::new();
@@ -147,23 +188,6 @@ impl RoaringValue {
         Self { bitmap }
     }
 
-    /// Creates a RoaringValue from an iterator of values.
-    pub fn from_iter<I>(iter: I) -> Self
-    where
-        I: IntoIterator<Item = u64>,
-    {
-        let values: Vec<u64> = iter.into_iter().collect();
-        let bitmap =
-            RoaringTreemap::from_sorted_iter(values.iter().cloned()).unwrap_or_else(|_| {
-                let mut bitmap = RoaringTreemap::new();
-                for value in &values {
-                    bitmap.insert(*value);
-                }
-                bitmap
-            });
-        Self { bitmap }
-    }
-
     /// Returns the number of members in the bitmap.
     pub fn len(&self) -> u64 {
         self.bitmap.len()
@@ -181,6 +205,25 @@ impl From<RoaringTreemap> for RoaringValue {
     }
 }
 
+impl FromIterator<u64> for RoaringValue {
+    /// Creates a RoaringValue from an iterator of values.
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        let values: Vec<u64> = iter.into_iter().collect();
+        let bitmap =
+            RoaringTreemap::from_sorted_iter(values.iter().cloned()).unwrap_or_else(|_| {
+                let mut bitmap = RoaringTreemap::new();
+                for value in &values {
+                    bitmap.insert(*value);
+                }
+                bitmap
+            });
+        Self { bitmap }
+    }
+}
+
 impl Default for RoaringValue {
     fn default() -> Self {
         Self::empty()
@@ -191,7 +234,7 @@ impl MergeableValue for RoaringValue {
     fn merge(existing: Option<Self>, incoming: Self) -> Self {
         match existing {
             Some(mut existing) => {
-                existing.bitmap.extend(incoming.bitmap.into_iter());
+                existing.bitmap.extend(incoming.bitmap);
                 existing
             }
             None => incoming,
@@ -306,6 +349,37 @@ mod tests {
         assert_eq!(value, decoded);
     }
 
+    #[test]
+    fn test_split_bitmap() {
+        let bitmap: RoaringTreemap = (0..10_000u64).map(|i| i * 7).collect();
+        let max_bytes = 512;
+
+        let parts = RoaringValue::split_bitmap(&bitmap, max_bytes).unwrap();
+        assert!(parts.len() > 1);
+
+        let mut previous_max = None;
+        let mut union = RoaringTreemap::new();
+        for part in &parts {
+            assert!(RoaringValue::get_serialized_size_for(part).unwrap() <= max_bytes);
+            // Parts cover disjoint, ascending member ranges
+            assert!(previous_max < part.min());
+            previous_max = part.max();
+            union |= part;
+        }
+        assert_eq!(union, bitmap);
+
+        let small = RoaringValue::from_single(1).into_bitmap();
+        assert_eq!(
+            RoaringValue::split_bitmap(&small, max_bytes).unwrap(),
+            vec![small]
+        );
+        assert!(
+            RoaringValue::split_bitmap(&RoaringTreemap::new(), max_bytes)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
     #[test]
     fn test_invalid_version() {
         let mut invalid_data = vec![99]; // Invalid version
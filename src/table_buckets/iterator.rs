@@ -3,13 +3,28 @@
 //! Provides efficient iteration over bucket ranges for specific base keys
 //! by opening bucket-specific tables on demand.
 
+use crate::error::DatabaseError;
 use crate::key_buckets::BucketError;
 use crate::table_buckets::TableBucketBuilder;
-use redb::{ReadOnlyMultimapTable, ReadOnlyTable, ReadTransaction, TableError};
+use redb::{
+    AccessGuard, MultimapTableDefinition, MultimapValue, Range, ReadOnlyMultimapTable,
+    ReadOnlyTable, ReadTransaction, StorageError, TableDefinition, TableError, TableHandle,
+};
 use std::borrow::Borrow;
-use std::collections::VecDeque;
+use std::iter::{Rev, Take};
 use std::marker::PhantomData;
 
+/// Resolves the table name of a bucket for the range iterators.
+pub(crate) trait BucketTableNames {
+    fn table_name(&self, bucket: u64) -> String;
+}
+
+impl BucketTableNames for TableBucketBuilder {
+    fn table_name(&self, bucket: u64) -> String {
+        TableBucketBuilder::table_name(self, bucket)
+    }
+}
+
 /// Iterator over a range of buckets for a specific base key.
 ///
 /// Each bucket is stored in its own table. The iterator opens each bucket
@@ -24,7 +39,7 @@ where
     for<'b> V: From<V::SelfType<'b>>,
 {
     txn: &'a ReadTransaction,
-    builder: &'a TableBucketBuilder,
+    names: &'a dyn BucketTableNames,
     base_key: K,
     start_bucket: u64,
     end_bucket: u64,
@@ -57,20 +72,71 @@ where
         }
 
         let bucket_size = builder.bucket_size();
-        let start_bucket = start_sequence / bucket_size;
-        let end_bucket = end_sequence / bucket_size;
-
-        Ok(Self {
+        Ok(Self::with_buckets(
             txn,
             builder,
             base_key,
+            start_sequence / bucket_size,
+            end_sequence / bucket_size,
+        ))
+    }
+
+    /// Create an iterator over an already resolved bucket range.
+    pub(crate) fn with_buckets(
+        txn: &'a ReadTransaction,
+        names: &'a dyn BucketTableNames,
+        base_key: K,
+        start_bucket: u64,
+        end_bucket: u64,
+    ) -> Self {
+        Self {
+            txn,
+            names,
+            base_key,
             start_bucket,
             end_bucket,
             front_bucket: start_bucket as i64,
             back_bucket: end_bucket as i64,
             finished: false,
             _phantom: PhantomData,
-        })
+        }
+    }
+
+    /// Iterate the most recent values of a base key, newest first.
+    ///
+    /// The bucket range is discovered from the bucket tables present in the
+    /// transaction, so callers don't need to know the current sequence.
+    ///
+    /// # Arguments
+    /// * `txn` - The read transaction
+    /// * `builder` - The builder the bucket tables were created with
+    /// * `base_key` - The key to look up in each table
+    /// * `count` - Maximum number of values to yield
+    ///
+    /// # Returns
+    /// Iterator over up to `count` values from the newest bucket backwards
+    pub fn latest(
+        txn: &'a ReadTransaction,
+        builder: &'a TableBucketBuilder,
+        base_key: K,
+        count: usize,
+    ) -> Result<Take<Rev<Self>>, BucketError> {
+        let tables = txn.list_tables().map_err(|err| {
+            BucketError::IterationError(DatabaseError::new("Failed to list tables", err))
+        })?;
+
+        let iter = match builder.bucket_span(tables.map(|table| table.name().to_string())) {
+            Some((start_bucket, end_bucket)) => {
+                Self::with_buckets(txn, builder, base_key, start_bucket, end_bucket)
+            }
+            None => {
+                let mut iter = Self::with_buckets(txn, builder, base_key, 0, 0);
+                iter.finished = true;
+                iter
+            }
+        };
+
+        Ok(iter.rev().take(count))
     }
 
     /// Get the bucket range.
@@ -79,14 +145,13 @@ where
     }
 
     fn open_table(&self, bucket: u64) -> Result<Option<ReadOnlyTable<K, V>>, BucketError> {
-        let definition = self.builder.table_definition::<K, V>(bucket);
-        match self.txn.open_table(definition) {
+        let name = self.names.table_name(bucket);
+        match self.txn.open_table(TableDefinition::<K, V>::new(&name)) {
             Ok(table) => Ok(Some(table)),
             Err(TableError::TableDoesNotExist(_)) => Ok(None),
-            Err(err) => Err(BucketError::IterationError(format!(
-                "Failed to open bucket table {}: {}",
-                bucket, err
-            ))),
+            Err(err) => Err(BucketError::IterationError(
+                DatabaseError::new("Failed to open bucket table", err).bucket(bucket),
+            )),
         }
     }
 }
@@ -125,9 +190,9 @@ where
                 Ok(None) => continue,
                 Err(err) => {
                     self.finished = true;
-                    return Some(Err(BucketError::IterationError(format!(
-                        "Database error during point lookup: {}",
-                        err
+                    return Some(Err(BucketError::IterationError(DatabaseError::new(
+                        "Database error during point lookup",
+                        err,
                     ))));
                 }
             }
@@ -170,9 +235,9 @@ where
                 Ok(None) => continue,
                 Err(err) => {
                     self.finished = true;
-                    return Some(Err(BucketError::IterationError(format!(
-                        "Database error during point lookup: {}",
-                        err
+                    return Some(Err(BucketError::IterationError(DatabaseError::new(
+                        "Database error during point lookup",
+                        err,
                     ))));
                 }
             }
@@ -186,9 +251,13 @@ where
 /// Iterator over a range of buckets for a specific base key in multimap tables.
 ///
 /// This iterator flattens the multimap values, yielding each value in order
-/// across the requested bucket range via per-bucket point lookups.
+/// across the requested bucket range via per-bucket point lookups. Values
+/// are streamed from the table rather than collected, so memory use doesn't
+/// grow with the number of values per key.
 ///
 /// Implements `DoubleEndedIterator` to iterate buckets and values in reverse.
+/// Each bucket table is opened once, by whichever cursor reaches it first,
+/// and its values are shared with the other cursor.
 pub struct TableBucketRangeMultimapIterator<'a, K, V>
 where
     K: redb::Key + Clone + 'static,
@@ -197,15 +266,15 @@ where
     for<'b> V: From<V::SelfType<'b>>,
 {
     txn: &'a ReadTransaction,
-    builder: &'a TableBucketBuilder,
+    names: &'a dyn BucketTableNames,
     base_key: K,
     start_bucket: u64,
     end_bucket: u64,
     front_bucket: i64,
     back_bucket: i64,
     finished: bool,
-    front_values: Option<VecDeque<V>>,
-    back_values: Option<VecDeque<V>>,
+    front_values: Option<MultimapValue<'static, V>>,
+    back_values: Option<MultimapValue<'static, V>>,
 }
 
 impl<'a, K, V> TableBucketRangeMultimapIterator<'a, K, V>
@@ -231,13 +300,27 @@ where
         }
 
         let bucket_size = builder.bucket_size();
-        let start_bucket = start_sequence / bucket_size;
-        let end_bucket = end_sequence / bucket_size;
-
-        Ok(Self {
+        Ok(Self::with_buckets(
             txn,
             builder,
             base_key,
+            start_sequence / bucket_size,
+            end_sequence / bucket_size,
+        ))
+    }
+
+    /// Create an iterator over an already resolved bucket range.
+    pub(crate) fn with_buckets(
+        txn: &'a ReadTransaction,
+        names: &'a dyn BucketTableNames,
+        base_key: K,
+        start_bucket: u64,
+        end_bucket: u64,
+    ) -> Self {
+        Self {
+            txn,
+            names,
+            base_key,
             start_bucket,
             end_bucket,
             front_bucket: start_bucket as i64,
@@ -245,7 +328,7 @@ where
             finished: false,
             front_values: None,
             back_values: None,
-        })
+        }
     }
 
     /// Get the bucket range.
@@ -254,16 +337,44 @@ where
     }
 
     fn open_table(&self, bucket: u64) -> Result<Option<ReadOnlyMultimapTable<K, V>>, BucketError> {
-        let definition = self.builder.multimap_table_definition::<K, V>(bucket);
-        match self.txn.open_multimap_table(definition) {
+        let name = self.names.table_name(bucket);
+        match self
+            .txn
+            .open_multimap_table(MultimapTableDefinition::<K, V>::new(&name))
+        {
             Ok(table) => Ok(Some(table)),
             Err(TableError::TableDoesNotExist(_)) => Ok(None),
-            Err(err) => Err(BucketError::IterationError(format!(
-                "Failed to open bucket table {}: {}",
-                bucket, err
-            ))),
+            Err(err) => Err(BucketError::IterationError(
+                DatabaseError::new("Failed to open bucket table", err).bucket(bucket),
+            )),
         }
     }
+
+    /// Look the base key up in a bucket, None if the bucket has no table.
+    fn load(&self, bucket: u64) -> Result<Option<MultimapValue<'static, V>>, BucketError> {
+        let Some(table) = self.open_table(bucket)? else {
+            return Ok(None);
+        };
+        table.get(self.base_key.clone()).map(Some).map_err(|err| {
+            BucketError::IterationError(DatabaseError::new(
+                "Database error during point lookup",
+                err,
+            ))
+        })
+    }
+
+    fn decode(
+        &mut self,
+        value: Result<AccessGuard<'static, V>, StorageError>,
+    ) -> Result<V, BucketError> {
+        value.map(|guard| V::from(guard.value())).map_err(|err| {
+            self.finished = true;
+            BucketError::IterationError(DatabaseError::new(
+                "Database error during point lookup",
+                err,
+            ))
+        })
+    }
 }
 
 impl<'a, K, V> Iterator for TableBucketRangeMultimapIterator<'a, K, V>
@@ -282,13 +393,17 @@ where
 
         loop {
             if let Some(values) = self.front_values.as_mut() {
-                if let Some(value) = values.pop_front() {
-                    return Some(Ok(value));
+                match values.next() {
+                    Some(value) => return Some(self.decode(value)),
+                    None => self.front_values = None,
                 }
-                self.front_values = None;
             }
 
             if self.front_bucket > self.back_bucket {
+                // The back cursor may have loaded the bucket both cursors met in
+                if let Some(value) = self.back_values.as_mut().and_then(Iterator::next) {
+                    return Some(self.decode(value));
+                }
                 self.finished = true;
                 return None;
             }
@@ -296,44 +411,12 @@ where
             let bucket = self.front_bucket as u64;
             self.front_bucket += 1;
 
-            let table = match self.open_table(bucket) {
-                Ok(Some(table)) => table,
-                Ok(None) => continue,
+            match self.load(bucket) {
+                Ok(values) => self.front_values = values,
                 Err(err) => {
                     self.finished = true;
                     return Some(Err(err));
                 }
-            };
-
-            match table.get(self.base_key.clone()) {
-                Ok(values) => {
-                    let mut collected = VecDeque::new();
-                    for value_result in values {
-                        match value_result {
-                            Ok(value_guard) => {
-                                collected.push_back(V::from(value_guard.value()));
-                            }
-                            Err(err) => {
-                                self.finished = true;
-                                return Some(Err(BucketError::IterationError(format!(
-                                    "Database error during point lookup: {}",
-                                    err
-                                ))));
-                            }
-                        }
-                    }
-                    if collected.is_empty() {
-                        continue;
-                    }
-                    self.front_values = Some(collected);
-                }
-                Err(err) => {
-                    self.finished = true;
-                    return Some(Err(BucketError::IterationError(format!(
-                        "Database error during point lookup: {}",
-                        err
-                    ))));
-                }
             }
         }
     }
@@ -353,13 +436,21 @@ where
 
         loop {
             if let Some(values) = self.back_values.as_mut() {
-                if let Some(value) = values.pop_back() {
-                    return Some(Ok(value));
+                match values.next_back() {
+                    Some(value) => return Some(self.decode(value)),
+                    None => self.back_values = None,
                 }
-                self.back_values = None;
             }
 
             if self.front_bucket > self.back_bucket {
+                // The front cursor may have loaded the bucket both cursors met in
+                if let Some(value) = self
+                    .front_values
+                    .as_mut()
+                    .and_then(DoubleEndedIterator::next_back)
+                {
+                    return Some(self.decode(value));
+                }
                 self.finished = true;
                 return None;
             }
@@ -367,46 +458,180 @@ where
             let bucket = self.back_bucket as u64;
             self.back_bucket -= 1;
 
-            let table = match self.open_table(bucket) {
-                Ok(Some(table)) => table,
-                Ok(None) => continue,
+            match self.load(bucket) {
+                Ok(values) => self.back_values = values,
                 Err(err) => {
                     self.finished = true;
                     return Some(Err(err));
                 }
-            };
+            }
+        }
+    }
+}
 
-            match table.get(self.base_key.clone()) {
-                Ok(values) => {
-                    let mut collected = VecDeque::new();
-                    for value_result in values {
-                        match value_result {
-                            Ok(value_guard) => {
-                                collected.push_back(V::from(value_guard.value()));
-                            }
-                            Err(err) => {
-                                self.finished = true;
-                                return Some(Err(BucketError::IterationError(format!(
-                                    "Database error during point lookup: {}",
-                                    err
-                                ))));
-                            }
-                        }
-                    }
-                    if collected.is_empty() {
-                        continue;
-                    }
-                    self.back_values = Some(collected);
-                }
+/// Next entry of one bucket table taking part in a scan.
+struct ScanHead<K, V>
+where
+    K: redb::Key + 'static,
+    V: redb::Value + 'static,
+{
+    bucket: u64,
+    range: Range<'static, K, V>,
+    peeked: Result<(AccessGuard<'static, K>, AccessGuard<'static, V>), StorageError>,
+}
+
+/// Iterator over all keys stored in a range of bucket tables.
+///
+/// Each bucket table is read with its own range iterator and the iterators
+/// are merged by key, yielding `(bucket, key, value)` in key order. A key
+/// stored in several buckets is yielded once per bucket, in bucket order.
+pub struct TableBucketScanIterator<K, V>
+where
+    K: redb::Key + 'static,
+    for<'b> K: From<K::SelfType<'b>>,
+    V: redb::Value + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+{
+    start_bucket: u64,
+    end_bucket: u64,
+    /// Buckets with entries left, in bucket order
+    heads: Vec<ScanHead<K, V>>,
+    finished: bool,
+}
+
+impl<K, V> TableBucketScanIterator<K, V>
+where
+    K: redb::Key + 'static,
+    for<'b> K: From<K::SelfType<'b>>,
+    V: redb::Value + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+{
+    /// Create a new scan over the bucket tables covering a sequence range.
+    pub fn new(
+        txn: &ReadTransaction,
+        builder: &TableBucketBuilder,
+        start_sequence: u64,
+        end_sequence: u64,
+    ) -> Result<Self, BucketError> {
+        if start_sequence > end_sequence {
+            return Err(BucketError::InvalidRange {
+                start: start_sequence,
+                end: end_sequence,
+            });
+        }
+
+        let bucket_size = builder.bucket_size();
+        let start_bucket = start_sequence / bucket_size;
+        let end_bucket = end_sequence / bucket_size;
+
+        let mut heads = Vec::new();
+        for bucket in start_bucket..=end_bucket {
+            let name = builder.table_name(bucket);
+            let table = match txn.open_table(TableDefinition::<K, V>::new(&name)) {
+                Ok(table) => table,
+                Err(TableError::TableDoesNotExist(_)) => continue,
                 Err(err) => {
-                    self.finished = true;
-                    return Some(Err(BucketError::IterationError(format!(
-                        "Database error during point lookup: {}",
-                        err
-                    ))));
+                    return Err(BucketError::IterationError(
+                        DatabaseError::new("Failed to open bucket table", err).bucket(bucket),
+                    ))
                 }
+            };
+
+            let mut range = table.range::<K::SelfType<'static>>(..).map_err(|err| {
+                BucketError::IterationError(
+                    DatabaseError::new("Failed to scan bucket table", err).bucket(bucket),
+                )
+            })?;
+            if let Some(peeked) = range.next() {
+                heads.push(ScanHead {
+                    bucket,
+                    range,
+                    peeked,
+                });
             }
         }
+
+        Ok(Self {
+            start_bucket,
+            end_bucket,
+            heads,
+            finished: false,
+        })
+    }
+
+    /// Get the bucket range.
+    pub fn bucket_range(&self) -> (u64, u64) {
+        (self.start_bucket, self.end_bucket)
+    }
+}
+
+impl<K, V> Iterator for TableBucketScanIterator<K, V>
+where
+    K: redb::Key + 'static,
+    for<'b> K: From<K::SelfType<'b>>,
+    V: redb::Value + 'static,
+    for<'b> V: From<V::SelfType<'b>>,
+{
+    type Item = Result<(u64, K, V), BucketError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        if let Some(index) = self.heads.iter().position(|head| head.peeked.is_err()) {
+            self.finished = true;
+            let head = self.heads.remove(index);
+            let Err(err) = head.peeked else {
+                unreachable!("position matched an error");
+            };
+            return Some(Err(BucketError::IterationError(
+                DatabaseError::new("Database error while scanning bucket table", err)
+                    .bucket(head.bucket),
+            )));
+        }
+
+        // Pick the smallest key; on ties the earlier bucket wins
+        let mut lowest: Option<usize> = None;
+        for (index, head) in self.heads.iter().enumerate() {
+            let Ok((key, _)) = &head.peeked else {
+                unreachable!("errors are returned before comparison");
+            };
+
+            let is_lower = match lowest {
+                None => true,
+                Some(current) => {
+                    let Ok((current_key, _)) = &self.heads[current].peeked else {
+                        unreachable!("errors are returned before comparison");
+                    };
+                    K::compare(
+                        K::as_bytes(&key.value()).as_ref(),
+                        K::as_bytes(&current_key.value()).as_ref(),
+                    )
+                    .is_lt()
+                }
+            };
+            if is_lower {
+                lowest = Some(index);
+            }
+        }
+
+        let Some(index) = lowest else {
+            self.finished = true;
+            return None;
+        };
+
+        let head = &mut self.heads[index];
+        let bucket = head.bucket;
+        let entry = match head.range.next() {
+            Some(next) => std::mem::replace(&mut head.peeked, next),
+            None => self.heads.remove(index).peeked,
+        };
+
+        match entry {
+            Ok((key, value)) => Some(Ok((bucket, K::from(key.value()), V::from(value.value())))),
+            Err(_) => unreachable!("errors are returned before advancing"),
+        }
     }
 }
 
@@ -424,6 +649,27 @@ pub trait TableBucketIterExt {
         for<'b> K: Borrow<K::SelfType<'b>>,
         V: redb::Value + 'static,
         for<'b> V: From<V::SelfType<'b>>;
+
+    /// Iterate every key of the bucket tables covering a sequence range.
+    ///
+    /// # Arguments
+    /// * `builder` - The builder the bucket tables were created with
+    /// * `start_sequence` - First sequence of the range
+    /// * `end_sequence` - Last sequence of the range (inclusive)
+    ///
+    /// # Returns
+    /// Iterator yielding `(bucket, key, value)` in key order
+    fn table_bucket_scan<K, V>(
+        &self,
+        builder: &TableBucketBuilder,
+        start_sequence: u64,
+        end_sequence: u64,
+    ) -> Result<TableBucketScanIterator<K, V>, BucketError>
+    where
+        K: redb::Key + 'static,
+        for<'b> K: From<K::SelfType<'b>>,
+        V: redb::Value + 'static,
+        for<'b> V: From<V::SelfType<'b>>;
 }
 
 impl TableBucketIterExt for ReadTransaction {
@@ -442,6 +688,21 @@ impl TableBucketIterExt for ReadTransaction {
     {
         TableBucketRangeIterator::<K, V>::new(self, builder, base_key, start_sequence, end_sequence)
     }
+
+    fn table_bucket_scan<K, V>(
+        &self,
+        builder: &TableBucketBuilder,
+        start_sequence: u64,
+        end_sequence: u64,
+    ) -> Result<TableBucketScanIterator<K, V>, BucketError>
+    where
+        K: redb::Key + 'static,
+        for<'b> K: From<K::SelfType<'b>>,
+        V: redb::Value + 'static,
+        for<'b> V: From<V::SelfType<'b>>,
+    {
+        TableBucketScanIterator::<K, V>::new(self, builder, start_sequence, end_sequence)
+    }
 }
 
 /// Extension trait for table bucket iteration on read transactions for multimap tables.
@@ -555,6 +816,18 @@ mod tests {
             TableBucketRangeIterator::<u64, String>::new(&read_txn, &builder, 123u64, 200, 100);
         assert!(invalid_iter.is_err());
 
+        let values: Vec<String> = TableBucketRangeIterator::latest(&read_txn, &builder, 123u64, 2)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(
+            values,
+            vec!["value_250".to_string(), "value_150".to_string()]
+        );
+
+        let other = TableBucketBuilder::new(100, "table_bucket_other")?;
+        let mut empty =
+            TableBucketRangeIterator::<u64, String>::latest(&read_txn, &other, 1u64, 5)?;
+        assert!(empty.next().is_none());
+
         Ok(())
     }
 
@@ -601,6 +874,59 @@ mod tests {
         let values: Vec<u64> = iter.collect::<Result<_, _>>()?;
         assert_eq!(values, vec![99u64, 100u64]);
 
+        // Both cursors share the bucket they meet in
+        let mut iter =
+            read_txn.table_bucket_multimap_range::<u64, u64>(&builder, 123u64, 0, 199)?;
+        assert_eq!(iter.next().transpose()?, Some(10));
+        assert_eq!(iter.next_back().transpose()?, Some(40));
+        assert_eq!(iter.next_back().transpose()?, Some(30));
+        assert_eq!(iter.next_back().transpose()?, Some(20));
+        assert_eq!(iter.next().transpose()?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_bucket_scan() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(100, "table_bucket_scan")?;
+
+        {
+            let write_txn = db.begin_write()?;
+            for (sequence, key, value) in [
+                (10, 5u64, "a"),
+                (20, 1, "b"),
+                (150, 3, "c"),
+                (160, 5, "d"),
+                (250, 1, "e"),
+                (450, 2, "f"),
+            ] {
+                builder.insert::<u64, String>(&write_txn, sequence, key, value.to_string())?;
+            }
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        let iter = read_txn.table_bucket_scan::<u64, String>(&builder, 0, 399)?;
+        assert_eq!(iter.bucket_range(), (0, 3));
+
+        let entries: Vec<(u64, u64, String)> = iter.collect::<Result<_, _>>()?;
+        assert_eq!(
+            entries,
+            vec![
+                (0, 1, "b".to_string()),
+                (2, 1, "e".to_string()),
+                (1, 3, "c".to_string()),
+                (0, 5, "a".to_string()),
+                (1, 5, "d".to_string()),
+            ]
+        );
+
+        let iter = TableBucketScanIterator::<u64, String>::new(&read_txn, &builder, 500, 900)?;
+        assert_eq!(iter.count(), 0);
+        assert!(TableBucketScanIterator::<u64, String>::new(&read_txn, &builder, 9, 1).is_err());
+
         Ok(())
     }
 }
@@ -0,0 +1,126 @@
+//! Value lookups across multimap bucket tables.
+//!
+//! Answers "when did X happen" for multimap buckets by scanning the bucket
+//! tables of a sequence range for a value, without a separate reverse index.
+
+use crate::error::DatabaseError;
+use crate::key_buckets::BucketError;
+use crate::table_buckets::TableBucketBuilder;
+use redb::{Key, MultimapTableDefinition, ReadTransaction, ReadableMultimapTable, TableError};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+
+fn find_error(bucket: u64, err: impl Into<redb::Error>) -> BucketError {
+    BucketError::IterationError(
+        DatabaseError::new("Failed to search bucket table", err).bucket(bucket),
+    )
+}
+
+impl TableBucketBuilder {
+    /// Find the buckets and keys under which a value is stored in multimap bucket tables.
+    ///
+    /// Every key of the bucket tables covering the sequence range is
+    /// checked for `value`, so the cost grows with the data in the range,
+    /// not with the number of matches.
+    ///
+    /// # Arguments
+    /// * `txn` - The read transaction
+    /// * `value` - The value to look for
+    /// * `start_sequence` - First sequence of the range
+    /// * `end_sequence` - Last sequence of the range (inclusive)
+    ///
+    /// # Returns
+    /// `(bucket, key)` pairs holding the value, in bucket and then key order
+    pub fn find_multimap_value<'v, K, V>(
+        &self,
+        txn: &ReadTransaction,
+        value: impl Borrow<V::SelfType<'v>>,
+        start_sequence: u64,
+        end_sequence: u64,
+    ) -> Result<Vec<(u64, K)>, BucketError>
+    where
+        K: Key + 'static,
+        for<'b> K: From<K::SelfType<'b>>,
+        V: Key + 'static,
+    {
+        if start_sequence > end_sequence {
+            return Err(BucketError::InvalidRange {
+                start: start_sequence,
+                end: end_sequence,
+            });
+        }
+
+        let needle = V::as_bytes(value.borrow()).as_ref().to_vec();
+        let mut found = Vec::new();
+        for bucket in
+            self.bucket_for_sequence(start_sequence)..=self.bucket_for_sequence(end_sequence)
+        {
+            let name = self.table_name(bucket);
+            let table = match txn.open_multimap_table(MultimapTableDefinition::<K, V>::new(&name)) {
+                Ok(table) => table,
+                Err(TableError::TableDoesNotExist(_)) => continue,
+                Err(err) => return Err(find_error(bucket, err)),
+            };
+
+            for entry in table.iter().map_err(|err| find_error(bucket, err))? {
+                let (key, values) = entry.map_err(|err| find_error(bucket, err))?;
+                for stored in values {
+                    let stored = stored.map_err(|err| find_error(bucket, err))?;
+                    // Values are sorted, so stop once past the needle
+                    match V::compare(V::as_bytes(&stored.value()).as_ref(), &needle) {
+                        Ordering::Less => continue,
+                        Ordering::Equal => found.push((bucket, K::from(key.value()))),
+                        Ordering::Greater => {}
+                    }
+                    break;
+                }
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::table_buckets::TableBucketBuilder;
+    use redb::{Database, ReadableDatabase};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_find_multimap_value() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(100, "find_value")?;
+
+        let write_txn = db.begin_write()?;
+        for (sequence, account, event) in [
+            (10, 1u64, 5u64),
+            (20, 2, 5),
+            (30, 2, 6),
+            (150, 1, 6),
+            (250, 3, 5),
+            (260, 3, 9),
+            (450, 1, 5),
+        ] {
+            builder.insert_multimap::<u64, u64>(&write_txn, sequence, account, event)?;
+        }
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        let found: Vec<(u64, u64)> =
+            builder.find_multimap_value::<u64, u64>(&read_txn, 5u64, 0, 399)?;
+        assert_eq!(found, vec![(0, 1), (0, 2), (2, 3)]);
+        let found: Vec<(u64, u64)> =
+            builder.find_multimap_value::<u64, u64>(&read_txn, 6u64, 100, 999)?;
+        assert_eq!(found, vec![(1, 1)]);
+        assert!(builder
+            .find_multimap_value::<u64, u64>(&read_txn, 7u64, 0, 999)?
+            .is_empty());
+        assert!(builder
+            .find_multimap_value::<u64, u64>(&read_txn, 5u64, 9, 1)
+            .is_err());
+
+        Ok(())
+    }
+}
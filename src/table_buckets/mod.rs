@@ -4,145 +4,83 @@
 //! mapping each bucket to its own redb table. It mirrors the bucketed key
 //! approach but uses table-per-bucket instead of key prefixes.
 
+use crate::dbcopy::{copy_database, CopyPlan};
+use crate::error::DatabaseError;
 use crate::MergeableValue;
 use redb::{
-    Key, MultimapTableDefinition, ReadableTable, TableDefinition, TableHandle, Value,
+    Database, Key, MultimapTable, MultimapTableDefinition, MultimapTableHandle, ReadTransaction,
+    ReadableMultimapTable, ReadableTable, Table, TableDefinition, TableError, TableHandle, Value,
     WriteTransaction,
 };
 use std::borrow::Borrow;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 
 pub mod iterator;
+mod lookup;
+#[cfg(feature = "parallel")]
+mod parallel;
+pub mod time;
 
 pub use crate::key_buckets::BucketError;
 pub use iterator::{
     TableBucketIterExt, TableBucketMultimapIterExt, TableBucketRangeIterator,
-    TableBucketRangeMultimapIterator,
+    TableBucketRangeMultimapIterator, TableBucketScanIterator,
 };
+pub use time::{TimeGranularity, TimeTableBucketBuilder};
 
-/// Builder for table bucket configuration and name resolution.
-#[derive(Debug, Clone)]
-pub struct TableBucketBuilder {
-    bucket_size: u64,
-    table_prefix: String,
-    table_names: Arc<Mutex<HashMap<u64, &'static str>>>,
-}
-
-#[cfg(test)]
-mod tests {
-    use super::TableBucketBuilder;
-    use crate::MergeableValue;
-    use redb::{Database, ReadableDatabase, TableDefinition, TableError};
-    use tempfile::NamedTempFile;
-
-    impl MergeableValue for String {
-        fn merge(existing: Option<Self>, incoming: Self) -> Self {
-            match existing {
-                Some(existing) => format!("{}+{}", existing, incoming),
-                None => incoming,
-            }
-        }
-    }
-
-    #[test]
-    fn merge_bucket_tables_into_target() -> Result<(), Box<dyn std::error::Error>> {
-        let temp_file = NamedTempFile::new()?;
-        let db = Database::create(temp_file.path())?;
-        let builder = TableBucketBuilder::new(100, "merge_test")?;
-        let target: TableDefinition<u64, String> = TableDefinition::new("merged");
-
-        {
-            let write_txn = db.begin_write()?;
-            {
-                let mut table = write_txn.open_table(builder.table_definition::<u64, String>(0))?;
-                table.insert(1u64, "a".to_string())?;
-                table.insert(2u64, "x".to_string())?;
-            }
-            {
-                let mut table = write_txn.open_table(builder.table_definition::<u64, String>(1))?;
-                table.insert(1u64, "b".to_string())?;
-                table.insert(3u64, "y".to_string())?;
-            }
-            {
-                let mut table = write_txn.open_table(builder.table_definition::<u64, String>(2))?;
-                table.insert(1u64, "c".to_string())?;
-            }
-            write_txn.commit()?;
-        }
-
-        {
-            let mut write_txn = db.begin_write()?;
-            builder.merge(&mut write_txn, target, 0, 1)?;
-            write_txn.commit()?;
-        }
+/// Registry of the buckets opened through `TableBucketBuilder`, keyed by
+/// table prefix and bucket.
+pub const TABLE_BUCKET_REGISTRY: TableDefinition<'static, (&str, u64), ()> =
+    TableDefinition::new("redb_extras_table_buckets");
 
-        let read_txn = db.begin_read()?;
-        let target_read: TableDefinition<u64, String> = TableDefinition::new("merged");
-        let table = read_txn.open_table(target_read)?;
-        assert_eq!(table.get(1u64)?.unwrap().value(), "a+b");
-        assert_eq!(table.get(2u64)?.unwrap().value(), "x");
-        assert_eq!(table.get(3u64)?.unwrap().value(), "y");
+fn registry_error(err: impl Into<redb::Error>) -> BucketError {
+    BucketError::DatabaseError(DatabaseError::new(
+        "Failed to access table bucket registry",
+        err,
+    ))
+}
 
-        match read_txn.open_table(builder.table_definition::<u64, String>(0)) {
-            Err(TableError::TableDoesNotExist(_)) => {}
-            _ => panic!("bucket 0 table should be deleted"),
-        }
+fn compact_error(bucket: u64, err: impl Into<redb::Error>) -> BucketError {
+    BucketError::DatabaseError(
+        DatabaseError::new("Failed to compact bucket table", err).bucket(bucket),
+    )
+}
 
-        match read_txn.open_table(builder.table_definition::<u64, String>(1)) {
-            Err(TableError::TableDoesNotExist(_)) => {}
-            _ => panic!("bucket 1 table should be deleted"),
-        }
+fn archive_error(err: impl Into<redb::Error>) -> BucketError {
+    BucketError::DatabaseError(DatabaseError::new("Failed to archive bucket tables", err))
+}
 
-        let bucket_two = read_txn.open_table(builder.table_definition::<u64, String>(2))?;
-        assert_eq!(bucket_two.get(1u64)?.unwrap().value(), "c");
+/// Preview of a bucket table merge, as reported by `merge_dry_run`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeReport<K> {
+    /// Number of rows in each existing bucket table of the range
+    pub bucket_rows: BTreeMap<u64, u64>,
+    /// Keys stored in more than one bucket or already present in the target,
+    /// in key order; these are the keys `MergeableValue::merge` would combine
+    pub conflicts: Vec<K>,
+}
 
-        Ok(())
+impl<K> MergeReport<K> {
+    /// Total number of rows that would be merged.
+    pub fn total_rows(&self) -> u64 {
+        self.bucket_rows.values().sum()
     }
+}
 
-    #[test]
-    fn merge_all_bucket_tables_into_target() -> Result<(), Box<dyn std::error::Error>> {
-        let temp_file = NamedTempFile::new()?;
-        let db = Database::create(temp_file.path())?;
-        let builder = TableBucketBuilder::new(100, "merge_all")?;
-        let target: TableDefinition<u64, String> = TableDefinition::new("merged_all");
-
-        {
-            let write_txn = db.begin_write()?;
-            {
-                let mut table = write_txn.open_table(builder.table_definition::<u64, String>(0))?;
-                table.insert(1u64, "a".to_string())?;
-            }
-            {
-                let mut table = write_txn.open_table(builder.table_definition::<u64, String>(2))?;
-                table.insert(1u64, "c".to_string())?;
-            }
-            write_txn.commit()?;
-        }
-
-        {
-            let mut write_txn = db.begin_write()?;
-            builder.merge_all(&mut write_txn, target)?;
-            write_txn.commit()?;
-        }
-
-        let read_txn = db.begin_read()?;
-        let target_read: TableDefinition<u64, String> = TableDefinition::new("merged_all");
-        let table = read_txn.open_table(target_read)?;
-        assert_eq!(table.get(1u64)?.unwrap().value(), "a+c");
-
-        match read_txn.open_table(builder.table_definition::<u64, String>(0)) {
-            Err(TableError::TableDoesNotExist(_)) => {}
-            _ => panic!("bucket 0 table should be deleted"),
-        }
-
-        match read_txn.open_table(builder.table_definition::<u64, String>(2)) {
-            Err(TableError::TableDoesNotExist(_)) => {}
-            _ => panic!("bucket 2 table should be deleted"),
-        }
-
-        Ok(())
-    }
+/// Builder for table bucket configuration and name resolution.
+///
+/// redb doesn't need `'static` table names, so `open_table`,
+/// `open_multimap_table` and the iterators build each name on demand and
+/// keep no per-bucket state. `bucket_table_name` and the `*_definition`
+/// methods remain for callers that need `'static` definitions; they leak one
+/// string per distinct bucket, so long-running processes should prefer the
+/// former.
+#[derive(Debug, Clone)]
+pub struct TableBucketBuilder {
+    bucket_size: u64,
+    table_prefix: String,
+    table_names: Arc<Mutex<HashMap<u64, &'static str>>>,
 }
 
 impl TableBucketBuilder {
@@ -178,7 +116,16 @@ impl TableBucketBuilder {
         sequence / self.bucket_size
     }
 
+    /// Get the name of a bucket's table without caching or leaking it.
+    pub fn table_name(&self, bucket: u64) -> String {
+        format!("{}_{}", self.table_prefix, bucket)
+    }
+
     /// Resolve the bucket table name, caching and leaking the name string.
+    ///
+    /// Every distinct bucket leaks one string for the life of the process.
+    /// Prefer `table_name` or the `open_*` helpers unless a `'static`
+    /// definition is required.
     pub fn bucket_table_name(&self, bucket: u64) -> &'static str {
         let mut table_names = self
             .table_names
@@ -189,8 +136,7 @@ impl TableBucketBuilder {
             return name;
         }
 
-        let name = format!("{}_{}", self.table_prefix, bucket);
-        let leaked = Box::leak(name.into_boxed_str());
+        let leaked = Box::leak(self.table_name(bucket).into_boxed_str());
         table_names.insert(bucket, leaked);
         leaked
     }
@@ -211,6 +157,156 @@ impl TableBucketBuilder {
         MultimapTableDefinition::new(self.bucket_table_name(bucket))
     }
 
+    /// Open a bucket table for writing and record the bucket in the registry.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `bucket` - The bucket whose table to open, created if missing
+    ///
+    /// # Returns
+    /// The opened bucket table
+    pub fn open_table<'txn, K: Key + 'static, V: Value + 'static>(
+        &self,
+        txn: &'txn WriteTransaction,
+        bucket: u64,
+    ) -> Result<Table<'txn, K, V>, BucketError> {
+        self.register(txn, bucket)?;
+        let name = self.table_name(bucket);
+        txn.open_table(TableDefinition::new(&name)).map_err(|err| {
+            BucketError::DatabaseError(
+                DatabaseError::new("Failed to open bucket table", err).bucket(bucket),
+            )
+        })
+    }
+
+    /// Open a bucket multimap table for writing and record the bucket in the registry.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `bucket` - The bucket whose table to open, created if missing
+    ///
+    /// # Returns
+    /// The opened bucket multimap table
+    pub fn open_multimap_table<'txn, K: Key + 'static, V: Key + 'static>(
+        &self,
+        txn: &'txn WriteTransaction,
+        bucket: u64,
+    ) -> Result<MultimapTable<'txn, K, V>, BucketError> {
+        self.register(txn, bucket)?;
+        let name = self.table_name(bucket);
+        txn.open_multimap_table(MultimapTableDefinition::new(&name))
+            .map_err(|err| {
+                BucketError::DatabaseError(
+                    DatabaseError::new("Failed to open bucket table", err).bucket(bucket),
+                )
+            })
+    }
+
+    /// Insert a value into the bucket table covering a sequence.
+    ///
+    /// Resolves the bucket, opens (or creates) its table through `open_table`
+    /// and inserts the entry, replacing any value stored under the key.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `sequence` - The sequence the entry belongs to
+    /// * `key` - The key within the bucket table
+    /// * `value` - The value to store
+    pub fn insert<'k, 'v, K: Key + 'static, V: Value + 'static>(
+        &self,
+        txn: &WriteTransaction,
+        sequence: u64,
+        key: impl Borrow<K::SelfType<'k>>,
+        value: impl Borrow<V::SelfType<'v>>,
+    ) -> Result<(), BucketError> {
+        let bucket = self.bucket_for_sequence(sequence);
+        let mut table = self.open_table::<K, V>(txn, bucket)?;
+        table.insert(key, value).map_err(|err| {
+            BucketError::DatabaseError(
+                DatabaseError::new("Failed to insert into bucket table", err).bucket(bucket),
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Insert a value into the bucket multimap table covering a sequence.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `sequence` - The sequence the entry belongs to
+    /// * `key` - The key within the bucket table
+    /// * `value` - The value to add under the key
+    ///
+    /// # Returns
+    /// Whether the value was already present under the key
+    pub fn insert_multimap<'k, 'v, K: Key + 'static, V: Key + 'static>(
+        &self,
+        txn: &WriteTransaction,
+        sequence: u64,
+        key: impl Borrow<K::SelfType<'k>>,
+        value: impl Borrow<V::SelfType<'v>>,
+    ) -> Result<bool, BucketError> {
+        let bucket = self.bucket_for_sequence(sequence);
+        let mut table = self.open_multimap_table::<K, V>(txn, bucket)?;
+        table.insert(key, value).map_err(|err| {
+            BucketError::DatabaseError(
+                DatabaseError::new("Failed to insert into bucket table", err).bucket(bucket),
+            )
+        })
+    }
+
+    /// List the buckets recorded in the registry for this prefix.
+    ///
+    /// Buckets are recorded when opened through `open_table` or
+    /// `open_multimap_table` and forgotten when `merge` or `drop_before`
+    /// deletes their tables. Tables opened through the `*_definition`
+    /// methods are not recorded.
+    ///
+    /// # Returns
+    /// Recorded buckets in ascending order
+    pub fn registered_buckets(&self, txn: &ReadTransaction) -> Result<Vec<u64>, BucketError> {
+        let registry = match txn.open_table(TABLE_BUCKET_REGISTRY) {
+            Ok(registry) => registry,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(err) => return Err(registry_error(err)),
+        };
+
+        let prefix = self.table_prefix.as_str();
+        let mut buckets = Vec::new();
+        for entry in registry
+            .range((prefix, 0)..=(prefix, u64::MAX))
+            .map_err(registry_error)?
+        {
+            let (key, _) = entry.map_err(registry_error)?;
+            buckets.push(key.value().1);
+        }
+        Ok(buckets)
+    }
+
+    pub(crate) fn register(&self, txn: &WriteTransaction, bucket: u64) -> Result<(), BucketError> {
+        let mut registry = txn
+            .open_table(TABLE_BUCKET_REGISTRY)
+            .map_err(registry_error)?;
+        registry
+            .insert((self.table_prefix.as_str(), bucket), ())
+            .map_err(registry_error)?;
+        Ok(())
+    }
+
+    pub(crate) fn unregister(
+        &self,
+        txn: &WriteTransaction,
+        bucket: u64,
+    ) -> Result<(), BucketError> {
+        let mut registry = txn
+            .open_table(TABLE_BUCKET_REGISTRY)
+            .map_err(registry_error)?;
+        registry
+            .remove((self.table_prefix.as_str(), bucket))
+            .map_err(registry_error)?;
+        Ok(())
+    }
+
     /// Merge bucket tables into a single non-bucketed target table and delete the originals.
     pub fn merge<K, V>(
         &self,
@@ -224,6 +320,37 @@ impl TableBucketBuilder {
         V: Value + MergeableValue + 'static,
         for<'b> V: From<V::SelfType<'b>>,
         for<'b> V: Borrow<V::SelfType<'b>>,
+    {
+        self.merge_with(txn, target, start_bucket, end_bucket, V::merge)
+    }
+
+    /// Merge bucket tables into a target table with a caller-supplied resolver.
+    ///
+    /// Works like `merge`, but values under the same key are combined with
+    /// `resolver` instead of `MergeableValue::merge`, so each call site can
+    /// pick its own strategy, e.g. `|_, incoming| incoming` for last write
+    /// wins. The resolver receives the value merged so far (None for the
+    /// first one) and the incoming value, in bucket order.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `target` - The table receiving the merged values
+    /// * `start_bucket` - First bucket to merge
+    /// * `end_bucket` - Last bucket to merge (inclusive)
+    /// * `resolver` - Combines the existing and incoming value of a key
+    pub fn merge_with<K, V>(
+        &self,
+        txn: &mut WriteTransaction,
+        target: TableDefinition<'static, K, V>,
+        start_bucket: u64,
+        end_bucket: u64,
+        resolver: impl Fn(Option<V>, V) -> V,
+    ) -> Result<(), BucketError>
+    where
+        K: Key + 'static,
+        V: Value + 'static,
+        for<'b> V: From<V::SelfType<'b>>,
+        for<'b> V: Borrow<V::SelfType<'b>>,
     {
         if start_bucket > end_bucket {
             return Err(BucketError::InvalidRange {
@@ -234,116 +361,821 @@ impl TableBucketBuilder {
 
         let mut existing_tables = HashSet::new();
         let tables = txn.list_tables().map_err(|err| {
-            BucketError::IterationError(format!("Failed to list tables: {}", err))
+            BucketError::IterationError(DatabaseError::new("Failed to list tables", err))
         })?;
         for table in tables {
             existing_tables.insert(table.name().to_string());
         }
 
         let mut target_table = txn.open_table(target).map_err(|err| {
-            BucketError::IterationError(format!("Failed to open target table: {}", err))
+            BucketError::IterationError(DatabaseError::new("Failed to open target table", err))
         })?;
 
         for bucket in start_bucket..=end_bucket {
-            let bucket_name = self.bucket_table_name(bucket);
-            if !existing_tables.contains(bucket_name) {
-                continue;
+            if existing_tables.contains(&self.table_name(bucket)) {
+                self.merge_bucket_into(txn, bucket, &mut target_table, &resolver)?;
             }
+        }
 
-            let definition = self.table_definition::<K, V>(bucket);
-            let bucket_table = txn.open_table(definition).map_err(|err| {
-                BucketError::IterationError(format!(
-                    "Failed to open bucket table {}: {}",
-                    bucket, err
-                ))
-            })?;
+        Ok(())
+    }
+
+    /// Report what `merge` would do for a bucket range without changing anything.
+    ///
+    /// Reads every bucket table in the range and the target table, which may
+    /// not exist yet, from a read transaction.
+    ///
+    /// # Arguments
+    /// * `txn` - The read transaction
+    /// * `target` - The table the buckets would be merged into
+    /// * `start_bucket` - First bucket of the range
+    /// * `end_bucket` - Last bucket of the range (inclusive)
+    ///
+    /// # Returns
+    /// Row counts per bucket and the keys whose values would be combined
+    pub fn merge_dry_run<K, V>(
+        &self,
+        txn: &ReadTransaction,
+        target: TableDefinition<'static, K, V>,
+        start_bucket: u64,
+        end_bucket: u64,
+    ) -> Result<MergeReport<K>, BucketError>
+    where
+        K: Key + 'static,
+        for<'b> K: From<K::SelfType<'b>>,
+        V: Value + 'static,
+    {
+        if start_bucket > end_bucket {
+            return Err(BucketError::InvalidRange {
+                start: start_bucket,
+                end: end_bucket,
+            });
+        }
+
+        let target_table = match txn.open_table(target) {
+            Ok(table) => Some(table),
+            Err(TableError::TableDoesNotExist(_)) => None,
+            Err(err) => {
+                return Err(BucketError::IterationError(DatabaseError::new(
+                    "Failed to open target table",
+                    err,
+                )))
+            }
+        };
+
+        let mut bucket_rows = BTreeMap::new();
+        // Encoded key -> whether it would be combined with another value
+        let mut keys: HashMap<Vec<u8>, bool> = HashMap::new();
+        for bucket in start_bucket..=end_bucket {
+            let name = self.table_name(bucket);
+            let bucket_table = match txn.open_table(TableDefinition::<K, V>::new(&name)) {
+                Ok(table) => table,
+                Err(TableError::TableDoesNotExist(_)) => continue,
+                Err(err) => {
+                    return Err(BucketError::IterationError(
+                        DatabaseError::new("Failed to open bucket table", err).bucket(bucket),
+                    ))
+                }
+            };
 
             let iter = bucket_table.iter().map_err(|err| {
-                BucketError::IterationError(format!(
-                    "Failed to iterate bucket table {}: {}",
-                    bucket, err
-                ))
+                BucketError::IterationError(
+                    DatabaseError::new("Failed to iterate bucket table", err).bucket(bucket),
+                )
             })?;
 
+            let mut rows = 0;
             for entry in iter {
-                let (key_guard, value_guard) = entry.map_err(|err| {
-                    BucketError::IterationError(format!(
-                        "Failed to read bucket table {}: {}",
-                        bucket, err
-                    ))
+                let (key_guard, _) = entry.map_err(|err| {
+                    BucketError::IterationError(
+                        DatabaseError::new("Failed to read bucket table", err).bucket(bucket),
+                    )
                 })?;
+                rows += 1;
 
-                let incoming = V::from(value_guard.value());
-                let existing_value = match target_table.get(key_guard.value()) {
-                    Ok(Some(existing_guard)) => Some(V::from(existing_guard.value())),
-                    Ok(None) => None,
-                    Err(err) => {
-                        return Err(BucketError::IterationError(format!(
-                            "Failed to read target table: {}",
-                            err
-                        )))
-                    }
+                let key = K::as_bytes(&key_guard.value()).as_ref().to_vec();
+                if let Some(conflict) = keys.get_mut(&key) {
+                    *conflict = true;
+                    continue;
+                }
+                let in_target = match &target_table {
+                    Some(table) => table
+                        .get(K::from_bytes(&key))
+                        .map_err(|err| {
+                            BucketError::IterationError(DatabaseError::new(
+                                "Failed to read target table",
+                                err,
+                            ))
+                        })?
+                        .is_some(),
+                    None => false,
                 };
-                let merged = V::merge(existing_value, incoming);
-                target_table
-                    .insert(key_guard.value(), merged)
-                    .map_err(|err| {
-                        BucketError::IterationError(format!(
-                            "Failed to write merged value: {}",
-                            err
-                        ))
-                    })?;
+                keys.insert(key, in_target);
             }
-
-            drop(bucket_table);
-            txn.delete_table(definition).map_err(|err| {
-                BucketError::IterationError(format!(
-                    "Failed to delete bucket table {}: {}",
-                    bucket, err
-                ))
-            })?;
+            bucket_rows.insert(bucket, rows);
         }
 
-        Ok(())
+        let mut conflicts: Vec<Vec<u8>> = keys
+            .into_iter()
+            .filter_map(|(key, conflict)| conflict.then_some(key))
+            .collect();
+        conflicts.sort_by(|a, b| K::compare(a, b));
+
+        Ok(MergeReport {
+            bucket_rows,
+            conflicts: conflicts
+                .iter()
+                .map(|key| K::from(K::from_bytes(key)))
+                .collect(),
+        })
     }
 
-    /// Merge all bucket tables discovered in the database into the target table.
-    pub fn merge_all<K, V>(
+    /// Merge every bucket table into a coarser bucketed layout.
+    ///
+    /// Each bucket table of this builder is merged into the table of
+    /// `target_builder` covering the same sequences (e.g. hourly tables into
+    /// daily tables), combining values under the same key with
+    /// `MergeableValue::merge`, and deleted afterwards. Values already stored
+    /// in a target table take part in the merge. The target bucket size must
+    /// be a multiple of this builder's bucket size, so no bucket table is
+    /// split, and the two builders must use different prefixes.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `target_builder` - The coarser bucket layout
+    ///
+    /// # Returns
+    /// Number of bucket tables merged
+    pub fn merge_into_buckets<K, V>(
         &self,
         txn: &mut WriteTransaction,
-        target: TableDefinition<'static, K, V>,
-    ) -> Result<(), BucketError>
+        target_builder: &TableBucketBuilder,
+    ) -> Result<u64, BucketError>
     where
         K: Key + 'static,
         V: Value + MergeableValue + 'static,
         for<'b> V: From<V::SelfType<'b>>,
         for<'b> V: Borrow<V::SelfType<'b>>,
     {
-        let Some((min_bucket, max_bucket)) = self.bucket_range_from_tables(txn)? else {
-            return Ok(());
-        };
-
-        self.merge(txn, target, min_bucket, max_bucket)
-    }
-
-    fn bucket_range_from_tables(
-        &self,
-        txn: &WriteTransaction,
-    ) -> Result<Option<(u64, u64)>, BucketError> {
-        let mut min_bucket: Option<u64> = None;
-        let mut max_bucket: Option<u64> = None;
-        let prefix = format!("{}_", self.table_prefix);
+        if target_builder.bucket_size % self.bucket_size != 0 {
+            return Err(BucketError::IncompatibleBuckets(format!(
+                "bucket size {} is not a multiple of {}",
+                target_builder.bucket_size, self.bucket_size
+            )));
+        }
+        if target_builder.table_prefix == self.table_prefix {
+            return Err(BucketError::IncompatibleBuckets(format!(
+                "source and target share the table prefix '{}'",
+                self.table_prefix
+            )));
+        }
 
         let tables = txn.list_tables().map_err(|err| {
-            BucketError::IterationError(format!("Failed to list tables: {}", err))
+            BucketError::IterationError(DatabaseError::new("Failed to list tables", err))
         })?;
+        let mut by_target: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for table in tables {
+            if let Some(bucket) = self.bucket_of_table(table.name()) {
+                let first_sequence = bucket.saturating_mul(self.bucket_size);
+                by_target
+                    .entry(target_builder.bucket_for_sequence(first_sequence))
+                    .or_default()
+                    .push(bucket);
+            }
+        }
+
+        let mut merged = 0;
+        for (target_bucket, buckets) in by_target {
+            let mut target_table = target_builder.open_table::<K, V>(txn, target_bucket)?;
+            for bucket in buckets {
+                self.merge_bucket_into(txn, bucket, &mut target_table, &V::merge)?;
+                merged += 1;
+            }
+        }
+
+        Ok(merged)
+    }
+
+    /// Merge one bucket table into an open target table, then delete it.
+    fn merge_bucket_into<K, V>(
+        &self,
+        txn: &WriteTransaction,
+        bucket: u64,
+        target_table: &mut Table<'_, K, V>,
+        resolver: &impl Fn(Option<V>, V) -> V,
+    ) -> Result<(), BucketError>
+    where
+        K: Key + 'static,
+        V: Value + 'static,
+        for<'b> V: From<V::SelfType<'b>>,
+        for<'b> V: Borrow<V::SelfType<'b>>,
+    {
+        let bucket_name = self.table_name(bucket);
+        let definition = TableDefinition::<K, V>::new(&bucket_name);
+        let bucket_table = txn.open_table(definition).map_err(|err| {
+            BucketError::IterationError(
+                DatabaseError::new("Failed to open bucket table", err).bucket(bucket),
+            )
+        })?;
+
+        let iter = bucket_table.iter().map_err(|err| {
+            BucketError::IterationError(
+                DatabaseError::new("Failed to iterate bucket table", err).bucket(bucket),
+            )
+        })?;
+
+        for entry in iter {
+            let (key_guard, value_guard) = entry.map_err(|err| {
+                BucketError::IterationError(
+                    DatabaseError::new("Failed to read bucket table", err).bucket(bucket),
+                )
+            })?;
+
+            let incoming = V::from(value_guard.value());
+            let existing_value = match target_table.get(key_guard.value()) {
+                Ok(Some(existing_guard)) => Some(V::from(existing_guard.value())),
+                Ok(None) => None,
+                Err(err) => {
+                    return Err(BucketError::IterationError(DatabaseError::new(
+                        "Failed to read target table",
+                        err,
+                    )))
+                }
+            };
+            let merged = resolver(existing_value, incoming);
+            target_table
+                .insert(key_guard.value(), merged)
+                .map_err(|err| {
+                    BucketError::IterationError(DatabaseError::new(
+                        "Failed to write merged value",
+                        err,
+                    ))
+                })?;
+        }
+
+        drop(bucket_table);
+        txn.delete_table(definition).map_err(|err| {
+            BucketError::IterationError(
+                DatabaseError::new("Failed to delete bucket table", err).bucket(bucket),
+            )
+        })?;
+        self.unregister(txn, bucket)
+    }
+
+    /// Merge all bucket tables discovered in the database into the target table.
+    pub fn merge_all<K, V>(
+        &self,
+        txn: &mut WriteTransaction,
+        target: TableDefinition<'static, K, V>,
+    ) -> Result<(), BucketError>
+    where
+        K: Key + 'static,
+        V: Value + MergeableValue + 'static,
+        for<'b> V: From<V::SelfType<'b>>,
+        for<'b> V: Borrow<V::SelfType<'b>>,
+    {
+        let Some((min_bucket, max_bucket)) = self.bucket_range_from_tables(txn)? else {
+            return Ok(());
+        };
+
+        self.merge(txn, target, min_bucket, max_bucket)
+    }
+
+    /// Merge bucket multimap tables into a single non-bucketed target and delete the originals.
+    ///
+    /// The values of each key are unioned: every value found under a key in
+    /// any bucket ends up under that key in `target`, once.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `target` - The multimap receiving the values
+    /// * `start_bucket` - First bucket to merge
+    /// * `end_bucket` - Last bucket to merge (inclusive)
+    pub fn merge_multimap<K, V>(
+        &self,
+        txn: &mut WriteTransaction,
+        target: MultimapTableDefinition<'static, K, V>,
+        start_bucket: u64,
+        end_bucket: u64,
+    ) -> Result<(), BucketError>
+    where
+        K: Key + 'static,
+        V: Key + 'static,
+    {
+        if start_bucket > end_bucket {
+            return Err(BucketError::InvalidRange {
+                start: start_bucket,
+                end: end_bucket,
+            });
+        }
+
+        let mut existing_tables = HashSet::new();
+        let tables = txn.list_multimap_tables().map_err(|err| {
+            BucketError::IterationError(DatabaseError::new("Failed to list multimap tables", err))
+        })?;
+        for table in tables {
+            existing_tables.insert(table.name().to_string());
+        }
+
+        let mut target_table = txn.open_multimap_table(target).map_err(|err| {
+            BucketError::IterationError(DatabaseError::new("Failed to open target table", err))
+        })?;
+
+        for bucket in start_bucket..=end_bucket {
+            let bucket_name = self.table_name(bucket);
+            if !existing_tables.contains(&bucket_name) {
+                continue;
+            }
+
+            let definition = MultimapTableDefinition::<K, V>::new(&bucket_name);
+            let bucket_table = txn.open_multimap_table(definition).map_err(|err| {
+                BucketError::IterationError(
+                    DatabaseError::new("Failed to open bucket table", err).bucket(bucket),
+                )
+            })?;
+
+            let iter = bucket_table.iter().map_err(|err| {
+                BucketError::IterationError(
+                    DatabaseError::new("Failed to iterate bucket table", err).bucket(bucket),
+                )
+            })?;
+
+            for entry in iter {
+                let (key_guard, values) = entry.map_err(|err| {
+                    BucketError::IterationError(
+                        DatabaseError::new("Failed to read bucket table", err).bucket(bucket),
+                    )
+                })?;
+
+                for value in values {
+                    let value_guard = value.map_err(|err| {
+                        BucketError::IterationError(
+                            DatabaseError::new("Failed to read bucket table", err).bucket(bucket),
+                        )
+                    })?;
+                    target_table
+                        .insert(key_guard.value(), value_guard.value())
+                        .map_err(|err| {
+                            BucketError::IterationError(DatabaseError::new(
+                                "Failed to write merged value",
+                                err,
+                            ))
+                        })?;
+                }
+            }
+
+            drop(bucket_table);
+            txn.delete_multimap_table(definition).map_err(|err| {
+                BucketError::IterationError(
+                    DatabaseError::new("Failed to delete bucket table", err).bucket(bucket),
+                )
+            })?;
+            self.unregister(txn, bucket)?;
+        }
+
+        Ok(())
+    }
+
+    /// Merge all bucket multimap tables discovered in the database into the target.
+    pub fn merge_all_multimap<K, V>(
+        &self,
+        txn: &mut WriteTransaction,
+        target: MultimapTableDefinition<'static, K, V>,
+    ) -> Result<(), BucketError>
+    where
+        K: Key + 'static,
+        V: Key + 'static,
+    {
+        let Some((min_bucket, max_bucket)) = self.bucket_range_from_multimap_tables(txn)? else {
+            return Ok(());
+        };
+
+        self.merge_multimap(txn, target, min_bucket, max_bucket)
+    }
+
+    /// Replace the values of each key in a bucket multimap table with one summary value.
+    ///
+    /// The values of every key are folded in value order with `summarizer`,
+    /// which receives the summary so far (None for the first value) and the
+    /// next value, and the key is left holding only the result. This shrinks
+    /// old buckets in place once full detail is no longer needed; a missing
+    /// bucket table is left alone.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `bucket` - The bucket whose table to compact
+    /// * `summarizer` - Folds the values of a key into a summary
+    ///
+    /// # Returns
+    /// Number of values removed from the table
+    pub fn compact<K, V>(
+        &self,
+        txn: &WriteTransaction,
+        bucket: u64,
+        summarizer: impl Fn(Option<V>, V) -> V,
+    ) -> Result<u64, BucketError>
+    where
+        K: Key + 'static,
+        V: Key + 'static,
+        for<'b> V: From<V::SelfType<'b>>,
+        for<'b> V: Borrow<V::SelfType<'b>>,
+    {
+        let name = self.table_name(bucket);
+        let exists = txn
+            .list_multimap_tables()
+            .map_err(|err| compact_error(bucket, err))?
+            .any(|table| table.name() == name);
+        if !exists {
+            return Ok(0);
+        }
+
+        let mut table = txn
+            .open_multimap_table(MultimapTableDefinition::<K, V>::new(&name))
+            .map_err(|err| compact_error(bucket, err))?;
+
+        let mut keys: Vec<Vec<u8>> = Vec::new();
+        for entry in table.iter().map_err(|err| compact_error(bucket, err))? {
+            let (key, _) = entry.map_err(|err| compact_error(bucket, err))?;
+            keys.push(K::as_bytes(&key.value()).as_ref().to_vec());
+        }
+
+        let mut removed = 0;
+        for key in keys {
+            let mut summary: Option<V> = None;
+            for value in table
+                .remove_all(K::from_bytes(&key))
+                .map_err(|err| compact_error(bucket, err))?
+            {
+                let value = V::from(value.map_err(|err| compact_error(bucket, err))?.value());
+                if summary.is_some() {
+                    removed += 1;
+                }
+                summary = Some(summarizer(summary, value));
+            }
+
+            if let Some(summary) = summary {
+                table
+                    .insert(K::from_bytes(&key), summary)
+                    .map_err(|err| compact_error(bucket, err))?;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Delete every bucket table older than the cutoff bucket.
+    ///
+    /// Both regular and multimap bucket tables are dropped whole with
+    /// `delete_table`, so retention costs one table deletion per bucket no
+    /// matter how many entries a bucket holds. Tables of the cutoff bucket
+    /// and later buckets are kept.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `cutoff_bucket` - The oldest bucket to keep
+    ///
+    /// # Returns
+    /// Number of bucket tables deleted
+    pub fn drop_before(
+        &self,
+        txn: &WriteTransaction,
+        cutoff_bucket: u64,
+    ) -> Result<u64, BucketError> {
+        let is_expired = |name: &str| {
+            self.bucket_of_table(name)
+                .is_some_and(|bucket| bucket < cutoff_bucket)
+        };
+
+        let tables: Vec<_> = txn
+            .list_tables()
+            .map_err(|err| {
+                BucketError::IterationError(DatabaseError::new("Failed to list tables", err))
+            })?
+            .filter(|table| is_expired(table.name()))
+            .collect();
+        let multimap_tables: Vec<_> = txn
+            .list_multimap_tables()
+            .map_err(|err| {
+                BucketError::IterationError(DatabaseError::new(
+                    "Failed to list multimap tables",
+                    err,
+                ))
+            })?
+            .filter(|table| is_expired(table.name()))
+            .collect();
+
+        let mut dropped = 0;
+        for bucket in tables
+            .iter()
+            .map(|table| table.name())
+            .chain(multimap_tables.iter().map(|table| table.name()))
+            .filter_map(|name| self.bucket_of_table(name))
+        {
+            self.unregister(txn, bucket)?;
+        }
+        for table in tables {
+            let name = table.name().to_string();
+            txn.delete_table(table).map_err(|err| {
+                BucketError::IterationError(
+                    DatabaseError::new("Failed to delete bucket table", err).table(name),
+                )
+            })?;
+            dropped += 1;
+        }
+        for table in multimap_tables {
+            let name = table.name().to_string();
+            txn.delete_multimap_table(table).map_err(|err| {
+                BucketError::IterationError(
+                    DatabaseError::new("Failed to delete bucket table", err).table(name),
+                )
+            })?;
+            dropped += 1;
+        }
+
+        Ok(dropped)
+    }
+
+    /// Move every bucket table of this builder to a new table prefix.
+    ///
+    /// Regular and multimap bucket tables are renamed in place, without
+    /// copying their contents, and the registry is updated to match. Fails
+    /// if the prefixes are equal or a renamed table would replace an
+    /// existing one; the transaction should then be aborted, since tables
+    /// renamed before the failure keep their new name.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `new_prefix` - The table prefix to move to
+    ///
+    /// # Returns
+    /// A builder with the same bucket size for the new prefix
+    pub fn rename_prefix(
+        &self,
+        txn: &WriteTransaction,
+        new_prefix: impl Into<String>,
+    ) -> Result<TableBucketBuilder, BucketError> {
+        let renamed = TableBucketBuilder::new(self.bucket_size, new_prefix)?;
+        if renamed.table_prefix == self.table_prefix {
+            return Err(BucketError::IncompatibleBuckets(format!(
+                "table prefix is already '{}'",
+                self.table_prefix
+            )));
+        }
+
+        let rename_error = |name: &str, err: TableError| {
+            BucketError::DatabaseError(
+                DatabaseError::new("Failed to rename bucket table", err).table(name),
+            )
+        };
+        let tables: Vec<_> = txn
+            .list_tables()
+            .map_err(|err| {
+                BucketError::IterationError(DatabaseError::new("Failed to list tables", err))
+            })?
+            .filter(|table| self.bucket_of_table(table.name()).is_some())
+            .collect();
+        let multimap_tables: Vec<_> = txn
+            .list_multimap_tables()
+            .map_err(|err| {
+                BucketError::IterationError(DatabaseError::new(
+                    "Failed to list multimap tables",
+                    err,
+                ))
+            })?
+            .filter(|table| self.bucket_of_table(table.name()).is_some())
+            .collect();
 
+        // Renaming only looks at names, so unit-typed definitions stand in
+        // for the new names whatever the tables hold
         for table in tables {
-            let name = table.name();
-            let Some(bucket_suffix) = name.strip_prefix(&prefix) else {
+            let name = table.name().to_string();
+            let Some(bucket) = self.bucket_of_table(&name) else {
+                continue;
+            };
+            let new_name = renamed.table_name(bucket);
+            txn.rename_table(table, TableDefinition::<(), ()>::new(&new_name))
+                .map_err(|err| rename_error(&name, err))?;
+            self.unregister(txn, bucket)?;
+            renamed.register(txn, bucket)?;
+        }
+        for table in multimap_tables {
+            let name = table.name().to_string();
+            let Some(bucket) = self.bucket_of_table(&name) else {
                 continue;
             };
-            let Ok(bucket) = bucket_suffix.parse::<u64>() else {
+            let new_name = renamed.table_name(bucket);
+            txn.rename_multimap_table(table, MultimapTableDefinition::<(), ()>::new(&new_name))
+                .map_err(|err| rename_error(&name, err))?;
+            self.unregister(txn, bucket)?;
+            renamed.register(txn, bucket)?;
+        }
+
+        Ok(renamed)
+    }
+
+    /// Build a copy plan covering the bucket tables in a range.
+    ///
+    /// Only buckets with an existing table are included, so the plan can be
+    /// passed to `dbcopy::copy_database` as is. The plan allows unplanned
+    /// tables, since buckets outside the range are left out on purpose.
+    /// Copied tables are not recorded in the destination's registry; see
+    /// `archive` for a copy that does.
+    ///
+    /// # Arguments
+    /// * `txn` - A read transaction on the source database
+    /// * `start_bucket` - First bucket of the range
+    /// * `end_bucket` - Last bucket of the range (inclusive)
+    ///
+    /// # Returns
+    /// Plan copying each bucket table in the range
+    pub fn copy_plan<K, V>(
+        &self,
+        txn: &ReadTransaction,
+        start_bucket: u64,
+        end_bucket: u64,
+    ) -> Result<CopyPlan, BucketError>
+    where
+        K: Key + 'static,
+        V: Value + 'static,
+    {
+        let tables = txn.list_tables().map_err(|err| {
+            BucketError::IterationError(DatabaseError::new("Failed to list tables", err))
+        })?;
+        let buckets = self.buckets_in(
+            tables.map(|table| table.name().to_string()),
+            start_bucket,
+            end_bucket,
+        )?;
+
+        Ok(buckets
+            .into_iter()
+            .fold(CopyPlan::new().allow_unplanned(), |plan, bucket| {
+                plan.table(TableDefinition::<K, V>::new(&self.table_name(bucket)))
+            }))
+    }
+
+    /// Build a copy plan covering the bucket multimap tables in a range.
+    ///
+    /// See `copy_plan`.
+    pub fn multimap_copy_plan<K, V>(
+        &self,
+        txn: &ReadTransaction,
+        start_bucket: u64,
+        end_bucket: u64,
+    ) -> Result<CopyPlan, BucketError>
+    where
+        K: Key + 'static,
+        V: Key + 'static,
+    {
+        let tables = txn.list_multimap_tables().map_err(|err| {
+            BucketError::IterationError(DatabaseError::new("Failed to list multimap tables", err))
+        })?;
+        let buckets = self.buckets_in(
+            tables.map(|table| table.name().to_string()),
+            start_bucket,
+            end_bucket,
+        )?;
+
+        Ok(buckets
+            .into_iter()
+            .fold(CopyPlan::new().allow_unplanned(), |plan, bucket| {
+                plan.multimap(MultimapTableDefinition::<K, V>::new(
+                    &self.table_name(bucket),
+                ))
+            }))
+    }
+
+    /// Move a range of bucket tables into another database file.
+    ///
+    /// The bucket tables in the range are copied to `destination` with
+    /// `dbcopy::copy_database`, registered there, and then deleted from
+    /// `source`. The source write lock is held throughout, so no writes to
+    /// the archived buckets can be lost in between. Fails without changes if
+    /// `destination` already holds one of the tables.
+    ///
+    /// # Arguments
+    /// * `source` - The database holding the bucket tables
+    /// * `start_bucket` - First bucket to archive
+    /// * `end_bucket` - Last bucket to archive (inclusive)
+    /// * `destination` - The archive database
+    ///
+    /// # Returns
+    /// Number of bucket tables archived
+    pub fn archive<K, V>(
+        &self,
+        source: &Database,
+        start_bucket: u64,
+        end_bucket: u64,
+        destination: &Database,
+    ) -> crate::Result<u64>
+    where
+        K: Key + 'static,
+        V: Value + 'static,
+    {
+        let source_txn = source.begin_write().map_err(archive_error)?;
+        let tables = source_txn.list_tables().map_err(archive_error)?;
+        let buckets = self.buckets_in(
+            tables.map(|table| table.name().to_string()),
+            start_bucket,
+            end_bucket,
+        )?;
+        if buckets.is_empty() {
+            return Ok(0);
+        }
+
+        let names: Vec<String> = buckets
+            .iter()
+            .map(|&bucket| self.table_name(bucket))
+            .collect();
+        let plan = names
+            .iter()
+            .fold(CopyPlan::new().allow_unplanned(), |plan, name| {
+                plan.table(TableDefinition::<K, V>::new(name))
+            });
+        copy_database(source, destination, &plan)?;
+
+        let destination_txn = destination.begin_write().map_err(archive_error)?;
+        for &bucket in &buckets {
+            self.register(&destination_txn, bucket)?;
+        }
+        destination_txn.commit().map_err(archive_error)?;
+
+        for (&bucket, name) in buckets.iter().zip(&names) {
+            source_txn
+                .delete_table(TableDefinition::<K, V>::new(name))
+                .map_err(archive_error)?;
+            self.unregister(&source_txn, bucket)?;
+        }
+        source_txn.commit().map_err(archive_error)?;
+
+        Ok(buckets.len() as u64)
+    }
+
+    /// Parse the bucket out of a table name created by this builder.
+    ///
+    /// Only the exact names produced by `table_name` match, so foreign tables
+    /// such as `{prefix}_05` or `{prefix}_+5` are never taken for buckets.
+    pub(crate) fn bucket_of_table(&self, name: &str) -> Option<u64> {
+        let suffix = name
+            .strip_prefix(self.table_prefix.as_str())?
+            .strip_prefix('_')?;
+        if !suffix.bytes().all(|byte| byte.is_ascii_digit())
+            || (suffix.len() > 1 && suffix.starts_with('0'))
+        {
+            return None;
+        }
+        suffix.parse().ok()
+    }
+
+    fn bucket_range_from_tables(
+        &self,
+        txn: &WriteTransaction,
+    ) -> Result<Option<(u64, u64)>, BucketError> {
+        let tables = txn.list_tables().map_err(|err| {
+            BucketError::IterationError(DatabaseError::new("Failed to list tables", err))
+        })?;
+
+        Ok(self.bucket_span(tables.map(|table| table.name().to_string())))
+    }
+
+    fn bucket_range_from_multimap_tables(
+        &self,
+        txn: &WriteTransaction,
+    ) -> Result<Option<(u64, u64)>, BucketError> {
+        let tables = txn.list_multimap_tables().map_err(|err| {
+            BucketError::IterationError(DatabaseError::new("Failed to list multimap tables", err))
+        })?;
+
+        Ok(self.bucket_span(tables.map(|table| table.name().to_string())))
+    }
+
+    /// Buckets of this builder among the given table names, within a range.
+    fn buckets_in(
+        &self,
+        names: impl Iterator<Item = String>,
+        start_bucket: u64,
+        end_bucket: u64,
+    ) -> Result<BTreeSet<u64>, BucketError> {
+        if start_bucket > end_bucket {
+            return Err(BucketError::InvalidRange {
+                start: start_bucket,
+                end: end_bucket,
+            });
+        }
+
+        Ok(names
+            .filter_map(|name| self.bucket_of_table(&name))
+            .filter(|bucket| (start_bucket..=end_bucket).contains(bucket))
+            .collect())
+    }
+
+    /// Smallest and largest bucket among the given table names.
+    fn bucket_span(&self, names: impl Iterator<Item = String>) -> Option<(u64, u64)> {
+        let mut min_bucket: Option<u64> = None;
+        let mut max_bucket: Option<u64> = None;
+
+        for name in names {
+            let Some(bucket) = self.bucket_of_table(&name) else {
                 continue;
             };
 
@@ -351,6 +1183,641 @@ impl TableBucketBuilder {
             max_bucket = Some(max_bucket.map_or(bucket, |current| current.max(bucket)));
         }
 
-        Ok(min_bucket.zip(max_bucket))
+        min_bucket.zip(max_bucket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TableBucketBuilder, TableBucketIterExt, TableBucketMultimapIterExt};
+    use crate::dbcopy::copy_database;
+    use crate::MergeableValue;
+    use redb::{Database, MultimapTableDefinition, ReadableDatabase, TableDefinition, TableError};
+    use std::collections::BTreeMap;
+    use tempfile::NamedTempFile;
+
+    impl MergeableValue for String {
+        fn merge(existing: Option<Self>, incoming: Self) -> Self {
+            match existing {
+                Some(existing) => format!("{}+{}", existing, incoming),
+                None => incoming,
+            }
+        }
+    }
+
+    #[test]
+    fn merge_bucket_tables_into_target() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(100, "merge_test")?;
+        let target: TableDefinition<u64, String> = TableDefinition::new("merged");
+
+        {
+            let write_txn = db.begin_write()?;
+            {
+                let mut table = write_txn.open_table(builder.table_definition::<u64, String>(0))?;
+                table.insert(1u64, "a".to_string())?;
+                table.insert(2u64, "x".to_string())?;
+            }
+            {
+                let mut table = write_txn.open_table(builder.table_definition::<u64, String>(1))?;
+                table.insert(1u64, "b".to_string())?;
+                table.insert(3u64, "y".to_string())?;
+            }
+            {
+                let mut table = write_txn.open_table(builder.table_definition::<u64, String>(2))?;
+                table.insert(1u64, "c".to_string())?;
+            }
+            write_txn.commit()?;
+        }
+
+        {
+            let mut write_txn = db.begin_write()?;
+            builder.merge(&mut write_txn, target, 0, 1)?;
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        let target_read: TableDefinition<u64, String> = TableDefinition::new("merged");
+        let table = read_txn.open_table(target_read)?;
+        assert_eq!(table.get(1u64)?.unwrap().value(), "a+b");
+        assert_eq!(table.get(2u64)?.unwrap().value(), "x");
+        assert_eq!(table.get(3u64)?.unwrap().value(), "y");
+
+        match read_txn.open_table(builder.table_definition::<u64, String>(0)) {
+            Err(TableError::TableDoesNotExist(_)) => {}
+            _ => panic!("bucket 0 table should be deleted"),
+        }
+
+        match read_txn.open_table(builder.table_definition::<u64, String>(1)) {
+            Err(TableError::TableDoesNotExist(_)) => {}
+            _ => panic!("bucket 1 table should be deleted"),
+        }
+
+        let bucket_two = read_txn.open_table(builder.table_definition::<u64, String>(2))?;
+        assert_eq!(bucket_two.get(1u64)?.unwrap().value(), "c");
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_all_bucket_tables_into_target() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(100, "merge_all")?;
+        let target: TableDefinition<u64, String> = TableDefinition::new("merged_all");
+
+        {
+            let write_txn = db.begin_write()?;
+            {
+                let mut table = write_txn.open_table(builder.table_definition::<u64, String>(0))?;
+                table.insert(1u64, "a".to_string())?;
+            }
+            {
+                let mut table = write_txn.open_table(builder.table_definition::<u64, String>(2))?;
+                table.insert(1u64, "c".to_string())?;
+            }
+            write_txn.commit()?;
+        }
+
+        {
+            let mut write_txn = db.begin_write()?;
+            builder.merge_all(&mut write_txn, target)?;
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        let target_read: TableDefinition<u64, String> = TableDefinition::new("merged_all");
+        let table = read_txn.open_table(target_read)?;
+        assert_eq!(table.get(1u64)?.unwrap().value(), "a+c");
+
+        match read_txn.open_table(builder.table_definition::<u64, String>(0)) {
+            Err(TableError::TableDoesNotExist(_)) => {}
+            _ => panic!("bucket 0 table should be deleted"),
+        }
+
+        match read_txn.open_table(builder.table_definition::<u64, String>(2)) {
+            Err(TableError::TableDoesNotExist(_)) => {}
+            _ => panic!("bucket 2 table should be deleted"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn drop_bucket_tables_before_cutoff() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(100, "retention")?;
+
+        {
+            let write_txn = db.begin_write()?;
+            for bucket in [0, 1, 3] {
+                let mut table =
+                    write_txn.open_table(builder.table_definition::<u64, u64>(bucket))?;
+                table.insert(bucket, bucket)?;
+            }
+            {
+                let mut table = write_txn
+                    .open_multimap_table(builder.multimap_table_definition::<u64, u64>(2))?;
+                table.insert(1u64, 2u64)?;
+            }
+            let mut other =
+                write_txn.open_table(TableDefinition::<u64, u64>::new("retention_log"))?;
+            other.insert(0u64, 0u64)?;
+            drop(other);
+            write_txn.commit()?;
+        }
+
+        {
+            let write_txn = db.begin_write()?;
+            assert_eq!(builder.drop_before(&write_txn, 3)?, 3);
+            assert_eq!(builder.drop_before(&write_txn, 3)?, 0);
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        for bucket in [0, 1] {
+            assert!(matches!(
+                read_txn.open_table(builder.table_definition::<u64, u64>(bucket)),
+                Err(TableError::TableDoesNotExist(_))
+            ));
+        }
+        assert!(matches!(
+            read_txn.open_multimap_table(builder.multimap_table_definition::<u64, u64>(2)),
+            Err(TableError::TableDoesNotExist(_))
+        ));
+        let kept = read_txn.open_table(builder.table_definition::<u64, u64>(3))?;
+        assert_eq!(kept.get(3u64)?.unwrap().value(), 3);
+        assert!(read_txn
+            .open_table(TableDefinition::<u64, u64>::new("retention_log"))
+            .is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_tables_through_registry() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(100, "registry")?;
+        let other = TableBucketBuilder::new(100, "registry_other")?;
+        assert_eq!(
+            builder.registered_buckets(&db.begin_read()?)?,
+            Vec::<u64>::new()
+        );
+
+        {
+            let write_txn = db.begin_write()?;
+            for bucket in [4, 1, 7] {
+                let mut table = builder.open_table::<u64, String>(&write_txn, bucket)?;
+                table.insert(bucket, bucket.to_string())?;
+            }
+            let mut multimap = other.open_multimap_table::<u64, u64>(&write_txn, 2)?;
+            multimap.insert(1u64, 2u64)?;
+            drop(multimap);
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        assert_eq!(builder.table_name(4), "registry_4");
+        assert_eq!(builder.registered_buckets(&read_txn)?, vec![1, 4, 7]);
+        assert_eq!(other.registered_buckets(&read_txn)?, vec![2]);
+        let values: Vec<String> = read_txn
+            .table_bucket_range(&builder, 4u64, 0, 999)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec!["4"]);
+        drop(read_txn);
+
+        {
+            let write_txn = db.begin_write()?;
+            assert_eq!(builder.drop_before(&write_txn, 5)?, 2);
+            write_txn.commit()?;
+        }
+        assert_eq!(builder.registered_buckets(&db.begin_read()?)?, vec![7]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn insert_through_builder() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(100, "facade")?;
+        let multimap_builder = TableBucketBuilder::new(100, "facade_multi")?;
+
+        {
+            let write_txn = db.begin_write()?;
+            builder.insert::<u64, String>(&write_txn, 50, 42u64, "a".to_string())?;
+            builder.insert::<u64, String>(&write_txn, 250, 42u64, "b".to_string())?;
+            builder.insert::<u64, String>(&write_txn, 260, 42u64, "c".to_string())?;
+            assert!(!multimap_builder.insert_multimap::<u64, u64>(&write_txn, 10, 7u64, 1u64)?);
+            assert!(multimap_builder.insert_multimap::<u64, u64>(&write_txn, 20, 7u64, 1u64)?);
+            multimap_builder.insert_multimap::<u64, u64>(&write_txn, 120, 7u64, 2u64)?;
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        assert_eq!(builder.registered_buckets(&read_txn)?, vec![0, 2]);
+        let values: Vec<String> = read_txn
+            .table_bucket_range(&builder, 42u64, 0, 299)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec!["a", "c"]);
+        let values: Vec<u64> = read_txn
+            .table_bucket_multimap_range(&multimap_builder, 7u64, 0, 199)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![1, 2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_into_coarser_buckets() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let hourly = TableBucketBuilder::new(10, "hourly")?;
+        let daily = TableBucketBuilder::new(30, "daily")?;
+
+        {
+            let write_txn = db.begin_write()?;
+            for (sequence, value) in [(5, "a"), (15, "b"), (25, "c"), (35, "d")] {
+                hourly.insert::<u64, String>(&write_txn, sequence, 1u64, value.to_string())?;
+            }
+            daily.insert::<u64, String>(&write_txn, 40, 1u64, "x".to_string())?;
+            write_txn.commit()?;
+        }
+
+        {
+            let mut write_txn = db.begin_write()?;
+            assert!(hourly
+                .merge_into_buckets::<u64, String>(
+                    &mut write_txn,
+                    &TableBucketBuilder::new(25, "odd")?
+                )
+                .is_err());
+            assert_eq!(
+                hourly.merge_into_buckets::<u64, String>(&mut write_txn, &daily)?,
+                4
+            );
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        let values: Vec<String> = read_txn
+            .table_bucket_range(&daily, 1u64, 0, 59)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec!["a+b+c", "x+d"]);
+        assert!(hourly.registered_buckets(&read_txn)?.is_empty());
+        assert!(matches!(
+            read_txn.open_table(hourly.table_definition::<u64, String>(0)),
+            Err(TableError::TableDoesNotExist(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_multimap_bucket_tables() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(100, "merge_multi")?;
+        let target: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("union");
+        let all_target: MultimapTableDefinition<u64, u64> =
+            MultimapTableDefinition::new("union_all");
+
+        {
+            let write_txn = db.begin_write()?;
+            for (sequence, key, value) in [(10, 1u64, 1u64), (20, 1, 2), (110, 1, 2), (110, 2, 5)] {
+                builder.insert_multimap::<u64, u64>(&write_txn, sequence, key, value)?;
+            }
+            builder.insert_multimap::<u64, u64>(&write_txn, 310, 1u64, 9u64)?;
+            write_txn.commit()?;
+        }
+
+        {
+            let mut write_txn = db.begin_write()?;
+            builder.merge_multimap(&mut write_txn, target, 0, 1)?;
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        let union = read_txn.open_multimap_table(target)?;
+        let values: Vec<u64> = union
+            .get(1u64)?
+            .map(|value| value.map(|value| value.value()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![1, 2]);
+        assert_eq!(union.get(2u64)?.len(), 1);
+        assert!(matches!(
+            read_txn.open_multimap_table(builder.multimap_table_definition::<u64, u64>(0)),
+            Err(TableError::TableDoesNotExist(_))
+        ));
+        assert_eq!(builder.registered_buckets(&read_txn)?, vec![3]);
+        drop(union);
+        drop(read_txn);
+
+        {
+            let mut write_txn = db.begin_write()?;
+            builder.merge_all_multimap(&mut write_txn, all_target)?;
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        assert_eq!(
+            read_txn.open_multimap_table(all_target)?.get(1u64)?.len(),
+            1
+        );
+        assert!(builder.registered_buckets(&read_txn)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn archive_bucket_range() -> Result<(), Box<dyn std::error::Error>> {
+        let source_file = NamedTempFile::new()?;
+        let archive_file = NamedTempFile::new()?;
+        let source = Database::create(source_file.path())?;
+        let archive = Database::create(archive_file.path())?;
+        let builder = TableBucketBuilder::new(100, "archive")?;
+
+        {
+            let write_txn = source.begin_write()?;
+            for (sequence, value) in [(10, "a"), (150, "b"), (250, "c"), (350, "d")] {
+                builder.insert::<u64, String>(&write_txn, sequence, 7u64, value.to_string())?;
+            }
+            write_txn.commit()?;
+        }
+
+        assert_eq!(builder.archive::<u64, String>(&source, 0, 2, &archive)?, 3);
+        assert_eq!(builder.archive::<u64, String>(&source, 0, 2, &archive)?, 0);
+
+        let read_txn = archive.begin_read()?;
+        let values: Vec<String> = read_txn
+            .table_bucket_range(&builder, 7u64, 0, 399)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec!["a", "b", "c"]);
+        assert_eq!(builder.registered_buckets(&read_txn)?, vec![0, 1, 2]);
+
+        let read_txn = source.begin_read()?;
+        let values: Vec<String> = read_txn
+            .table_bucket_range(&builder, 7u64, 0, 399)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec!["d"]);
+        assert_eq!(builder.registered_buckets(&read_txn)?, vec![3]);
+        drop(read_txn);
+
+        // Archiving onto existing tables is refused and leaves the source intact
+        {
+            let write_txn = source.begin_write()?;
+            builder.insert::<u64, String>(&write_txn, 50, 7u64, "e".to_string())?;
+            write_txn.commit()?;
+        }
+        assert!(builder
+            .archive::<u64, String>(&source, 0, 0, &archive)
+            .is_err());
+        let read_txn = source.begin_read()?;
+        assert_eq!(builder.registered_buckets(&read_txn)?, vec![0, 3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_with_custom_resolver() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(100, "resolver")?;
+        let latest: TableDefinition<u64, u64> = TableDefinition::new("latest");
+        let totals: TableDefinition<u64, u64> = TableDefinition::new("totals");
+
+        let fill = |db: &Database| -> Result<(), Box<dyn std::error::Error>> {
+            let write_txn = db.begin_write()?;
+            for (sequence, value) in [(10, 3u64), (150, 4), (250, 5)] {
+                builder.insert::<u64, u64>(&write_txn, sequence, 1u64, value)?;
+            }
+            write_txn.commit()?;
+            Ok(())
+        };
+
+        fill(&db)?;
+        let mut write_txn = db.begin_write()?;
+        builder.merge_with(&mut write_txn, latest, 0, 2, |_, incoming| incoming)?;
+        write_txn.commit()?;
+
+        fill(&db)?;
+        let mut write_txn = db.begin_write()?;
+        builder.merge_with(&mut write_txn, totals, 0, 2, |existing, incoming| {
+            existing.unwrap_or(0) + incoming
+        })?;
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        assert_eq!(read_txn.open_table(latest)?.get(1u64)?.unwrap().value(), 5);
+        assert_eq!(read_txn.open_table(totals)?.get(1u64)?.unwrap().value(), 12);
+        assert!(builder.registered_buckets(&read_txn)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_dry_run_reports_without_merging() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(100, "dry_run")?;
+        let target: TableDefinition<u64, String> = TableDefinition::new("dry_run_target");
+
+        {
+            let write_txn = db.begin_write()?;
+            for (sequence, key) in [(10, 1u64), (20, 2), (110, 2), (120, 3), (310, 4)] {
+                builder.insert::<u64, String>(&write_txn, sequence, key, key.to_string())?;
+            }
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        let report = builder.merge_dry_run(&read_txn, target, 0, 3)?;
+        assert_eq!(report.bucket_rows, BTreeMap::from([(0, 2), (1, 2), (3, 1)]));
+        assert_eq!(report.total_rows(), 5);
+        assert_eq!(report.conflicts, vec![2]);
+        drop(read_txn);
+
+        {
+            let write_txn = db.begin_write()?;
+            write_txn
+                .open_table(target)?
+                .insert(4u64, "old".to_string())?;
+            write_txn.commit()?;
+        }
+
+        let read_txn = db.begin_read()?;
+        let report = builder.merge_dry_run(&read_txn, target, 0, 3)?;
+        assert_eq!(report.conflicts, vec![2, 4]);
+        assert_eq!(builder.registered_buckets(&read_txn)?, vec![0, 1, 3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rename_bucket_tables_to_new_prefix() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(100, "old")?;
+        let multimap = TableBucketBuilder::new(100, "old_multi")?;
+
+        {
+            let write_txn = db.begin_write()?;
+            builder.insert::<u64, String>(&write_txn, 10, 1u64, "a".to_string())?;
+            builder.insert::<u64, String>(&write_txn, 250, 1u64, "b".to_string())?;
+            multimap.insert_multimap::<u64, u64>(&write_txn, 10, 1u64, 7u64)?;
+            write_txn.commit()?;
+        }
+
+        let write_txn = db.begin_write()?;
+        let renamed = builder.rename_prefix(&write_txn, "new")?;
+        assert!(builder.rename_prefix(&write_txn, "old").is_err());
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        assert_eq!(renamed.bucket_size(), 100);
+        let values: Vec<String> = read_txn
+            .table_bucket_range(&renamed, 1u64, 0, 299)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec!["a", "b"]);
+        assert_eq!(
+            read_txn
+                .table_bucket_range::<u64, String>(&builder, 1u64, 0, 299)?
+                .count(),
+            0
+        );
+        assert!(builder.registered_buckets(&read_txn)?.is_empty());
+        assert_eq!(renamed.registered_buckets(&read_txn)?, vec![0, 2]);
+
+        // Prefixes that merely start with the old one are left alone
+        let values: Vec<u64> = read_txn
+            .table_bucket_multimap_range(&multimap, 1u64, 0, 99)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![7]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_all_ignores_foreign_tables() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(100, "merge_test")?;
+        let target: TableDefinition<u64, String> = TableDefinition::new("merged_strict");
+        let foreign = [
+            "merge_test_05",
+            "merge_test_+7",
+            "merge_test_2extra",
+            "merge_testing_5",
+            "merge_test_",
+        ];
+
+        {
+            let write_txn = db.begin_write()?;
+            builder.insert::<u64, String>(&write_txn, 150, 1u64, "kept".to_string())?;
+            for name in foreign {
+                write_txn
+                    .open_table(TableDefinition::<u64, String>::new(name))?
+                    .insert(1u64, "foreign".to_string())?;
+            }
+            write_txn.commit()?;
+        }
+
+        let mut write_txn = db.begin_write()?;
+        builder.merge_all(&mut write_txn, target)?;
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        assert_eq!(
+            read_txn.open_table(target)?.get(1u64)?.unwrap().value(),
+            "kept"
+        );
+        for name in foreign {
+            let table = read_txn.open_table(TableDefinition::<u64, String>::new(name))?;
+            assert_eq!(table.get(1u64)?.unwrap().value(), "foreign");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn copy_plan_covers_bucket_range() -> Result<(), Box<dyn std::error::Error>> {
+        let source_file = NamedTempFile::new()?;
+        let destination_file = NamedTempFile::new()?;
+        let source = Database::create(source_file.path())?;
+        let destination = Database::create(destination_file.path())?;
+        let builder = TableBucketBuilder::new(100, "ship")?;
+        let tags = TableBucketBuilder::new(100, "ship_tags")?;
+
+        {
+            let write_txn = source.begin_write()?;
+            for sequence in [10, 150, 350] {
+                builder.insert::<u64, u64>(&write_txn, sequence, 1u64, sequence)?;
+                tags.insert_multimap::<u64, u64>(&write_txn, sequence, 1u64, sequence)?;
+            }
+            write_txn.commit()?;
+        }
+
+        let read_txn = source.begin_read()?;
+        copy_database(
+            &source,
+            &destination,
+            &builder.copy_plan::<u64, u64>(&read_txn, 0, 2)?,
+        )?;
+        copy_database(
+            &source,
+            &destination,
+            &tags.multimap_copy_plan::<u64, u64>(&read_txn, 1, 3)?,
+        )?;
+        assert!(builder.copy_plan::<u64, u64>(&read_txn, 2, 0).is_err());
+
+        let read_txn = destination.begin_read()?;
+        let values: Vec<u64> = read_txn
+            .table_bucket_range(&builder, 1u64, 0, 399)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![10, 150]);
+        let values: Vec<u64> = read_txn
+            .table_bucket_multimap_range(&tags, 1u64, 0, 399)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![150, 350]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn compact_bucket_into_summaries() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(100, "compact")?;
+
+        {
+            let write_txn = db.begin_write()?;
+            for (sequence, key, amount) in [(10, 1u64, 5u64), (20, 1, 7), (30, 1, 30), (40, 2, 4)] {
+                builder.insert_multimap::<u64, u64>(&write_txn, sequence, key, amount)?;
+            }
+            builder.insert_multimap::<u64, u64>(&write_txn, 150, 1u64, 1u64)?;
+            write_txn.commit()?;
+        }
+
+        let write_txn = db.begin_write()?;
+        let sum = |total: Option<u64>, amount: u64| total.unwrap_or(0) + amount;
+        assert_eq!(builder.compact::<u64, u64>(&write_txn, 0, sum)?, 2);
+        assert_eq!(builder.compact::<u64, u64>(&write_txn, 0, sum)?, 0);
+        assert_eq!(builder.compact::<u64, u64>(&write_txn, 7, sum)?, 0);
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        let values: Vec<u64> = read_txn
+            .table_bucket_multimap_range(&builder, 1u64, 0, 199)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![42, 1]);
+        let values: Vec<u64> = read_txn
+            .table_bucket_multimap_range(&builder, 2u64, 0, 99)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![4]);
+
+        Ok(())
     }
 }
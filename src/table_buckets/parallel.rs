@@ -0,0 +1,228 @@
+//! Parallel bucket table merge.
+//!
+//! Bucket tables don't depend on each other until their values land in the
+//! target table, so large backfills can read and pre-merge them on worker
+//! threads. Only the final inserts into the target go through the single
+//! write transaction.
+
+use crate::error::DatabaseError;
+use crate::key_buckets::BucketError;
+use crate::table_buckets::TableBucketBuilder;
+use crate::MergeableValue;
+use redb::{
+    Database, Key, ReadTransaction, ReadableDatabase, ReadableTable, TableDefinition, TableError,
+    TableHandle, Value,
+};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+
+fn parallel_error(err: impl Into<redb::Error>) -> BucketError {
+    BucketError::IterationError(DatabaseError::new("Parallel merge failed", err))
+}
+
+impl TableBucketBuilder {
+    /// Merge bucket tables into a single target table using worker threads.
+    ///
+    /// Like `merge`, but runs its own write transaction and commits it. The
+    /// existing bucket tables in the range are split into `threads`
+    /// contiguous runs; each run is read and folded per key on its own
+    /// thread, and the per-run results are then merged into the target in
+    /// bucket order. This regroups the calls to `MergeableValue::merge`, so
+    /// the result only matches `merge` when it is associative.
+    ///
+    /// # Arguments
+    /// * `db` - The database holding the bucket tables
+    /// * `target` - The table receiving the merged values
+    /// * `start_bucket` - First bucket to merge
+    /// * `end_bucket` - Last bucket to merge (inclusive)
+    /// * `threads` - Number of worker threads, at least one is used
+    ///
+    /// # Returns
+    /// Number of bucket tables merged
+    pub fn merge_parallel<K, V>(
+        &self,
+        db: &Database,
+        target: TableDefinition<'static, K, V>,
+        start_bucket: u64,
+        end_bucket: u64,
+        threads: usize,
+    ) -> Result<u64, BucketError>
+    where
+        K: Key + 'static,
+        V: Value + MergeableValue + Send + 'static,
+        for<'b> V: From<V::SelfType<'b>>,
+        for<'b> V: Borrow<V::SelfType<'b>>,
+    {
+        if start_bucket > end_bucket {
+            return Err(BucketError::InvalidRange {
+                start: start_bucket,
+                end: end_bucket,
+            });
+        }
+
+        // Take the write lock first so the read snapshot matches it
+        let write_txn = db.begin_write().map_err(parallel_error)?;
+        let read_txn = db.begin_read().map_err(parallel_error)?;
+
+        let mut buckets: Vec<u64> = write_txn
+            .list_tables()
+            .map_err(parallel_error)?
+            .filter_map(|table| self.bucket_of_table(table.name()))
+            .filter(|bucket| (start_bucket..=end_bucket).contains(bucket))
+            .collect();
+        buckets.sort_unstable();
+        if buckets.is_empty() {
+            return Ok(0);
+        }
+
+        let threads = threads.max(1);
+        let run_len = (buckets.len() + threads - 1) / threads;
+        let runs: Vec<Vec<(Vec<u8>, V)>> = std::thread::scope(|scope| {
+            let workers: Vec<_> = buckets
+                .chunks(run_len)
+                .map(|run| scope.spawn(|| self.fold_run::<K, V>(&read_txn, run)))
+                .collect();
+
+            workers
+                .into_iter()
+                .map(|worker| {
+                    worker.join().map_err(|_| {
+                        parallel_error(std::io::Error::new(
+                            std::io::ErrorKind::Other,
+                            "worker thread panicked",
+                        ))
+                    })?
+                })
+                .collect::<Result<_, _>>()
+        })?;
+        drop(read_txn);
+
+        {
+            let mut target_table = write_txn.open_table(target).map_err(parallel_error)?;
+            for run in runs {
+                for (key, incoming) in run {
+                    let key = K::from_bytes(&key);
+                    let existing = target_table
+                        .get(&key)
+                        .map_err(parallel_error)?
+                        .map(|guard| V::from(guard.value()));
+                    target_table
+                        .insert(&key, V::merge(existing, incoming))
+                        .map_err(parallel_error)?;
+                }
+            }
+        }
+
+        for &bucket in &buckets {
+            let name = self.table_name(bucket);
+            write_txn
+                .delete_table(TableDefinition::<K, V>::new(&name))
+                .map_err(parallel_error)?;
+            self.unregister(&write_txn, bucket)?;
+        }
+        write_txn.commit().map_err(parallel_error)?;
+
+        Ok(buckets.len() as u64)
+    }
+
+    /// Folds the values of a run of bucket tables per encoded key, in bucket order.
+    fn fold_run<K, V>(
+        &self,
+        txn: &ReadTransaction,
+        run: &[u64],
+    ) -> Result<Vec<(Vec<u8>, V)>, BucketError>
+    where
+        K: Key + 'static,
+        V: Value + MergeableValue + 'static,
+        for<'b> V: From<V::SelfType<'b>>,
+    {
+        let mut folded: HashMap<Vec<u8>, V> = HashMap::new();
+        for &bucket in run {
+            let name = self.table_name(bucket);
+            let table = match txn.open_table(TableDefinition::<K, V>::new(&name)) {
+                Ok(table) => table,
+                Err(TableError::TableDoesNotExist(_)) => continue,
+                Err(err) => return Err(parallel_error(err)),
+            };
+
+            for entry in table.iter().map_err(parallel_error)? {
+                let (key, value) = entry.map_err(parallel_error)?;
+                let key = K::as_bytes(&key.value()).as_ref().to_vec();
+                let incoming = V::from(value.value());
+                let merged = V::merge(folded.remove(&key), incoming);
+                folded.insert(key, merged);
+            }
+        }
+
+        // Target inserts are cheaper in key order
+        let mut folded: Vec<(Vec<u8>, V)> = folded.into_iter().collect();
+        folded.sort_by(|(a, _), (b, _)| K::compare(a, b));
+        Ok(folded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::table_buckets::TableBucketBuilder;
+    use redb::{Database, ReadableDatabase, TableDefinition, TableError};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_merge_parallel() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TableBucketBuilder::new(10, "parallel")?;
+        let target: TableDefinition<u64, String> = TableDefinition::new("parallel_log");
+
+        {
+            let write_txn = db.begin_write()?;
+            for sequence in 0..200u64 {
+                builder.insert::<u64, String>(
+                    &write_txn,
+                    sequence,
+                    sequence % 3,
+                    sequence.to_string(),
+                )?;
+            }
+            write_txn
+                .open_table(target)?
+                .insert(0u64, "seed".to_string())?;
+            write_txn.commit()?;
+        }
+
+        assert_eq!(builder.merge_parallel(&db, target, 0, 14, 4)?, 15);
+
+        // Each bucket table holds the last value written to a key, joined in bucket order
+        let expected = |key: u64| {
+            (0..15u64)
+                .filter_map(|bucket| {
+                    (bucket * 10..bucket * 10 + 10)
+                        .rev()
+                        .find(|sequence| sequence % 3 == key)
+                })
+                .map(|sequence| sequence.to_string())
+                .collect::<Vec<_>>()
+                .join("+")
+        };
+        let read_txn = db.begin_read()?;
+        let log = read_txn.open_table(target)?;
+        assert_eq!(
+            log.get(0u64)?.unwrap().value(),
+            format!("seed+{}", expected(0))
+        );
+        assert_eq!(log.get(1u64)?.unwrap().value(), expected(1));
+        assert_eq!(log.get(2u64)?.unwrap().value(), expected(2));
+        assert!(matches!(
+            read_txn.open_table(builder.table_definition::<u64, String>(3)),
+            Err(TableError::TableDoesNotExist(_))
+        ));
+        assert_eq!(
+            builder.registered_buckets(&read_txn)?,
+            (15..20).collect::<Vec<_>>()
+        );
+
+        assert!(builder.merge_parallel(&db, target, 5, 1, 2).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,396 @@
+//! Calendar-based table buckets.
+//!
+//! Names bucket tables after the UTC day or hour they cover, e.g.
+//! `events_2024_05_17` or `events_2024_05_17_13`, so log-style data can be
+//! found and inspected by date without knowing the bucket numbering.
+
+use crate::error::DatabaseError;
+use crate::key_buckets::key::{unix_millis, DAY_MILLIS, HOUR_MILLIS};
+use crate::key_buckets::BucketError;
+use crate::table_buckets::iterator::{
+    BucketTableNames, TableBucketRangeIterator, TableBucketRangeMultimapIterator,
+};
+use redb::{
+    Key, MultimapTable, MultimapTableDefinition, ReadTransaction, Table, TableDefinition, Value,
+    WriteTransaction,
+};
+use std::borrow::Borrow;
+use std::time::SystemTime;
+
+/// Calendar unit covered by one table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeGranularity {
+    /// One table per UTC hour
+    Hourly,
+    /// One table per UTC day
+    Daily,
+}
+
+impl TimeGranularity {
+    fn millis(self) -> u64 {
+        match self {
+            TimeGranularity::Hourly => HOUR_MILLIS,
+            TimeGranularity::Daily => DAY_MILLIS,
+        }
+    }
+}
+
+/// Builder for table buckets named after calendar dates.
+///
+/// Timestamps are unix milliseconds, as with `KeyBuilder::daily`. Buckets
+/// count hours or days since the epoch and map to tables named
+/// `{prefix}_{yyyy}_{mm}_{dd}`, with a trailing `_{hh}` for hourly buckets.
+#[derive(Debug, Clone)]
+pub struct TimeTableBucketBuilder {
+    table_prefix: String,
+    granularity: TimeGranularity,
+}
+
+impl TimeTableBucketBuilder {
+    /// Create a builder with one table per UTC hour.
+    pub fn hourly(table_prefix: impl Into<String>) -> Self {
+        Self {
+            table_prefix: table_prefix.into(),
+            granularity: TimeGranularity::Hourly,
+        }
+    }
+
+    /// Create a builder with one table per UTC day.
+    pub fn daily(table_prefix: impl Into<String>) -> Self {
+        Self {
+            table_prefix: table_prefix.into(),
+            granularity: TimeGranularity::Daily,
+        }
+    }
+
+    /// Get the configured table prefix.
+    pub fn table_prefix(&self) -> &str {
+        &self.table_prefix
+    }
+
+    /// Get the configured granularity.
+    pub fn granularity(&self) -> TimeGranularity {
+        self.granularity
+    }
+
+    /// Get the bucket a timestamp in unix milliseconds falls into.
+    pub fn bucket_for(&self, timestamp: u64) -> u64 {
+        timestamp / self.granularity.millis()
+    }
+
+    /// Get the bucket a point in time falls into.
+    ///
+    /// # Returns
+    /// The bucket or error if the time is before the unix epoch
+    pub fn bucket_at(&self, time: SystemTime) -> Result<u64, BucketError> {
+        Ok(self.bucket_for(unix_millis(time)?))
+    }
+
+    /// Get the table name for a bucket, e.g. `events_2024_05_17`.
+    pub fn table_name(&self, bucket: u64) -> String {
+        match self.granularity {
+            TimeGranularity::Daily => {
+                let (year, month, day) = civil_from_days(bucket);
+                format!("{}_{:04}_{:02}_{:02}", self.table_prefix, year, month, day)
+            }
+            TimeGranularity::Hourly => {
+                let (year, month, day) = civil_from_days(bucket / 24);
+                format!(
+                    "{}_{:04}_{:02}_{:02}_{:02}",
+                    self.table_prefix,
+                    year,
+                    month,
+                    day,
+                    bucket % 24
+                )
+            }
+        }
+    }
+
+    /// Open a bucket table for writing.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `bucket` - The bucket whose table to open, created if missing
+    ///
+    /// # Returns
+    /// The opened bucket table
+    pub fn open_table<'txn, K: Key + 'static, V: Value + 'static>(
+        &self,
+        txn: &'txn WriteTransaction,
+        bucket: u64,
+    ) -> Result<Table<'txn, K, V>, BucketError> {
+        let name = self.table_name(bucket);
+        txn.open_table(TableDefinition::new(&name)).map_err(|err| {
+            BucketError::DatabaseError(
+                DatabaseError::new("Failed to open bucket table", err)
+                    .table(name)
+                    .bucket(bucket),
+            )
+        })
+    }
+
+    /// Open a bucket multimap table for writing.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `bucket` - The bucket whose table to open, created if missing
+    ///
+    /// # Returns
+    /// The opened bucket multimap table
+    pub fn open_multimap_table<'txn, K: Key + 'static, V: Key + 'static>(
+        &self,
+        txn: &'txn WriteTransaction,
+        bucket: u64,
+    ) -> Result<MultimapTable<'txn, K, V>, BucketError> {
+        let name = self.table_name(bucket);
+        txn.open_multimap_table(MultimapTableDefinition::new(&name))
+            .map_err(|err| {
+                BucketError::DatabaseError(
+                    DatabaseError::new("Failed to open bucket table", err)
+                        .table(name)
+                        .bucket(bucket),
+                )
+            })
+    }
+
+    /// Insert a value into the table covering a timestamp.
+    ///
+    /// # Arguments
+    /// * `txn` - The write transaction
+    /// * `timestamp` - Unix milliseconds the entry belongs to
+    /// * `key` - The key within the bucket table
+    /// * `value` - The value to store
+    pub fn insert<'k, 'v, K: Key + 'static, V: Value + 'static>(
+        &self,
+        txn: &WriteTransaction,
+        timestamp: u64,
+        key: impl Borrow<K::SelfType<'k>>,
+        value: impl Borrow<V::SelfType<'v>>,
+    ) -> Result<(), BucketError> {
+        let mut table = self.open_table::<K, V>(txn, self.bucket_for(timestamp))?;
+        table.insert(key, value).map_err(|err| {
+            BucketError::DatabaseError(DatabaseError::new(
+                "Failed to insert into bucket table",
+                err,
+            ))
+        })?;
+        Ok(())
+    }
+
+    /// Insert a value into the multimap table covering a timestamp.
+    ///
+    /// # Returns
+    /// Whether the value was already present under the key
+    pub fn insert_multimap<'k, 'v, K: Key + 'static, V: Key + 'static>(
+        &self,
+        txn: &WriteTransaction,
+        timestamp: u64,
+        key: impl Borrow<K::SelfType<'k>>,
+        value: impl Borrow<V::SelfType<'v>>,
+    ) -> Result<bool, BucketError> {
+        let mut table = self.open_multimap_table::<K, V>(txn, self.bucket_for(timestamp))?;
+        table.insert(key, value).map_err(|err| {
+            BucketError::DatabaseError(DatabaseError::new(
+                "Failed to insert into bucket table",
+                err,
+            ))
+        })
+    }
+
+    /// Iterate the values of a base key between two timestamps.
+    ///
+    /// # Arguments
+    /// * `txn` - The read transaction
+    /// * `base_key` - The key to look up in each table
+    /// * `start_timestamp` - Start of the range in unix milliseconds
+    /// * `end_timestamp` - End of the range in unix milliseconds (inclusive)
+    ///
+    /// # Returns
+    /// Iterator over the tables covering the range, oldest first
+    pub fn range<'a, K, V>(
+        &'a self,
+        txn: &'a ReadTransaction,
+        base_key: K,
+        start_timestamp: u64,
+        end_timestamp: u64,
+    ) -> Result<TableBucketRangeIterator<'a, K, V>, BucketError>
+    where
+        K: Key + Clone + 'static,
+        for<'b> K: Borrow<K::SelfType<'b>>,
+        V: Value + 'static,
+        for<'b> V: From<V::SelfType<'b>>,
+    {
+        let (start_bucket, end_bucket) = self.bucket_bounds(start_timestamp, end_timestamp)?;
+        Ok(TableBucketRangeIterator::with_buckets(
+            txn,
+            self,
+            base_key,
+            start_bucket,
+            end_bucket,
+        ))
+    }
+
+    /// Iterate the multimap values of a base key between two timestamps.
+    pub fn multimap_range<'a, K, V>(
+        &'a self,
+        txn: &'a ReadTransaction,
+        base_key: K,
+        start_timestamp: u64,
+        end_timestamp: u64,
+    ) -> Result<TableBucketRangeMultimapIterator<'a, K, V>, BucketError>
+    where
+        K: Key + Clone + 'static,
+        for<'b> K: Borrow<K::SelfType<'b>>,
+        V: Key + 'static,
+        for<'b> V: From<V::SelfType<'b>>,
+    {
+        let (start_bucket, end_bucket) = self.bucket_bounds(start_timestamp, end_timestamp)?;
+        Ok(TableBucketRangeMultimapIterator::with_buckets(
+            txn,
+            self,
+            base_key,
+            start_bucket,
+            end_bucket,
+        ))
+    }
+
+    fn bucket_bounds(
+        &self,
+        start_timestamp: u64,
+        end_timestamp: u64,
+    ) -> Result<(u64, u64), BucketError> {
+        if start_timestamp > end_timestamp {
+            return Err(BucketError::InvalidRange {
+                start: start_timestamp,
+                end: end_timestamp,
+            });
+        }
+        Ok((
+            self.bucket_for(start_timestamp),
+            self.bucket_for(end_timestamp),
+        ))
+    }
+}
+
+impl BucketTableNames for TimeTableBucketBuilder {
+    fn table_name(&self, bucket: u64) -> String {
+        TimeTableBucketBuilder::table_name(self, bucket)
+    }
+}
+
+/// Converts days since the unix epoch to a proleptic Gregorian (year, month, day).
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Shift the epoch to 0000-03-01 so leap days end each 400-year era
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redb::{Database, ReadableDatabase};
+    use tempfile::NamedTempFile;
+
+    /// 2024-05-17T00:00:00Z
+    const MAY_17: u64 = 1_715_904_000_000;
+
+    #[test]
+    fn test_calendar_table_names() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_722), (2023, 12, 31));
+
+        let daily = TimeTableBucketBuilder::daily("events");
+        assert_eq!(
+            daily.table_name(daily.bucket_for(MAY_17 + 5)),
+            "events_2024_05_17"
+        );
+        assert_eq!(
+            daily.table_name(daily.bucket_for(MAY_17 - 1)),
+            "events_2024_05_16"
+        );
+
+        let hourly = TimeTableBucketBuilder::hourly("events");
+        assert_eq!(
+            hourly.table_name(hourly.bucket_for(MAY_17 + 13 * HOUR_MILLIS + 59)),
+            "events_2024_05_17_13"
+        );
+        assert_eq!(
+            hourly.table_name(hourly.bucket_for(MAY_17 - 1)),
+            "events_2024_05_16_23"
+        );
+    }
+
+    #[test]
+    fn test_calendar_range_iteration() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = NamedTempFile::new()?;
+        let db = Database::create(temp_file.path())?;
+        let builder = TimeTableBucketBuilder::daily("logs");
+
+        let write_txn = db.begin_write()?;
+        for (day, line) in [(0, "boot"), (1, "warn"), (3, "halt")] {
+            let timestamp = MAY_17 + day * DAY_MILLIS;
+            builder.insert::<u64, String>(&write_txn, timestamp, 7u64, line.to_string())?;
+        }
+        let counts = TimeTableBucketBuilder::hourly("counts");
+        counts.insert_multimap::<u64, u64>(&write_txn, MAY_17, 7u64, 1u64)?;
+        counts.insert_multimap::<u64, u64>(&write_txn, MAY_17 + HOUR_MILLIS, 7u64, 2u64)?;
+        write_txn.commit()?;
+
+        let read_txn = db.begin_read()?;
+        assert!(read_txn
+            .open_table(TableDefinition::<u64, String>::new("logs_2024_05_20"))
+            .is_ok());
+
+        let lines: Vec<String> = builder
+            .range::<u64, String>(&read_txn, 7u64, MAY_17 + 1, MAY_17 + 2 * DAY_MILLIS)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines, vec!["boot", "warn"]);
+
+        let lines: Vec<String> = builder
+            .range::<u64, String>(&read_txn, 7u64, MAY_17, MAY_17 + 3 * DAY_MILLIS)?
+            .rev()
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines, vec!["halt", "warn", "boot"]);
+
+        let values: Vec<u64> = counts
+            .multimap_range::<u64, u64>(&read_txn, 7u64, MAY_17, MAY_17 + DAY_MILLIS)?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values, vec![1, 2]);
+
+        assert!(builder
+            .range::<u64, String>(&read_txn, 7u64, MAY_17, 0)
+            .is_err());
+
+        // Opening a bucket with other types reports the bucket and its table
+        let write_txn = db.begin_write()?;
+        let bucket = builder.bucket_for(MAY_17);
+        let Err(BucketError::DatabaseError(err)) =
+            builder.open_table::<u32, String>(&write_txn, bucket)
+        else {
+            panic!("expected a database error");
+        };
+        assert_eq!(err.context().bucket, Some(bucket));
+        assert_eq!(err.context().table.as_deref(), Some("logs_2024_05_17"));
+        assert!(matches!(
+            err.redb_error(),
+            redb::Error::TableTypeMismatch { .. }
+        ));
+
+        Ok(())
+    }
+}
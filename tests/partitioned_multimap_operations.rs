@@ -0,0 +1,178 @@
+//! Integration tests for fixed-width sets stored in partitioned tables.
+
+#[cfg(test)]
+mod tests {
+    use redb::{Database, ReadableDatabase, ReadableTable, ReadableTableMetadata};
+    use redb_extras::multimap::{
+        FixedSetValue, PartitionedMultimapRead, PartitionedMultimapTable, PartitionedMultimapWrite,
+    };
+    use redb_extras::partition::{
+        MetaEntry, PartitionConfig, PartitionedRead, PartitionedTable, PartitionedWrite,
+    };
+    use redb_extras::roaring::{PartitionedRoaringRead, PartitionedRoaringWrite, RoaringValue};
+    use tempfile::NamedTempFile;
+
+    type Hash = [u8; 32];
+
+    fn hash(seed: u8) -> Hash {
+        let mut value = [0u8; 32];
+        value[0] = seed.wrapping_mul(37);
+        value[31] = seed;
+        value
+    }
+
+    fn setup(
+        config: PartitionConfig,
+    ) -> (NamedTempFile, Database, PartitionedMultimapTable<u64, 32>) {
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = Database::create(temp_file.path()).unwrap();
+        let table = PartitionedMultimapTable::new("hashes", config);
+        table.ensure_table_exists(&db).unwrap();
+        (temp_file, db, table)
+    }
+
+    #[test]
+    fn test_multimap_insert_and_read() {
+        // Room for four hashes per segment
+        let max_bytes = FixedSetValue::<32>::encoded_size(4);
+        let (_file, db, table) = setup(PartitionConfig::new(2, max_bytes, true).unwrap());
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_values(1u64, (0..10).map(hash)).unwrap();
+            writer.insert_values(1u64, (5..20).map(hash)).unwrap();
+            writer.insert_value(2u64, hash(0)).unwrap();
+
+            assert_eq!(writer.get_value_count(1u64).unwrap(), 20);
+            assert!(writer.contains_value(1u64, &hash(19)).unwrap());
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let segments = read_txn.open_table(table.segment_table()).unwrap();
+        assert!(segments.len().unwrap() > 5);
+        for entry in segments.iter().unwrap() {
+            let (_, value) = entry.unwrap();
+            assert!(value.value().len() <= max_bytes);
+        }
+
+        let reader = PartitionedRead::new(&table, &read_txn);
+        let mut expected: Vec<Hash> = (0..20).map(hash).collect();
+        expected.sort_unstable();
+        assert_eq!(reader.get_values(1u64).unwrap(), expected);
+        assert!(reader.contains_value(1u64, &hash(3)).unwrap());
+        assert!(!reader.contains_value(1u64, &hash(20)).unwrap());
+        assert_eq!(reader.get_values(2u64).unwrap(), vec![hash(0)]);
+        assert!(reader.get_values(3u64).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_multimap_remove_and_clear() {
+        let max_bytes = FixedSetValue::<32>::encoded_size(2);
+        let (_file, db, table) = setup(PartitionConfig::new(1, max_bytes, true).unwrap());
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_values(1u64, (0..6).map(hash)).unwrap();
+            writer
+                .remove_values(1u64, [hash(0), hash(1), hash(4)])
+                .unwrap();
+            writer.remove_value(1u64, hash(42)).unwrap();
+
+            let mut expected = vec![hash(2), hash(3), hash(5)];
+            expected.sort_unstable();
+            assert_eq!(writer.get_values(1u64).unwrap(), expected);
+        }
+        write_txn.commit().unwrap();
+
+        {
+            let read_txn = db.begin_read().unwrap();
+            let meta = read_txn.open_table(table.meta_table()).unwrap();
+            let (_, entry) = meta.first().unwrap().unwrap();
+            assert_eq!(MetaEntry::decode(entry.value()).unwrap().member_count, 3);
+        }
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.clear_values(1u64).unwrap();
+            assert_eq!(writer.get_value_count(1u64).unwrap(), 0);
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        assert!(read_txn
+            .open_table(table.segment_table())
+            .unwrap()
+            .is_empty()
+            .unwrap());
+        assert!(read_txn
+            .open_table(table.meta_table())
+            .unwrap()
+            .is_empty()
+            .unwrap());
+    }
+
+    #[test]
+    fn test_multimap_and_roaring_keep_separate_storage() {
+        let config = PartitionConfig::new(2, 256, true)
+            .unwrap()
+            .with_tombstones(true);
+        let (_file, db, hashes) = setup(config.clone());
+        let members: PartitionedTable<u64, RoaringValue> = PartitionedTable::new("members", config);
+        members.ensure_table_exists(&db).unwrap();
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&hashes, &mut write_txn);
+            writer.insert_values(1u64, (0..10).map(hash)).unwrap();
+        }
+        {
+            let writer = PartitionedWrite::new(&members, &mut write_txn);
+            writer.insert_members(1u64, 0..100).unwrap();
+            writer.remove_member(1u64, 5).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        assert_eq!(
+            PartitionedRead::new(&hashes, &read_txn)
+                .get_value_count(1u64)
+                .unwrap(),
+            10
+        );
+        assert_eq!(
+            PartitionedRead::new(&members, &read_txn)
+                .get_member_count(1u64)
+                .unwrap(),
+            99
+        );
+        drop(read_txn);
+
+        // Only the roaring table's own shards are planned and compacted
+        let units: Vec<_> = members
+            .compaction_plan(&db)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(!units.is_empty());
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&members, &mut write_txn);
+            for unit in &units {
+                writer.compact_unit(unit).unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        assert_eq!(
+            PartitionedRead::new(&hashes, &read_txn)
+                .get_values(1u64)
+                .unwrap()
+                .len(),
+            10
+        );
+    }
+}
@@ -0,0 +1,1036 @@
+//! Integration tests for roaring bitmaps stored in partitioned tables.
+
+#[cfg(test)]
+mod tests {
+    use redb::{
+        Database, ReadableDatabase, ReadableTable, ReadableTableMetadata, TableDefinition,
+        TableHandle,
+    };
+    use redb_extras::multimap::{
+        PartitionedMultimapRead, PartitionedMultimapTable, PartitionedMultimapWrite,
+    };
+    use redb_extras::partition::table::{CONFIG_TABLE, MIGRATION_TABLE};
+    use redb_extras::partition::{
+        PartitionConfig, PartitionError, PartitionObserver, PartitionedRead, PartitionedTable,
+        PartitionedWrite, SegmentTransform,
+    };
+    use redb_extras::roaring::{PartitionedRoaringRead, PartitionedRoaringWrite, RoaringValue};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tempfile::NamedTempFile;
+
+    fn setup(
+        config: PartitionConfig,
+    ) -> (
+        NamedTempFile,
+        Database,
+        PartitionedTable<&'static str, RoaringValue>,
+    ) {
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = Database::create(temp_file.path()).unwrap();
+        let table = PartitionedTable::new("bitmaps", config);
+        table.ensure_table_exists(&db).unwrap();
+        (temp_file, db, table)
+    }
+
+    fn dump_segments(
+        db: &Database,
+        table: &PartitionedTable<&'static str, RoaringValue>,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let read_txn = db.begin_read().unwrap();
+        let segments = read_txn.open_table(table.segment_table()).unwrap();
+        segments
+            .iter()
+            .unwrap()
+            .map(|entry| {
+                let (key, value) = entry.unwrap();
+                (key.value().to_vec(), value.value().to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_partitioned_insert_and_read() {
+        let (_file, db, table) = setup(PartitionConfig::new(4, 1024, false).unwrap());
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_member("user1", 42).unwrap();
+            writer.insert_members("user1", 0..100).unwrap();
+            writer.insert_member("user2", 7).unwrap();
+
+            assert!(writer.contains_member("user1", 42).unwrap());
+            assert_eq!(writer.get_member_count("user1").unwrap(), 100);
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let reader = PartitionedRead::new(&table, &read_txn);
+        let bitmap = reader.get_bitmap("user1").unwrap();
+        assert_eq!(bitmap.len(), 100);
+        assert!(bitmap.iter().eq(0..100));
+        assert!(reader.contains_member("user2", 7).unwrap());
+        assert!(!reader.contains_member("user2", 8).unwrap());
+        assert_eq!(reader.get_member_count("missing").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_partitioned_remove_rewrites_segments() {
+        let (_file, db, table) = setup(PartitionConfig::new(2, 1024, false).unwrap());
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_members("key", [1, 2, 3]).unwrap();
+            writer.remove_member("key", 2).unwrap();
+            writer.remove_members("key", [1, 3]).unwrap();
+            assert!(writer.get_bitmap("key").unwrap().is_empty());
+        }
+        write_txn.commit().unwrap();
+
+        // Emptied segments are deleted rather than left behind
+        let read_txn = db.begin_read().unwrap();
+        let segments = read_txn.open_table(table.segment_table()).unwrap();
+        assert_eq!(segments.len().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_tombstones_record_removals_without_rewriting() {
+        let config = PartitionConfig::new(2, 1024, false)
+            .unwrap()
+            .with_tombstones(true);
+        let (_file, db, table) = setup(config);
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_members("key", 0..10).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let segments_before = dump_segments(&db, &table);
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.remove_member("key", 3).unwrap();
+            writer.remove_members("key", [5, 6]).unwrap();
+            assert!(!writer.contains_member("key", 3).unwrap());
+        }
+        write_txn.commit().unwrap();
+
+        // Removals only touched the tombstone rows
+        assert_eq!(segments_before, dump_segments(&db, &table));
+
+        {
+            let read_txn = db.begin_read().unwrap();
+            let tombstones = read_txn.open_table(table.tombstone_table()).unwrap();
+            assert!(tombstones.len().unwrap() > 0);
+
+            let reader = PartitionedRead::new(&table, &read_txn);
+            let bitmap = reader.get_bitmap("key").unwrap();
+            assert!(bitmap.iter().eq([0, 1, 2, 4, 7, 8, 9]));
+        }
+
+        // Re-inserting clears the tombstone, compaction folds the rest in
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_member("key", 5).unwrap();
+            assert!(writer.contains_member("key", 5).unwrap());
+            writer.compact("key").unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let tombstones = read_txn.open_table(table.tombstone_table()).unwrap();
+        assert_eq!(tombstones.len().unwrap(), 0);
+
+        let reader = PartitionedRead::new(&table, &read_txn);
+        let bitmap = reader.get_bitmap("key").unwrap();
+        assert!(bitmap.iter().eq([0, 1, 2, 4, 5, 7, 8, 9]));
+    }
+
+    #[test]
+    fn test_tombstones_skip_absent_members() {
+        let config = PartitionConfig::new(2, 1024, true)
+            .unwrap()
+            .with_tombstones(true);
+        let (_file, db, table) = setup(config);
+        let tombstoned = |db: &Database| {
+            let read_txn = db.begin_read().unwrap();
+            let tombstones = read_txn.open_table(table.tombstone_table()).unwrap();
+            let mut members = roaring::RoaringTreemap::new();
+            for entry in tombstones.iter().unwrap() {
+                let (_, value) = entry.unwrap();
+                members |= RoaringValue::decode(value.value()).unwrap().into_bitmap();
+            }
+            members
+        };
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_members("key", 0..10).unwrap();
+            writer.remove_member("key", 3).unwrap();
+        }
+        write_txn.commit().unwrap();
+        assert!(tombstoned(&db).iter().eq([3]));
+
+        // Absent members don't grow the tombstone, present ones still do
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.remove_members("key", [100, 200]).unwrap();
+            writer.remove_members("missing", [1, 2]).unwrap();
+            writer.remove_members("key", [3, 9, 500]).unwrap();
+            assert_eq!(writer.get_member_count("key").unwrap(), 8);
+        }
+        write_txn.commit().unwrap();
+        assert!(tombstoned(&db).iter().eq([3, 9]));
+
+        // Inserting a member that was removed while absent keeps it
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_member("key", 500).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let reader = PartitionedRead::new(&table, &read_txn);
+        assert!(reader.contains_member("key", 500).unwrap());
+        assert_eq!(reader.get_member_count("key").unwrap(), 9);
+    }
+
+    #[test]
+    fn test_compaction_plan_runs_unit_per_transaction() {
+        let config = PartitionConfig::new(1, 1024, true)
+            .unwrap()
+            .with_tombstones(true);
+        let (_file, db, table) = setup(config);
+        let keys: Vec<String> = (0..300).map(|i| format!("k{:03}", i)).collect();
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            for key in &keys {
+                writer.insert_members(key.as_str(), [1, 2]).unwrap();
+                writer.remove_member(key.as_str(), 1).unwrap();
+            }
+            // Shrinking every segment of "big" to a single member leaves
+            // adjacent segments that fit together
+            writer
+                .insert_members("big", (0..2000u64).map(|m| m * 3))
+                .unwrap();
+            for i in 0..8 {
+                writer
+                    .remove_range("big", i * 750 + 1..(i + 1) * 750)
+                    .unwrap();
+            }
+            assert!(writer.enumerate_shard_segments("big", 0).unwrap().len() > 1);
+
+            // Already compact: every segment is close to the size limit
+            writer
+                .insert_members("full", (0..2000u64).map(|m| m * 3))
+                .unwrap();
+            writer.insert_member("clean", 5).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let units: Vec<_> = table
+            .compaction_plan(&db)
+            .collect::<redb_extras::Result<_>>()
+            .unwrap();
+        assert_eq!(units.len(), keys.len() + 1);
+        assert!(units
+            .windows(2)
+            .all(|pair| pair[0].base_key < pair[1].base_key));
+
+        let big = units.iter().find(|unit| !unit.has_tombstone).unwrap();
+        assert!(big.segment_count > 1);
+
+        let mut executed = 0;
+        for unit in table.compaction_plan(&db) {
+            let unit = unit.unwrap();
+            let mut write_txn = db.begin_write().unwrap();
+            {
+                let writer = PartitionedWrite::new(&table, &mut write_txn);
+                writer.compact_unit(&unit).unwrap();
+            }
+            write_txn.commit().unwrap();
+            executed += 1;
+        }
+        assert_eq!(executed, units.len());
+
+        assert_eq!(table.compaction_plan(&db).count(), 0);
+
+        let read_txn = db.begin_read().unwrap();
+        let tombstones = read_txn.open_table(table.tombstone_table()).unwrap();
+        assert_eq!(tombstones.len().unwrap(), 0);
+
+        let reader = PartitionedRead::new(&table, &read_txn);
+        assert!(reader.get_bitmap("k042").unwrap().iter().eq([2]));
+        assert!(reader
+            .get_bitmap("big")
+            .unwrap()
+            .iter()
+            .eq((0..8).map(|i| i * 750)));
+        assert_eq!(reader.enumerate_shard_segments("big", 0).unwrap().len(), 1);
+        assert_eq!(reader.get_member_count("full").unwrap(), 2000);
+        assert!(reader.get_bitmap("clean").unwrap().iter().eq([5]));
+    }
+
+    #[test]
+    fn test_apply_batch_across_keys() {
+        let (_file, db, table) = setup(PartitionConfig::new(4, 1024, false).unwrap());
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_member("a", 1000).unwrap();
+            writer
+                .apply_batch([
+                    ("a", (0..50).collect::<Vec<u64>>()),
+                    ("b", vec![7, 8, 9]),
+                    ("a", vec![50, 51]),
+                ])
+                .unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let reader = PartitionedRead::new(&table, &read_txn);
+        let a = reader.get_bitmap("a").unwrap();
+        assert!(a.iter().eq((0..52).chain([1000])));
+        assert!(reader.get_bitmap("b").unwrap().iter().eq([7, 8, 9]));
+    }
+
+    #[test]
+    fn test_iter_shard_bitmaps() {
+        let (_file, db, table) = setup(PartitionConfig::new(4, 1024, false).unwrap());
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_members("key", 0..1000).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let reader = PartitionedRead::new(&table, &read_txn);
+
+        let mut total = 0;
+        let mut previous_shard = None;
+        for entry in reader.iter_shard_bitmaps("key") {
+            let (shard, bitmap) = entry.unwrap();
+            assert!(previous_shard < Some(shard));
+            previous_shard = Some(shard);
+
+            assert!(!bitmap.is_empty());
+            for member in &bitmap {
+                assert_eq!(table.select_shard("key", member).unwrap(), shard);
+            }
+            total += bitmap.len();
+        }
+        assert_eq!(total, 1000);
+        assert_eq!(reader.iter_shard_bitmaps("missing").count(), 0);
+    }
+
+    #[test]
+    fn test_cached_member_count_tracks_writes() {
+        let config = PartitionConfig::new(4, 64, true)
+            .unwrap()
+            .with_tombstones(true);
+        let (_file, db, table) = setup(config);
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer
+                .insert_members("key", (0..300u64).map(|i| i * 65_537))
+                .unwrap();
+            writer.insert_members("key", [0, 65_537]).unwrap();
+            assert_eq!(writer.cached_member_count("key").unwrap(), 300);
+
+            writer.remove_members("key", [0, 131_074]).unwrap();
+            assert_eq!(writer.cached_member_count("key").unwrap(), 298);
+
+            writer.compact("key").unwrap();
+            assert_eq!(writer.cached_member_count("key").unwrap(), 298);
+            writer.insert_member("key", 1).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        {
+            let read_txn = db.begin_read().unwrap();
+            let reader = PartitionedRead::new(&table, &read_txn);
+            assert_eq!(reader.cached_member_count("key").unwrap(), 299);
+            assert_eq!(reader.get_member_count("key").unwrap(), 299);
+            assert_eq!(reader.cached_member_count("missing").unwrap(), 0);
+
+            // One entry per shard plus the member bounds of every segment
+            let meta = read_txn.open_table(table.meta_table()).unwrap();
+            assert_eq!(
+                meta.len().unwrap(),
+                4 + dump_segments(&db, &table).len() as u64
+            );
+        }
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.clear_bitmap("key").unwrap();
+            assert_eq!(writer.cached_member_count("key").unwrap(), 0);
+        }
+        write_txn.commit().unwrap();
+
+        // Meta rows go away together with the last segment of each shard
+        let read_txn = db.begin_read().unwrap();
+        let meta = read_txn.open_table(table.meta_table()).unwrap();
+        assert_eq!(meta.len().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_cached_member_count_matches_bitmap() {
+        for use_tombstones in [false, true] {
+            let config = PartitionConfig::new(2, 64, true)
+                .unwrap()
+                .with_tombstones(use_tombstones);
+            let (_file, db, table) = setup(config);
+
+            let mut write_txn = db.begin_write().unwrap();
+            {
+                let writer = PartitionedWrite::new(&table, &mut write_txn);
+                let check = |step: &str| {
+                    assert_eq!(
+                        writer.cached_member_count("key").unwrap(),
+                        writer.get_bitmap("key").unwrap().len(),
+                        "{} (tombstones: {})",
+                        step,
+                        use_tombstones
+                    );
+                };
+
+                // Spread over several segments per shard
+                writer
+                    .insert_members("key", (0..200u64).map(|i| i * 65_537))
+                    .unwrap();
+                check("insert");
+                writer.insert_members("key", [0, 65_537, 7, 8]).unwrap();
+                check("reinsert into older segments");
+                writer.remove_members("key", [0, 7, 9, 131_074]).unwrap();
+                check("remove present and absent members");
+                writer.remove_members("key", [0, 7]).unwrap();
+                check("remove again");
+                writer.insert_members("key", [0, 7, 10]).unwrap();
+                check("reinsert removed members");
+                writer.remove_range("key", 5..=65_537 * 50).unwrap();
+                check("remove range");
+                writer.insert_members("key", 0..20).unwrap();
+                check("insert into range");
+                writer.compact("key").unwrap();
+                check("compact");
+                writer.remove_range("key", ..).unwrap();
+                check("remove everything");
+            }
+            write_txn.commit().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_remove_range_skips_disjoint_segments() {
+        let config = PartitionConfig::new(2, 64, true)
+            .unwrap()
+            .with_tombstones(true);
+        let (_file, db, table) = setup(config);
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer
+                .insert_members("key", (0..200u64).map(|i| i * 65_537))
+                .unwrap();
+            writer.remove_member("key", 65_537 * 150).unwrap();
+            let before = writer.stats();
+
+            writer
+                .remove_range("key", 65_537 * 10..65_537 * 20)
+                .unwrap();
+            let bitmap = writer.get_bitmap("key").unwrap();
+            assert_eq!(bitmap.len(), 189);
+            assert!(bitmap.contains(65_537 * 9));
+            assert!(!bitmap.contains(65_537 * 10));
+            assert!(!bitmap.contains(65_537 * 19));
+            assert!(bitmap.contains(65_537 * 20));
+            assert_eq!(writer.cached_member_count("key").unwrap(), 189);
+
+            // Only the segments holding the range were rewritten
+            let stats = writer.stats();
+            let rewritten = stats.segments_written + stats.segments_deleted
+                - before.segments_written
+                - before.segments_deleted;
+            assert!(rewritten > 0);
+            let segments: usize = (0..2)
+                .map(|shard| writer.enumerate_shard_segments("key", shard).unwrap().len())
+                .sum();
+            assert!(rewritten < segments as u64);
+
+            writer.remove_range("key", 65_537 * 100..).unwrap();
+            assert_eq!(writer.get_member_count("key").unwrap(), 90);
+
+            // Empty and inverted ranges are no-ops
+            writer.remove_range("key", 5..5).unwrap();
+            #[allow(clippy::reversed_empty_ranges)]
+            writer.remove_range("key", 10..=5).unwrap();
+            assert_eq!(writer.get_member_count("key").unwrap(), 90);
+        }
+        write_txn.commit().unwrap();
+
+        // The tombstone for a removed member is dropped with its range
+        let read_txn = db.begin_read().unwrap();
+        assert!(read_txn
+            .open_table(table.tombstone_table())
+            .unwrap()
+            .is_empty()
+            .unwrap());
+    }
+
+    #[test]
+    fn test_segment_rolling_and_clear() {
+        let (_file, db, table) = setup(PartitionConfig::new(1, 64, false).unwrap());
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            for member in 0..200u64 {
+                writer.insert_member("key", member * 1_000_003).unwrap();
+            }
+            assert_eq!(writer.get_member_count("key").unwrap(), 200);
+            assert!(writer.enumerate_shard_segments("key", 0).unwrap().len() > 1);
+
+            writer.clear_bitmap("key").unwrap();
+            assert_eq!(writer.get_member_count("key").unwrap(), 0);
+        }
+        write_txn.commit().unwrap();
+    }
+
+    #[test]
+    fn test_oversized_batch_is_split_across_segments() {
+        let max_bytes = 256;
+        let (_file, db, table) = setup(PartitionConfig::new(1, max_bytes, false).unwrap());
+        let members: Vec<u64> = (0..5_000u64).map(|i| i * 65_537).collect();
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer
+                .insert_members("key", members.iter().copied())
+                .unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let segments = dump_segments(&db, &table);
+        assert!(segments.len() > 1);
+        assert!(segments.iter().all(|(_, data)| data.len() <= max_bytes));
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.compact("key").unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        // Compaction re-splits instead of producing one oversized segment
+        let segments = dump_segments(&db, &table);
+        assert!(segments.len() > 1);
+        assert!(segments.iter().all(|(_, data)| data.len() <= max_bytes));
+
+        let read_txn = db.begin_read().unwrap();
+        let reader = PartitionedRead::new(&table, &read_txn);
+        assert!(reader.get_bitmap("key").unwrap().iter().eq(members));
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl PartitionObserver for RecordingObserver {
+        fn on_segment_roll(&self, table: &str, _base_key: &[u8], shard: u16, segment: u16) {
+            let event = format!("roll {} {} {}", table, shard, segment);
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_compaction(
+            &self,
+            table: &str,
+            _base_key: &[u8],
+            shard: u16,
+            segments_before: usize,
+            segments_after: usize,
+        ) {
+            let event = format!(
+                "compact {} {} {}->{}",
+                table, shard, segments_before, segments_after
+            );
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_oversized_write(
+            &self,
+            table: &str,
+            base_key: &[u8],
+            shard: u16,
+            size: usize,
+            max_size: usize,
+        ) {
+            assert_eq!(base_key, b"key");
+            assert!(size > max_size);
+            let event = format!("oversized {} {}", table, shard);
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn test_observer_receives_partition_events() {
+        let observer = Arc::new(RecordingObserver::default());
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = Database::create(temp_file.path()).unwrap();
+        let table: PartitionedTable<&str, RoaringValue> =
+            PartitionedTable::new("observed", PartitionConfig::new(1, 64, false).unwrap())
+                .with_observer(observer.clone());
+        table.ensure_table_exists(&db).unwrap();
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_member("key", 1).unwrap();
+            assert!(observer.events.lock().unwrap().is_empty());
+
+            // Spread members across containers so the batch cannot fit one segment
+            writer
+                .insert_members("key", (1..20u64).map(|i| i * 65_537))
+                .unwrap();
+            writer.compact("key").unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let events = observer.events.lock().unwrap();
+        assert_eq!(events[0], "oversized observed 0");
+        assert_eq!(events[1], "roll observed 0 1");
+        assert!(events.iter().any(|e| e.starts_with("compact observed 0 ")));
+    }
+
+    #[test]
+    fn test_write_stats_track_amplification() {
+        let (_file, db, table) = setup(PartitionConfig::new(1, 1024, true).unwrap());
+
+        let mut write_txn = db.begin_write().unwrap();
+        let writer = PartitionedWrite::new(&table, &mut write_txn);
+        assert_eq!(writer.stats(), Default::default());
+
+        // Every single-member insert rewrites the whole head segment
+        for member in 0..100u64 {
+            writer.insert_member("key", member).unwrap();
+        }
+        let stats = writer.stats();
+        assert_eq!(stats.logical_bytes, 100 * 8);
+        assert_eq!(stats.segments_written, 100);
+        assert!(stats.write_amplification().unwrap() > 1.0);
+
+        // Re-inserting existing members changes nothing physically
+        writer.insert_members("key", 0..10).unwrap();
+        assert_eq!(writer.stats().bytes_written, stats.bytes_written);
+
+        writer.clear_bitmap("key").unwrap();
+        let stats = writer.finish();
+        assert_eq!(stats.logical_bytes, 110 * 8);
+        assert_eq!(stats.segments_deleted, 1);
+        write_txn.commit().unwrap();
+    }
+
+    /// Reversible transform that flips every byte, standing in for encryption.
+    struct XorTransform;
+
+    impl SegmentTransform for XorTransform {
+        fn encode(&self, data: &[u8]) -> redb_extras::Result<Vec<u8>> {
+            Ok(data.iter().map(|b| b ^ 0xff).collect())
+        }
+
+        fn decode(&self, data: &[u8]) -> redb_extras::Result<Vec<u8>> {
+            self.encode(data)
+        }
+
+        fn id(&self) -> &str {
+            "xor"
+        }
+    }
+
+    #[test]
+    fn test_segment_transform_is_transparent() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = Database::create(temp_file.path()).unwrap();
+        let config = PartitionConfig::new(2, 1024, true)
+            .unwrap()
+            .with_tombstones(true);
+        let table: PartitionedTable<&str, RoaringValue> =
+            PartitionedTable::new("transformed", config).with_transform(Arc::new(XorTransform));
+        table.ensure_table_exists(&db).unwrap();
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_members("key", 0..100).unwrap();
+            writer.remove_members("key", [5, 50]).unwrap();
+            assert_eq!(writer.get_member_count("key").unwrap(), 98);
+        }
+        write_txn.commit().unwrap();
+
+        // Stored payloads are transformed, so they aren't valid bitmaps as-is
+        for (_, value) in dump_segments(&db, &table) {
+            assert!(RoaringValue::decode(&value).is_err());
+            let plain = XorTransform.decode(&value).unwrap();
+            assert!(RoaringValue::decode(&plain).is_ok());
+        }
+
+        let read_txn = db.begin_read().unwrap();
+        let reader = PartitionedRead::new(&table, &read_txn);
+        let bitmap = reader.get_bitmap("key").unwrap();
+        assert_eq!(bitmap.len(), 98);
+        assert!(!bitmap.contains(5));
+        assert!(!reader.contains_member("key", 50).unwrap());
+        assert!(reader.contains_member("key", 51).unwrap());
+    }
+
+    #[derive(Default)]
+    struct CountingTransform {
+        decodes: AtomicUsize,
+    }
+
+    impl SegmentTransform for CountingTransform {
+        fn encode(&self, data: &[u8]) -> redb_extras::Result<Vec<u8>> {
+            Ok(data.to_vec())
+        }
+
+        fn decode(&self, data: &[u8]) -> redb_extras::Result<Vec<u8>> {
+            self.decodes.fetch_add(1, Ordering::SeqCst);
+            Ok(data.to_vec())
+        }
+
+        fn id(&self) -> &str {
+            "counting"
+        }
+    }
+
+    #[test]
+    fn test_iter_members_in_skips_disjoint_segments() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let db = Database::create(temp_file.path()).unwrap();
+        let transform = Arc::new(CountingTransform::default());
+        let config = PartitionConfig::new(1, 1024, true)
+            .unwrap()
+            .with_tombstones(true);
+        let table: PartitionedTable<&str, RoaringValue> =
+            PartitionedTable::new("ranged", config).with_transform(transform.clone());
+        table.ensure_table_exists(&db).unwrap();
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer
+                .insert_members("key", (0..2000u64).map(|m| m * 3))
+                .unwrap();
+            writer.remove_member("key", 450).unwrap();
+
+            let members: Vec<u64> = writer.iter_members_in("key", 300..600).unwrap().collect();
+            assert_eq!(members.len(), 99);
+            assert_eq!(members.first(), Some(&300));
+            assert_eq!(members.last(), Some(&597));
+        }
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let reader = PartitionedRead::new(&table, &read_txn);
+        let segment_count = reader.enumerate_shard_segments("key", 0).unwrap().len();
+        assert!(segment_count > 2);
+
+        transform.decodes.store(0, Ordering::SeqCst);
+        let members: Vec<u64> = reader.iter_members_in("key", 300..=600).unwrap().collect();
+        let expected: Vec<u64> = (100..=200).map(|m| m * 3).filter(|m| *m != 450).collect();
+        assert_eq!(members, expected);
+
+        // One segment holds the range, plus the tombstone
+        assert_eq!(transform.decodes.load(Ordering::SeqCst), 2);
+
+        assert!(reader
+            .iter_members_in("key", 10..10)
+            .unwrap()
+            .next()
+            .is_none());
+        assert_eq!(reader.iter_members_in("key", 5998..).unwrap().count(), 0);
+        assert_eq!(reader.iter_members_in("key", ..).unwrap().count(), 1999);
+    }
+
+    #[test]
+    fn test_migrate_resegments_existing_data() {
+        let config = PartitionConfig::new(2, 1024, false)
+            .unwrap()
+            .with_tombstones(true);
+        let (_file, db, mut table) = setup(config);
+        let keys = ["a", "b", "c", "d", "e"];
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            for (i, key) in keys.iter().enumerate() {
+                writer
+                    .insert_members(*key, (0..200u64).map(|m| m * 65_537 + i as u64))
+                    .unwrap();
+                writer.remove_member(*key, 65_537).unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+
+        let before: Vec<_> = {
+            let read_txn = db.begin_read().unwrap();
+            let reader = PartitionedRead::new(&table, &read_txn);
+            keys.iter()
+                .map(|k| reader.get_bitmap(*k).unwrap())
+                .collect()
+        };
+
+        let report = table
+            .migrate_in_chunks(&db, PartitionConfig::new(2, 64, true).unwrap(), 2)
+            .unwrap();
+        assert_eq!(report.keys_migrated, 5);
+        assert_eq!(report.transactions, 3);
+        assert!(!report.resumed);
+        assert_eq!(table.config().segment_max_bytes, 64);
+        assert!(table.config().use_meta);
+        table.ensure_table_exists(&db).unwrap();
+
+        assert!(dump_segments(&db, &table)
+            .iter()
+            .all(|(_, value)| value.len() <= 64));
+
+        let read_txn = db.begin_read().unwrap();
+        assert!(!read_txn
+            .open_table(table.meta_table())
+            .unwrap()
+            .is_empty()
+            .unwrap());
+        assert!(read_txn
+            .open_table(table.tombstone_table())
+            .unwrap()
+            .is_empty()
+            .unwrap());
+        assert!(read_txn
+            .open_table(MIGRATION_TABLE)
+            .unwrap()
+            .is_empty()
+            .unwrap());
+
+        let reader = PartitionedRead::new(&table, &read_txn);
+        for (key, bitmap) in keys.iter().zip(&before) {
+            assert_eq!(&reader.get_bitmap(*key).unwrap(), bitmap);
+            assert_eq!(reader.cached_member_count(*key).unwrap(), bitmap.len());
+        }
+    }
+
+    #[test]
+    fn test_migrate_only_rewrites_own_keys() {
+        let config = PartitionConfig::new(2, 1024, false).unwrap();
+        let (_file, db, mut table) = setup(config.clone());
+        let hashes: PartitionedMultimapTable<&str, 32> =
+            PartitionedMultimapTable::new("hashes", config);
+        hashes.ensure_table_exists(&db).unwrap();
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&hashes, &mut write_txn);
+            writer.insert_values("a", [[1u8; 32], [2u8; 32]]).unwrap();
+            writer.insert_value("b", [3u8; 32]).unwrap();
+        }
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_members("b", 0..50).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let report = table
+            .migrate(&db, PartitionConfig::new(2, 64, true).unwrap())
+            .unwrap();
+        assert_eq!(report.keys_migrated, 1);
+
+        let read_txn = db.begin_read().unwrap();
+        let reader = PartitionedRead::new(&table, &read_txn);
+        assert_eq!(reader.get_member_count("b").unwrap(), 50);
+        let reader = PartitionedRead::new(&hashes, &read_txn);
+        assert_eq!(reader.get_value_count("a").unwrap(), 2);
+        assert_eq!(reader.get_values("b").unwrap(), vec![[3u8; 32]]);
+    }
+
+    #[test]
+    fn test_migrate_resumes_from_progress() {
+        let (_file, db, mut table) = setup(PartitionConfig::new(1, 1024, false).unwrap());
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            for key in ["a", "b", "c"] {
+                writer.insert_members(key, 0..10).unwrap();
+            }
+        }
+        {
+            // Pretend a previous run committed "a" and was interrupted
+            let mut progress = write_txn.open_table(MIGRATION_TABLE).unwrap();
+            progress.insert("bitmaps", b"a".as_slice()).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let report = table
+            .migrate(&db, PartitionConfig::new(1, 1024, true).unwrap())
+            .unwrap();
+        assert!(report.resumed);
+        assert_eq!(report.keys_migrated, 2);
+        assert_eq!(report.transactions, 1);
+
+        // Only the keys after the recorded progress were rewritten with meta
+        // rows: a shard entry and the bounds of its single segment each
+        let read_txn = db.begin_read().unwrap();
+        assert_eq!(
+            read_txn
+                .open_table(table.meta_table())
+                .unwrap()
+                .len()
+                .unwrap(),
+            4
+        );
+        let reader = PartitionedRead::new(&table, &read_txn);
+        for key in ["a", "b", "c"] {
+            assert_eq!(reader.get_member_count(key).unwrap(), 10);
+        }
+    }
+
+    #[test]
+    fn test_reopen_with_different_config_fails() {
+        let (_file, db, _table) = setup(PartitionConfig::new(4, 1024, false).unwrap());
+
+        // Meta doesn't affect how data is read, so toggling it is fine
+        let reopened: PartitionedTable<&str, RoaringValue> =
+            PartitionedTable::new("bitmaps", PartitionConfig::new(4, 1024, true).unwrap());
+        reopened.ensure_table_exists(&db).unwrap();
+
+        // Pending tombstones would be ignored without them
+        let tombstoned: PartitionedTable<&str, RoaringValue> = PartitionedTable::new(
+            "bitmaps",
+            PartitionConfig::new(4, 1024, false)
+                .unwrap()
+                .with_tombstones(true),
+        );
+        let err = tombstoned.ensure_table_exists(&db).unwrap_err();
+        assert!(err.to_string().contains("use_tombstones false"));
+
+        let transformed: PartitionedTable<&str, RoaringValue> =
+            PartitionedTable::new("bitmaps", PartitionConfig::new(4, 1024, false).unwrap())
+                .with_transform(Arc::new(XorTransform));
+        let err = transformed.ensure_table_exists(&db).unwrap_err();
+        assert!(err.to_string().contains("transform none"));
+
+        let resharded: PartitionedTable<&str, RoaringValue> =
+            PartitionedTable::new("bitmaps", PartitionConfig::new(8, 1024, false).unwrap());
+        let err = resharded.ensure_table_exists(&db).unwrap_err();
+        assert!(matches!(
+            err,
+            redb_extras::Error::Partition(PartitionError::ConfigMismatch(_))
+        ));
+        assert!(err.to_string().contains("shard_count 4"));
+
+        let resized: PartitionedTable<&str, RoaringValue> =
+            PartitionedTable::new("bitmaps", PartitionConfig::new(4, 2048, false).unwrap());
+        assert!(resized.ensure_table_exists(&db).is_err());
+
+        // Headers are per table name
+        let other: PartitionedTable<&str, RoaringValue> =
+            PartitionedTable::new("other", PartitionConfig::new(8, 2048, false).unwrap());
+        other.ensure_table_exists(&db).unwrap();
+    }
+
+    #[test]
+    fn test_legacy_shared_storage_is_rejected_then_adopted() {
+        let config = PartitionConfig::new(4, 1024, true).unwrap();
+        let (_file, db, table) = setup(config.clone());
+
+        let mut write_txn = db.begin_write().unwrap();
+        {
+            let writer = PartitionedWrite::new(&table, &mut write_txn);
+            writer.insert_members("user1", 0..100).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        // Rewrite the database the way format version 1 laid it out: shared
+        // storage tables and an 11 byte header
+        let write_txn = db.begin_write().unwrap();
+        for (own, shared) in [
+            (table.segment_table(), "redb_extras_segments"),
+            (table.meta_table(), "redb_extras_meta"),
+        ] {
+            {
+                let from = write_txn.open_table(own).unwrap();
+                let mut to = write_txn
+                    .open_table(TableDefinition::<&[u8], &[u8]>::new(shared))
+                    .unwrap();
+                for entry in from.iter().unwrap() {
+                    let (key, value) = entry.unwrap();
+                    to.insert(key.value(), value.value()).unwrap();
+                }
+            }
+            write_txn.delete_table(own).unwrap();
+        }
+        {
+            let mut header = vec![1u8];
+            header.extend_from_slice(&4u16.to_be_bytes());
+            header.extend_from_slice(&1024u64.to_be_bytes());
+            let mut configs = write_txn.open_table(CONFIG_TABLE).unwrap();
+            configs.insert("bitmaps", header.as_slice()).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let err = table.ensure_table_exists(&db).unwrap_err();
+        assert!(err.to_string().contains("format version 1"), "{}", err);
+
+        // Other table names can't take over the shared rows
+        let other: PartitionedTable<&str, RoaringValue> =
+            PartitionedTable::new("other", config.clone());
+        assert!(other.ensure_table_exists(&db).is_err());
+        assert!(other.adopt_legacy_storage(&db).is_err());
+
+        let moved = table.adopt_legacy_storage(&db).unwrap();
+        assert!(moved > 0);
+        table.ensure_table_exists(&db).unwrap();
+        other.ensure_table_exists(&db).unwrap();
+        assert_eq!(table.adopt_legacy_storage(&db).unwrap(), 0);
+
+        let read_txn = db.begin_read().unwrap();
+        let names: Vec<String> = read_txn
+            .list_tables()
+            .unwrap()
+            .map(|table| table.name().to_string())
+            .collect();
+        assert!(!names.iter().any(|name| name == "redb_extras_segments"));
+        let reader = PartitionedRead::new(&table, &read_txn);
+        assert!(reader.get_bitmap("user1").unwrap().iter().eq(0..100));
+        assert_eq!(reader.get_member_count("user1").unwrap(), 100);
+    }
+
+    #[test]
+    fn test_migrate_rejects_shard_count_change() {
+        let (_file, db, mut table) = setup(PartitionConfig::new(2, 1024, false).unwrap());
+
+        let result = table.migrate(&db, PartitionConfig::new(4, 1024, false).unwrap());
+        assert!(result.is_err());
+        assert_eq!(table.config().shard_count, 2);
+    }
+}